@@ -0,0 +1,239 @@
+//! Animated PNG, an extension of PNG that is supported by all major browsers.
+//!
+//! Unlike gifs, APNG frames are stored with full 8 bit RGBA colors, so nothing is
+//! lost to quantization.
+
+use filters;
+use png;
+use zlib;
+use {Animation, FrameData};
+
+/// What happens to the area of a frame before the next frame is drawn.
+#[derive(PartialEq)]
+enum Dispose {
+    None,
+    Background,
+    Previous,
+}
+
+/// The properties of a frame from its fcTL chunk.
+struct FrameControl {
+    width: u32,
+    height: u32,
+    left: u32,
+    top: u32,
+    /// The delay in hundredths of a second
+    delay: u16,
+    dispose: Dispose,
+    /// Whether the frame is alpha blended over the canvas instead of replacing it
+    blend: bool,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+impl FrameControl {
+    fn read(data: &[u8]) -> Result<FrameControl, String> {
+        if data.len() < 26 {
+            return Err("invalid fcTL chunk".to_string());
+        }
+
+        let numerator = u16::from_be_bytes([data[20], data[21]]) as u32;
+        // a denominator of 0 means hundredths of a second
+        let denominator = match u16::from_be_bytes([data[22], data[23]]) as u32 {
+            0 => 100,
+            denominator => denominator,
+        };
+
+        Ok(FrameControl {
+            width: u32_at(data, 4),
+            height: u32_at(data, 8),
+            left: u32_at(data, 12),
+            top: u32_at(data, 16),
+            delay: ((numerator * 100 + denominator / 2) / denominator).min(u16::MAX as u32) as u16,
+            dispose: match data[24] {
+                1 => Dispose::Background,
+                2 => Dispose::Previous,
+                _ => Dispose::None,
+            },
+            blend: data[25] == 1,
+        })
+    }
+}
+
+/// Encodes full canvas sized frames as an animated PNG.
+///
+/// `plays` is how often the animation is played, 0 plays it forever. Every frame
+/// replaces the whole canvas including its transparency.
+pub fn encode(width: u32, height: u32, frames: &[FrameData], plays: u32) -> Vec<u8> {
+    let mut output = png::SIGNATURE.to_vec();
+    png::write_chunk(&mut output, b"IHDR", &png::header(width, height));
+
+    let mut animation_control = Vec::with_capacity(8);
+    animation_control.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    animation_control.extend_from_slice(&plays.to_be_bytes());
+    png::write_chunk(&mut output, b"acTL", &animation_control);
+
+    // fcTL and fdAT chunks share one sequence number
+    let mut sequence = 0u32;
+    for (i, frame) in frames.iter().enumerate() {
+        let mut frame_control = Vec::with_capacity(26);
+        frame_control.extend_from_slice(&sequence.to_be_bytes());
+        frame_control.extend_from_slice(&(frame.width as u32).to_be_bytes());
+        frame_control.extend_from_slice(&(frame.height as u32).to_be_bytes());
+        // x and y offset
+        frame_control.extend_from_slice(&[0; 8]);
+        // gif delays are given in hundredths of a second
+        frame_control.extend_from_slice(&frame.delay.to_be_bytes());
+        frame_control.extend_from_slice(&100u16.to_be_bytes());
+        // dispose op none, blend op source
+        frame_control.extend_from_slice(&[0, 0]);
+        png::write_chunk(&mut output, b"fcTL", &frame_control);
+        sequence += 1;
+
+        let compressed = zlib::compress(&png::filter_image(frame.width as u32, frame.height as u32, &frame.rgba));
+        if i == 0 {
+            // the first frame doubles as the still image for decoders without APNG support
+            png::write_chunk(&mut output, b"IDAT", &compressed);
+        } else {
+            let mut frame_data = Vec::with_capacity(compressed.len() + 4);
+            frame_data.extend_from_slice(&sequence.to_be_bytes());
+            frame_data.extend_from_slice(&compressed);
+            png::write_chunk(&mut output, b"fdAT", &frame_data);
+            sequence += 1;
+        }
+    }
+
+    png::write_chunk(&mut output, b"IEND", &[]);
+    output
+}
+
+/// Decodes an animated PNG and composes its frames. PNGs without animation are
+/// returned as a single frame.
+pub fn decode(data: &[u8]) -> Result<Animation, String> {
+    if !data.starts_with(&png::SIGNATURE) {
+        return Err("not a PNG".to_string());
+    }
+
+    let chunks = png::chunks(&data[png::SIGNATURE.len()..])?;
+    let header = chunks
+        .first()
+        .filter(|chunk| chunk.kind == b"IHDR")
+        .ok_or("the PNG has no IHDR chunk")?;
+    let mut format = png::Format::from_header(header.data)?;
+    if format.width == 0 || format.height == 0 || format.width > u16::MAX as u32 || format.height > u16::MAX as u32 {
+        return Err(format!(
+            "{}x{} is not a valid size for a gif",
+            format.width, format.height
+        ));
+    }
+
+    let mut plays = 0;
+    let mut is_animated = false;
+    let mut image_data = Vec::new();
+    // the compressed data of every frame, the default image only belongs to the
+    // animation if its fcTL comes first
+    let mut frames: Vec<(FrameControl, Vec<u8>)> = Vec::new();
+
+    for chunk in &chunks {
+        match chunk.kind {
+            b"PLTE" => format.palette = chunk.data.to_vec(),
+            b"tRNS" => format.transparency = Some(chunk.data.to_vec()),
+            b"acTL" if chunk.data.len() >= 8 => {
+                is_animated = true;
+                plays = u32_at(chunk.data, 4);
+            }
+            b"fcTL" => frames.push((FrameControl::read(chunk.data)?, Vec::new())),
+            b"IDAT" => {
+                image_data.extend_from_slice(chunk.data);
+                if let Some((_, frame_data)) = frames.last_mut() {
+                    frame_data.extend_from_slice(chunk.data);
+                }
+            }
+            // fdAT starts with a sequence number
+            b"fdAT" if chunk.data.len() >= 4 => {
+                if let Some((_, frame_data)) = frames.last_mut() {
+                    frame_data.extend_from_slice(&chunk.data[4..]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (width, height) = (format.width as usize, format.height as usize);
+
+    if !is_animated || frames.is_empty() {
+        let rgba = format.decode(format.width, format.height, &zlib::decompress(&image_data)?)?;
+        return Ok(Animation {
+            width: width as u16,
+            height: height as u16,
+            plays: 0,
+            frames: vec![FrameData {
+                width: width as u16,
+                height: height as u16,
+                rgba,
+                delay: 0,
+            }],
+        });
+    }
+
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut composed = Vec::with_capacity(frames.len());
+
+    for (i, (control, frame_data)) in frames.iter().enumerate() {
+        let (left, top) = (control.left as usize, control.top as usize);
+        let right = left + control.width as usize;
+        let bottom = top + control.height as usize;
+        if right > width || bottom > height {
+            return Err(format!("frame {} lies outside of the canvas", i));
+        }
+
+        let rgba = format.decode(control.width, control.height, &zlib::decompress(frame_data)?)?;
+        let previous = if control.dispose == Dispose::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        for y in top..bottom {
+            for x in left..right {
+                let source = &rgba[((y - top) * control.width as usize + x - left) * 4..][..4];
+                let target = &mut canvas[(y * width + x) * 4..][..4];
+                if control.blend {
+                    filters::blend_over(target, source);
+                } else {
+                    target.copy_from_slice(source);
+                }
+            }
+        }
+
+        composed.push(FrameData {
+            width: width as u16,
+            height: height as u16,
+            rgba: canvas.clone(),
+            delay: control.delay,
+        });
+
+        match control.dispose {
+            Dispose::Background => {
+                for y in top..bottom {
+                    for value in &mut canvas[(y * width + left) * 4..(y * width + right) * 4] {
+                        *value = 0;
+                    }
+                }
+            }
+            // restoring the canvas before the first frame clears it, which is what
+            // the specification asks for
+            Dispose::Previous => canvas = previous.unwrap(),
+            Dispose::None => {}
+        }
+    }
+
+    Ok(Animation {
+        width: width as u16,
+        height: height as u16,
+        plays,
+        frames: composed,
+    })
+}
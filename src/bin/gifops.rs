@@ -0,0 +1,67 @@
+//! Processes gifs on the command line with the native functions of the library.
+//!
+//! ```text
+//! gifops info <input>
+//! gifops reverse <input> <output>
+//! gifops speed <input> <output> <factor>
+//! gifops trim <input> <output> <start_frame> <end_frame>
+//! ```
+
+extern crate gif;
+
+use gif::native;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage:
+    gifops info <input>
+    gifops reverse <input> <output>
+    gifops speed <input> <output> <factor>
+    gifops trim <input> <output> <start_frame> <end_frame>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("gifops: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args[..] {
+        ["info", input] => {
+            let info = native::info(&read(input)?)?;
+            println!("size: {}x{}", info.width, info.height);
+            println!("frames: {}", info.frame_count);
+            println!("duration: {} ms", info.duration_ms);
+            println!("loop count: {}", info.loop_count);
+            Ok(())
+        }
+        ["reverse", input, output] => write(output, native::reverse(&read(input)?)?),
+        ["speed", input, output, factor] => {
+            let factor = parse(factor, "factor")?;
+            write(output, native::speed(&read(input)?, factor)?)
+        }
+        ["trim", input, output, start, end] => {
+            let (start, end) = (parse(start, "start_frame")?, parse(end, "end_frame")?);
+            write(output, native::trim(&read(input)?, start, end)?)
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn write(path: &str, data: Vec<u8>) -> Result<(), String> {
+    fs::write(path, data).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} has to be a number but is {}", name, value))
+}
@@ -0,0 +1,570 @@
+//! Composition of partial gif frames into full frames.
+
+#[cfg(feature = "wasm")]
+use filters;
+use gif::{Decoder, DisposalMethod, Frame};
+use std::io::Read;
+#[cfg(feature = "wasm")]
+use timeline::Entry;
+use FrameData;
+
+/// Keeps track of what the animation looks like after every frame.
+///
+/// Gifs can contain frames that only cover a part of the image, and every frame
+/// decides how the area it covered is cleaned up before the next frame is drawn.
+/// The compositor takes care of both and turns every frame into a full canvas
+/// sized RGBA image.
+pub struct Compositor {
+    width: u16,
+    height: u16,
+    canvas: Vec<u8>,
+    /// The palette of frames that don't have their own
+    global_palette: Vec<u8>,
+    /// The RGBA background color of the gif, see `background_color`
+    background: [u8; 4],
+    /// Whether a frame has been drawn yet
+    started: bool,
+    /// The RGBA pixels of the row that is drawn
+    row: Vec<u8>,
+}
+
+impl Compositor {
+    pub fn new(width: u16, height: u16, global_palette: &[u8], background: [u8; 4]) -> Compositor {
+        // allocate enough memory to fit in a full sized frame
+        // width * height is the number of pixels and times 4 for the color channels (r, g, b, and a)
+        Compositor {
+            width,
+            height,
+            canvas: vec![0; (width as usize) * (height as usize) * 4_usize],
+            global_palette: global_palette.to_vec(),
+            background,
+            started: false,
+            row: Vec::new(),
+        }
+    }
+
+    /// Creates a compositor for the gif that `reader` has read the header of. The
+    /// reader has to put out palette indices.
+    pub fn for_decoder<R: Read>(reader: &Decoder<R>) -> Compositor {
+        Compositor::new(
+            reader.width(),
+            reader.height(),
+            reader.global_palette().unwrap_or(&[]),
+            background_color(reader.global_palette(), reader.bg_color()),
+        )
+    }
+
+    /// Draws a decoded frame onto the canvas and returns the result.
+    ///
+    /// Only the position, timing, disposal, palette and transparent color are taken
+    /// from `frame`, its palette indices are passed separately so decoders can reuse
+    /// the same buffer for all frames.
+    pub fn compose(&mut self, frame: &Frame, indices: &[u8]) -> FrameData {
+        let previous = self.draw(frame, indices);
+
+        // this copy is necessary because we need the canvas to put (parts of) the next
+        // frame on top of the existing buffer data.
+        let frame_data = FrameData {
+            width: self.width,
+            height: self.height,
+            rgba: self.canvas.clone(),
+            delay: frame.delay,
+        };
+
+        self.dispose(frame, previous);
+        frame_data
+    }
+
+    /// Like `compose` but appends the composed frame to `arena` instead of returning
+    /// a copy of the canvas. The arena has to have the size of the canvas.
+    #[cfg(feature = "wasm")]
+    pub fn compose_into(&mut self, frame: &Frame, indices: &[u8], arena: &mut FrameArena) {
+        let previous = self.draw(frame, indices);
+        arena.push(&self.canvas, frame.delay);
+        self.dispose(frame, previous);
+    }
+
+    /// Like `compose` for frames that are only needed for the frames after them,
+    /// the canvas isn't copied.
+    #[cfg(feature = "wasm")]
+    pub fn skip(&mut self, frame: &Frame, indices: &[u8]) {
+        let previous = self.draw(frame, indices);
+        self.dispose(frame, previous);
+    }
+
+    /// Draws the frame onto the canvas. Returns the canvas from before if the frame
+    /// is disposed to the previous state.
+    fn draw(&mut self, frame: &Frame, indices: &[u8]) -> Option<Vec<u8>> {
+        // the canvas starts out empty, which looks like the background unless the
+        // first frame has transparent pixels
+        if !self.started {
+            self.started = true;
+            let empty = clear_color(frame, self.background);
+            for pixel in self.canvas.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&empty);
+            }
+        }
+
+        // frames that are disposed with `Previous` restore the canvas to what it looked
+        // like before they were drawn, so we have to keep a copy.
+        let previous = if frame.dispose == DisposalMethod::Previous {
+            Some(self.canvas.clone())
+        } else {
+            None
+        };
+
+        let palette = frame.palette.as_ref().unwrap_or(&self.global_palette);
+        draw_frame(
+            &mut self.canvas,
+            self.width,
+            self.height,
+            frame,
+            indices,
+            palette,
+            &mut self.row,
+        );
+
+        previous
+    }
+
+    /// Prepares the canvas for the next frame.
+    fn dispose(&mut self, frame: &Frame, previous: Option<Vec<u8>>) {
+        match frame.dispose {
+            DisposalMethod::Background => clear_area(
+                &mut self.canvas,
+                self.width,
+                self.height,
+                (frame.left, frame.top, frame.width, frame.height),
+                clear_color(frame, self.background),
+            ),
+            DisposalMethod::Previous => self.canvas = previous.unwrap(),
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+    }
+}
+
+/// Fully composed frames of the same size, stored one after the other in a single
+/// buffer.
+///
+/// Gifs with many frames would otherwise need an allocation for every frame, and
+/// growing a list of frames copies all of them whenever it runs out of space. The
+/// arena reserves the memory for all frames up front, transforms change the frames
+/// in place and the encoder reads them through `FrameRef`s without copying them.
+#[cfg(feature = "wasm")]
+pub struct FrameArena {
+    pub width: u16,
+    pub height: u16,
+    pixels: Vec<u8>,
+    delays: Vec<u16>,
+}
+
+#[cfg(feature = "wasm")]
+impl FrameArena {
+    /// Creates an empty arena with room for `capacity` frames of `width` x `height`
+    /// pixels.
+    pub fn with_capacity(width: u16, height: u16, capacity: usize) -> FrameArena {
+        let frame_size = width as usize * height as usize * 4;
+        FrameArena {
+            width,
+            height,
+            pixels: Vec::with_capacity(frame_size * capacity),
+            delays: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// The number of bytes of a frame
+    fn frame_size(&self) -> usize {
+        self.width as usize * self.height as usize * 4
+    }
+
+    pub fn len(&self) -> usize {
+        self.delays.len()
+    }
+
+    /// Makes room for `additional` more frames.
+    pub fn reserve(&mut self, additional: usize) {
+        self.pixels.reserve(self.frame_size() * additional);
+        self.delays.reserve(additional);
+    }
+
+    /// Appends a frame and returns its index.
+    pub fn push(&mut self, rgba: &[u8], delay: u16) -> usize {
+        debug_assert_eq!(rgba.len(), self.frame_size());
+        self.pixels.extend_from_slice(rgba);
+        self.delays.push(delay);
+        self.delays.len() - 1
+    }
+
+    /// The RGBA pixels of the frame `index`.
+    pub fn rgba(&self, index: usize) -> &[u8] {
+        let size = self.frame_size();
+        &self.pixels[index * size..(index + 1) * size]
+    }
+
+    /// The RGBA pixels of the frame `index` to change them in place.
+    pub fn rgba_mut(&mut self, index: usize) -> &mut [u8] {
+        let size = self.frame_size();
+        &mut self.pixels[index * size..(index + 1) * size]
+    }
+
+    pub fn delays(&self) -> &[u16] {
+        &self.delays
+    }
+
+    /// One entry per frame in the order they were added.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.delays
+            .iter()
+            .enumerate()
+            .map(|(index, &delay)| Entry { index, delay })
+            .collect()
+    }
+
+    /// Borrows the frame `index`.
+    pub fn frame(&self, index: usize) -> FrameRef<'_> {
+        FrameRef {
+            width: self.width,
+            height: self.height,
+            rgba: self.rgba(index),
+            delay: self.delays[index],
+        }
+    }
+
+    /// Changes the frame `target` to `t` of the way towards the frame `source`, see
+    /// `filters::mix_into`.
+    pub fn blend(&mut self, target: usize, source: usize, t: f32) {
+        let size = self.frame_size();
+        if target == source || size == 0 {
+            return;
+        }
+        let (first, second) = self.pixels.split_at_mut(target.max(source) * size);
+        let (target, source) = if target < source {
+            (&mut first[target * size..(target + 1) * size], &second[..size])
+        } else {
+            (&mut second[..size], &first[source * size..(source + 1) * size])
+        };
+        filters::mix_into(target, source, t);
+    }
+}
+
+/// A frame that borrows its pixels from a `FrameArena` or a `FrameData`.
+#[derive(Clone, Copy)]
+pub struct FrameRef<'a> {
+    pub width: u16,
+    pub height: u16,
+    pub rgba: &'a [u8],
+    pub delay: u16,
+}
+
+impl<'a> FrameRef<'a> {
+    /// The same frame shown for `delay` instead.
+    #[cfg(feature = "wasm")]
+    pub fn with_delay(self, delay: u16) -> FrameRef<'a> {
+        FrameRef { delay, ..self }
+    }
+}
+
+/// The rows of a frame that lie within a canvas of `width` x `height` pixels.
+///
+/// Some frames may be smaller than the whole image. We need to calculate the
+/// correct index to map the frame to the correct parts of the canvas.
+///
+/// ```text
+///  canvas   -   width
+/// +-------------------------------------------------------+
+/// | frame  top        frame width                         |
+/// |  left   +---------------------------------------+     | canvas
+/// |         |                                 frame |     | height
+/// |         |                                height |     |
+/// |         +---------------------------------------+     |
+/// +-------------------------------------------------------+
+/// ```
+///
+/// Row `y` of the frame starts `top + y` lines and `left` pixels into the canvas.
+/// Yields the index of the first pixel of every row in the canvas together with
+/// the palette indices of the row. Parts of the frame that stick out of the canvas
+/// are cut off, frames that lie completely outside of it have no rows.
+pub fn visible_rows<'a>(
+    frame: &Frame,
+    indices: &'a [u8],
+    width: u16,
+    height: u16,
+) -> impl Iterator<Item = (usize, &'a [u8])> + 'a {
+    let left = frame.left as usize;
+    let top = frame.top as usize;
+    let frame_width = frame.width as usize;
+    let canvas_width = width as usize;
+    let visible_width = frame_width.min(canvas_width.saturating_sub(left));
+    let visible_height = if visible_width == 0 {
+        0
+    } else {
+        (height as usize).saturating_sub(top)
+    };
+
+    indices
+        .chunks(frame_width.max(1))
+        .take(visible_height)
+        .enumerate()
+        .map(move |(y, row)| ((top + y) * canvas_width + left, &row[..row.len().min(visible_width)]))
+}
+
+/// Draws `indices`, the palette indices of `frame`, onto an RGBA canvas of `width`
+/// x `height` pixels. Only pixels that don't have the transparent index are drawn.
+/// `row` is scratch space for the colors of a row, so it can be reused between
+/// frames.
+pub fn draw_frame(
+    canvas: &mut [u8],
+    width: u16,
+    height: u16,
+    frame: &Frame,
+    indices: &[u8],
+    palette: &[u8],
+    row: &mut Vec<u8>,
+) {
+    // the colors are looked up row by row, transparent pixels get an alpha of 0
+    for (start, indices) in visible_rows(frame, indices, width, height) {
+        expand_row(row, indices, palette, frame.transparent);
+        draw_row(&mut canvas[start * 4..start * 4 + row.len()], row);
+    }
+}
+
+/// Looks up the colors of a row of palette indices. Pixels with the `transparent`
+/// index are fully transparent, all others fully opaque. Indices outside of the
+/// palette are black.
+fn expand_row(rgba: &mut Vec<u8>, indices: &[u8], palette: &[u8], transparent: Option<u8>) {
+    rgba.clear();
+    for &index in indices {
+        if Some(index) == transparent {
+            rgba.extend_from_slice(&[0, 0, 0, 0]);
+            continue;
+        }
+        match palette.get(index as usize * 3..index as usize * 3 + 3) {
+            Some(rgb) => rgba.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]),
+            None => rgba.extend_from_slice(&[0, 0, 0, 255]),
+        }
+    }
+}
+
+/// Copies the pixels of `source` that aren't fully transparent onto `target`, both
+/// are RGBA pixels of the same length.
+///
+/// With the `simd` feature on a wasm target with `simd128` enabled, four pixels are
+/// handled at once.
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+fn draw_row(target: &mut [u8], source: &[u8]) {
+    use core::arch::wasm32::*;
+
+    let lanes = source.len() / 16 * 16;
+    for (target, source) in target[..lanes]
+        .chunks_exact_mut(16)
+        .zip(source[..lanes].chunks_exact(16))
+    {
+        // safe because both chunks are exactly 16 bytes long, v128_load and
+        // v128_store don't need aligned addresses
+        unsafe {
+            let pixels = v128_load(source.as_ptr() as *const v128);
+            let canvas = v128_load(target.as_ptr() as *const v128);
+            // the alpha channel is the highest byte of every little endian pixel
+            let visible = i32x4_ne(v128_and(pixels, u32x4_splat(0xff00_0000)), u32x4_splat(0));
+            v128_store(
+                target.as_mut_ptr() as *mut v128,
+                v128_bitselect(pixels, canvas, visible),
+            );
+        }
+    }
+    draw_pixels(&mut target[lanes..], &source[lanes..]);
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128")))]
+fn draw_row(target: &mut [u8], source: &[u8]) {
+    draw_pixels(target, source);
+}
+
+/// The scalar version of `draw_row`.
+fn draw_pixels(target: &mut [u8], source: &[u8]) {
+    for (target, source) in target.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+        if source[3] != 0 {
+            target.copy_from_slice(source);
+        }
+    }
+}
+
+/// The RGBA color of the entry `index` of the global palette that the logical screen
+/// descriptor names as the background color. Transparent if the gif has no global
+/// palette or the index is out of range.
+pub fn background_color(global_palette: Option<&[u8]>, index: Option<usize>) -> [u8; 4] {
+    match (global_palette, index) {
+        (Some(palette), Some(index)) if index * 3 + 3 <= palette.len() => {
+            [palette[index * 3], palette[index * 3 + 1], palette[index * 3 + 2], 255]
+        }
+        _ => [0, 0, 0, 0],
+    }
+}
+
+/// The color that areas are cleared to before and after `frame`.
+///
+/// The specification fills them with the background color. Browsers ignore it and
+/// always clear to transparent, so gifs with transparent frames rely on that. Like
+/// most other decoders, the background color is used only for frames without a
+/// transparent color.
+pub fn clear_color(frame: &Frame, background: [u8; 4]) -> [u8; 4] {
+    if frame.transparent.is_some() {
+        [0, 0, 0, 0]
+    } else {
+        background
+    }
+}
+
+/// Resets the `area` given as `(left, top, width, height)` of the canvas to `color`,
+/// which is what the `Background` disposal method asks for.
+fn clear_area(canvas: &mut [u8], width: u16, height: u16, area: (u16, u16, u16, u16), color: [u8; 4]) {
+    let (left, top, area_width, area_height) = area;
+    let right = (left as usize + area_width as usize).min(width as usize);
+    let bottom = (top as usize + area_height as usize).min(height as usize);
+
+    for y in top as usize..bottom {
+        let row = y * width as usize;
+        for x in left as usize..right {
+            canvas[(row + x) * 4..(row + x + 1) * 4].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Red, green and blue
+    const PALETTE: [u8; 9] = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    /// A frame of `width` x `height` pixels at `left`, `top` that is disposed with
+    /// `dispose`.
+    fn frame(left: u16, top: u16, width: u16, height: u16, dispose: DisposalMethod) -> Frame<'static> {
+        Frame {
+            left,
+            top,
+            width,
+            height,
+            dispose,
+            ..Frame::default()
+        }
+    }
+
+    fn pixel(frame: &FrameData, x: usize, y: usize) -> [u8; 4] {
+        let start = (y * frame.width as usize + x) * 4;
+        [
+            frame.rgba[start],
+            frame.rgba[start + 1],
+            frame.rgba[start + 2],
+            frame.rgba[start + 3],
+        ]
+    }
+
+    /// The colors of all pixels row by row.
+    fn pixels(frame: &FrameData) -> Vec<[u8; 4]> {
+        frame
+            .rgba
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect()
+    }
+
+    #[test]
+    fn partial_frames_are_drawn_on_top_of_the_previous_ones() {
+        let mut compositor = Compositor::new(4, 2, &PALETTE, BLUE);
+        compositor.compose(&frame(0, 0, 4, 2, DisposalMethod::Keep), &[0; 8]);
+        let composed = compositor.compose(&frame(1, 1, 2, 1, DisposalMethod::Keep), &[1, 1]);
+
+        assert_eq!(pixels(&composed), vec![RED, RED, RED, RED, RED, GREEN, GREEN, RED]);
+    }
+
+    #[test]
+    fn the_canvas_starts_out_with_the_background_unless_the_frame_is_transparent() {
+        let mut compositor = Compositor::new(2, 1, &PALETTE, BLUE);
+        let composed = compositor.compose(&frame(0, 0, 1, 1, DisposalMethod::Keep), &[0]);
+        assert_eq!(pixels(&composed), vec![RED, BLUE]);
+
+        let mut compositor = Compositor::new(2, 1, &PALETTE, BLUE);
+        let transparent = Frame {
+            transparent: Some(2),
+            ..frame(0, 0, 1, 1, DisposalMethod::Keep)
+        };
+        let composed = compositor.compose(&transparent, &[0]);
+        assert_eq!(pixels(&composed), vec![RED, [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn transparent_pixels_keep_the_canvas() {
+        let mut compositor = Compositor::new(2, 1, &PALETTE, BLUE);
+        compositor.compose(&frame(0, 0, 2, 1, DisposalMethod::Keep), &[0, 0]);
+        let transparent = Frame {
+            transparent: Some(1),
+            ..frame(0, 0, 2, 1, DisposalMethod::Keep)
+        };
+        let composed = compositor.compose(&transparent, &[1, 2]);
+
+        assert_eq!(pixels(&composed), vec![RED, BLUE]);
+    }
+
+    #[test]
+    fn background_disposal_clears_the_area_of_the_frame() {
+        let mut compositor = Compositor::new(4, 2, &PALETTE, BLUE);
+        let first = compositor.compose(&frame(0, 0, 2, 1, DisposalMethod::Background), &[0, 0]);
+        assert_eq!(pixel(&first, 0, 0), RED);
+
+        let second = compositor.compose(&frame(3, 1, 1, 1, DisposalMethod::Keep), &[1]);
+        assert_eq!(pixels(&second), vec![BLUE, BLUE, BLUE, BLUE, BLUE, BLUE, BLUE, GREEN]);
+    }
+
+    #[test]
+    fn previous_disposal_restores_the_canvas_from_before_the_frame() {
+        let mut compositor = Compositor::new(4, 2, &PALETTE, BLUE);
+        compositor.compose(&frame(0, 0, 4, 2, DisposalMethod::Keep), &[0; 8]);
+        let shown = compositor.compose(&frame(0, 0, 1, 1, DisposalMethod::Previous), &[1]);
+        assert_eq!(pixel(&shown, 0, 0), GREEN);
+
+        let after = compositor.compose(&frame(3, 1, 1, 1, DisposalMethod::Keep), &[2]);
+        assert_eq!(pixels(&after), vec![RED, RED, RED, RED, RED, RED, RED, BLUE]);
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn skipped_frames_still_change_the_canvas() {
+        let mut compositor = Compositor::new(2, 1, &PALETTE, BLUE);
+        compositor.skip(&frame(0, 0, 2, 1, DisposalMethod::Keep), &[0, 1]);
+        let composed = compositor.compose(&frame(1, 0, 1, 1, DisposalMethod::Keep), &[2]);
+
+        assert_eq!(pixels(&composed), vec![RED, BLUE]);
+    }
+
+    #[test]
+    fn visible_rows_cut_off_what_sticks_out_of_the_canvas() {
+        let rows: Vec<_> = visible_rows(&frame(3, 1, 3, 2, DisposalMethod::Keep), &[1, 2, 3, 4, 5, 6], 4, 2).collect();
+        assert_eq!(rows, vec![(7, &[1][..])]);
+
+        let rows: Vec<_> = visible_rows(&frame(1, 0, 2, 2, DisposalMethod::Keep), &[1, 2, 3, 4], 4, 2).collect();
+        assert_eq!(rows, vec![(1, &[1, 2][..]), (5, &[3, 4][..])]);
+    }
+
+    #[test]
+    fn frames_outside_of_the_canvas_have_no_rows() {
+        assert_eq!(
+            visible_rows(&frame(4, 0, 2, 1, DisposalMethod::Keep), &[1, 2], 4, 2).count(),
+            0
+        );
+        assert_eq!(
+            visible_rows(&frame(0, 2, 2, 1, DisposalMethod::Keep), &[1, 2], 4, 2).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn the_background_color_needs_a_global_palette_entry() {
+        assert_eq!(background_color(Some(&PALETTE), Some(2)), BLUE);
+        assert_eq!(background_color(Some(&PALETTE), Some(3)), [0, 0, 0, 0]);
+        assert_eq!(background_color(None, Some(0)), [0, 0, 0, 0]);
+    }
+}
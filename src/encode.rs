@@ -0,0 +1,826 @@
+//! Writing gifs.
+
+use compose::FrameRef;
+use gif::{AnyExtension, DisposalMethod, Encoder, EncodingError, Extension, Frame, Repeat};
+use limits::Limits;
+use optimize::Optimizer;
+use quantize::{self, Dithering, Quantizer};
+#[cfg(feature = "wasm")]
+use stats::{self, Phase};
+use std::borrow::Cow;
+#[cfg(feature = "wasm")]
+use std::cell::RefCell;
+use std::io::{self, Write};
+#[cfg(feature = "wasm")]
+use std::rc::Rc;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+#[cfg(feature = "wasm")]
+use {cancelled_error, errors, js_error, types};
+use {lossy, rgb, scan, FrameData};
+
+/// Options that control how gifs are written.
+///
+/// Every function that produces a gif accepts these as its last argument.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Default)]
+pub struct EncodeOptions {
+    /// How often the animation is repeated after it played once. `0` plays it only
+    /// once, negative values repeat it forever. `undefined`, the default, keeps the
+    /// loop count of the gif that is transformed or repeats forever if there is
+    /// none.
+    pub loop_count: Option<i32>,
+    /// How the palettes of frames with more than 256 colors are chosen
+    pub quantizer: Quantizer,
+    /// Maximum number of colors per frame from 2 to 256, including the transparent
+    /// color. `undefined` means 256.
+    pub max_colors: Option<u16>,
+    /// How colors are approximated that don't make it into the palette
+    pub dithering: Dithering,
+    /// Crops every frame to the area that changed since the previous frame and
+    /// makes unchanged pixels transparent, which makes gifs a lot smaller. Off by
+    /// default.
+    pub optimize: bool,
+    /// Allows small color differences to make the LZW compression more effective,
+    /// from 0 for lossless up to 200. Around 30 to 80 the gif gets a lot smaller
+    /// with little visible noise.
+    pub lossy: u8,
+    /// Writes the rows of every frame in interlaced order, so viewers can show a
+    /// coarse version of a frame while it's still loading. Off by default.
+    pub interlaced: bool,
+    /// A color given as `0xRRGGBB` that is written as transparent, on top of the
+    /// pixels that are transparent already. `undefined`, the default, keeps the
+    /// colors as they are.
+    pub transparent_color: Option<u32>,
+    palette: Option<Vec<[u8; 3]>>,
+    comment: Option<String>,
+    /// Written to the logical screen descriptor, only set for gifs that copy the
+    /// frames of another one
+    background_color_index: u8,
+    #[cfg(feature = "wasm")]
+    chunk_callback: Option<js_sys::Function>,
+    #[cfg(feature = "wasm")]
+    cancel_check: Option<js_sys::Function>,
+    #[cfg(feature = "wasm")]
+    keep_output: bool,
+    limits: Limits,
+    /// Shared with copies of the options, so the gif ends up in the options the
+    /// caller passed in
+    #[cfg(feature = "wasm")]
+    output: Rc<RefCell<Option<Vec<u8>>>>,
+    /// Warnings of lenient decoding, shared with copies of the options like the
+    /// output
+    #[cfg(feature = "wasm")]
+    warnings: Rc<RefCell<Vec<String>>>,
+}
+
+/// An encoded gif that stays in wasm memory.
+///
+/// `view()` gives access to the gif without copying it. Call `free()` once the
+/// gif isn't needed anymore, it's not garbage collected.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub struct GifBuffer {
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl GifBuffer {
+    /// Address of the gif in the wasm memory, to be used together with `len()` on
+    /// `memory.buffer` of the wasm module.
+    pub fn ptr(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns a `Uint8Array` that refers to the gif in wasm memory directly.
+    ///
+    /// The view becomes invalid when the buffer is freed and whenever the wasm
+    /// memory grows, which can happen on every call into the module. Use it right
+    /// away or `slice()` it to get a copy.
+    pub fn view(&self) -> js_sys::Uint8Array {
+        // safe as long as the view is used as documented above
+        unsafe { js_sys::Uint8Array::view(&self.data) }
+    }
+
+    /// Copies the gif into a new `Uint8Array`.
+    pub fn to_array(&self) -> js_sys::Uint8Array {
+        js_sys::Uint8Array::from(&self.data[..])
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl EncodeOptions {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> EncodeOptions {
+        EncodeOptions::default()
+    }
+
+    /// Creates options from a plain object like `{ loopCount: 0, maxColors: 64 }`.
+    /// Fields that are left out keep their defaults.
+    #[cfg(feature = "wasm")]
+    #[wasm_bindgen(js_name = fromObject)]
+    pub fn from_object(init: &types::JsEncodeOptionsInit) -> Result<EncodeOptions, JsValue> {
+        let init = types::EncodeOptionsInit::from_js(init)?;
+        let quantizer = match types::within(init.quantizer, "quantizer", 0, 2)? {
+            Some(value) if value == Quantizer::MedianCut as u32 => Quantizer::MedianCut,
+            Some(value) if value == Quantizer::Octree as u32 => Quantizer::Octree,
+            _ => Quantizer::NeuQuant,
+        };
+        let dithering = match types::within(init.dithering, "dithering", 0, 2)? {
+            Some(value) if value == Dithering::FloydSteinberg as u32 => Dithering::FloydSteinberg,
+            Some(value) if value == Dithering::Bayer as u32 => Dithering::Bayer,
+            _ => Dithering::None,
+        };
+
+        Ok(EncodeOptions {
+            loop_count: init.loop_count,
+            quantizer,
+            max_colors: types::within(init.max_colors, "maxColors", 2, 256)?,
+            dithering,
+            comment: init.comment,
+            chunk_callback: init.chunk_callback,
+            cancel_check: init.cancel_check,
+            optimize: init.optimize.unwrap_or(false),
+            lossy: types::within(init.lossy, "lossy", 0, 200)?.unwrap_or(0),
+            interlaced: init.interlaced.unwrap_or(false),
+            transparent_color: types::within(init.transparent_color, "transparentColor", 0, 0xffffff)?,
+            palette: init.palette,
+            keep_output: init.keep_output.unwrap_or(false),
+            ..EncodeOptions::default()
+        })
+    }
+
+    /// Maps all frames to a fixed palette instead of picking the colors of every
+    /// frame, e.g. a retro color scheme or brand colors.
+    ///
+    /// `palette` is an array of up to 255 `[r, g, b]` colors, one index is kept for
+    /// transparent pixels. Colors that aren't in the palette are dithered as the
+    /// `dithering` option says, `quantizer` and `max_colors` don't apply. Pass
+    /// `undefined` to go back to automatic palettes.
+    #[cfg(feature = "wasm")]
+    pub fn set_palette(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "[number, number, number][] | undefined")] palette: &JsValue,
+    ) -> Result<(), JsValue> {
+        self.palette = if palette.is_undefined() || palette.is_null() {
+            None
+        } else {
+            Some(types::FromJs::from_js(palette.clone(), "palette")?)
+        };
+        Ok(())
+    }
+
+    /// Embeds `comment` as a comment extension in the written gif, e.g. to note
+    /// how it was created. Pass `undefined` to leave it out.
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    /// Streams the encoded gif instead of returning it.
+    ///
+    /// The callback receives a `Uint8Array` with the next part of the gif after
+    /// every frame that was written, the function that encodes the gif returns an
+    /// empty array in that case. Functions that change every frame on its own, like
+    /// the filters or `caption_gif`, then decode, change and write one frame after
+    /// the other, so only about two frames are in memory at a time. If the callback
+    /// throws, encoding stops and the exception is rethrown. Pass `undefined` to go
+    /// back to returning the gif as a whole.
+    #[cfg(feature = "wasm")]
+    pub fn set_chunk_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.chunk_callback = callback;
+    }
+
+    /// Keeps the encoded gif in wasm memory instead of copying it to JavaScript.
+    ///
+    /// The function that encodes the gif returns an empty array and the gif can
+    /// be picked up with `take_output()` afterwards. `split_gif` returns an array
+    /// of `GifBuffer`s instead. A chunk callback takes precedence.
+    #[cfg(feature = "wasm")]
+    pub fn set_keep_output(&mut self, keep: bool) {
+        self.keep_output = keep;
+    }
+
+    /// Returns the gif that was kept by the last call with `keep_output` set, or
+    /// `undefined` if there is none. Every gif can be taken only once.
+    #[cfg(feature = "wasm")]
+    pub fn take_output(&self) -> Option<GifBuffer> {
+        self.output.borrow_mut().take().map(|data| GifBuffer { data })
+    }
+
+    /// Returns what went wrong in the gifs that were decoded leniently with these
+    /// options, see `Limits.lenient`, and clears the list. Gifs without defects
+    /// add no warnings.
+    #[cfg(feature = "wasm")]
+    #[wasm_bindgen(unchecked_return_type = "string[]")]
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings.borrow_mut().split_off(0)
+    }
+
+    /// Makes long running operations cancellable.
+    ///
+    /// The callback is called between frames while decoding and encoding. Once it
+    /// returns a truthy value the operation stops and throws an error with the
+    /// name `CancelledError`. Since wasm blocks the thread it runs on, the
+    /// callback usually checks something that is shared with another thread, e.g.
+    /// `() => Atomics.load(flags, 0) === 1` on a `SharedArrayBuffer`.
+    #[cfg(feature = "wasm")]
+    pub fn set_cancel_check(&mut self, callback: Option<js_sys::Function>) {
+        self.cancel_check = callback;
+    }
+
+    /// Sets the limits for the gifs and images that are decoded with these
+    /// options, the defaults of `Limits` if it's never called.
+    pub fn set_limits(&mut self, limits: &Limits) {
+        self.limits = *limits;
+    }
+
+    /// Returns the limits that decoding with these options keeps to, see
+    /// `set_limits`
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+}
+
+impl EncodeOptions {
+    /// Fails with a `CancelledError` if the cancel check asks to stop.
+    #[cfg(feature = "wasm")]
+    pub fn check_cancelled(&self) -> Result<(), JsValue> {
+        if let Some(ref check) = self.cancel_check {
+            if check.call0(&JsValue::NULL)?.is_truthy() {
+                return Err(cancelled_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands the warnings of lenient decoding to the caller, see `take_warnings`.
+    #[cfg(feature = "wasm")]
+    pub fn add_warnings(&self, warnings: Vec<String>) {
+        self.warnings.borrow_mut().extend(warnings);
+    }
+
+    /// Returns a copy of the options that keeps the loop count of the gif `data`,
+    /// unless a loop count was set explicitly.
+    pub fn with_source(&self, data: &[u8]) -> EncodeOptions {
+        self.with_default_loop_count(scan::summary(data).ok().map(|summary| summary.loop_count()))
+    }
+
+    /// Returns a copy of the options that uses `loop_count` unless a loop count was
+    /// set explicitly.
+    pub fn with_default_loop_count(&self, loop_count: Option<i32>) -> EncodeOptions {
+        let mut options = self.clone();
+        if options.loop_count.is_none() {
+            options.loop_count = loop_count;
+        }
+        options
+    }
+
+    /// Returns a copy of the options that writes `index` as the background color
+    /// index, so frames copied from a gif are shown on the same background.
+    #[cfg(feature = "wasm")]
+    pub fn with_background_color_index(&self, index: u8) -> EncodeOptions {
+        EncodeOptions {
+            background_color_index: index,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the options that only writes what changes from one frame
+    /// to the next, like `optimize` does.
+    pub fn with_optimize(&self) -> EncodeOptions {
+        EncodeOptions {
+            optimize: true,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the options that returns the gif as a whole, without a
+    /// chunk callback or keeping it.
+    #[cfg(feature = "wasm")]
+    pub fn without_output(&self) -> EncodeOptions {
+        EncodeOptions {
+            chunk_callback: None,
+            keep_output: false,
+            output: Rc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Fails if the options can't be used to write a gif.
+    #[cfg(feature = "wasm")]
+    pub fn validate(&self) -> Result<(), JsValue> {
+        if let Some(max_colors) = self.max_colors {
+            if !(2..=256).contains(&max_colors) {
+                return Err(js_error(&format!(
+                    "max_colors has to be between 2 and 256 but is {}",
+                    max_colors
+                )));
+            }
+        }
+        if let Some(ref palette) = self.palette {
+            if !(1..=255).contains(&palette.len()) {
+                return Err(js_error(&format!(
+                    "palette has to have 1 to 255 colors but has {}",
+                    palette.len()
+                )));
+            }
+        }
+        if let Some(color) = self.transparent_color {
+            if color > 0xffffff {
+                return Err(js_error(&format!(
+                    "transparent_color has to be given as 0xRRGGBB but is {:#x}",
+                    color
+                )));
+            }
+        }
+        if self.lossy > 200 {
+            return Err(js_error(&format!(
+                "lossy has to be between 0 and 200 but is {}",
+                self.lossy
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether frames can keep their original palettes, which isn't the case if
+    /// the palettes have to be made smaller, a fixed palette is used or a color
+    /// becomes transparent.
+    pub fn keeps_palettes(&self) -> bool {
+        !matches!(self.max_colors, Some(colors) if colors < 256)
+            && self.palette.is_none()
+            && self.transparent_color.is_none()
+    }
+
+    /// Whether frames can be copied from a gif as they are, which isn't the case
+    /// if any option changes how they are encoded.
+    pub fn keeps_frames(&self) -> bool {
+        self.keeps_palettes() && !self.optimize && self.lossy == 0 && !self.interlaced
+    }
+
+    /// The repeat setting for the NETSCAPE2.0 extension. `None` means the extension
+    /// is left out, which makes viewers play the animation once.
+    fn repeat(&self) -> Option<Repeat> {
+        match self.loop_count {
+            None => Some(Repeat::Infinite),
+            Some(count) if count < 0 => Some(Repeat::Infinite),
+            Some(0) => None,
+            Some(count) => Some(Repeat::Finite(count.min(u16::MAX as i32) as u16)),
+        }
+    }
+}
+
+/// A frame that can be written to a gif.
+pub trait Encodable {
+    /// Turns the frame into a gif frame, reducing its colors if necessary.
+    ///
+    /// Frames always cover the full canvas, so they are disposed to the background.
+    /// Otherwise transparent pixels would show the previous frame.
+    fn to_frame(&self, options: &EncodeOptions) -> Frame<'_>;
+}
+
+impl<'a> Encodable for FrameRef<'a> {
+    fn to_frame(&self, options: &EncodeOptions) -> Frame<'_> {
+        quantized_frame(self, options)
+    }
+}
+
+impl Encodable for FrameData {
+    fn to_frame(&self, options: &EncodeOptions) -> Frame<'_> {
+        quantized_frame(&self.as_ref(), options)
+    }
+}
+
+/// Reduces the colors of a frame, the pixels of the result don't borrow from it.
+fn quantized_frame(frame: &FrameRef, options: &EncodeOptions) -> Frame<'static> {
+    // the quantizer keeps an index for transparent pixels if there are any
+    let rgba = match options.transparent_color.map(rgb) {
+        Some(color) => {
+            let mut rgba = frame.rgba.to_vec();
+            for pixel in rgba.chunks_exact_mut(4) {
+                if pixel[..3] == color {
+                    pixel.copy_from_slice(&[0, 0, 0, 0]);
+                }
+            }
+            Cow::Owned(rgba)
+        }
+        None => Cow::Borrowed(frame.rgba),
+    };
+    let quantized = match options.palette {
+        Some(ref palette) => quantize::quantize_to(&rgba, frame.width as usize, palette, options.dithering),
+        None => quantize::quantize(
+            &rgba,
+            frame.width as usize,
+            options.max_colors.unwrap_or(256) as usize,
+            options.quantizer,
+            options.dithering,
+        ),
+    };
+    Frame {
+        width: frame.width,
+        height: frame.height,
+        buffer: Cow::Owned(quantized.indices),
+        palette: Some(quantized.palette),
+        transparent: quantized.transparent,
+        delay: frame.delay,
+        dispose: DisposalMethod::Background,
+        ..Frame::default()
+    }
+}
+
+impl<T: Encodable> Encodable for &T {
+    fn to_frame(&self, options: &EncodeOptions) -> Frame<'_> {
+        (*self).to_frame(options)
+    }
+}
+
+/// Creates a gif from a set of frames and a color palette
+///
+/// The `global_palette` may be an empty vector. `progress` is called with the number
+/// of frames written so far after every frame. If the options contain a chunk
+/// callback, the gif is passed to it piece by piece and the returned vector is empty.
+/// The same goes for `keep_output`, which stores the gif in the options.
+/// Fails if the options are invalid or the operation was cancelled.
+#[cfg(feature = "wasm")]
+pub fn gif_from_frames<T: Encodable>(
+    frames: &[T],
+    width: u16,
+    height: u16,
+    global_palette: Vec<u8>,
+    options: &EncodeOptions,
+    progress: &dyn Fn(usize),
+) -> Result<Vec<u8>, JsValue> {
+    gif_from_iter(frames, width, height, global_palette, options, progress)
+}
+
+/// Like `gif_from_frames` for frames that are produced one after the other, so
+/// they don't all have to be in memory at the same time.
+#[cfg(feature = "wasm")]
+pub fn gif_from_iter<I>(
+    frames: I,
+    width: u16,
+    height: u16,
+    global_palette: Vec<u8>,
+    options: &EncodeOptions,
+    progress: &dyn Fn(usize),
+) -> Result<Vec<u8>, JsValue>
+where
+    I: IntoIterator,
+    I::Item: Encodable,
+{
+    options.validate()?;
+
+    output(options, |writer| {
+        write_gif(writer, frames, width, height, &global_palette, options, progress)?;
+        Ok(())
+    })
+}
+
+/// Creates a gif from frames that are already encoded, see `GifWriter::write_raw`.
+/// The options that change how frames are encoded, like `optimize` or `lossy`,
+/// don't apply to them, but they still have to be valid.
+#[cfg(feature = "wasm")]
+pub fn gif_from_raw_frames(
+    frames: &[scan::RawFrame],
+    width: u16,
+    height: u16,
+    global_palette: &[u8],
+    options: &EncodeOptions,
+    progress: &dyn Fn(usize),
+) -> Result<Vec<u8>, JsValue> {
+    options.validate()?;
+
+    output(options, |writer| {
+        let mut writer = GifWriter::new(writer, width, height, global_palette, options).map_err(encoding_error)?;
+        for (written, frame) in frames.iter().enumerate() {
+            options.check_cancelled()?;
+            writer.write_raw(frame).map_err(encoding_error)?;
+            writer.flush().map_err(encoding_error)?;
+            progress(written + 1);
+        }
+        writer.finish().map_err(encoding_error)?;
+        Ok(())
+    })
+}
+
+/// Like `gif_from_iter` for frames that `produce` passes to the sink it's given one
+/// after the other, e.g. while it decodes another gif. Every frame is written as
+/// soon as it arrives, so with a chunk callback only about one frame is in memory
+/// at a time. The size of the canvas is taken from the first frame, `width` and
+/// `height` are only used if there is none.
+#[cfg(feature = "wasm")]
+pub fn gif_from_stream<F>(
+    width: u16,
+    height: u16,
+    global_palette: Vec<u8>,
+    options: &EncodeOptions,
+    progress: &dyn Fn(usize),
+    produce: F,
+) -> Result<Vec<u8>, JsValue>
+where
+    F: FnOnce(&mut dyn FnMut(FrameRef) -> Result<(), JsValue>) -> Result<(), JsValue>,
+{
+    options.validate()?;
+
+    output(options, |out| {
+        let mut out = Some(out);
+        let mut writer = None;
+        let mut written = 0;
+        produce(&mut |frame: FrameRef| {
+            options.check_cancelled()?;
+            let writer = match writer {
+                Some(ref mut writer) => writer,
+                None => writer.get_or_insert(
+                    GifWriter::new(out.take().unwrap(), frame.width, frame.height, &global_palette, options)
+                        .map_err(encoding_error)?,
+                ),
+            };
+
+            errors::at("encode", Some(written));
+            let frame = stats::time(Phase::Quantize, || frame.to_frame(options));
+            stats::time(Phase::Encode, || writer.write(frame)).map_err(encoding_error)?;
+            writer.flush().map_err(encoding_error)?;
+            written += 1;
+            progress(written);
+            Ok(())
+        })?;
+
+        let writer = match (writer, out) {
+            (Some(writer), _) => writer,
+            (None, Some(out)) => {
+                GifWriter::new(out, width, height, &global_palette, options).map_err(encoding_error)?
+            }
+            (None, None) => unreachable!("the writer is created when the output is taken"),
+        };
+        writer.finish().map_err(encoding_error)?;
+        Ok(())
+    })
+}
+
+/// Turns an error of the encoder or the writer into a JavaScript error.
+#[cfg(feature = "wasm")]
+fn encoding_error<E: ToString>(e: E) -> JsValue {
+    js_error(&e.to_string())
+}
+
+/// Runs `encode` with the writer the options ask for and returns the gif unless
+/// it's passed to the chunk callback or kept in the options.
+#[cfg(feature = "wasm")]
+fn output<F>(options: &EncodeOptions, encode: F) -> Result<Vec<u8>, JsValue>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), JsValue>,
+{
+    match options.chunk_callback {
+        Some(ref callback) => {
+            let mut writer = ChunkWriter {
+                buffer: Vec::new(),
+                callback,
+                error: None,
+            };
+            let result = encode(&mut writer).and_then(|()| writer.flush().map_err(encoding_error));
+            // the exception the callback threw explains more than the failed write
+            match writer.error.take() {
+                Some(error) => Err(error),
+                None => result.map(|()| Vec::new()),
+            }
+        }
+        None => {
+            let mut gif = Vec::new();
+            encode(&mut gif)?;
+            stats::output_bytes(gif.len());
+            if options.keep_output {
+                *options.output.borrow_mut() = Some(gif);
+                Ok(Vec::new())
+            } else {
+                Ok(gif)
+            }
+        }
+    }
+}
+
+/// Encodes the frames into `writer`. The writer is flushed after every frame.
+#[cfg(feature = "wasm")]
+fn write_gif<W, I>(
+    writer: W,
+    frames: I,
+    width: u16,
+    height: u16,
+    global_palette: &[u8],
+    options: &EncodeOptions,
+    progress: &dyn Fn(usize),
+) -> Result<W, JsValue>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Encodable,
+{
+    let mut writer = GifWriter::new(writer, width, height, global_palette, options).map_err(encoding_error)?;
+    for (i, frame) in frames.into_iter().enumerate() {
+        options.check_cancelled()?;
+
+        errors::at("encode", Some(i));
+        let frame = stats::time(Phase::Quantize, || frame.to_frame(options));
+        stats::time(Phase::Encode, || writer.write(frame)).map_err(encoding_error)?;
+        writer.flush().map_err(encoding_error)?;
+
+        progress(i + 1);
+    }
+
+    writer.finish().map_err(encoding_error)
+}
+
+/// Writes frames into a gif one after the other, interlacing and optimizing them
+/// if the options ask for it.
+pub struct GifWriter<W: Write> {
+    encoder: Encoder<BackgroundWriter<W>>,
+    optimizer: Option<Optimizer>,
+    interlaced: bool,
+    lossy: u8,
+    global_palette: Vec<u8>,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Writes the header, the loop count and the comment of a gif into `writer`.
+    pub fn new(
+        writer: W,
+        width: u16,
+        height: u16,
+        global_palette: &[u8],
+        options: &EncodeOptions,
+    ) -> Result<GifWriter<W>, EncodingError> {
+        let writer = BackgroundWriter {
+            writer,
+            written: 0,
+            background_color_index: options.background_color_index,
+        };
+        let mut encoder = Encoder::new(writer, width, height, global_palette)?;
+        if let Some(repeat) = options.repeat() {
+            encoder.set_repeat(repeat)?;
+        }
+        if let Some(ref comment) = options.comment {
+            if !comment.is_empty() {
+                encoder.write_raw_extension(AnyExtension(Extension::Comment as u8), &[comment.as_bytes()])?;
+            }
+        }
+
+        Ok(GifWriter {
+            encoder,
+            optimizer: if options.optimize {
+                Some(Optimizer::new(width, height, global_palette))
+            } else {
+                None
+            },
+            interlaced: options.interlaced,
+            lossy: options.lossy,
+            global_palette: global_palette.to_vec(),
+        })
+    }
+
+    /// Adds a frame that covers the full canvas. When optimizing, a frame is only
+    /// written once the next one has been added or the gif is finished.
+    pub fn write(&mut self, frame: Frame) -> Result<(), EncodingError> {
+        let frame = match self.optimizer {
+            Some(ref mut optimizer) => optimizer.push(frame),
+            None => Some(frame),
+        };
+        match frame {
+            Some(frame) => self.write_frame(frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Copies a frame from another gif without decoding it. The frame has to fit
+    /// the global palette of this gif if it doesn't have its own, and can't be
+    /// mixed with optimized frames.
+    #[cfg(feature = "wasm")]
+    pub fn write_raw(&mut self, frame: &scan::RawFrame) -> io::Result<()> {
+        if let Some(control) = frame.control {
+            self.encoder
+                .write_raw_extension(AnyExtension(Extension::Control as u8), &[control])?;
+        }
+        self.encoder.get_mut().write_all(frame.image)
+    }
+
+    /// Passes everything written so far on to the writer.
+    #[cfg(feature = "wasm")]
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.encoder.get_mut().flush()
+    }
+
+    /// Writes the remaining frame and the end of the gif and returns the writer.
+    pub fn finish(mut self) -> Result<W, EncodingError> {
+        if let Some(frame) = self.optimizer.as_mut().and_then(|optimizer| optimizer.finish()) {
+            self.write_frame(frame)?;
+        }
+        self.encoder
+            .into_inner()
+            .map(|writer| writer.writer)
+            .map_err(EncodingError::from)
+    }
+
+    fn write_frame(&mut self, mut frame: Frame) -> Result<(), EncodingError> {
+        if self.interlaced {
+            frame.buffer = Cow::Owned(interlace(&frame.buffer, frame.width as usize));
+            frame.interlaced = true;
+        }
+        // the pixels are changed in the order they are compressed
+        if self.lossy > 0 {
+            let palette = frame.palette.as_ref().unwrap_or(&self.global_palette);
+            lossy::apply(frame.buffer.to_mut(), palette, frame.transparent, self.lossy);
+        }
+        self.encoder.write_frame(&frame)
+    }
+}
+
+/// Passes everything on to `writer` and replaces the background color index of
+/// the logical screen descriptor on the way, the gif encoder always writes 0.
+struct BackgroundWriter<W> {
+    writer: W,
+    written: usize,
+    background_color_index: u8,
+}
+
+impl<W: Write> Write for BackgroundWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // the signature, the size of the canvas and the flags come first
+        const OFFSET: usize = 11;
+
+        if (self.written..self.written + data.len()).contains(&OFFSET) {
+            let mut data = data.to_vec();
+            data[OFFSET - self.written] = self.background_color_index;
+            self.writer.write_all(&data)?;
+            self.written += data.len();
+            return Ok(data.len());
+        }
+        let written = self.writer.write(data)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reorders the rows of a frame for interlacing. Gifs store every 8th row starting
+/// with the first, then every 8th row starting with the 5th, every 4th row starting
+/// with the 3rd and finally all odd rows.
+fn interlace(indices: &[u8], width: usize) -> Vec<u8> {
+    if width == 0 {
+        return indices.to_vec();
+    }
+
+    let rows: Vec<&[u8]> = indices.chunks(width).collect();
+    let mut interlaced = Vec::with_capacity(indices.len());
+    for &(start, step) in &[(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for row in rows.iter().skip(start).step_by(step) {
+            interlaced.extend_from_slice(row);
+        }
+    }
+    interlaced
+}
+
+/// Collects everything that is written and passes it on to a JavaScript callback
+/// whenever it's flushed.
+#[cfg(feature = "wasm")]
+struct ChunkWriter<'a> {
+    buffer: Vec<u8>,
+    callback: &'a js_sys::Function,
+    /// What the callback threw, writing fails from then on
+    error: Option<JsValue>,
+}
+
+#[cfg(feature = "wasm")]
+impl<'a> Write for ChunkWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Err(io::Error::other("the chunk callback threw an exception"));
+        }
+        self.buffer.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.error.is_some() {
+            return Err(io::Error::other("the chunk callback threw an exception"));
+        }
+        if !self.buffer.is_empty() {
+            stats::output_bytes(self.buffer.len());
+            let chunk = js_sys::Uint8Array::from(&self.buffer[..]);
+            self.buffer.clear();
+            if let Err(error) = self.callback.call1(&JsValue::NULL, &chunk) {
+                self.error = Some(error);
+                return Err(io::Error::other("the chunk callback threw an exception"));
+            }
+        }
+        Ok(())
+    }
+}
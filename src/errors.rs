@@ -0,0 +1,103 @@
+//! Reporting failures to a JavaScript error handler with their context.
+//!
+//! Errors are still thrown as before. An error handler registered with
+//! `set_error_handler` additionally receives an `ErrorReport` for every error and
+//! panic, with the job and the frame that was processed when it happened.
+
+use logging;
+use std::cell::{Cell, RefCell};
+use types::{ErrorHandler, ErrorReport};
+use wasm_bindgen::prelude::*;
+
+/// What was being done when an error happened.
+#[derive(Default)]
+struct Context {
+    id: Option<String>,
+    name: Option<String>,
+    operation: Option<&'static str>,
+    frame: Option<usize>,
+}
+
+thread_local! {
+    static HANDLER: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+    static RECOVERABLE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Calls `handler` with an `ErrorReport` whenever an operation fails or panics,
+/// before the error is thrown. Pass `undefined` to remove the handler.
+#[wasm_bindgen]
+pub fn set_error_handler(handler: Option<ErrorHandler>) {
+    // the panic hook of the console has to be in place first, it doesn't replace
+    // other hooks once it's set
+    console_error_panic_hook::set_once();
+    if handler.is_some() {
+        std::panic::set_hook(Box::new(|info| {
+            console_error_panic_hook::hook(info);
+            report(&info.to_string(), false);
+        }));
+    }
+    HANDLER.with(|current| *current.borrow_mut() = handler.map(js_sys::Function::from));
+}
+
+/// Notes the job that runs until the returned guard is dropped.
+pub fn begin(id: &str, name: &str) -> Job {
+    CONTEXT.with(|context| {
+        *context.borrow_mut() = Context {
+            id: Some(id.to_string()),
+            name: Some(name.to_string()),
+            ..Context::default()
+        }
+    });
+    Job
+}
+
+/// Clears the context of a job when it ends.
+pub struct Job;
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| *context.borrow_mut() = Context::default());
+    }
+}
+
+/// Notes that `operation`, e.g. `"decode"`, works on the frame `frame` now.
+pub fn at(operation: &'static str, frame: Option<usize>) {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        context.operation = Some(operation);
+        context.frame = frame;
+    });
+}
+
+/// Marks the next error as recoverable, which means the operation could succeed
+/// when it's tried again differently, e.g. with lenient decoding.
+pub fn recoverable() {
+    RECOVERABLE.with(|current| current.set(true));
+}
+
+/// Passes an error to the error handler.
+pub fn report(message: &str, recoverable: bool) {
+    let recoverable = RECOVERABLE.with(|current| current.replace(false)) || recoverable;
+    let handler = match HANDLER.with(|handler| handler.borrow().clone()) {
+        Some(handler) => handler,
+        None => return,
+    };
+
+    let report = CONTEXT.with(|context| {
+        let context = context.borrow();
+        ErrorReport {
+            id: context.id.clone(),
+            name: context.name.clone(),
+            operation: context.operation,
+            frame: context.frame.map(|frame| frame as u32),
+            message: message.to_string(),
+            recoverable,
+        }
+    });
+
+    // a failing handler must not hide the actual error
+    if handler.call1(&JsValue::NULL, &report.into_object()).is_err() {
+        logging::error("the error handler failed");
+    }
+}
@@ -0,0 +1,760 @@
+//! Transformations that work on fully composed frames.
+//!
+//! All functions in here operate on the `FrameData` produced by `collect_frames`
+//! or the frames of a `FrameArena`, i.e. every frame is a full canvas sized RGBA
+//! buffer. This keeps the transformations simple because they never have to care
+//! about partial frames, disposal methods or palettes.
+
+use quantize::BAYER;
+use FrameData;
+
+/// Mirrors a frame along its vertical axis (`horizontal`) and/or along its
+/// horizontal axis (`vertical`).
+pub fn flip(frame: &mut FrameData, horizontal: bool, vertical: bool) {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let row_length = width * 4;
+
+    if horizontal {
+        for row in frame.rgba.chunks_mut(row_length) {
+            for x in 0..width / 2 {
+                let mirrored = width - 1 - x;
+                for c in 0..4 {
+                    row.swap(x * 4 + c, mirrored * 4 + c);
+                }
+            }
+        }
+    }
+
+    if vertical {
+        for y in 0..height / 2 {
+            let mirrored = height - 1 - y;
+            let (upper, lower) = frame.rgba.split_at_mut(mirrored * row_length);
+            upper[y * row_length..(y + 1) * row_length].swap_with_slice(&mut lower[..row_length]);
+        }
+    }
+}
+
+/// Calls `f` for every RGBA pixel of the frame.
+fn for_each_pixel<F>(frame: &mut FrameData, mut f: F)
+where
+    F: FnMut(&mut [u8]),
+{
+    for pixel in frame.rgba.chunks_mut(4) {
+        f(pixel);
+    }
+}
+
+/// The luminance of an RGBA pixel using the BT.709 weights.
+pub fn luma(pixel: &[u8]) -> u32 {
+    (2126 * pixel[0] as u32 + 7152 * pixel[1] as u32 + 722 * pixel[2] as u32 + 5000) / 10000
+}
+
+/// Replaces the color of every pixel with its luminance using the BT.709 weights.
+/// Alpha is kept as is.
+pub fn grayscale(frame: &mut FrameData) {
+    for_each_pixel(frame, |pixel| {
+        let luma = luma(pixel);
+        pixel[0] = luma as u8;
+        pixel[1] = luma as u8;
+        pixel[2] = luma as u8;
+    });
+}
+
+/// Turns every pixel black or white depending on whether its luminance is below
+/// `cutoff`. Alpha is kept as is.
+pub fn threshold(frame: &mut FrameData, cutoff: u8) {
+    for_each_pixel(frame, |pixel| {
+        let value = if luma(pixel) >= cutoff as u32 { 255 } else { 0 };
+        pixel[..3].copy_from_slice(&[value, value, value]);
+    });
+}
+
+/// Applies the standard sepia matrix to every pixel. `intensity` is clamped to
+/// `0..=1` and blends between the original color (0) and full sepia (1).
+pub fn sepia(frame: &mut FrameData, intensity: f32) {
+    let intensity = intensity.clamp(0.0, 1.0);
+
+    for_each_pixel(frame, |pixel| {
+        let r = pixel[0] as f32;
+        let g = pixel[1] as f32;
+        let b = pixel[2] as f32;
+
+        let sepia_r = (0.393 * r + 0.769 * g + 0.189 * b).min(255.0);
+        let sepia_g = (0.349 * r + 0.686 * g + 0.168 * b).min(255.0);
+        let sepia_b = (0.272 * r + 0.534 * g + 0.131 * b).min(255.0);
+
+        pixel[0] = (r + (sepia_r - r) * intensity).round() as u8;
+        pixel[1] = (g + (sepia_g - g) * intensity).round() as u8;
+        pixel[2] = (b + (sepia_b - b) * intensity).round() as u8;
+    });
+}
+
+/// Inverts the color channels of every pixel, alpha is left untouched.
+pub fn invert(frame: &mut FrameData) {
+    for_each_pixel(frame, |pixel| {
+        pixel[0] = 255 - pixel[0];
+        pixel[1] = 255 - pixel[1];
+        pixel[2] = 255 - pixel[2];
+    });
+}
+
+/// Applies the lookup table `lut` to the red, green and blue channel of every pixel.
+pub fn apply_lut(frame: &mut FrameData, lut: &[u8; 256]) {
+    for_each_pixel(frame, |pixel| {
+        pixel[0] = lut[pixel[0] as usize];
+        pixel[1] = lut[pixel[1] as usize];
+        pixel[2] = lut[pixel[2] as usize];
+    });
+}
+
+/// Builds a lookup table for a linear brightness and contrast adjustment.
+///
+/// Both values are expected in `-100..=100` and are clamped to that range. Zero
+/// means no change. Brightness shifts every channel by up to ±255, contrast scales
+/// the distance of every channel from the midpoint using the common
+/// `259 * (c + 255) / (255 * (259 - c))` factor with `c` scaled to `-255..=255`.
+pub fn brightness_contrast_lut(brightness: i32, contrast: i32) -> [u8; 256] {
+    let brightness = brightness.clamp(-100, 100) * 255 / 100;
+    let contrast = contrast.clamp(-100, 100) * 255 / 100;
+
+    // the factor is kept as a fraction to stay in integer math
+    let numerator = 259 * (contrast + 255);
+    let denominator = 255 * (259 - contrast);
+
+    let mut lut = [0u8; 256];
+    for (value, entry) in lut.iter_mut().enumerate() {
+        let adjusted = (value as i32 - 128) * numerator / denominator + 128 + brightness;
+        *entry = adjusted.clamp(0, 255) as u8;
+    }
+
+    lut
+}
+
+/// Builds a lookup table that rounds every channel to one of `levels` evenly spaced
+/// values, from 0 to 255. `levels` is expected in `2..=256`.
+pub fn posterize_lut(levels: u16) -> [u8; 256] {
+    let steps = (levels.clamp(2, 256) - 1) as f32;
+    let mut lut = [0u8; 256];
+    for (value, entry) in lut.iter_mut().enumerate() {
+        *entry = ((value as f32 / 255.0 * steps).round() / steps * 255.0).round() as u8;
+    }
+    lut
+}
+
+/// Makes every pixel transparent whose red, green and blue each differ by at most
+/// `tolerance` from `key`.
+pub fn chroma_key(frame: &mut FrameData, key: [u8; 3], tolerance: u8) {
+    for_each_pixel(frame, |pixel| {
+        if (0..3).all(|c| pixel[c].abs_diff(key[c]) <= tolerance) {
+            pixel.copy_from_slice(&[0, 0, 0, 0]);
+        }
+    });
+}
+
+/// Converts an RGB color to HSL. Hue is in degrees `0..360`, saturation and lightness in `0..=1`.
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        60.0 * (((g - b) / delta) % 6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    (hue.rem_euclid(360.0), saturation.min(1.0), lightness)
+}
+
+/// Converts an HSL color back to RGB, see `rgb_to_hsl`.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = lightness - chroma / 2.0;
+    let to_u8 = |c: f32| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
+/// Shifts the hue of every pixel by `hue_shift` degrees and adjusts saturation and
+/// lightness.
+///
+/// `saturation` and `lightness` range from -100 to 100. Saturation scales the
+/// saturation of every pixel, -100 removes all color and 100 doubles it. Lightness
+/// moves every pixel towards black (negative) or white (positive).
+pub fn hsl_adjust(frame: &mut FrameData, hue_shift: f32, saturation: f32, lightness: f32) {
+    let saturation = 1.0 + saturation.clamp(-100.0, 100.0) / 100.0;
+    let lightness = lightness.clamp(-100.0, 100.0) / 100.0;
+
+    for_each_pixel(frame, |pixel| {
+        let (h, s, l) = rgb_to_hsl(pixel[0], pixel[1], pixel[2]);
+
+        let h = (h + hue_shift).rem_euclid(360.0);
+        let s = (s * saturation).clamp(0.0, 1.0);
+        let l = if lightness > 0.0 {
+            l + (1.0 - l) * lightness
+        } else {
+            l * (1.0 + lightness)
+        };
+
+        let (r, g, b) = hsl_to_rgb(h, s, l);
+        pixel[0] = r;
+        pixel[1] = g;
+        pixel[2] = b;
+    });
+}
+
+/// Scales a frame to `width` × `height` using bilinear interpolation.
+pub fn resize(frame: &mut FrameData, width: u16, height: u16) {
+    let source_width = frame.width as usize;
+    let source_height = frame.height as usize;
+    let target_width = width as usize;
+    let target_height = height as usize;
+    let mut rgba = vec![0; target_width * target_height * 4];
+
+    if source_width > 0 && source_height > 0 {
+        let x_ratio = source_width as f32 / target_width as f32;
+        let y_ratio = source_height as f32 / target_height as f32;
+
+        for y in 0..target_height {
+            // sample at pixel centers so that up and downscaling stay centered
+            let sy = ((y as f32 + 0.5) * y_ratio - 0.5).max(0.0);
+            let y0 = (sy as usize).min(source_height - 1);
+            let y1 = (y0 + 1).min(source_height - 1);
+            let fy = sy - y0 as f32;
+
+            for x in 0..target_width {
+                let sx = ((x as f32 + 0.5) * x_ratio - 0.5).max(0.0);
+                let x0 = (sx as usize).min(source_width - 1);
+                let x1 = (x0 + 1).min(source_width - 1);
+                let fx = sx - x0 as f32;
+
+                for c in 0..4 {
+                    let sample = |x: usize, y: usize| frame.rgba[(y * source_width + x) * 4 + c] as f32;
+                    let top = sample(x0, y0) + (sample(x1, y0) - sample(x0, y0)) * fx;
+                    let bottom = sample(x0, y1) + (sample(x1, y1) - sample(x0, y1)) * fx;
+                    rgba[(y * target_width + x) * 4 + c] = (top + (bottom - top) * fy).round() as u8;
+                }
+            }
+        }
+    }
+
+    frame.width = width;
+    frame.height = height;
+    frame.rgba = rgba;
+}
+
+/// Scales a frame down to `width` × `height` by averaging all pixels that end up
+/// in the same target pixel. Unlike `resize` this doesn't skip pixels when the
+/// frame gets much smaller, so fine details don't turn into noise. Colors are
+/// weighted by their alpha so transparent pixels don't darken the edges.
+pub fn shrink(frame: &mut FrameData, width: u16, height: u16) {
+    let source_width = frame.width as usize;
+    let source_height = frame.height as usize;
+    let target_width = width as usize;
+    let target_height = height as usize;
+    let mut rgba = vec![0; target_width * target_height * 4];
+
+    for y in 0..target_height {
+        let top = y * source_height / target_height;
+        let bottom = ((y + 1) * source_height / target_height)
+            .max(top + 1)
+            .min(source_height);
+
+        for x in 0..target_width {
+            let left = x * source_width / target_width;
+            let right = ((x + 1) * source_width / target_width).max(left + 1).min(source_width);
+
+            let mut sums = [0u64; 4];
+            for sy in top..bottom {
+                for pixel in frame.rgba[(sy * source_width + left) * 4..(sy * source_width + right) * 4].chunks_exact(4)
+                {
+                    let alpha = pixel[3] as u64;
+                    for c in 0..3 {
+                        sums[c] += pixel[c] as u64 * alpha;
+                    }
+                    sums[3] += alpha;
+                }
+            }
+
+            let count = ((bottom - top) * (right - left)) as u64;
+            let alpha = sums[3];
+            let target = &mut rgba[(y * target_width + x) * 4..(y * target_width + x + 1) * 4];
+            for c in 0..3 {
+                target[c] = (sums[c] + alpha / 2).checked_div(alpha).unwrap_or(0) as u8;
+            }
+            target[3] = ((alpha + count / 2) / count) as u8;
+        }
+    }
+
+    frame.width = width;
+    frame.height = height;
+    frame.rgba = rgba;
+}
+
+/// Replaces every `block` × `block` square of pixels with their average color, like
+/// `shrink` followed by scaling up without smoothing.
+///
+/// Only the `area` given as `(left, top, width, height)` is pixelated if there is
+/// one, e.g. to hide a face. The blocks start at the top left corner of the area.
+pub fn pixelate(frame: &mut FrameData, block: u16, area: Option<(u16, u16, u16, u16)>) {
+    let frame_width = frame.width as usize;
+    let (left, top, width, height) = area.unwrap_or((0, 0, frame.width, frame.height));
+    let right = (left as usize + width as usize).min(frame_width);
+    let bottom = (top as usize + height as usize).min(frame.height as usize);
+    let block = block.max(1) as usize;
+
+    for block_top in (top as usize..bottom).step_by(block) {
+        let block_bottom = (block_top + block).min(bottom);
+        for block_left in (left as usize..right).step_by(block) {
+            let block_right = (block_left + block).min(right);
+            let pixels = |y: usize| (y * frame_width + block_left) * 4..(y * frame_width + block_right) * 4;
+
+            let mut sums = [0u64; 4];
+            for y in block_top..block_bottom {
+                for pixel in frame.rgba[pixels(y)].chunks_exact(4) {
+                    let alpha = pixel[3] as u64;
+                    for c in 0..3 {
+                        sums[c] += pixel[c] as u64 * alpha;
+                    }
+                    sums[3] += alpha;
+                }
+            }
+
+            let count = ((block_bottom - block_top) * (block_right - block_left)) as u64;
+            let alpha = sums[3];
+            let mut average = [0u8; 4];
+            for c in 0..3 {
+                average[c] = (sums[c] + alpha / 2).checked_div(alpha).unwrap_or(0) as u8;
+            }
+            average[3] = ((alpha + count / 2) / count) as u8;
+
+            for y in block_top..block_bottom {
+                for pixel in frame.rgba[pixels(y)].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&average);
+                }
+            }
+        }
+    }
+}
+
+/// The weights of a Gaussian kernel for the standard deviation `sigma`, reaching
+/// three times as far to both sides. They add up to 1.
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|weight| weight / sum).collect()
+}
+
+/// Convolves the `lines` of premultiplied RGBA pixels in `source` with `kernel`
+/// into `target`. The pixels of a line are `step` pixels apart and lines start
+/// `stride` pixels apart. Pixels beyond the ends repeat the outermost pixel.
+fn convolve(
+    source: &[f32],
+    target: &mut [f32],
+    kernel: &[f32],
+    lines: usize,
+    length: usize,
+    stride: usize,
+    step: usize,
+) {
+    let radius = (kernel.len() / 2) as isize;
+    for line in 0..lines {
+        for i in 0..length {
+            let mut sum = [0f32; 4];
+            for (k, weight) in kernel.iter().enumerate() {
+                let j = (i as isize + k as isize - radius).clamp(0, length as isize - 1) as usize;
+                let pixel = (line * stride + j * step) * 4;
+                for c in 0..4 {
+                    sum[c] += source[pixel + c] * weight;
+                }
+            }
+            let pixel = (line * stride + i * step) * 4;
+            target[pixel..pixel + 4].copy_from_slice(&sum);
+        }
+    }
+}
+
+/// Blurs a frame with a Gaussian blur of the standard deviation `sigma` in pixels.
+///
+/// The blur is done in two passes, first along the rows and then along the
+/// columns, which needs far fewer operations than a two dimensional kernel. Colors
+/// are weighted by their alpha so transparent pixels don't darken the edges.
+pub fn blur(frame: &mut FrameData, sigma: f32) {
+    if sigma.is_nan() || sigma <= 0.0 {
+        return;
+    }
+    let kernel = gaussian_kernel(sigma);
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+
+    let mut premultiplied: Vec<f32> = Vec::with_capacity(frame.rgba.len());
+    for pixel in frame.rgba.chunks_exact(4) {
+        let alpha = pixel[3] as f32 / 255.0;
+        premultiplied.extend(pixel[..3].iter().map(|&c| c as f32 * alpha));
+        premultiplied.push(pixel[3] as f32);
+    }
+
+    let mut rows = vec![0f32; premultiplied.len()];
+    convolve(&premultiplied, &mut rows, &kernel, height, width, width, 1);
+    convolve(&rows, &mut premultiplied, &kernel, width, height, 1, width);
+
+    for (pixel, blurred) in frame.rgba.chunks_exact_mut(4).zip(premultiplied.chunks_exact(4)) {
+        let alpha = blurred[3].round().clamp(0.0, 255.0);
+        if alpha == 0.0 {
+            pixel.copy_from_slice(&[0, 0, 0, 0]);
+            continue;
+        }
+        for c in 0..3 {
+            pixel[c] = (blurred[c] * 255.0 / blurred[3]).round().clamp(0.0, 255.0) as u8;
+        }
+        pixel[3] = alpha as u8;
+    }
+}
+
+/// Sharpens a frame with an unsharp mask: the difference between the frame and a
+/// blurred copy with the standard deviation `sigma` is added `amount` times to the
+/// colors. Alpha is left untouched.
+pub fn sharpen(frame: &mut FrameData, amount: f32, sigma: f32) {
+    let mut blurred = frame.clone();
+    blur(&mut blurred, sigma);
+
+    for (pixel, blurred) in frame.rgba.chunks_exact_mut(4).zip(blurred.rgba.chunks_exact(4)) {
+        if pixel[3] == 0 {
+            continue;
+        }
+        for c in 0..3 {
+            let difference = pixel[c] as f32 - blurred[c] as f32;
+            pixel[c] = (pixel[c] as f32 + difference * amount).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Convolves a single channel image of `width` × `height` values with a square
+/// `kernel` of `size` × `size` weights, given row by row. Values beyond the edges
+/// repeat the outermost ones.
+pub fn convolve_2d(values: &[f32], width: usize, height: usize, kernel: &[f32], size: usize) -> Vec<f32> {
+    let radius = (size / 2) as isize;
+    let mut result = vec![0f32; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let sy = (y as isize + (k / size) as isize - radius).clamp(0, height as isize - 1) as usize;
+                let sx = (x as isize + (k % size) as isize - radius).clamp(0, width as isize - 1) as usize;
+                sum += values[sy * width + sx] * weight;
+            }
+            result[y * width + x] = sum;
+        }
+    }
+    result
+}
+
+#[rustfmt::skip]
+const SOBEL_X: [f32; 9] = [
+    -1.0, 0.0, 1.0,
+    -2.0, 0.0, 2.0,
+    -1.0, 0.0, 1.0,
+];
+
+#[rustfmt::skip]
+const SOBEL_Y: [f32; 9] = [
+    -1.0, -2.0, -1.0,
+     0.0,  0.0,  0.0,
+     1.0,  2.0,  1.0,
+];
+
+/// Draws the edges of a frame in white, as bright as the edge is strong.
+///
+/// The strength is the Sobel gradient of the luminance. Edges weaker than
+/// `threshold` are left out. They are drawn over the frame if `on_original` is
+/// set and over black otherwise. Alpha is kept as is, transparent pixels count as
+/// black.
+pub fn edges(frame: &mut FrameData, threshold: u8, on_original: bool) {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let luma: Vec<f32> = frame
+        .rgba
+        .chunks_exact(4)
+        .map(|pixel| {
+            let luma = 0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+            luma * pixel[3] as f32 / 255.0
+        })
+        .collect();
+    let horizontal = convolve_2d(&luma, width, height, &SOBEL_X, 3);
+    let vertical = convolve_2d(&luma, width, height, &SOBEL_Y, 3);
+
+    for (i, pixel) in frame.rgba.chunks_exact_mut(4).enumerate() {
+        let strength = horizontal[i].hypot(vertical[i]).round().min(255.0) as u8;
+        if !on_original {
+            pixel[..3].copy_from_slice(&[0, 0, 0]);
+        }
+        if strength >= threshold && pixel[3] != 0 {
+            let alpha = pixel[3];
+            blend_pixel(pixel, [255, 255, 255], strength);
+            pixel[3] = alpha;
+        }
+    }
+}
+
+/// Computes how much every pixel of a `width` × `height` frame keeps of its
+/// brightness for a vignette, from 0 for black to 1 for unchanged.
+///
+/// Pixels closer to the center than `radius` times the distance to the corners
+/// aren't darkened. From there the darkening grows smoothly up to `strength` in the
+/// corners. Both are clamped to `0..=1`.
+pub fn vignette_mask(width: u16, height: u16, strength: f32, radius: f32) -> Vec<f32> {
+    let strength = strength.clamp(0.0, 1.0);
+    let radius = radius.clamp(0.0, 1.0);
+    let center_x = width as f32 / 2.0;
+    let center_y = height as f32 / 2.0;
+    let corner = center_x.hypot(center_y).max(1.0);
+
+    let mut mask = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let distance = (x as f32 + 0.5 - center_x).hypot(y as f32 + 0.5 - center_y) / corner;
+            let t = if radius < 1.0 {
+                ((distance - radius) / (1.0 - radius)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            mask.push(1.0 - strength * t * t * (3.0 - 2.0 * t));
+        }
+    }
+    mask
+}
+
+/// Multiplies the colors of every pixel with its weight in `mask`, which has one
+/// weight per pixel. Alpha is kept as is.
+pub fn apply_mask(frame: &mut FrameData, mask: &[f32]) {
+    for (pixel, &weight) in frame.rgba.chunks_exact_mut(4).zip(mask.iter()) {
+        for channel in pixel[..3].iter_mut() {
+            *channel = (*channel as f32 * weight).round() as u8;
+        }
+    }
+}
+
+/// Makes the corners of a frame transparent outside of quarter circles with the
+/// given `radius`, which is at most half the shorter side.
+pub fn round_corners(frame: &mut FrameData, radius: u16) {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let radius = (radius as usize).min(width / 2).min(height / 2);
+    let r = radius as f32;
+
+    for y in 0..radius {
+        for x in 0..radius {
+            // pixels are inside if their center is inside the circle
+            let dx = r - (x as f32 + 0.5);
+            let dy = r - (y as f32 + 0.5);
+            if dx * dx + dy * dy <= r * r {
+                continue;
+            }
+            for (cx, cy) in [
+                (x, y),
+                (width - 1 - x, y),
+                (x, height - 1 - y),
+                (width - 1 - x, height - 1 - y),
+            ] {
+                let i = (cy * width + cx) * 4;
+                frame.rgba[i..i + 4].copy_from_slice(&[0, 0, 0, 0]);
+            }
+        }
+    }
+}
+
+/// Puts a frame onto a new canvas of size `width` × `height` filled with `fill`,
+/// with its top left corner at `left`/`top`. Parts of the frame that end up
+/// outside of the canvas are cut off.
+pub fn place(frame: &mut FrameData, width: u16, height: u16, left: i32, top: i32, fill: [u8; 4]) {
+    let canvas_width = width as i32;
+    let canvas_height = height as i32;
+    let mut rgba: Vec<u8> = fill
+        .iter()
+        .cycle()
+        .take(width as usize * height as usize * 4)
+        .cloned()
+        .collect();
+
+    for y in 0..frame.height as i32 {
+        let canvas_y = y + top;
+        if canvas_y < 0 || canvas_y >= canvas_height {
+            continue;
+        }
+
+        for x in 0..frame.width as i32 {
+            let canvas_x = x + left;
+            if canvas_x < 0 || canvas_x >= canvas_width {
+                continue;
+            }
+
+            let source = ((y * frame.width as i32 + x) * 4) as usize;
+            let target = ((canvas_y * canvas_width + canvas_x) * 4) as usize;
+            rgba[target..target + 4].copy_from_slice(&frame.rgba[source..source + 4]);
+        }
+    }
+
+    frame.width = width;
+    frame.height = height;
+    frame.rgba = rgba;
+}
+
+/// Scales a frame so it fits into `width` × `height` keeping its aspect ratio and
+/// centers it on a canvas of that size. The remaining area is filled with `fill`.
+pub fn letterbox(frame: &mut FrameData, width: u16, height: u16, fill: [u8; 4]) {
+    let scale = (width as f32 / frame.width as f32).min(height as f32 / frame.height as f32);
+    let scaled_width = ((frame.width as f32 * scale).round() as u16).clamp(1, width);
+    let scaled_height = ((frame.height as f32 * scale).round() as u16).clamp(1, height);
+
+    if scaled_width != frame.width || scaled_height != frame.height {
+        resize(frame, scaled_width, scaled_height);
+    }
+
+    let left = (width as i32 - scaled_width as i32) / 2;
+    let top = (height as i32 - scaled_height as i32) / 2;
+    place(frame, width, height, left, top, fill);
+}
+
+/// Scales a frame so it covers `width` × `height` keeping its aspect ratio and
+/// crops what sticks out on both sides, so the center stays in view.
+pub fn cover(frame: &mut FrameData, width: u16, height: u16) {
+    let scale = (width as f32 / frame.width as f32).max(height as f32 / frame.height as f32);
+    let scaled_width = ((frame.width as f32 * scale).round() as u16).max(width);
+    let scaled_height = ((frame.height as f32 * scale).round() as u16).max(height);
+
+    if scaled_width != frame.width || scaled_height != frame.height {
+        resize(frame, scaled_width, scaled_height);
+    }
+
+    let left = (width as i32 - scaled_width as i32) / 2;
+    let top = (height as i32 - scaled_height as i32) / 2;
+    place(frame, width, height, left, top, [0, 0, 0, 0]);
+}
+
+/// Blends `color` with the given `alpha` over an RGBA pixel.
+pub fn blend_pixel(pixel: &mut [u8], color: [u8; 3], alpha: u8) {
+    let alpha = alpha as u32;
+    let inverse = 255 - alpha;
+
+    for c in 0..3 {
+        pixel[c] = ((color[c] as u32 * alpha + pixel[c] as u32 * inverse + 127) / 255) as u8;
+    }
+    pixel[3] = (alpha + (pixel[3] as u32 * inverse + 127) / 255) as u8;
+}
+
+/// Fades the RGBA pixels of a frame that is `width` pixels wide by `amount`, from 0
+/// for no change to 1 for a solid frame.
+///
+/// The visible pixels are blended towards `color`, transparent ones stay
+/// transparent. Without a color the frame fades out instead: gifs have no partial
+/// transparency, so a growing share of the pixels in a Bayer pattern is removed.
+pub fn fade(rgba: &mut [u8], width: u16, color: Option<[u8; 3]>, amount: f32) {
+    let amount = amount.clamp(0.0, 1.0);
+    let width = width as usize;
+
+    match color {
+        Some(color) => {
+            let alpha = (amount * 255.0).round() as u8;
+            for pixel in rgba.chunks_exact_mut(4) {
+                if pixel[3] != 0 {
+                    blend_pixel(pixel, color, alpha);
+                }
+            }
+        }
+        None => {
+            let threshold = amount * 64.0;
+            for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+                if (BAYER[(i / width) % 8][(i % width) % 8] as f32) < threshold {
+                    pixel.copy_from_slice(&[0, 0, 0, 0]);
+                }
+            }
+        }
+    }
+}
+
+/// Draws an RGBA pixel over another one like APNG and WebP blend their frames.
+/// Unlike `blend_pixel` this keeps the colors of the source if the target is
+/// transparent.
+pub fn blend_over(target: &mut [u8], source: &[u8]) {
+    let source_alpha = source[3] as u32;
+    if source_alpha == 255 {
+        target.copy_from_slice(source);
+        return;
+    }
+
+    let target_alpha = target[3] as u32 * (255 - source_alpha) / 255;
+    let alpha = source_alpha + target_alpha;
+    if alpha == 0 {
+        target.copy_from_slice(&[0, 0, 0, 0]);
+        return;
+    }
+    for i in 0..3 {
+        target[i] = ((source[i] as u32 * source_alpha + target[i] as u32 * target_alpha) / alpha) as u8;
+    }
+    target[3] = alpha as u8;
+}
+
+/// Alpha blends an RGBA image of size `width` × `height` onto the frame with its top
+/// left corner at `left`/`top`. Parts outside of the frame are cut off.
+pub fn overlay(frame: &mut FrameData, rgba: &[u8], width: u16, height: u16, left: i32, top: i32) {
+    let frame_width = frame.width as i32;
+    let frame_height = frame.height as i32;
+
+    for y in 0..height as i32 {
+        let frame_y = top + y;
+        if frame_y < 0 || frame_y >= frame_height {
+            continue;
+        }
+
+        for x in 0..width as i32 {
+            let frame_x = left + x;
+            if frame_x < 0 || frame_x >= frame_width {
+                continue;
+            }
+
+            let source = ((y * width as i32 + x) * 4) as usize;
+            let alpha = rgba[source + 3];
+            if alpha > 0 {
+                let target = ((frame_y * frame_width + frame_x) * 4) as usize;
+                let color = [rgba[source], rgba[source + 1], rgba[source + 2]];
+                blend_pixel(&mut frame.rgba[target..target + 4], color, alpha);
+            }
+        }
+    }
+}
+
+/// Linearly interpolates between two RGBA buffers of the same size and stores the
+/// result in `a`. `t = 0` keeps `a`, `t = 1` turns it into `b`.
+pub fn mix_into(a: &mut [u8], b: &[u8], t: f32) {
+    for (a, &b) in a.iter_mut().zip(b.iter()) {
+        *a = (*a as f32 + (b as f32 - *a as f32) * t).round() as u8;
+    }
+}
@@ -0,0 +1,474 @@
+//! Reading glyph outlines from TrueType fonts for captions.
+//!
+//! Only the tables needed to draw text are read: `cmap` to find the glyph of a
+//! character, `hmtx` for how far it advances, `loca` and `glyf` for its outline
+//! and `head` and `hhea` for the metrics of the font. Outlines are made of
+//! straight lines and quadratic curves, the curves are flattened into lines so
+//! `text` only has to fill polygons. OpenType fonts with CFF outlines and font
+//! collections aren't supported. Text isn't shaped, so scripts that need glyphs
+//! to be joined or reordered don't come out right.
+
+use std::borrow::Cow;
+
+/// A point of an outline in font units, y grows upwards.
+pub type Point = (f32, f32);
+
+/// A point of a stored outline and whether it lies on the curve.
+type OutlinePoint = (f32, f32, bool);
+
+/// Composite glyphs may be nested, this keeps broken fonts from looping forever.
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+/// The font captions use unless they are given one, see `fonts/LICENSE`.
+const BUILTIN: &[u8] = include_bytes!("fonts/DejaVuSans-Bold-subset.ttf");
+
+/// A parsed TrueType font.
+pub struct Font {
+    data: Cow<'static, [u8]>,
+    /// Offset of the character to glyph mapping that is used
+    cmap: usize,
+    loca: usize,
+    glyf: usize,
+    hmtx: usize,
+    long_loca: bool,
+    glyph_count: u16,
+    metrics_count: u16,
+    pub units_per_em: u16,
+    /// Distance from the baseline to the top of the tallest glyphs
+    pub ascender: i16,
+    /// Distance from the baseline to the bottom of the lowest glyphs, negative
+    pub descender: i16,
+    /// Extra space between lines
+    pub line_gap: i16,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| format!("the font ends before byte {}", offset + 2))
+}
+
+fn i16_at(data: &[u8], offset: usize) -> Result<i16, String> {
+    u16_at(data, offset).map(|value| value as i16)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| format!("the font ends before byte {}", offset + 4))
+}
+
+impl Font {
+    /// Reads the tables of a TrueType font, or an OpenType font with TrueType
+    /// outlines.
+    pub fn parse<D: Into<Cow<'static, [u8]>>>(data: D) -> Result<Font, String> {
+        let data = data.into();
+        match u32_at(&data, 0)? {
+            0x0001_0000 | 0x7472_7565 => {}
+            0x4f54_544f => return Err("fonts with CFF outlines aren't supported, only TrueType outlines".to_string()),
+            0x7474_6366 => return Err("font collections aren't supported, pass a single font".to_string()),
+            _ => return Err("not a TrueType or OpenType font".to_string()),
+        }
+
+        let table = |tag: &[u8; 4]| -> Result<usize, String> {
+            let count = u16_at(&data, 4)? as usize;
+            for record in (12..12 + count * 16).step_by(16) {
+                if data.get(record..record + 4) == Some(&tag[..]) {
+                    return Ok(u32_at(&data, record + 8)? as usize);
+                }
+            }
+            Err(format!("the font has no {} table", String::from_utf8_lossy(tag)))
+        };
+        let head = table(b"head")?;
+        let hhea = table(b"hhea")?;
+        let maxp = table(b"maxp")?;
+        let cmap = table(b"cmap")?;
+
+        Ok(Font {
+            cmap: unicode_mapping(&data, cmap)?,
+            loca: table(b"loca")?,
+            glyf: table(b"glyf")?,
+            hmtx: table(b"hmtx")?,
+            long_loca: i16_at(&data, head + 50)? != 0,
+            glyph_count: u16_at(&data, maxp + 4)?,
+            metrics_count: u16_at(&data, hhea + 34)?,
+            units_per_em: u16_at(&data, head + 18)?.max(1),
+            ascender: i16_at(&data, hhea + 4)?,
+            descender: i16_at(&data, hhea + 6)?,
+            line_gap: i16_at(&data, hhea + 8)?,
+            data,
+        })
+    }
+
+    /// The built-in font.
+    pub fn builtin() -> Font {
+        Font::parse(BUILTIN).expect("the built-in font is valid")
+    }
+
+    /// The glyph of a character, `0` is the glyph fonts show for missing characters.
+    pub fn glyph(&self, c: char) -> u16 {
+        let data = &self.data;
+        let c = c as u32;
+        let glyph = match u16_at(data, self.cmap) {
+            Ok(4) => format_4_glyph(data, self.cmap, c),
+            Ok(12) => format_12_glyph(data, self.cmap, c),
+            _ => Ok(0),
+        };
+        glyph.ok().filter(|&glyph| glyph < self.glyph_count).unwrap_or(0)
+    }
+
+    /// How far the pen moves after drawing `glyph`, in font units.
+    pub fn advance(&self, glyph: u16) -> f32 {
+        let metric = glyph.min(self.metrics_count.saturating_sub(1)) as usize;
+        u16_at(&self.data, self.hmtx + metric * 4).unwrap_or(0) as f32
+    }
+
+    /// The outline of `glyph` as closed polygons in font units. Curves are split
+    /// into lines that are at most `tolerance` font units off.
+    pub fn outline(&self, glyph: u16, tolerance: f32) -> Vec<Vec<Point>> {
+        let mut contours = Vec::new();
+        // a broken glyph is drawn as far as it could be read
+        let _ = self.add_outline(glyph, &[1.0, 0.0, 0.0, 1.0, 0.0, 0.0], tolerance, 0, &mut contours);
+        contours
+    }
+
+    /// The range of the `glyf` table that holds `glyph`.
+    fn glyph_data(&self, glyph: u16) -> Result<(usize, usize), String> {
+        let index = glyph as usize;
+        let (start, end) = if self.long_loca {
+            (
+                u32_at(&self.data, self.loca + index * 4)?,
+                u32_at(&self.data, self.loca + index * 4 + 4)?,
+            )
+        } else {
+            let start = u16_at(&self.data, self.loca + index * 2)? as u32 * 2;
+            (start, u16_at(&self.data, self.loca + index * 2 + 2)? as u32 * 2)
+        };
+        Ok((self.glyf + start as usize, self.glyf + end as usize))
+    }
+
+    /// Adds the contours of `glyph` transformed by the matrix `[a, b, c, d, e, f]`
+    /// which maps `(x, y)` to `(a x + c y + e, b x + d y + f)`.
+    fn add_outline(
+        &self,
+        glyph: u16,
+        transform: &[f32; 6],
+        tolerance: f32,
+        depth: usize,
+        contours: &mut Vec<Vec<Point>>,
+    ) -> Result<(), String> {
+        let (start, end) = self.glyph_data(glyph)?;
+        // glyphs without an outline like spaces take up no data
+        if end <= start {
+            return Ok(());
+        }
+
+        let data = &self.data;
+        let contour_count = i16_at(data, start)?;
+        if contour_count >= 0 {
+            let points = simple_glyph(data, start, contour_count as usize)?;
+            let [a, b, c, d, e, f] = *transform;
+            for contour in points {
+                let transformed: Vec<OutlinePoint> = contour
+                    .into_iter()
+                    .map(|(x, y, on)| (a * x + c * y + e, b * x + d * y + f, on))
+                    .collect();
+                contours.push(flatten(&transformed, tolerance));
+            }
+            return Ok(());
+        }
+
+        if depth >= MAX_COMPONENT_DEPTH {
+            return Err("composite glyphs are nested too deeply".to_string());
+        }
+
+        // a composite glyph places other glyphs, each with its own transform
+        let mut offset = start + 10;
+        loop {
+            let flags = u16_at(data, offset)?;
+            let component = u16_at(data, offset + 2)?;
+            offset += 4;
+            let (dx, dy) = if flags & 0x0001 != 0 {
+                offset += 4;
+                (i16_at(data, offset - 4)? as f32, i16_at(data, offset - 2)? as f32)
+            } else {
+                offset += 2;
+                let [dx, dy] = u16_at(data, offset - 2)?.to_be_bytes();
+                (dx as i8 as f32, dy as i8 as f32)
+            };
+            // components can also be placed by matching points, which is rare
+            // enough to leave them where they are
+            let (dx, dy) = if flags & 0x0002 != 0 { (dx, dy) } else { (0.0, 0.0) };
+
+            let scale = |offset: usize| i16_at(data, offset).map(|value| value as f32 / 16384.0);
+            let [m0, m1, m2, m3] = if flags & 0x0008 != 0 {
+                offset += 2;
+                let s = scale(offset - 2)?;
+                [s, 0.0, 0.0, s]
+            } else if flags & 0x0040 != 0 {
+                offset += 4;
+                [scale(offset - 4)?, 0.0, 0.0, scale(offset - 2)?]
+            } else if flags & 0x0080 != 0 {
+                offset += 8;
+                [
+                    scale(offset - 8)?,
+                    scale(offset - 6)?,
+                    scale(offset - 4)?,
+                    scale(offset - 2)?,
+                ]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+
+            let [a, b, c, d, e, f] = *transform;
+            let combined = [
+                a * m0 + c * m1,
+                b * m0 + d * m1,
+                a * m2 + c * m3,
+                b * m2 + d * m3,
+                a * dx + c * dy + e,
+                b * dx + d * dy + f,
+            ];
+            self.add_outline(component, &combined, tolerance, depth + 1, contours)?;
+
+            if flags & 0x0020 == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Finds the subtable of `cmap` that maps Unicode characters, preferring the one
+/// for all of Unicode over the one for the basic multilingual plane.
+fn unicode_mapping(data: &[u8], cmap: usize) -> Result<usize, String> {
+    let count = u16_at(data, cmap + 2)? as usize;
+    let mut best = None;
+    for record in (cmap + 4..cmap + 4 + count * 8).step_by(8) {
+        let platform = u16_at(data, record)?;
+        let encoding = u16_at(data, record + 2)?;
+        let subtable = cmap + u32_at(data, record + 4)? as usize;
+        let format = u16_at(data, subtable)?;
+        let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+        match format {
+            12 if unicode => return Ok(subtable),
+            4 if unicode => best = Some(subtable),
+            _ => {}
+        }
+    }
+    best.ok_or_else(|| "the font has no mapping for Unicode characters".to_string())
+}
+
+/// Looks up a character in a `cmap` subtable of format 4, segments of 16 bit
+/// characters.
+fn format_4_glyph(data: &[u8], table: usize, c: u32) -> Result<u16, String> {
+    if c > 0xffff {
+        return Ok(0);
+    }
+    let segments = u16_at(data, table + 6)? as usize / 2;
+    let ends = table + 14;
+    let starts = ends + segments * 2 + 2;
+    let deltas = starts + segments * 2;
+    let range_offsets = deltas + segments * 2;
+
+    for segment in 0..segments {
+        if (u16_at(data, ends + segment * 2)? as u32) < c {
+            continue;
+        }
+        let start = u16_at(data, starts + segment * 2)? as u32;
+        if start > c {
+            return Ok(0);
+        }
+
+        let delta = u16_at(data, deltas + segment * 2)?;
+        let range_offset = u16_at(data, range_offsets + segment * 2)? as usize;
+        if range_offset == 0 {
+            return Ok((c as u16).wrapping_add(delta));
+        }
+        let glyph = u16_at(
+            data,
+            range_offsets + segment * 2 + range_offset + (c - start) as usize * 2,
+        )?;
+        return Ok(if glyph == 0 { 0 } else { glyph.wrapping_add(delta) });
+    }
+    Ok(0)
+}
+
+/// Looks up a character in a `cmap` subtable of format 12, groups of characters
+/// that map to consecutive glyphs.
+fn format_12_glyph(data: &[u8], table: usize, c: u32) -> Result<u16, String> {
+    let groups = u32_at(data, table + 12)? as usize;
+    for group in (table + 16..table + 16 + groups * 12).step_by(12) {
+        let start = u32_at(data, group)?;
+        let end = u32_at(data, group + 4)?;
+        if (start..=end).contains(&c) {
+            return Ok((u32_at(data, group + 8)? + c - start) as u16);
+        }
+    }
+    Ok(0)
+}
+
+/// Reads the points of a simple glyph, every contour as `(x, y, on_curve)`.
+fn simple_glyph(data: &[u8], start: usize, contour_count: usize) -> Result<Vec<Vec<OutlinePoint>>, String> {
+    let mut ends = Vec::with_capacity(contour_count);
+    for contour in 0..contour_count {
+        ends.push(u16_at(data, start + 10 + contour * 2)? as usize);
+    }
+    let point_count = ends.last().map_or(0, |&end| end + 1);
+    let instructions = start + 10 + contour_count * 2;
+    let mut offset = instructions + 2 + u16_at(data, instructions)? as usize;
+
+    let byte = |offset: usize| {
+        data.get(offset)
+            .cloned()
+            .ok_or_else(|| "the glyph is cut off".to_string())
+    };
+    let mut flags = Vec::with_capacity(point_count);
+    while flags.len() < point_count {
+        let flag = byte(offset)?;
+        offset += 1;
+        flags.push(flag);
+        if flag & 0x08 != 0 {
+            let repeat = byte(offset)?;
+            offset += 1;
+            for _ in 0..repeat {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(point_count);
+
+    // coordinates are stored as differences to the previous point, first all x
+    // then all y, either as a byte with a separate sign or as 16 bits
+    let mut coordinates = |short: u8, same_or_positive: u8| -> Result<Vec<f32>, String> {
+        let mut value = 0i32;
+        let mut values = Vec::with_capacity(point_count);
+        for &flag in &flags {
+            if flag & short != 0 {
+                let delta = byte(offset)? as i32;
+                offset += 1;
+                value += if flag & same_or_positive != 0 { delta } else { -delta };
+            } else if flag & same_or_positive == 0 {
+                value += i16_at(data, offset)? as i32;
+                offset += 2;
+            }
+            values.push(value as f32);
+        }
+        Ok(values)
+    };
+    let xs = coordinates(0x02, 0x10)?;
+    let ys = coordinates(0x04, 0x20)?;
+
+    let mut contours = Vec::with_capacity(contour_count);
+    let mut first = 0;
+    for &end in &ends {
+        if end < first || end >= point_count {
+            return Err("the contours of the glyph are out of order".to_string());
+        }
+        contours.push((first..=end).map(|i| (xs[i], ys[i], flags[i] & 0x01 != 0)).collect());
+        first = end + 1;
+    }
+    Ok(contours)
+}
+
+/// Turns a contour of points on and off the curve into a closed polygon. Two
+/// points off the curve in a row have an implied point on the curve between them.
+fn flatten(points: &[OutlinePoint], tolerance: f32) -> Vec<Point> {
+    let mut expanded = Vec::with_capacity(points.len() * 2);
+    for (i, &point) in points.iter().enumerate() {
+        let next = points[(i + 1) % points.len()];
+        expanded.push(point);
+        if !point.2 && !next.2 {
+            expanded.push(((point.0 + next.0) / 2.0, (point.1 + next.1) / 2.0, true));
+        }
+    }
+    let first_on = match expanded.iter().position(|point| point.2) {
+        Some(index) => index,
+        None => return Vec::new(),
+    };
+    expanded.rotate_left(first_on);
+
+    let count = expanded.len();
+    let mut polygon = vec![(expanded[0].0, expanded[0].1)];
+    let mut i = 1;
+    while i <= count {
+        let point = expanded[i % count];
+        if point.2 {
+            polygon.push((point.0, point.1));
+            i += 1;
+        } else {
+            let from = *polygon.last().unwrap();
+            let to = expanded[(i + 1) % count];
+            add_curve(&mut polygon, from, (point.0, point.1), (to.0, to.1), tolerance);
+            i += 2;
+        }
+    }
+    polygon
+}
+
+/// Adds the quadratic curve from `from` over `control` to `to` as lines, without
+/// the starting point.
+fn add_curve(polygon: &mut Vec<Point>, from: Point, control: Point, to: Point, tolerance: f32) {
+    // a curve deviates from its n lines by at most |from - 2 control + to| / (8 n²)
+    let dx = from.0 - 2.0 * control.0 + to.0;
+    let dy = from.1 - 2.0 * control.1 + to.1;
+    let deviation = (dx * dx + dy * dy).sqrt();
+    let steps = ((deviation / (8.0 * tolerance)).sqrt().ceil() as usize).clamp(1, 64);
+
+    for step in 1..=steps {
+        let t = step as f32 / steps as f32;
+        let u = 1.0 - t;
+        polygon.push((
+            u * u * from.0 + 2.0 * u * t * control.0 + t * t * to.0,
+            u * u * from.1 + 2.0 * u * t * control.1 + t * t * to.1,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_builtin_font_has_latin_glyphs() {
+        let font = Font::builtin();
+        assert!(font.units_per_em > 0);
+        assert!(font.ascender > 0 && font.descender < 0);
+
+        let a = font.glyph('A');
+        assert_ne!(a, 0);
+        assert_ne!(font.glyph('B'), a);
+        assert!(font.advance(a) > 0.0);
+        // a character that isn't in the subset
+        assert_eq!(font.glyph('\u{4e2d}'), 0);
+    }
+
+    #[test]
+    fn outlines_have_a_contour_for_every_shape() {
+        let font = Font::builtin();
+        assert_eq!(font.outline(font.glyph('O'), 1.0).len(), 2);
+        assert_eq!(font.outline(font.glyph('i'), 1.0).len(), 2);
+        assert!(font.outline(font.glyph(' '), 1.0).is_empty());
+
+        // the outline stays within the metrics of the font
+        for &(x, y) in font.outline(font.glyph('O'), 1.0).iter().flatten() {
+            assert!(x >= 0.0 && x <= font.advance(font.glyph('O')));
+            assert!(y >= font.descender as f32 && y <= font.ascender as f32);
+        }
+    }
+
+    #[test]
+    fn curves_are_flattened_to_the_tolerance() {
+        let font = Font::builtin();
+        let points = |tolerance| -> usize { font.outline(font.glyph('O'), tolerance).iter().map(Vec::len).sum() };
+        assert!(points(0.5) > points(20.0));
+    }
+
+    #[test]
+    fn other_fonts_are_errors() {
+        assert!(Font::parse(&b"OTTO\0\0\0\0"[..]).is_err());
+        assert!(Font::parse(&b"ttcf\0\0\0\0"[..]).is_err());
+        assert!(Font::parse(&b"GIF89a"[..]).is_err());
+        assert!(Font::parse(&b"\0\x01\0\0\0\0"[..]).is_err());
+        assert!(Font::parse(&BUILTIN[..200]).is_err());
+    }
+}
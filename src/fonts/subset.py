@@ -0,0 +1,237 @@
+#!/usr/bin/env python3
+"""Cuts a TrueType font down to the characters captions need most.
+
+    python3 subset.py DejaVuSans-Bold.ttf DejaVuSans-Bold-subset.ttf
+
+Only the tables the caption renderer and common font tools read are kept, hinting
+instructions are dropped since captions are drawn without hinting.
+"""
+
+import struct
+import sys
+
+# Latin with its extensions, Greek, Cyrillic, general punctuation like dashes and
+# quotes, and currency signs. Symbols and arrows would add half as much again.
+RANGES = [
+    (0x0020, 0x007E),
+    (0x00A0, 0x024F),
+    (0x0370, 0x03FF),
+    (0x0400, 0x052F),
+    (0x1E00, 0x1EFF),
+    (0x2000, 0x206F),
+    (0x20A0, 0x20CF),
+]
+
+KEPT_TABLES = [b"OS/2", b"cmap", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"name", b"post"]
+
+
+def tables(font):
+    count = struct.unpack(">H", font[4:6])[0]
+    result = {}
+    for i in range(count):
+        tag, _, offset, length = struct.unpack(">4sIII", font[12 + i * 16 : 28 + i * 16])
+        result[tag] = font[offset : offset + length]
+    return result
+
+
+def character_map(cmap):
+    """Reads the Unicode subtable of format 4 into a dict of character to glyph."""
+    count = struct.unpack(">H", cmap[2:4])[0]
+    for i in range(count):
+        platform, encoding, offset = struct.unpack(">HHI", cmap[4 + i * 8 : 12 + i * 8])
+        if platform == 3 and encoding == 1 and struct.unpack(">H", cmap[offset : offset + 2])[0] == 4:
+            break
+    else:
+        raise ValueError("the font has no Unicode subtable of format 4")
+
+    table = cmap[offset:]
+    segments = struct.unpack(">H", table[6:8])[0] // 2
+    u16 = lambda at: struct.unpack(">H", table[at : at + 2])[0]
+    ends, starts = 14, 16 + segments * 2
+    deltas, range_offsets = starts + segments * 2, starts + segments * 4
+
+    mapping = {}
+    for segment in range(segments):
+        start, end = u16(starts + segment * 2), u16(ends + segment * 2)
+        delta, range_offset = u16(deltas + segment * 2), u16(range_offsets + segment * 2)
+        for c in range(start, min(end, 0xFFFE) + 1):
+            if range_offset == 0:
+                glyph = (c + delta) & 0xFFFF
+            else:
+                glyph = u16(range_offsets + segment * 2 + range_offset + (c - start) * 2)
+                glyph = (glyph + delta) & 0xFFFF if glyph else 0
+            if glyph:
+                mapping[c] = glyph
+    return mapping
+
+
+def glyph_data(tabs, glyph):
+    long_loca = struct.unpack(">h", tabs[b"head"][50:52])[0] != 0
+    loca = tabs[b"loca"]
+    if long_loca:
+        start, end = struct.unpack(">II", loca[glyph * 4 : glyph * 4 + 8])
+    else:
+        start, end = (value * 2 for value in struct.unpack(">HH", loca[glyph * 2 : glyph * 2 + 4]))
+    return tabs[b"glyf"][start:end]
+
+
+def components(data):
+    """Yields the offset of the glyph index of every component of a composite glyph."""
+    offset = 10
+    while True:
+        flags = struct.unpack(">H", data[offset : offset + 2])[0]
+        yield offset + 2
+        offset += 4 + (4 if flags & 0x0001 else 2)
+        if flags & 0x0008:
+            offset += 2
+        elif flags & 0x0040:
+            offset += 4
+        elif flags & 0x0080:
+            offset += 8
+        if not flags & 0x0020:
+            return
+
+
+def strip_instructions(data):
+    """Removes the hinting instructions of a glyph."""
+    if not data:
+        return data
+    contours = struct.unpack(">h", data[0:2])[0]
+    if contours >= 0:
+        at = 10 + contours * 2
+        length = struct.unpack(">H", data[at : at + 2])[0]
+        return data[:at] + b"\0\0" + data[at + 2 + length :]
+
+    data = bytearray(data)
+    end = 0
+    for index in components(data):
+        flag_at = index - 2
+        flags = struct.unpack(">H", data[flag_at:index])[0]
+        end = index + 2 + (4 if flags & 0x0001 else 2)
+        end += 2 if flags & 0x0008 else 4 if flags & 0x0040 else 8 if flags & 0x0080 else 0
+        data[flag_at:index] = struct.pack(">H", flags & ~0x0100)
+    return bytes(data[:end])
+
+
+def format_4(mapping):
+    """Writes a cmap subtable of format 4 for a dict of character to glyph."""
+    characters = sorted(mapping)
+    segments = []
+    for c in characters:
+        if segments and segments[-1][1] == c - 1 and mapping[segments[-1][1]] == mapping[c] - 1:
+            segments[-1][1] = c
+        else:
+            segments.append([c, c])
+    segments.append([0xFFFF, 0xFFFF])
+
+    count = len(segments)
+    search = 1 << (count.bit_length() - 1)
+    ends = b"".join(struct.pack(">H", end) for _, end in segments)
+    starts = b"".join(struct.pack(">H", start) for start, _ in segments)
+    deltas = b"".join(
+        struct.pack(">H", (mapping[start] - start) & 0xFFFF if start != 0xFFFF else 1) for start, _ in segments
+    )
+    range_offsets = b"\0\0" * count
+    body = ends + b"\0\0" + starts + deltas + range_offsets
+    header = struct.pack(
+        ">HHHHHHH", 4, 14 + len(body), 0, count * 2, search * 2, (search.bit_length() - 1), (count - search) * 2
+    )
+    return header + body
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def subset(font):
+    tabs = tables(font)
+    mapping = {
+        c: glyph
+        for c, glyph in character_map(tabs[b"cmap"]).items()
+        if any(start <= c <= end for start, end in RANGES)
+    }
+
+    # glyph 0 is shown for missing characters, composite glyphs need their parts
+    kept = {0} | set(mapping.values())
+    pending = list(kept)
+    while pending:
+        data = glyph_data(tabs, pending.pop())
+        if data and struct.unpack(">h", data[0:2])[0] < 0:
+            for index in components(data):
+                component = struct.unpack(">H", data[index : index + 2])[0]
+                if component not in kept:
+                    kept.add(component)
+                    pending.append(component)
+    order = sorted(kept)
+    new_index = {glyph: index for index, glyph in enumerate(order)}
+
+    metrics_count = struct.unpack(">H", tabs[b"hhea"][34:36])[0]
+    hmtx, glyf, loca = b"", b"", b""
+    for glyph in order:
+        metric = min(glyph, metrics_count - 1)
+        advance = tabs[b"hmtx"][metric * 4 : metric * 4 + 2]
+        if glyph < metrics_count:
+            bearing = tabs[b"hmtx"][glyph * 4 + 2 : glyph * 4 + 4]
+        else:
+            at = metrics_count * 4 + (glyph - metrics_count) * 2
+            bearing = tabs[b"hmtx"][at : at + 2]
+        hmtx += advance + bearing
+
+        data = bytearray(strip_instructions(glyph_data(tabs, glyph)))
+        if data and struct.unpack(">h", data[0:2])[0] < 0:
+            for index in components(data):
+                component = struct.unpack(">H", data[index : index + 2])[0]
+                data[index : index + 2] = struct.pack(">H", new_index[component])
+        data += b"\0" * (-len(data) % 4)
+        loca += struct.pack(">I", len(glyf))
+        glyf += bytes(data)
+    loca += struct.pack(">I", len(glyf))
+
+    head = bytearray(tabs[b"head"])
+    head[8:12] = b"\0\0\0\0"
+    head[50:52] = struct.pack(">h", 1)
+    hhea = bytearray(tabs[b"hhea"])
+    hhea[34:36] = struct.pack(">H", len(order))
+    maxp = bytearray(tabs[b"maxp"])
+    maxp[4:6] = struct.pack(">H", len(order))
+    # version 3 of the post table lists no glyph names
+    post = b"\x00\x03\x00\x00" + tabs[b"post"][4:32]
+    subtable = format_4({c: new_index[glyph] for c, glyph in mapping.items() if c <= 0xFFFF})
+    cmap = struct.pack(">HHHHI", 0, 1, 3, 1, 12) + subtable
+
+    new_tables = {
+        b"OS/2": tabs[b"OS/2"],
+        b"cmap": cmap,
+        b"glyf": glyf,
+        b"head": bytes(head),
+        b"hhea": bytes(hhea),
+        b"hmtx": hmtx,
+        b"loca": loca,
+        b"maxp": bytes(maxp),
+        b"name": tabs[b"name"],
+        b"post": post,
+    }
+
+    count = len(KEPT_TABLES)
+    search = 1 << (count.bit_length() - 1)
+    out = struct.pack(">IHHHH", 0x00010000, count, search * 16, search.bit_length() - 1, count * 16 - search * 16)
+    offset = len(out) + count * 16
+    directory, body, offsets = b"", b"", {}
+    for tag in KEPT_TABLES:
+        data = new_tables[tag]
+        offsets[tag] = offset + len(body)
+        directory += struct.pack(">4sIII", tag, checksum(data), offsets[tag], len(data))
+        body += data + b"\0" * (-len(data) % 4)
+    out = bytearray(out + directory + body)
+
+    head_at = offsets[b"head"]
+    out[head_at + 8 : head_at + 12] = struct.pack(">I", (0xB1B0AFBA - checksum(bytes(out))) & 0xFFFFFFFF)
+    return bytes(out)
+
+
+if __name__ == "__main__":
+    with open(sys.argv[1], "rb") as source:
+        font = source.read()
+    with open(sys.argv[2], "wb") as target:
+        target.write(subset(font))
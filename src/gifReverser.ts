@@ -147,6 +147,7 @@ type RegisterProgressEventData = {
   id: string;
   name: string;
   numberOfFrames: number;
+  phase: ProgressPhase;
 }
 
 type ReportProgressEventData = {
@@ -154,6 +155,7 @@ type ReportProgressEventData = {
   id: string;
   name: string;
   currentFrame: number;
+  phase: ProgressPhase;
 }
 
 type FinishedEventData = {
@@ -174,11 +176,14 @@ type ErrorEventData = {
 
 type MessageEventData = RegisterProgressEventData | ReportProgressEventData | FinishedEventData | ErrorEventData;
 
+export type ProgressPhase = 'decoding' | 'encoding';
+
 export interface ProgressEvent {
   id: string;
   name: string;
   currentFrame: number;
   numberOfFrames: number;
+  phase: ProgressPhase;
 }
 
 export interface ProcessingErrorEvent {
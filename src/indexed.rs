@@ -0,0 +1,296 @@
+//! Composition of gif frames without expanding them to RGBA.
+//!
+//! Transforms that only change the order or timing of frames don't have to look at
+//! colors at all. Keeping the palette indices of the original avoids quantizing the
+//! frames again, which is slow and loses quality. This only works as long as every
+//! composed frame can be described with a single palette, so `collect_frames` gives
+//! up on gifs that mix palettes within a frame.
+
+use compose::{self, background_color, clear_color};
+use encode::{Encodable, EncodeOptions};
+use gif::{ColorOutput, DecodeOptions, DisposalMethod, Frame};
+use limits::Limits;
+use std::borrow::Cow;
+use timeline::Timed;
+use wasm_bindgen::prelude::*;
+use {js_error, lenient};
+
+/// A full canvas sized frame made of palette indices.
+#[derive(Clone)]
+pub struct IndexedFrame {
+    pub width: u16,
+    pub height: u16,
+    pub indices: Vec<u8>,
+    /// The local palette, `None` if the frame uses the global palette
+    pub palette: Option<Vec<u8>>,
+    pub transparent: Option<u8>,
+    pub delay: u16,
+}
+
+impl Encodable for IndexedFrame {
+    /// Wraps the frame for the encoder without copying the indices, the options
+    /// don't matter since the colors are kept as they are.
+    fn to_frame(&self, _options: &EncodeOptions) -> Frame<'_> {
+        Frame {
+            width: self.width,
+            height: self.height,
+            buffer: Cow::Borrowed(&self.indices),
+            palette: self.palette.clone(),
+            transparent: self.transparent,
+            delay: self.delay,
+            dispose: DisposalMethod::Background,
+            ..Frame::default()
+        }
+    }
+}
+
+impl Timed for IndexedFrame {
+    fn delay(&self) -> u16 {
+        self.delay
+    }
+
+    fn set_delay(&mut self, delay: u16) {
+        self.delay = delay;
+    }
+}
+
+/// All frames of a gif in their indexed form.
+pub struct Indexed {
+    pub width: u16,
+    pub height: u16,
+    pub global_palette: Vec<u8>,
+    pub frames: Vec<IndexedFrame>,
+    /// What lenient decoding stopped at
+    pub warnings: Vec<String>,
+}
+
+/// What the animation looks like between two frames. Pixels that are `holes` are
+/// transparent, their index is meaningless.
+#[derive(Clone)]
+struct Canvas {
+    indices: Vec<u8>,
+    holes: Vec<bool>,
+    /// The local palette the indices refer to, `None` for the global palette
+    palette: Option<Vec<u8>>,
+}
+
+impl Canvas {
+    /// Clears the `area` given as `(left, top, right, bottom)` before or after `frame`,
+    /// see `compose::clear_color`. Returns `false` if the background color isn't in
+    /// the palette of the canvas.
+    fn clear(
+        &mut self,
+        width: usize,
+        area: (usize, usize, usize, usize),
+        frame: &Frame,
+        background: Background,
+    ) -> bool {
+        let color = clear_color(frame, background.color);
+        let index = if color[3] == 0 {
+            None
+        } else {
+            let palette = self
+                .palette
+                .as_ref()
+                .map_or(background.global_palette, |palette| &palette[..]);
+            match palette.chunks_exact(3).position(|rgb| rgb == &color[..3]) {
+                Some(index) => Some(index as u8),
+                None => return false,
+            }
+        };
+
+        let (left, top, right, bottom) = area;
+        for y in top..bottom {
+            for i in y * width + left..y * width + right {
+                match index {
+                    Some(index) => {
+                        self.indices[i] = index;
+                        self.holes[i] = false;
+                    }
+                    None => self.holes[i] = true,
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The background color of a gif and the global palette to look it up in.
+#[derive(Clone, Copy)]
+struct Background<'a> {
+    color: [u8; 4],
+    global_palette: &'a [u8],
+}
+
+/// Decodes all frames of a gif and composes them without leaving palette space.
+///
+/// Returns `None` if a frame with a different palette only partially replaces what
+/// is on the canvas, or if a frame needs a transparent color and its palette has no
+/// room left for one. `after_frame` is called with the number of frames decoded so
+/// far after every frame, decoding stops as soon as it fails.
+pub fn collect_frames<F>(data: &[u8], limits: &Limits, mut after_frame: F) -> Result<Option<Indexed>, JsValue>
+where
+    F: FnMut(usize) -> Result<(), JsValue>,
+{
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut reader = options.read_info(data).map_err(|e| js_error(&e.to_string()))?;
+
+    let width = reader.width();
+    let height = reader.height();
+    limits.check_size(width, height).map_err(|e| js_error(&e))?;
+    let global_palette = reader
+        .global_palette()
+        .map(|palette| palette.to_vec())
+        .unwrap_or_default();
+    let background = Background {
+        color: background_color(reader.global_palette(), reader.bg_color()),
+        global_palette: &global_palette,
+    };
+
+    let size = width as usize * height as usize;
+    let mut canvas = Canvas {
+        indices: vec![0; size],
+        holes: vec![true; size],
+        palette: None,
+    };
+    let mut frames = Vec::new();
+    let mut buffer = Vec::new();
+    let mut warnings = Vec::new();
+
+    loop {
+        let frame = match reader.next_frame_info() {
+            Ok(Some(frame)) => frame.clone(),
+            Ok(None) => break,
+            Err(e) => {
+                warnings.push(lenient::stop(limits, frames.len(), e).map_err(|e| js_error(&e))?);
+                break;
+            }
+        };
+        if !limits.keeps_frame(frames.len() + 1).map_err(|e| js_error(&e))? {
+            break;
+        }
+        limits.check_size(frame.width, frame.height).map_err(|e| js_error(&e))?;
+        buffer.resize(reader.buffer_size(), 0);
+        if let Err(e) = reader.read_into_buffer(&mut buffer) {
+            warnings.push(lenient::stop(limits, frames.len(), e).map_err(|e| js_error(&e))?);
+            break;
+        }
+
+        let left = frame.left as usize;
+        let top = frame.top as usize;
+        let frame_width = frame.width as usize;
+        let right = (left + frame_width).min(width as usize);
+        let bottom = (top + frame.height as usize).min(height as usize);
+
+        let covers_canvas = left == 0 && top == 0 && right == width as usize && bottom == height as usize;
+        let is_opaque = frame
+            .transparent
+            .is_none_or(|transparent| !buffer.contains(&transparent));
+
+        if frame.palette.is_none() && global_palette.is_empty() {
+            return Ok(None);
+        }
+        if frame.palette != canvas.palette && !(covers_canvas && is_opaque) {
+            return Ok(None);
+        }
+
+        if frames.is_empty()
+            && !canvas.clear(
+                width as usize,
+                (0, 0, width as usize, height as usize),
+                &frame,
+                background,
+            )
+        {
+            return Ok(None);
+        }
+
+        let previous = if frame.dispose == DisposalMethod::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        for (start, row) in compose::visible_rows(&frame, &buffer, width, height) {
+            for (x, &index) in row.iter().enumerate() {
+                if Some(index) != frame.transparent {
+                    canvas.indices[start + x] = index;
+                    canvas.holes[start + x] = false;
+                }
+            }
+        }
+        canvas.palette = frame.palette.clone();
+
+        let palette = canvas.palette.as_ref().unwrap_or(&global_palette);
+        match snapshot(&canvas, palette, width, height, frame.delay) {
+            Some(composed) => frames.push(composed),
+            None => return Ok(None),
+        }
+
+        match frame.dispose {
+            DisposalMethod::Background => {
+                if !canvas.clear(width as usize, (left, top, right, bottom), &frame, background) {
+                    return Ok(None);
+                }
+            }
+            DisposalMethod::Previous => canvas = previous.unwrap(),
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+
+        after_frame(frames.len())?;
+    }
+
+    Ok(Some(Indexed {
+        width,
+        height,
+        global_palette,
+        frames,
+        warnings,
+    }))
+}
+
+/// Turns the canvas into a frame. Holes get a palette entry that no visible pixel
+/// uses, or a new one if the palette isn't full yet.
+fn snapshot(canvas: &Canvas, palette: &[u8], width: u16, height: u16, delay: u16) -> Option<IndexedFrame> {
+    let mut frame = IndexedFrame {
+        width,
+        height,
+        indices: canvas.indices.clone(),
+        palette: canvas.palette.clone(),
+        transparent: None,
+        delay,
+    };
+
+    if !canvas.holes.contains(&true) {
+        return Some(frame);
+    }
+
+    let mut used = [false; 256];
+    for (&index, &hole) in canvas.indices.iter().zip(canvas.holes.iter()) {
+        if !hole {
+            used[index as usize] = true;
+        }
+    }
+
+    let colors = palette.len() / 3;
+    let transparent = match (0..colors).find(|&i| !used[i]) {
+        Some(unused) => unused,
+        None if colors < 256 => {
+            let mut extended = palette.to_vec();
+            extended.extend_from_slice(&[0, 0, 0]);
+            frame.palette = Some(extended);
+            colors
+        }
+        None => return None,
+    } as u8;
+
+    for (index, &hole) in frame.indices.iter_mut().zip(canvas.holes.iter()) {
+        if hole {
+            *index = transparent;
+        }
+    }
+    frame.transparent = Some(transparent);
+
+    Some(frame)
+}
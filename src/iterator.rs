@@ -0,0 +1,140 @@
+//! Decoding a gif one frame at a time.
+
+use compose::Compositor;
+use gif::Decoder;
+use limits::Limits;
+use std::io::Cursor;
+use types::JsDecodedFrame;
+use wasm_bindgen::prelude::*;
+use {decode_owned_data, frame_to_js, js_error, lenient, scan};
+
+/// Hands out the composed frames of a gif one after the other.
+///
+/// Frames are only decoded when `next()` asks for them and only the canvas of the
+/// last frame is kept, so a scrubber can step through large gifs without holding
+/// all frames in memory. Call `free()` once the iterator isn't needed anymore.
+#[wasm_bindgen]
+pub struct FrameIterator {
+    data: Vec<u8>,
+    reader: Decoder<Cursor<Vec<u8>>>,
+    compositor: Compositor,
+    buffer: Vec<u8>,
+    width: u16,
+    height: u16,
+    frame_count: usize,
+    limits: Limits,
+    position: usize,
+    /// Whether decoding stopped before the end of the gif, at a defect or because
+    /// of the frame limit
+    stopped: bool,
+    /// What lenient decoding stopped at
+    warnings: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl FrameIterator {
+    /// Reads the header of the gif `data`, which is copied so the caller can reuse
+    /// its buffer right away. Its frames are decoded within `limits`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &[u8], limits: &Limits) -> Result<FrameIterator, JsValue> {
+        console_error_panic_hook::set_once();
+
+        let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+        let reader = decode_owned_data(data.to_vec())?;
+        let width = reader.width();
+        let height = reader.height();
+        limits.check_size(width, height).map_err(|e| js_error(&e))?;
+        let compositor = Compositor::for_decoder(&reader);
+
+        Ok(FrameIterator {
+            data: data.to_vec(),
+            reader,
+            compositor,
+            buffer: Vec::new(),
+            width,
+            height,
+            frame_count: limits.check_frames(summary.delays.len()).map_err(|e| js_error(&e))?,
+            limits: *limits,
+            position: 0,
+            stopped: false,
+            warnings: Vec::new(),
+        })
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Number of frames of the gif, known without decoding them. Frames beyond a
+    /// frame limit that cuts off gifs aren't counted.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Number of frames returned by `next()` so far, which is also the index of the
+    /// frame it returns next
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns what lenient decoding stopped at, see `Limits.lenient`. `reset()`
+    /// clears the warnings.
+    #[wasm_bindgen(unchecked_return_type = "string[]")]
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.clone()
+    }
+
+    /// Decodes the next frame and returns it as an object `{ width, height, delay,
+    /// rgba }` or `undefined` after the last frame.
+    #[wasm_bindgen(js_name = next)]
+    pub fn next_frame(&mut self) -> Result<Option<JsDecodedFrame>, JsValue> {
+        // a defect in lenient mode or the frame limit end the gif, it stays there
+        // until `reset()`
+        if self.stopped {
+            return Ok(None);
+        }
+        let frame = match self.reader.next_frame_info() {
+            Ok(Some(frame)) => frame.clone(),
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                let warning = lenient::stop(&self.limits, self.position, e).map_err(|e| js_error(&e))?;
+                self.warnings.push(warning);
+                self.stopped = true;
+                return Ok(None);
+            }
+        };
+        if !self.limits.keeps_frame(self.position + 1).map_err(|e| js_error(&e))? {
+            self.stopped = true;
+            return Ok(None);
+        }
+        self.limits
+            .check_size(frame.width, frame.height)
+            .map_err(|e| js_error(&e))?;
+
+        self.buffer.resize(self.reader.buffer_size(), 0);
+        if let Err(e) = self.reader.read_into_buffer(&mut self.buffer) {
+            let warning = lenient::stop(&self.limits, self.position, e).map_err(|e| js_error(&e))?;
+            self.warnings.push(warning);
+            self.stopped = true;
+            return Ok(None);
+        }
+        self.position += 1;
+
+        Ok(Some(frame_to_js(&self.compositor.compose(&frame, &self.buffer))))
+    }
+
+    /// Starts over at the first frame. Since every frame builds on the ones before
+    /// it, going back means decoding the gif from the start again.
+    pub fn reset(&mut self) -> Result<(), JsValue> {
+        self.reader = decode_owned_data(self.data.clone())?;
+        self.compositor = Compositor::for_decoder(&self.reader);
+        self.position = 0;
+        self.stopped = false;
+        self.warnings.clear();
+        Ok(())
+    }
+}
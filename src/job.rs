@@ -0,0 +1,236 @@
+//! Running a pipeline in small steps, meant for Web Workers.
+
+use compose::Compositor;
+use encode::{Encodable, EncodeOptions, GifWriter};
+use gif::Decoder;
+use pipeline::Pipeline;
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+use {decode_owned_data, js_error, lenient, scan, FrameData};
+
+/// What a job is doing at the moment.
+enum Stage {
+    Decoding {
+        reader: Box<Decoder<Cursor<Vec<u8>>>>,
+        compositor: Compositor,
+        buffer: Vec<u8>,
+        frames: Vec<FrameData>,
+    },
+    Encoding {
+        writer: GifWriter<Vec<u8>>,
+        frames: Vec<FrameData>,
+        written: usize,
+    },
+    Finished(Option<Vec<u8>>),
+}
+
+/// Applies a `Pipeline` to a gif a few frames at a time.
+///
+/// The job doesn't take any callbacks. Instead the worker calls `step()` until it
+/// returns `true` and can post `progress()` to the main thread in between. The
+/// result is an `ArrayBuffer` that can be transferred with `postMessage` without
+/// copying it:
+///
+/// ```js
+/// const job = new GifJob(data, new Pipeline().reverse(), EncodeOptions.fromObject(init));
+/// while (!job.step(5)) postMessage({ progress: job.progress() });
+/// const gif = job.take_result();
+/// postMessage({ gif }, [gif]);
+/// ```
+///
+/// Chunk callbacks, `keep_output` and cancel checks of the options are ignored,
+/// a job is cancelled by not calling `step()` anymore and freeing it.
+#[wasm_bindgen]
+pub struct GifJob {
+    pipeline: Pipeline,
+    options: EncodeOptions,
+    width: u16,
+    height: u16,
+    global_palette: Vec<u8>,
+    /// Number of frames of the input gif
+    input_frames: usize,
+    stage: Stage,
+    /// What lenient decoding stopped at
+    warnings: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl GifJob {
+    /// Prepares applying `pipeline` to the gif `data`, which is copied so the
+    /// caller can transfer or reuse its buffer right away.
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &[u8], pipeline: &Pipeline, options: &EncodeOptions) -> Result<GifJob, JsValue> {
+        console_error_panic_hook::set_once();
+
+        options.validate()?;
+        let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+
+        let reader = decode_owned_data(data.to_vec())?;
+        let width = reader.width();
+        let height = reader.height();
+        options.limits().check_size(width, height).map_err(|e| js_error(&e))?;
+        let global_palette = reader
+            .global_palette()
+            .map(|palette| palette.to_vec())
+            .unwrap_or_default();
+        let compositor = Compositor::for_decoder(&reader);
+
+        Ok(GifJob {
+            pipeline: pipeline.clone(),
+            options: options.with_default_loop_count(Some(summary.loop_count())),
+            width,
+            height,
+            global_palette,
+            input_frames: options
+                .limits()
+                .check_frames(summary.delays.len())
+                .map_err(|e| js_error(&e))?,
+            stage: Stage::Decoding {
+                reader: Box::new(reader),
+                compositor,
+                buffer: Vec::new(),
+                frames: Vec::new(),
+            },
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Decodes or encodes up to `frames` frames and returns whether the job is
+    /// finished. Applying the transforms between decoding and encoding happens in
+    /// one step.
+    pub fn step(&mut self, frames: u32) -> Result<bool, JsValue> {
+        for _ in 0..frames.max(1) {
+            if self.is_finished() {
+                break;
+            }
+            self.advance().map_err(|e| js_error(&e))?;
+        }
+        Ok(self.is_finished())
+    }
+
+    /// `"decoding"`, `"encoding"` or `"finished"`
+    pub fn phase(&self) -> String {
+        match self.stage {
+            Stage::Decoding { .. } => "decoding",
+            Stage::Encoding { .. } => "encoding",
+            Stage::Finished(_) => "finished",
+        }
+        .to_string()
+    }
+
+    /// How much of the job is done, from 0 to 1. Decoding and encoding a frame
+    /// count the same.
+    pub fn progress(&self) -> f64 {
+        let (done, total) = match self.stage {
+            Stage::Decoding { ref frames, .. } => (frames.len(), 2 * self.input_frames),
+            Stage::Encoding {
+                ref frames, written, ..
+            } => (self.input_frames + written, self.input_frames + frames.len()),
+            Stage::Finished(_) => return 1.0,
+        };
+        if total == 0 {
+            0.0
+        } else {
+            (done as f64 / total as f64).min(1.0)
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.stage, Stage::Finished(_))
+    }
+
+    /// Returns what lenient decoding stopped at, see `Limits.lenient`. Decoding
+    /// can only stop once, so there is at most one warning.
+    #[wasm_bindgen(unchecked_return_type = "string[]")]
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.clone()
+    }
+
+    /// Returns the encoded gif as an `ArrayBuffer`. Fails if the job isn't
+    /// finished yet or the result was taken already.
+    pub fn take_result(&mut self) -> Result<js_sys::ArrayBuffer, JsValue> {
+        match self.stage {
+            Stage::Finished(ref mut result) => result
+                .take()
+                .map(|gif| js_sys::Uint8Array::from(&gif[..]).buffer())
+                .ok_or_else(|| js_error("the result was taken already")),
+            _ => Err(js_error("the job isn't finished yet")),
+        }
+    }
+}
+
+impl GifJob {
+    /// Processes a single frame, or applies the transforms once all frames are
+    /// decoded.
+    fn advance(&mut self) -> Result<(), String> {
+        match self.stage {
+            Stage::Decoding {
+                ref mut reader,
+                ref mut compositor,
+                ref mut buffer,
+                ref mut frames,
+            } => {
+                // a defect in lenient mode or the frame limit end decoding like the
+                // end of the gif
+                let limits = self.options.limits();
+                match reader.next_frame_info() {
+                    Ok(Some(frame)) if limits.keeps_frame(frames.len() + 1)? => {
+                        let frame = frame.clone();
+                        limits.check_size(frame.width, frame.height)?;
+                        limits.check_memory(frames.len() + 1, self.width, self.height)?;
+                        buffer.resize(reader.buffer_size(), 0);
+                        match reader.read_into_buffer(buffer) {
+                            Ok(()) => {
+                                frames.push(compositor.compose(&frame, buffer));
+                                return Ok(());
+                            }
+                            Err(e) => self.warnings.push(lenient::stop(&limits, frames.len(), e)?),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.warnings.push(lenient::stop(&limits, frames.len(), e)?),
+                }
+
+                let frames = std::mem::take(frames);
+                self.start_encoding(frames)
+            }
+            Stage::Encoding {
+                ref mut writer,
+                ref frames,
+                ref mut written,
+            } => {
+                if let Some(frame) = frames.get(*written) {
+                    writer.write(frame.to_frame(&self.options)).map_err(|e| e.to_string())?;
+                    *written += 1;
+                    return Ok(());
+                }
+
+                self.finish()
+            }
+            Stage::Finished(_) => Ok(()),
+        }
+    }
+
+    fn start_encoding(&mut self, mut frames: Vec<FrameData>) -> Result<(), String> {
+        self.pipeline.apply(&mut frames)?;
+
+        // resizing changes the size of the canvas
+        let width = frames.iter().map(|frame| frame.width).max().unwrap_or(self.width);
+        let height = frames.iter().map(|frame| frame.height).max().unwrap_or(self.height);
+        self.stage = Stage::Encoding {
+            writer: GifWriter::new(Vec::new(), width, height, &self.global_palette, &self.options)
+                .map_err(|e| e.to_string())?,
+            frames,
+            written: 0,
+        };
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if let Stage::Encoding { writer, .. } = std::mem::replace(&mut self.stage, Stage::Finished(None)) {
+            let gif = writer.finish().map_err(|e| e.to_string())?;
+            self.stage = Stage::Finished(Some(gif));
+        }
+        Ok(())
+    }
+}
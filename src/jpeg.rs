@@ -0,0 +1,779 @@
+//! A decoder for JPEG images.
+//!
+//! This handles the JPEGs that are found in practice: baseline and progressive
+//! images with Huffman coding, 8 bit samples, grayscale or YCbCr, any chroma
+//! subsampling and restart intervals. Lossless, arithmetic coded and CMYK JPEGs
+//! are rejected with an error. Chroma is upsampled by repeating samples, which is
+//! good enough since the result is quantized to 256 colors anyway.
+
+use std::f32::consts::PI;
+
+/// Maps the position of a coefficient in zig-zag order to its natural position.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21,
+    28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54,
+    47, 55, 62, 63,
+];
+
+/// A Huffman table from a DHT segment.
+#[derive(Clone, Default)]
+struct Huffman {
+    /// The largest code of every length, -1 if there is none
+    max_code: [i32; 17],
+    /// Difference between the index of the first value and the first code of every
+    /// length
+    offsets: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], values: &[u8]) -> Huffman {
+        let mut huffman = Huffman {
+            max_code: [-1; 17],
+            offsets: [0; 17],
+            values: values.to_vec(),
+        };
+
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1..17 {
+            let count = counts[length - 1] as i32;
+            if count > 0 {
+                huffman.offsets[length] = index - code;
+                code += count;
+                index += count;
+                huffman.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        huffman
+    }
+}
+
+/// A component of the image, e.g. the luma or one of the chroma channels.
+struct Component {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    quantization: usize,
+    blocks_per_line: usize,
+    /// The coefficients of all blocks in zig-zag order, padded to whole MCUs
+    coefficients: Vec<i32>,
+    /// The decoded samples
+    samples: Vec<u8>,
+    /// Width of `samples`
+    stride: usize,
+}
+
+/// Reads the entropy coded data of a scan MSB first, skipping stuffed zero bytes.
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bit(&mut self) -> u32 {
+        if self.count == 0 {
+            // past the end of the data or at a marker, zeros are read
+            let byte = match self.data.get(self.offset) {
+                Some(&0xff) if self.data.get(self.offset + 1) == Some(&0) => {
+                    self.offset += 2;
+                    0xff
+                }
+                Some(&0xff) | None => 0,
+                Some(&byte) => {
+                    self.offset += 1;
+                    byte
+                }
+            };
+            self.buffer = byte as u32;
+            self.count = 8;
+        }
+        self.count -= 1;
+        (self.buffer >> self.count) & 1
+    }
+
+    fn read_bits(&mut self, count: u8) -> u32 {
+        (0..count).fold(0, |value, _| value << 1 | self.read_bit())
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u8, String> {
+        let mut code = 0i32;
+        for length in 1..17 {
+            code = code << 1 | self.read_bit() as i32;
+            if code <= huffman.max_code[length] {
+                return huffman
+                    .values
+                    .get((code + huffman.offsets[length]) as usize)
+                    .cloned()
+                    .ok_or_else(|| "invalid Huffman code".to_string());
+            }
+        }
+        Err("invalid Huffman code".to_string())
+    }
+
+    /// Reads a coefficient with `size` bits and restores its sign.
+    fn receive_extend(&mut self, size: u8) -> i32 {
+        if size == 0 {
+            return 0;
+        }
+        let value = self.read_bits(size) as i32;
+        if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        }
+    }
+
+    /// Skips to the restart marker that follows the current interval.
+    fn restart(&mut self) -> Result<(), String> {
+        self.count = 0;
+        match self.data.get(self.offset..self.offset + 2) {
+            Some([0xff, 0xd0..=0xd7]) => {
+                self.offset += 2;
+                Ok(())
+            }
+            _ => Err("missing restart marker".to_string()),
+        }
+    }
+}
+
+/// The scaled cosines of the inverse DCT, `cosines[x][u]` is the weight of
+/// frequency `u` at position `x`.
+fn cosine_table() -> [[f32; 8]; 8] {
+    let mut cosines = [[0f32; 8]; 8];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, cosine) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5f32.sqrt() } else { 1.0 };
+            *cosine = scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos() / 2.0;
+        }
+    }
+    cosines
+}
+
+/// Transforms the coefficients of a block back into samples.
+fn inverse_dct(cosines: &[[f32; 8]; 8], coefficients: &[i32; 64], output: &mut [u8], stride: usize) {
+    // rows first, then columns
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            rows[y * 8 + x] = (0..8).map(|u| cosines[x][u] * coefficients[y * 8 + u] as f32).sum();
+        }
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            let value: f32 = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum();
+            output[y * stride + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> usize {
+    (data[offset] as usize) << 8 | data[offset + 1] as usize
+}
+
+/// Finds the end of the entropy coded data that starts at `offset`, which is the
+/// next marker other than a restart marker.
+fn scan_end(data: &[u8], offset: usize) -> usize {
+    let mut end = offset;
+    while end + 1 < data.len() {
+        if data[end] == 0xff && data[end + 1] != 0 && !(0xd0..=0xd7).contains(&data[end + 1]) {
+            return end;
+        }
+        end += 1;
+    }
+    data.len()
+}
+
+/// The part of the coefficients a scan contains.
+struct Scan {
+    /// The first and last coefficient in zig-zag order
+    start: usize,
+    end: usize,
+    /// The bit that was sent by the previous scan of these coefficients, 0 if this
+    /// is the first one
+    previous_bit: u8,
+    /// The lowest bit of the coefficients in this scan
+    bit: u8,
+}
+
+impl Scan {
+    fn decode_dc(
+        &self,
+        reader: &mut BitReader,
+        huffman: &Huffman,
+        block: &mut [i32],
+        prediction: &mut i32,
+    ) -> Result<(), String> {
+        if self.previous_bit == 0 {
+            let size = reader.decode(huffman)?;
+            *prediction += reader.receive_extend(size);
+            block[0] = *prediction << self.bit;
+        } else if reader.read_bit() == 1 {
+            block[0] |= 1 << self.bit;
+        }
+        Ok(())
+    }
+
+    /// Decodes the AC coefficients of a block. A run of blocks without any more
+    /// coefficients in this scan is counted in `end_of_band_run`.
+    fn decode_ac(
+        &self,
+        reader: &mut BitReader,
+        huffman: &Huffman,
+        block: &mut [i32],
+        end_of_band_run: &mut u32,
+    ) -> Result<(), String> {
+        let mut k = self.start.max(1);
+
+        if self.previous_bit == 0 {
+            if *end_of_band_run > 0 {
+                *end_of_band_run -= 1;
+                return Ok(());
+            }
+            while k <= self.end {
+                let symbol = reader.decode(huffman)?;
+                let (run, size) = ((symbol >> 4) as usize, symbol & 0x0f);
+                if size == 0 {
+                    if run < 15 {
+                        *end_of_band_run = (1 << run) + reader.read_bits(run as u8) - 1;
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run;
+                if k > 63 {
+                    return Err("invalid AC coefficient".to_string());
+                }
+                block[k] = reader.receive_extend(size) << self.bit;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        // a refinement sends one more bit of coefficients that are already
+        // nonzero and new coefficients that became nonzero with this bit
+        let positive = 1 << self.bit;
+        let negative = -1 << self.bit;
+        let refine = |reader: &mut BitReader, coefficient: &mut i32| {
+            if reader.read_bit() == 1 && *coefficient & positive == 0 {
+                *coefficient += if *coefficient >= 0 { positive } else { negative };
+            }
+        };
+
+        if *end_of_band_run == 0 {
+            while k <= self.end {
+                let symbol = reader.decode(huffman)?;
+                let (run, size) = ((symbol >> 4) as i32, symbol & 0x0f);
+                let mut value = 0;
+                if size != 0 {
+                    value = if reader.read_bit() == 1 { positive } else { negative };
+                } else if run < 15 {
+                    *end_of_band_run = (1 << run) + reader.read_bits(run as u8);
+                    break;
+                }
+
+                // skip `run` zero coefficients, refining the nonzero ones on the way
+                let mut zeros = run;
+                while k <= self.end {
+                    if block[k] != 0 {
+                        refine(reader, &mut block[k]);
+                    } else {
+                        zeros -= 1;
+                        if zeros < 0 {
+                            break;
+                        }
+                    }
+                    k += 1;
+                }
+                if value != 0 && k <= self.end {
+                    block[k] = value;
+                }
+                k += 1;
+            }
+        }
+
+        if *end_of_band_run > 0 {
+            while k <= self.end {
+                if block[k] != 0 {
+                    refine(reader, &mut block[k]);
+                }
+                k += 1;
+            }
+            *end_of_band_run -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// Everything that is known about the image while its segments are read.
+struct Decoder {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    quantization: [[u16; 64]; 4],
+    dc_tables: [Huffman; 4],
+    ac_tables: [Huffman; 4],
+    restart_interval: usize,
+    max_horizontal: usize,
+    max_vertical: usize,
+}
+
+impl Decoder {
+    fn read_quantization(&mut self, mut segment: &[u8]) -> Result<(), String> {
+        while !segment.is_empty() {
+            let precision = segment[0] >> 4;
+            let table = (segment[0] & 0x0f) as usize;
+            let size = if precision == 0 { 64 } else { 128 };
+            if table > 3 || segment.len() < 1 + size {
+                return Err("invalid quantization table".to_string());
+            }
+            for i in 0..64 {
+                self.quantization[table][i] = if precision == 0 {
+                    segment[1 + i] as u16
+                } else {
+                    u16_at(segment, 1 + i * 2) as u16
+                };
+            }
+            segment = &segment[1 + size..];
+        }
+        Ok(())
+    }
+
+    fn read_huffman(&mut self, mut segment: &[u8]) -> Result<(), String> {
+        while !segment.is_empty() {
+            if segment.len() < 17 {
+                return Err("invalid Huffman table".to_string());
+            }
+            let class = segment[0] >> 4;
+            let table = (segment[0] & 0x0f) as usize;
+            let count: usize = segment[1..17].iter().map(|&count| count as usize).sum();
+            if table > 3 || segment.len() < 17 + count {
+                return Err("invalid Huffman table".to_string());
+            }
+
+            let huffman = Huffman::new(&segment[1..17], &segment[17..17 + count]);
+            if class == 0 {
+                self.dc_tables[table] = huffman;
+            } else {
+                self.ac_tables[table] = huffman;
+            }
+            segment = &segment[17 + count..];
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self, segment: &[u8]) -> Result<(), String> {
+        if segment.len() < 6 || segment[0] != 8 {
+            return Err("only JPEGs with 8 bit samples are supported".to_string());
+        }
+        self.height = u16_at(segment, 1);
+        self.width = u16_at(segment, 3);
+        let count = segment[5] as usize;
+        if self.width == 0 || self.height == 0 {
+            return Err("invalid image size".to_string());
+        }
+        if count != 1 && count != 3 {
+            return Err(format!("JPEGs with {} components are not supported", count));
+        }
+        if segment.len() < 6 + count * 3 {
+            return Err("invalid frame header".to_string());
+        }
+
+        for i in 0..count {
+            let component = &segment[6 + i * 3..9 + i * 3];
+            let horizontal = (component[1] >> 4) as usize;
+            let vertical = (component[1] & 0x0f) as usize;
+            if !(1..=4).contains(&horizontal) || !(1..=4).contains(&vertical) || component[2] > 3 {
+                return Err("invalid frame header".to_string());
+            }
+            self.components.push(Component {
+                id: component[0],
+                horizontal,
+                vertical,
+                quantization: component[2] as usize,
+                blocks_per_line: 0,
+                coefficients: Vec::new(),
+                samples: Vec::new(),
+                stride: 0,
+            });
+        }
+
+        // the blocks of every component are padded to whole MCUs
+        self.max_horizontal = self.components.iter().map(|c| c.horizontal).max().unwrap_or(1);
+        self.max_vertical = self.components.iter().map(|c| c.vertical).max().unwrap_or(1);
+        let mcu_columns = self.width.div_ceil(8 * self.max_horizontal);
+        let mcu_rows = self.height.div_ceil(8 * self.max_vertical);
+        for component in &mut self.components {
+            component.blocks_per_line = mcu_columns * component.horizontal;
+            component.coefficients = vec![0; component.blocks_per_line * mcu_rows * component.vertical * 64];
+        }
+        Ok(())
+    }
+
+    /// Decodes a scan and returns the offset of the data that follows it.
+    ///
+    /// Baseline JPEGs have a single scan with all coefficients. Progressive JPEGs
+    /// spread the coefficients over several scans, each with a range of the
+    /// coefficients in zig-zag order and possibly only some of their bits.
+    fn read_scan(&mut self, data: &[u8], offset: usize) -> Result<usize, String> {
+        let length = u16_at(data, offset);
+        let header = data.get(offset + 2..offset + length).ok_or("invalid scan header")?;
+        if self.components.is_empty() {
+            return Err("the scan comes before the frame header".to_string());
+        }
+
+        // (index of the component, DC table, AC table) for every component in the scan
+        let count = header[0] as usize;
+        if header.len() < 4 + count * 2 {
+            return Err("invalid scan header".to_string());
+        }
+        let mut components = Vec::with_capacity(count);
+        for selector in header[1..1 + count * 2].chunks_exact(2) {
+            let index = self
+                .components
+                .iter()
+                .position(|component| component.id == selector[0])
+                .ok_or("the scan refers to an unknown component")?;
+            components.push((
+                index,
+                (selector[1] >> 4) as usize & 3,
+                (selector[1] & 0x0f) as usize & 3,
+            ));
+        }
+        let scan = Scan {
+            start: header[1 + count * 2] as usize,
+            end: (header[2 + count * 2] as usize).min(63),
+            previous_bit: header[3 + count * 2] >> 4,
+            bit: header[3 + count * 2] & 0x0f,
+        };
+
+        let start = offset + length;
+        let end = scan_end(data, start);
+        let mut reader = BitReader {
+            data: &data[start..end],
+            offset: 0,
+            buffer: 0,
+            count: 0,
+        };
+
+        // a scan with a single component isn't interleaved, its MCUs are single
+        // blocks that only cover the component's actual size
+        let (mcu_columns, mcu_rows) = if components.len() == 1 {
+            let component = &self.components[components[0].0];
+            (
+                (self.width * component.horizontal)
+                    .div_ceil(self.max_horizontal)
+                    .div_ceil(8),
+                (self.height * component.vertical)
+                    .div_ceil(self.max_vertical)
+                    .div_ceil(8),
+            )
+        } else {
+            (
+                self.width.div_ceil(8 * self.max_horizontal),
+                self.height.div_ceil(8 * self.max_vertical),
+            )
+        };
+
+        let mut predictions = vec![0i32; components.len()];
+        let mut end_of_band_run = 0;
+        for mcu in 0..mcu_columns * mcu_rows {
+            if self.restart_interval > 0 && mcu > 0 && mcu % self.restart_interval == 0 {
+                reader.restart()?;
+                predictions.iter_mut().for_each(|prediction| *prediction = 0);
+                end_of_band_run = 0;
+            }
+
+            let (mcu_x, mcu_y) = (mcu % mcu_columns, mcu / mcu_columns);
+            for (prediction, &(index, dc, ac)) in predictions.iter_mut().zip(&components) {
+                let component = &mut self.components[index];
+                let (blocks_x, blocks_y) = if components.len() == 1 {
+                    (1, 1)
+                } else {
+                    (component.horizontal, component.vertical)
+                };
+
+                for block_y in 0..blocks_y {
+                    for block_x in 0..blocks_x {
+                        let x = mcu_x * blocks_x + block_x;
+                        let y = mcu_y * blocks_y + block_y;
+                        let block = (y * component.blocks_per_line + x) * 64;
+                        let block = &mut component.coefficients[block..block + 64];
+
+                        if scan.start == 0 {
+                            scan.decode_dc(&mut reader, &self.dc_tables[dc], block, prediction)?;
+                        }
+                        if scan.end > 0 {
+                            scan.decode_ac(&mut reader, &self.ac_tables[ac], block, &mut end_of_band_run)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(end)
+    }
+
+    /// Dequantizes the coefficients of all blocks and transforms them into samples.
+    fn transform_blocks(&mut self) {
+        let cosines = cosine_table();
+        for component in &mut self.components {
+            let quantization = &self.quantization[component.quantization];
+            component.stride = component.blocks_per_line * 8;
+            component.samples = vec![0; component.coefficients.len()];
+
+            for (i, block) in component.coefficients.chunks_exact(64).enumerate() {
+                let mut coefficients = [0i32; 64];
+                for k in 0..64 {
+                    coefficients[ZIGZAG[k]] = block[k] * quantization[k] as i32;
+                }
+
+                let x = i % component.blocks_per_line * 8;
+                let y = i / component.blocks_per_line * 8;
+                inverse_dct(
+                    &cosines,
+                    &coefficients,
+                    &mut component.samples[y * component.stride + x..],
+                    component.stride,
+                );
+            }
+        }
+    }
+
+    /// Upsamples the components and converts them to RGBA.
+    fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.width * self.height * 4);
+        let sample = |component: &Component, x: usize, y: usize| {
+            let x = x * component.horizontal / self.max_horizontal;
+            let y = y * component.vertical / self.max_vertical;
+            component.samples[y * component.stride + x] as f32
+        };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if let [luma, chroma_blue, chroma_red] = &self.components[..] {
+                    let luma = sample(luma, x, y);
+                    let blue = sample(chroma_blue, x, y) - 128.0;
+                    let red = sample(chroma_red, x, y) - 128.0;
+                    rgba.extend_from_slice(&[
+                        (luma + 1.402 * red).round().clamp(0.0, 255.0) as u8,
+                        (luma - 0.344_136 * blue - 0.714_136 * red).round().clamp(0.0, 255.0) as u8,
+                        (luma + 1.772 * blue).round().clamp(0.0, 255.0) as u8,
+                        255,
+                    ]);
+                } else {
+                    let gray = sample(&self.components[0], x, y) as u8;
+                    rgba.extend_from_slice(&[gray, gray, gray, 255]);
+                }
+            }
+        }
+        rgba
+    }
+}
+
+/// Reads the size of a JPEG from its frame header without decoding the image.
+pub fn size(data: &[u8]) -> Result<(u32, u32), String> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return Err("not a JPEG".to_string());
+    }
+
+    let mut offset = 2;
+    loop {
+        while data.get(offset) == Some(&0xff) && data.get(offset + 1) == Some(&0xff) {
+            offset += 1;
+        }
+        let marker = match data.get(offset..offset + 4) {
+            Some(&[0xff, marker, _, _]) => marker,
+            _ => return Err("the JPEG has no frame header".to_string()),
+        };
+        offset += 2;
+
+        match marker {
+            0xd0..=0xd7 | 0x01 => continue,
+            // the frame header comes before the first scan
+            0xd9 | 0xda => return Err("the JPEG has no frame header".to_string()),
+            0xc0..=0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                let header = data.get(offset + 2..offset + 7).ok_or("invalid frame header")?;
+                return Ok((u16_at(header, 3) as u32, u16_at(header, 1) as u32));
+            }
+            _ => offset += u16_at(data, offset),
+        }
+    }
+}
+
+/// Decodes a JPEG into RGBA pixels.
+pub fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return Err("not a JPEG".to_string());
+    }
+
+    let mut decoder = Decoder {
+        width: 0,
+        height: 0,
+        components: Vec::new(),
+        quantization: [[0; 64]; 4],
+        dc_tables: Default::default(),
+        ac_tables: Default::default(),
+        restart_interval: 0,
+        max_horizontal: 1,
+        max_vertical: 1,
+    };
+
+    let mut offset = 2;
+    let mut has_scan = false;
+    loop {
+        // markers may be padded with any number of 0xff bytes
+        while data.get(offset) == Some(&0xff) && data.get(offset + 1) == Some(&0xff) {
+            offset += 1;
+        }
+        let marker = match data.get(offset..offset + 2) {
+            Some(&[0xff, marker]) => marker,
+            _ if has_scan => break,
+            _ => return Err("unexpected end of data".to_string()),
+        };
+        offset += 2;
+
+        match marker {
+            0xd9 => break,
+            0xd0..=0xd7 | 0x01 => continue,
+            _ => {}
+        }
+
+        let length = data
+            .get(offset..offset + 2)
+            .map(|_| u16_at(data, offset))
+            .filter(|&length| length >= 2 && offset + length <= data.len())
+            .ok_or("invalid segment length")?;
+        let segment = &data[offset + 2..offset + length];
+
+        match marker {
+            0xdb => decoder.read_quantization(segment)?,
+            0xc4 => decoder.read_huffman(segment)?,
+            0xc0..=0xc2 => decoder.read_frame(segment)?,
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err("lossless and arithmetic coded JPEGs are not supported".to_string())
+            }
+            0xdd if segment.len() >= 2 => decoder.restart_interval = u16_at(segment, 0),
+            0xda => {
+                offset = decoder.read_scan(data, offset)?;
+                has_scan = true;
+                continue;
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+
+    if !has_scan {
+        return Err("the JPEG has no image data".to_string());
+    }
+    decoder.transform_blocks();
+    Ok((decoder.width as u32, decoder.height as u32, decoder.to_rgba()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(output: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+        output.extend_from_slice(&[0xff, marker]);
+        output.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        output.extend_from_slice(payload);
+    }
+
+    /// Builds a JPEG whose blocks only have a DC coefficient. All quantization
+    /// values are 1, the DC table has 1 bit codes for the categories 0 and 10 and
+    /// the AC table only has end of block.
+    fn build(frame: u8, width: u16, height: u16, components: &[u8], entropy_coded: &[u8]) -> Vec<u8> {
+        let mut output = vec![0xff, 0xd8];
+
+        let mut quantization = vec![0];
+        quantization.extend_from_slice(&[1; 64]);
+        segment(&mut output, 0xdb, &quantization);
+
+        let mut header = vec![8];
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&width.to_be_bytes());
+        header.push(components.len() as u8);
+        for (i, &sampling) in components.iter().enumerate() {
+            header.extend_from_slice(&[i as u8 + 1, sampling, 0]);
+        }
+        segment(&mut output, frame, &header);
+
+        let mut counts = [0; 16];
+        counts[0] = 2;
+        let mut dc = vec![0x00];
+        dc.extend_from_slice(&counts);
+        dc.extend_from_slice(&[0, 10]);
+        segment(&mut output, 0xc4, &dc);
+        counts[0] = 1;
+        let mut ac = vec![0x10];
+        ac.extend_from_slice(&counts);
+        ac.push(0);
+        segment(&mut output, 0xc4, &ac);
+
+        let mut scan = vec![components.len() as u8];
+        for i in 0..components.len() {
+            scan.extend_from_slice(&[i as u8 + 1, 0x00]);
+        }
+        scan.extend_from_slice(&[0, 63, 0]);
+        segment(&mut output, 0xda, &scan);
+        output.extend_from_slice(entropy_coded);
+        output.extend_from_slice(&[0xff, 0xd9]);
+        output
+    }
+
+    #[test]
+    fn grayscale_blocks_are_decoded() {
+        // a DC of 576 and end of block, 576 / 8 + 128 = 200
+        let data = build(0xc0, 8, 8, &[0x11], &[0xc8, 0x0f]);
+        assert_eq!(size(&data), Ok((8, 8)));
+
+        let (width, height, rgba) = decode(&data).unwrap();
+        assert_eq!((width, height), (8, 8));
+        assert!(rgba.chunks(4).all(|pixel| pixel == [200, 200, 200, 255]));
+    }
+
+    #[test]
+    fn subsampled_chroma_is_upsampled() {
+        // four luma blocks with a DC of 576, blue chroma with 512 and red chroma with 0
+        let data = build(0xc0, 16, 16, &[0x22, 0x11, 0x11], &[0xc8, 0x00, 0x30, 0x00]);
+        let (width, height, rgba) = decode(&data).unwrap();
+        assert_eq!((width, height), (16, 16));
+        for pixel in rgba.chunks(4) {
+            assert_eq!(pixel[0], 200);
+            assert!((pixel[1] as i32 - 178).abs() <= 1, "{:?}", pixel);
+            assert_eq!(pixel[2..], [255, 255]);
+        }
+    }
+
+    #[test]
+    fn images_can_be_cropped_to_their_size() {
+        let data = build(0xc0, 5, 3, &[0x11], &[0xc8, 0x0f]);
+        let (width, height, rgba) = decode(&data).unwrap();
+        assert_eq!((width, height, rgba.len()), (5, 3, 5 * 3 * 4));
+    }
+
+    #[test]
+    fn unsupported_jpegs_are_errors() {
+        // lossless, arithmetic coded and with two components
+        assert!(decode(&build(0xc3, 8, 8, &[0x11], &[0xc8, 0x0f])).is_err());
+        assert!(decode(&build(0xc9, 8, 8, &[0x11], &[0xc8, 0x0f])).is_err());
+        assert!(decode(&build(0xc0, 8, 8, &[0x11, 0x11], &[0xc8, 0x0f])).is_err());
+    }
+
+    #[test]
+    fn invalid_data_is_an_error() {
+        assert!(decode(b"\x89PNG").is_err());
+        assert!(size(b"\x89PNG").is_err());
+        assert!(decode(&build(0xc0, 0, 8, &[0x11], &[0xc8, 0x0f])).is_err());
+
+        let data = build(0xc0, 8, 8, &[0x11], &[0xc8, 0x0f]);
+        assert!(decode(&data[..40]).is_err());
+        assert!(size(&data[..20]).is_err());
+    }
+}
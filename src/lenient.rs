@@ -0,0 +1,26 @@
+//! Lenient decoding of gifs that are truncated or partly corrupt.
+//!
+//! Gifs from flaky downloads often end in the middle of a frame. By default such a
+//! gif can't be decoded at all. With `Limits.lenient` decoding stops at the first
+//! error instead, keeps the frames that were decoded completely and returns what
+//! went wrong as a warning together with them.
+
+#[cfg(feature = "wasm")]
+use errors;
+use limits::Limits;
+use std::fmt::Display;
+
+/// Handles an error that came up after `frames` frames were decoded completely.
+///
+/// If `limits` allow lenient decoding the error is turned into a warning for the
+/// caller and decoding should stop with the frames so far, otherwise it's passed
+/// on.
+pub fn stop<E: Display>(limits: &Limits, frames: usize, error: E) -> Result<String, String> {
+    if !limits.lenient {
+        #[cfg(feature = "wasm")]
+        errors::recoverable();
+        return Err(error.to_string());
+    }
+
+    Ok(format!("decoding stopped after {} frames: {}", frames, error))
+}
@@ -1,26 +1,81 @@
+// The exported functions mirror the JavaScript call signature, so they end up
+// with quite a few arguments.
+#![allow(clippy::too_many_arguments)]
+
+extern crate color_quant;
+#[cfg(feature = "wasm")]
 extern crate console_error_panic_hook;
-extern crate gif;
+extern crate gif_codec as gif;
+#[cfg(feature = "wasm")]
 extern crate wasm_bindgen;
 
-use gif::{ColorOutput, DecodeOptions, Decoder, Encoder, Frame, Repeat};
-use std::vec::Vec;
-use wasm_bindgen::prelude::*;
-
-#[wasm_bindgen]
-extern "C" {
-    // Access console.log() from the wasm module
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-}
-
-/// Tuples are apparently not supported by wasm-bindgen atm
-/// so we'll use our own datastructure.
-#[wasm_bindgen]
-pub struct Dimension {
-    pub width: u16,
-    pub height: u16,
-}
-
+#[cfg(feature = "wasm")]
+mod apng;
+mod compose;
+mod encode;
+#[cfg(feature = "wasm")]
+mod errors;
+#[cfg(feature = "wasm")]
+mod filters;
+#[cfg(feature = "wasm")]
+mod font;
+#[cfg(feature = "wasm")]
+mod indexed;
+#[cfg(feature = "wasm")]
+mod iterator;
+#[cfg(feature = "wasm")]
+mod job;
+#[cfg(feature = "wasm")]
+mod jpeg;
+mod lenient;
+mod limits;
+#[cfg(feature = "wasm")]
+mod logging;
+mod lossy;
+pub mod native;
+mod optimize;
+#[cfg(feature = "wasm")]
+mod pipeline;
+#[cfg(feature = "wasm")]
+mod png;
+mod quantize;
+#[cfg(feature = "wasm")]
+mod repair;
+#[cfg(feature = "wasm")]
+mod reverse;
+mod scan;
+#[cfg(feature = "wasm")]
+mod session;
+mod stats;
+#[cfg(feature = "wasm")]
+mod stream;
+#[cfg(feature = "wasm")]
+mod text;
+mod timeline;
+#[cfg(feature = "wasm")]
+mod types;
+#[cfg(feature = "wasm")]
+mod validate;
+#[cfg(feature = "wasm")]
+mod vp8;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "wasm")]
+mod webp;
+#[cfg(feature = "wasm")]
+mod zlib;
+
+use compose::{Compositor, FrameRef};
+use gif::Decoder;
+use stats::Phase;
+
+pub use encode::EncodeOptions;
+pub use limits::Limits;
+pub use quantize::{Dithering, Quantizer};
+#[cfg(feature = "wasm")]
+pub use wasm::*;
+
+#[derive(Clone)]
 struct FrameData {
     pub width: u16,
     pub height: u16,
@@ -28,30 +83,31 @@ struct FrameData {
     pub delay: u16,
 }
 
-/// A small function that decodes a gif and returns its dimensions.
-/// Input is a u8 slice which corresponds to a Uint8Array in JavaScript.
-#[wasm_bindgen]
-pub fn get_dimension(data: &[u8]) -> Dimension {
-    let mut decoder = DecodeOptions::new();
-
-    // Configure the decoder such that it will expand the image to RGBA.
-    decoder.set_color_output(ColorOutput::RGBA);
-
-    // Read the file header
-    let decoder = decoder.read_info(data).unwrap();
-
-    Dimension {
-        width: decoder.width(),
-        height: decoder.height(),
+impl FrameData {
+    /// Borrows the frame, e.g. to encode it.
+    fn as_ref(&self) -> FrameRef<'_> {
+        FrameRef {
+            width: self.width,
+            height: self.height,
+            rgba: &self.rgba,
+            delay: self.delay,
+        }
     }
 }
 
-// Instantiate a gif reader from the byte slice
-fn decode_data(data: &[u8]) -> Decoder<&[u8]> {
-    let mut decoder = DecodeOptions::new();
-    decoder.set_color_output(ColorOutput::RGBA);
+/// The composed frames of an animation that was decoded from another format.
+#[cfg(feature = "wasm")]
+struct Animation {
+    pub width: u16,
+    pub height: u16,
+    /// How often the animation is played, 0 means forever
+    pub plays: u32,
+    pub frames: Vec<FrameData>,
+}
 
-    decoder.read_info(data).unwrap()
+/// Splits a color given as `0xRRGGBB` into its channels.
+fn rgb(color: u32) -> [u8; 3] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
 }
 
 /// Reads global metadata from the gif like
@@ -66,134 +122,114 @@ fn metadata(reader: &Decoder<&[u8]>) -> (u16, u16, Vec<u8>) {
     (width, height, global_palette)
 }
 
-/// Extract all the frames from the gif
-///
-/// The `reader` has to be mutable because `read_next_frame()` probably has some state that
-/// is mutated like "what's the current frame". The returned vector contains all frames fully
-/// decoded. Gifs can sometime contain only partial images of just the areas that change from
-/// one frame to the next. This may cause reversed gifs to look funny because only parts of the
-/// image are rendered.
-fn collect_frames(reader: &mut Decoder<&[u8]>, width: u16, height: u16) -> Vec<FrameData> {
+/// The part of `collect_frames_with` that doesn't depend on JavaScript, errors are
+/// turned into `E` with `error`. Returns the frames together with the warnings of
+/// lenient decoding.
+fn read_frames<E, M, F>(
+    reader: &mut Decoder<&[u8]>,
+    width: u16,
+    height: u16,
+    limits: &Limits,
+    error: M,
+    mut after_frame: F,
+) -> Result<(Vec<FrameData>, Vec<String>), E>
+where
+    M: Fn(&str) -> E,
+    F: FnMut(usize) -> Result<(), E>,
+{
     let mut frames = Vec::new();
-    let mut full_frame: Vec<u8> = Vec::new();
-
-    // allocate enough memory to fit in a full sized frame
-    // width * height is the number of pixels and times 4 for the color channels (r, g, b, and a)
-    full_frame.resize((width as usize) * (height as usize) * 4_usize, 0);
-
-    // extract the single frames from the gif
-    while let Some(frame) = reader.read_next_frame().unwrap() {
-        // todo: try to get rid of this copy
-        let buffer = frame.buffer.to_vec();
-
-        // some frames may be smaller than the whole image. we need to calculate
-        // the correct index to map the frame to the correct parts of the full_frame.
-        //
-        //  full_frame   -   width
-        // +-------------------------------------------------------+
-        // | frame  top        frame width                         |
-        // |  left   +---------------------------------------+     | full_frame
-        // |         |                                 frame |     | height
-        // |         |                                height |     |
-        // |         +---------------------------------------+     |
-        // +-------------------------------------------------------+
-        //
-        // see also the index calculation inside the loop.
-        // to calculate the correct index in the full_frame buffer from the index `i`
-        // in the frame buffer we first have to add frame `top` number of lines:
-        //
-        //        top * (width as usize)
-        //
-        // this is done in the constant_offset. For every full line inside the frame -
-        // determined with `i / frame_width` - we add another line:
-        //
-        //        (i / frame_width) * (width as usize)
-        //
-        // all that is left to do now is add the constant left offset and advance the
-        // same number of pixels in the full_frame buffer as we do in the frame buffer,
-        // that is the remainder of the division above:
-        //
-        //         (i % frame_width)
-        let left = frame.left as usize;
-        let top = frame.top as usize;
-        let frame_width = frame.width as usize;
-        let constant_offset = top * (width as usize) + left;
-
-        // copy the current frame buffer over the full_frame buffer, but only if the
-        // current pixel is not opaque AND we have a full pixel. That last part should
-        // always be true, but it's there anyway just in case.
-        for (i, pixel) in buffer.chunks(4).enumerate() {
-            if pixel.len() == 4 && pixel[3] != 0 {
-                let index =
-                    constant_offset + (i / frame_width) * (width as usize) + (i % frame_width);
-                full_frame[index * 4] = pixel[0];
-                full_frame[index * 4 + 1] = pixel[1];
-                full_frame[index * 4 + 2] = pixel[2];
-                full_frame[index * 4 + 3] = pixel[3];
-            }
-        }
-
-        // this copy is necessary because we need the full_frame buffer to put (parts of) the next
-        // frame on top of the existing buffer data.
-        let frame_data = FrameData {
-            width,
-            height,
-            rgba: full_frame.clone(),
-            delay: frame.delay,
-        };
-        frames.push(frame_data);
-    }
+    let frame_size = width as usize * height as usize * 4;
+    let warnings = compose_frames(reader, width, height, limits, &error, |frame| {
+        limits
+            .check_memory(frames.len() + 1, width, height)
+            .map_err(|e| error(&e))?;
+        frames.push(frame);
+        stats::frame_bytes(frames.len() * frame_size);
+        after_frame(frames.len())
+    })?;
+
+    Ok((frames, warnings))
+}
 
-    frames
+/// Composes the frames of the gif one after the other and passes them to
+/// `on_frame` without keeping them. Decoding stops as soon as `on_frame` fails or
+/// the gif exceeds the limits. Returns the warnings of lenient decoding.
+fn compose_frames<E, M, F>(
+    reader: &mut Decoder<&[u8]>,
+    width: u16,
+    height: u16,
+    limits: &Limits,
+    error: M,
+    mut on_frame: F,
+) -> Result<Vec<String>, E>
+where
+    M: Fn(&str) -> E,
+    F: FnMut(FrameData) -> Result<(), E>,
+{
+    decode_indexed(
+        reader,
+        width,
+        height,
+        limits,
+        None,
+        error,
+        |compositor, frame, buffer| on_frame(stats::time(Phase::Compose, || compositor.compose(frame, buffer))),
+    )
 }
 
-/// Creates a gif from a set of frames and a color palette
-///
-/// The `global_palette` may be an empty vector.
-fn gif_from_frames(
-    frames: &mut[FrameData],
+/// Decodes the frames of the gif one after the other and passes them to `on_frame`
+/// together with the compositor that draws them and their palette indices.
+/// Decoding stops after the frame with the index `last` if it is given. Returns
+/// the warnings of lenient decoding.
+fn decode_indexed<E, M, F>(
+    reader: &mut Decoder<&[u8]>,
     width: u16,
     height: u16,
-    global_palette: Vec<u8>,
-    id: &str,
-    report: &js_sys::Function,
-) -> Vec<u8> {
+    limits: &Limits,
+    last: Option<usize>,
+    error: M,
+    mut on_frame: F,
+) -> Result<Vec<String>, E>
+where
+    M: Fn(&str) -> E,
+    F: FnMut(&mut Compositor, &gif::Frame, &[u8]) -> Result<(), E>,
+{
+    limits.check_size(width, height).map_err(|e| error(&e))?;
+
+    let mut compositor = Compositor::for_decoder(reader);
     let mut buffer = Vec::new();
-    {
-        let mut encoder = Encoder::new(&mut buffer, width, height, &global_palette).unwrap();
-        encoder.set_repeat(Repeat::Infinite).unwrap();
-
-        for (i, frame) in frames.iter().enumerate() {
-            let delay = frame.delay;
-            let mut frame = Frame::from_rgba(frame.width, frame.height, &mut frame.rgba.to_vec());
-            frame.delay = delay;
-            encoder.write_frame(&frame).unwrap();
+    let mut count = 0;
+    let mut warnings = Vec::new();
+
+    // extract the single frames from the gif. the pixels of all frames are read into
+    // the same buffer, only the frame information without any pixels is copied
+    loop {
+        #[cfg(feature = "wasm")]
+        errors::at("decode", Some(count));
+        let frame = match reader.next_frame_info() {
+            Ok(Some(frame)) => frame.clone(),
+            Ok(None) => break,
+            Err(e) => {
+                warnings.push(lenient::stop(limits, count, e).map_err(|e| error(&e))?);
+                break;
+            }
+        };
+        if !limits.keeps_frame(count + 1).map_err(|e| error(&e))? {
+            break;
+        }
+        limits.check_size(frame.width, frame.height).map_err(|e| error(&e))?;
+        buffer.resize(reader.buffer_size(), 0);
+        if let Err(e) = stats::time(Phase::Decode, || reader.read_into_buffer(&mut buffer)) {
+            warnings.push(lenient::stop(limits, count, e).map_err(|e| error(&e))?);
+            break;
+        }
 
-            report.call2(&JsValue::NULL, &JsValue::from(id), &JsValue::from(i + 1)).unwrap();
+        count += 1;
+        on_frame(&mut compositor, &frame, &buffer)?;
+        if last == Some(count - 1) {
+            break;
         }
     }
 
-    buffer
-}
-
-/// Reverses a gif
-#[wasm_bindgen]
-pub fn reverse_gif(id: &str, name: &str, data: &[u8], register: &js_sys::Function, report: &js_sys::Function) -> Vec<u8> {
-    console_error_panic_hook::set_once();
-
-    log("enter");
-    let mut reader = decode_data(data);
-
-    log("read metadata");
-    let (width, height, global_palette) = metadata(&reader);
-
-    log("read frames");
-    let mut frames = collect_frames(&mut reader, width, height);
-
-    register.call3(&JsValue::NULL, &JsValue::from(id), &JsValue::from(name), &JsValue::from(frames.len())).unwrap();
-
-    frames.reverse();
-
-    log("write buffer");
-    gif_from_frames(&mut frames, width, height, global_palette, id, report)
+    Ok(warnings)
 }
@@ -0,0 +1,214 @@
+//! Limits that stop decoding gifs that would need too much memory.
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Upper bounds for the gifs that are decoded and how defects in them are
+/// handled.
+///
+/// A gif only takes a few bytes to claim a canvas of 65535x65535 pixels, which
+/// would need 16 GB once it's decoded. Gifs that exceed a limit are rejected with
+/// an error before the memory is allocated. `undefined` removes a limit.
+///
+/// Functions that only decode take the limits as their last argument, the ones
+/// that produce a gif take them from `EncodeOptions.set_limits`.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy)]
+pub struct Limits {
+    /// Maximum number of pixels of the canvas and of every frame, 50 million by
+    /// default
+    pub max_pixels: Option<u32>,
+    /// Maximum number of frames, unlimited by default
+    pub max_frames: Option<u32>,
+    /// Whether gifs with more than `max_frames` frames are cut off after that many
+    /// frames instead of being rejected, `false` by default
+    pub truncate_frames: bool,
+    /// Maximum number of bytes the decoded frames may take up together, 2 GB by
+    /// default
+    pub max_memory: Option<u32>,
+    /// Whether decoding stops at the first defect and keeps the frames before it
+    /// instead of failing, `false` by default. What went wrong is returned as
+    /// warnings together with the frames: by `take_warnings()` of the options a
+    /// gif was produced with, `warnings()` of the decoding objects and the
+    /// `warnings` of the objects that `export_raw_frames` and `to_spritesheet`
+    /// return.
+    pub lenient: bool,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Limits {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_pixels: Some(50_000_000),
+            max_frames: None,
+            truncate_frames: false,
+            max_memory: Some(1 << 31),
+            lenient: false,
+        }
+    }
+}
+
+impl Limits {
+    /// Fails if an image of `width` x `height` pixels exceeds the limits. Checked
+    /// for the canvas right after reading the header of a gif and for every frame,
+    /// before its pixels are decoded.
+    pub fn check_size(&self, width: u16, height: u16) -> Result<(), String> {
+        self.check_pixels(width as u32, height as u32)
+    }
+
+    /// Like `check_size` for images that can be larger than a gif, like sprite
+    /// sheets.
+    pub fn check_pixels(&self, width: u32, height: u32) -> Result<(), String> {
+        let pixels = width as u64 * height as u64;
+
+        if let Some(max_pixels) = self.max_pixels {
+            if pixels > max_pixels as u64 {
+                return Err(format!(
+                    "{}x{} pixels exceed the limit of {} pixels",
+                    width, height, max_pixels
+                ));
+            }
+        }
+        if let Some(max_memory) = self.max_memory {
+            if pixels.saturating_mul(4) > max_memory as u64 {
+                return Err(format!(
+                    "a frame of {}x{} pixels needs {} bytes which exceeds the memory limit of {} bytes",
+                    width,
+                    height,
+                    pixels.saturating_mul(4),
+                    max_memory
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns how many frames of a gif with `frames` frames are decoded, or fails
+    /// if the gif is rejected. Checked as soon as the number of frames is known,
+    /// before any frame is decoded.
+    pub fn check_frames(&self, frames: usize) -> Result<usize, String> {
+        self.keeps_frame(frames).map(|_| match self.max_frames {
+            Some(max_frames) if self.truncate_frames => frames.min(max_frames as usize),
+            _ => frames,
+        })
+    }
+
+    /// Whether the frame with the number `frame`, counting from 1, is decoded.
+    /// Returns `false` once gifs are cut off after `max_frames` frames and fails if
+    /// they are rejected instead. Checked before every frame that is decoded.
+    pub fn keeps_frame(&self, frame: usize) -> Result<bool, String> {
+        match self.max_frames {
+            Some(max_frames) if frame as u64 > max_frames as u64 => {
+                if self.truncate_frames {
+                    Ok(false)
+                } else {
+                    Err(format!("the gif has more than {} frames", max_frames))
+                }
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Fails if `frames` decoded frames of `width` x `height` pixels that are kept
+    /// in memory at the same time exceed the limits.
+    pub fn check_memory(&self, frames: usize, width: u16, height: u16) -> Result<(), String> {
+        let memory = (frames as u64).saturating_mul(width as u64 * height as u64 * 4);
+        match self.max_memory {
+            Some(max_memory) if memory > max_memory as u64 => Err(format!(
+                "{} frames of {}x{} pixels exceed the memory limit of {} bytes",
+                frames, width, height, max_memory
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_canvas_size_is_checked_against_the_pixel_limit() {
+        let limits = Limits {
+            max_pixels: Some(100),
+            ..Limits::default()
+        };
+        assert!(limits.check_size(10, 10).is_ok());
+        assert!(limits.check_size(10, 11).is_err());
+        assert!(limits.check_size(65535, 65535).is_err());
+
+        let unlimited = Limits {
+            max_pixels: None,
+            max_memory: None,
+            ..Limits::default()
+        };
+        assert!(unlimited.check_size(65535, 65535).is_ok());
+    }
+
+    #[test]
+    fn a_single_frame_has_to_fit_into_the_memory_limit() {
+        let limits = Limits {
+            max_pixels: None,
+            max_memory: Some(400),
+            ..Limits::default()
+        };
+        assert!(limits.check_pixels(10, 10).is_ok());
+        assert!(limits.check_pixels(10, 11).is_err());
+        assert!(limits.check_pixels(u32::MAX, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn gifs_with_too_many_frames_are_rejected() {
+        let limits = Limits {
+            max_frames: Some(3),
+            ..Limits::default()
+        };
+        assert_eq!(limits.check_frames(3), Ok(3));
+        assert!(limits.check_frames(4).is_err());
+        assert_eq!(limits.keeps_frame(3), Ok(true));
+        assert!(limits.keeps_frame(4).is_err());
+    }
+
+    #[test]
+    fn gifs_with_too_many_frames_can_be_cut_off() {
+        let limits = Limits {
+            max_frames: Some(3),
+            truncate_frames: true,
+            ..Limits::default()
+        };
+        assert_eq!(limits.check_frames(2), Ok(2));
+        assert_eq!(limits.check_frames(10), Ok(3));
+        assert_eq!(limits.keeps_frame(3), Ok(true));
+        assert_eq!(limits.keeps_frame(4), Ok(false));
+    }
+
+    #[test]
+    fn the_frame_count_is_unlimited_by_default() {
+        assert_eq!(Limits::default().check_frames(100_000), Ok(100_000));
+        assert_eq!(Limits::default().keeps_frame(usize::MAX), Ok(true));
+    }
+
+    #[test]
+    fn all_decoded_frames_together_have_to_fit_into_the_memory_limit() {
+        let limits = Limits {
+            max_memory: Some(800),
+            ..Limits::default()
+        };
+        assert!(limits.check_memory(2, 10, 10).is_ok());
+        assert!(limits.check_memory(3, 10, 10).is_err());
+        assert!(limits.check_memory(usize::MAX, 65535, 65535).is_err());
+
+        let unlimited = Limits {
+            max_memory: None,
+            ..Limits::default()
+        };
+        assert!(unlimited.check_memory(usize::MAX, 65535, 65535).is_ok());
+    }
+}
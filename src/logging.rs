@@ -0,0 +1,85 @@
+//! Log messages about what the module is doing.
+//!
+//! Nothing is logged by default. `set_log_level` decides how much is logged and
+//! `set_log_sink` where it goes, the browser console if there is no sink.
+
+use std::cell::{Cell, RefCell};
+use types::LogSink;
+use wasm_bindgen::prelude::*;
+
+/// How much is logged, every level includes the ones before it.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    #[default]
+    Off,
+    /// Errors that are thrown
+    Error,
+    /// What an operation did, e.g. how many frames it decoded
+    Info,
+    /// The steps of an operation
+    Debug,
+}
+
+impl LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+thread_local! {
+    static LEVEL: Cell<LogLevel> = const { Cell::new(LogLevel::Off) };
+    static SINK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console, js_name = log)]
+    fn console_log(s: &str);
+}
+
+/// Logs all messages up to `level` from now on, `LogLevel.Off` by default
+#[wasm_bindgen]
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.with(|current| current.set(level));
+}
+
+/// Passes log messages to `sink` instead of the browser console. The sink is
+/// called with the level as `"error"`, `"info"` or `"debug"` and the message. Pass
+/// `undefined` to log to the console again.
+#[wasm_bindgen]
+pub fn set_log_sink(sink: Option<LogSink>) {
+    SINK.with(|current| *current.borrow_mut() = sink.map(js_sys::Function::from));
+}
+
+/// Logs `message` if the log level includes `level`.
+pub fn log(level: LogLevel, message: &str) {
+    if level == LogLevel::Off || level > LEVEL.with(|current| current.get()) {
+        return;
+    }
+
+    SINK.with(|sink| match *sink.borrow() {
+        // a failing sink must not break the operation that logs
+        Some(ref sink) => {
+            let _ = sink.call2(&JsValue::NULL, &JsValue::from(level.name()), &JsValue::from(message));
+        }
+        None => console_log(&format!("[{}] {}", level.name(), message)),
+    });
+}
+
+pub fn error(message: &str) {
+    log(LogLevel::Error, message);
+}
+
+pub fn info(message: &str) {
+    log(LogLevel::Info, message);
+}
+
+pub fn debug(message: &str) {
+    log(LogLevel::Debug, message);
+}
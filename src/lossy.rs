@@ -0,0 +1,188 @@
+//! Lossy compression by bending pixels towards what the LZW compressor has seen.
+//!
+//! LZW stores a frame as strings of palette indices it has seen before. Whenever
+//! the next pixel doesn't continue the current string, a new code is written.
+//! Like gifsicle's `--lossy`, pixels are replaced by a similar color if that color
+//! continues the current string, which results in longer strings and fewer codes.
+//! The compressor is simulated here and the frame is changed before it's actually
+//! compressed.
+
+/// Codes that can be in the LZW dictionary at the same time, it's reset after that
+const MAX_CODES: usize = 4096;
+/// The 256 single pixel strings and the clear and end codes come first
+const FIRST_CODE: usize = 258;
+const NONE: u16 = u16::MAX;
+
+/// The strings of the simulated LZW dictionary as a tree, every code is a string
+/// that continues the string of its parent by one pixel.
+struct Dictionary {
+    first_child: Vec<u16>,
+    next_sibling: Vec<u16>,
+    /// The last pixel of every string
+    index: Vec<u8>,
+    next_code: usize,
+}
+
+impl Dictionary {
+    fn new() -> Dictionary {
+        Dictionary {
+            first_child: vec![NONE; MAX_CODES],
+            next_sibling: vec![NONE; MAX_CODES],
+            index: (0..MAX_CODES).map(|code| code as u8).collect(),
+            next_code: FIRST_CODE,
+        }
+    }
+
+    fn reset(&mut self) {
+        for child in self.first_child.iter_mut() {
+            *child = NONE;
+        }
+        self.next_code = FIRST_CODE;
+    }
+
+    fn children(&self, code: u16) -> Children<'_> {
+        Children {
+            dictionary: self,
+            next: self.first_child[code as usize],
+        }
+    }
+
+    /// Adds the string of `code` followed by `index`, the compressor does this
+    /// whenever it writes a code.
+    fn add(&mut self, code: u16, index: u8) {
+        if self.next_code == MAX_CODES {
+            self.reset();
+            return;
+        }
+        let new = self.next_code as u16;
+        self.index[new as usize] = index;
+        self.next_sibling[new as usize] = self.first_child[code as usize];
+        self.first_child[code as usize] = new;
+        self.next_code += 1;
+    }
+}
+
+struct Children<'a> {
+    dictionary: &'a Dictionary,
+    next: u16,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        if self.next == NONE {
+            return None;
+        }
+        let code = self.next;
+        self.next = self.dictionary.next_sibling[code as usize];
+        Some(code)
+    }
+}
+
+/// Replaces pixels of a frame with similar colors where that makes the LZW strings
+/// longer. `lossiness` goes from 0, which changes nothing, to 200, which allows
+/// quite visible differences. Transparent pixels are never changed and no pixel
+/// becomes transparent.
+pub fn apply(indices: &mut [u8], palette: &[u8], transparent: Option<u8>, lossiness: u8) {
+    if lossiness == 0 || indices.is_empty() {
+        return;
+    }
+
+    // the squared distance of two colors that may replace each other
+    let max_distance = (lossiness as u32 / 2).pow(2) * 3;
+    let color = |index: u8| {
+        let i = index as usize * 3;
+        palette.get(i..i + 3).map_or([0, 0, 0], |rgb| [rgb[0], rgb[1], rgb[2]])
+    };
+    let distance = |a: u8, b: u8| {
+        let (a, b) = (color(a), color(b));
+        (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2) as u32).sum::<u32>()
+    };
+
+    let mut dictionary = Dictionary::new();
+    let mut current = indices[0] as u16;
+    for pixel in indices[1..].iter_mut() {
+        if let Some(code) = dictionary
+            .children(current)
+            .find(|&code| dictionary.index[code as usize] == *pixel)
+        {
+            current = code;
+            continue;
+        }
+
+        if Some(*pixel) != transparent {
+            let similar = dictionary
+                .children(current)
+                .filter(|&code| Some(dictionary.index[code as usize]) != transparent)
+                .map(|code| (code, distance(dictionary.index[code as usize], *pixel)))
+                .filter(|&(_, distance)| distance <= max_distance)
+                .min_by_key(|&(_, distance)| distance);
+            if let Some((code, _)) = similar {
+                *pixel = dictionary.index[code as usize];
+                current = code;
+                continue;
+            }
+        }
+
+        dictionary.add(current, *pixel);
+        current = *pixel as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black, almost black and white
+    const PALETTE: [u8; 9] = [0, 0, 0, 2, 2, 2, 255, 255, 255];
+
+    #[test]
+    fn similar_colors_continue_the_current_string() {
+        let mut indices = [0, 0, 0, 0, 0, 1];
+        apply(&mut indices, &PALETTE, None, 10);
+        assert_eq!(indices, [0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn different_colors_are_kept() {
+        let mut indices = [0, 0, 0, 0, 0, 2];
+        apply(&mut indices, &PALETTE, None, 200);
+        assert_eq!(indices, [0, 0, 0, 0, 0, 2]);
+
+        // a lossiness of 2 only allows a distance of 1 per channel
+        let mut indices = [0, 0, 0, 0, 0, 1];
+        apply(&mut indices, &PALETTE, None, 2);
+        assert_eq!(indices, [0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn zero_lossiness_changes_nothing() {
+        let mut indices = [0, 0, 0, 1, 0, 1, 1, 0];
+        apply(&mut indices, &PALETTE, None, 0);
+        assert_eq!(indices, [0, 0, 0, 1, 0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn transparency_is_never_added_or_removed() {
+        let mut indices = [0, 0, 0, 0, 0, 1];
+        apply(&mut indices, &PALETTE, Some(1), 200);
+        assert_eq!(indices, [0, 0, 0, 0, 0, 1]);
+
+        let mut indices = [1, 1, 1, 1, 1, 0];
+        apply(&mut indices, &PALETTE, Some(1), 200);
+        assert_eq!(indices, [1, 1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn the_dictionary_starts_over_when_it_is_full() {
+        let mut dictionary = Dictionary::new();
+        for i in 0..MAX_CODES - FIRST_CODE {
+            dictionary.add(0, i as u8);
+        }
+        assert_eq!(dictionary.children(0).count(), MAX_CODES - FIRST_CODE);
+
+        dictionary.add(0, 0);
+        assert_eq!(dictionary.children(0).count(), 0);
+    }
+}
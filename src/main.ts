@@ -25,7 +25,9 @@ const createJobFinishedHandler = (display: GifDisplay) => async function (data:
 
 // Then we define what happens on a progress event.
 const createJobProgressHandler = (display: GifDisplay) => (item: ProgressEvent) => {
-  display.updateProgress(item.currentFrame, item.numberOfFrames);
+  // Decoding fills the first half of the progress bar, encoding the second.
+  const offset = item.phase === 'encoding' ? item.numberOfFrames : 0;
+  display.updateProgress(offset + item.currentFrame, 2 * item.numberOfFrames);
 };
 
 const createErrorHandler = (display: GifDisplay) => (event: ProcessingErrorEvent) => {
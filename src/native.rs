@@ -0,0 +1,178 @@
+//! The core operations as a plain Rust library, without JavaScript.
+//!
+//! The exported functions report errors and progress through JavaScript values,
+//! which only exist inside a JavaScript engine. The functions here take and return
+//! plain Rust types instead, so they can be used natively, e.g. by the `gifops`
+//! command line tool or in tests. Frames are always decoded and encoded again with
+//! the default `EncodeOptions`.
+
+use encode::{Encodable, GifWriter};
+use gif::{ColorOutput, DecodeOptions, Decoder};
+use {metadata, read_frames, scan, timeline, EncodeOptions, FrameData, Limits};
+
+/// Global properties of a gif.
+pub struct Info {
+    pub width: u16,
+    pub height: u16,
+    pub frame_count: usize,
+    /// Sum of the delays of all frames in milliseconds
+    pub duration_ms: u64,
+    /// `0` plays the animation once, `-1` repeats it forever
+    pub loop_count: i32,
+}
+
+/// Reads the size, number of frames, duration and loop count of a gif without
+/// decoding it.
+pub fn info(data: &[u8]) -> Result<Info, String> {
+    scan::header(data)?;
+    let summary = scan::summary(data)?;
+    Ok(Info {
+        width: summary.width,
+        height: summary.height,
+        frame_count: summary.delays.len(),
+        duration_ms: summary.delays.iter().map(|&delay| delay as u64 * 10).sum(),
+        loop_count: summary.loop_count(),
+    })
+}
+
+/// Reverses the frames of a gif.
+pub fn reverse(data: &[u8]) -> Result<Vec<u8>, String> {
+    transform(data, |frames| timeline::reverse_range(frames, 0, None))
+}
+
+/// Plays a gif `factor` times as fast.
+pub fn speed(data: &[u8], factor: f32) -> Result<Vec<u8>, String> {
+    if factor.is_nan() || factor <= 0.0 {
+        return Err(format!("the speed factor has to be positive but is {}", factor));
+    }
+    transform(data, |frames| {
+        timeline::change_speed(frames, factor);
+        Ok(())
+    })
+}
+
+/// Cuts a gif down to the frames from `start` up to but not including `end`.
+pub fn trim(data: &[u8], start: usize, end: usize) -> Result<Vec<u8>, String> {
+    transform(data, |frames| timeline::trim(frames, start, end))
+}
+
+/// Decodes a gif, applies `transform` to the composed frames and encodes the
+/// result again, like `transform_gif` does for the exported functions.
+fn transform<F>(data: &[u8], transform: F) -> Result<Vec<u8>, String>
+where
+    F: FnOnce(&mut Vec<FrameData>) -> Result<(), String>,
+{
+    let mut reader = decoder(data)?;
+    let (width, height, global_palette) = metadata(&reader);
+    let (mut frames, _) = read_frames(
+        &mut reader,
+        width,
+        height,
+        &Limits::default(),
+        |e| e.to_string(),
+        |_| Ok(()),
+    )?;
+    transform(&mut frames)?;
+
+    let options = EncodeOptions::default().with_source(data);
+    let mut writer = GifWriter::new(Vec::new(), width, height, &global_palette, &options).map_err(|e| e.to_string())?;
+    for frame in &frames {
+        writer.write(frame.to_frame(&options)).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())
+}
+
+/// Like `decode_data` but with errors as strings.
+fn decoder(data: &[u8]) -> Result<Decoder<&[u8]>, String> {
+    let mut decoder = DecodeOptions::new();
+    decoder.set_color_output(ColorOutput::Indexed);
+
+    decoder.read_info(data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::{Encoder, Frame, Repeat};
+
+    /// A gif of 4x2 pixels with a frame for every color, each shown for the delay
+    /// next to it.
+    fn animation(frames: &[([u8; 3], u16)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 4, 2, &[]).unwrap();
+            encoder.set_repeat(Repeat::Finite(3)).unwrap();
+            for &(color, delay) in frames {
+                let rgb: Vec<u8> = color.iter().cloned().cycle().take(4 * 2 * 3).collect();
+                let mut frame = Frame::from_rgb(4, 2, &rgb);
+                frame.delay = delay;
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        data
+    }
+
+    /// The color of the top left pixel and the delay of every frame.
+    fn frames(data: &[u8]) -> Vec<([u8; 3], u16)> {
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::RGBA);
+        let mut reader = options.read_info(data).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_next_frame().unwrap() {
+            frames.push(([frame.buffer[0], frame.buffer[1], frame.buffer[2]], frame.delay));
+        }
+        frames
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    #[test]
+    fn info_reads_the_header_and_delays() {
+        let info = info(&animation(&[(RED, 10), (GREEN, 20), (BLUE, 5)])).unwrap();
+        assert_eq!((info.width, info.height), (4, 2));
+        assert_eq!(info.frame_count, 3);
+        assert_eq!(info.duration_ms, 350);
+        assert_eq!(info.loop_count, 3);
+    }
+
+    #[test]
+    fn reverse_plays_the_frames_backwards() {
+        let reversed = reverse(&animation(&[(RED, 10), (GREEN, 20), (BLUE, 5)])).unwrap();
+        assert_eq!(frames(&reversed), vec![(BLUE, 5), (GREEN, 20), (RED, 10)]);
+        assert_eq!(info(&reversed).unwrap().loop_count, 3);
+    }
+
+    #[test]
+    fn speed_divides_the_delays() {
+        let faster = speed(&animation(&[(RED, 10), (GREEN, 20), (BLUE, 0)]), 2.0).unwrap();
+        assert_eq!(frames(&faster), vec![(RED, 5), (GREEN, 10), (BLUE, 0)]);
+
+        let slower = speed(&animation(&[(RED, 10)]), 0.5).unwrap();
+        assert_eq!(frames(&slower), vec![(RED, 20)]);
+    }
+
+    #[test]
+    fn speed_rejects_factors_that_are_not_positive() {
+        let data = animation(&[(RED, 10)]);
+        assert!(speed(&data, 0.0).is_err());
+        assert!(speed(&data, -1.0).is_err());
+        assert!(speed(&data, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn trim_keeps_the_range_of_frames() {
+        let data = animation(&[(RED, 10), (GREEN, 20), (BLUE, 5)]);
+        assert_eq!(frames(&trim(&data, 1, 3).unwrap()), vec![(GREEN, 20), (BLUE, 5)]);
+        assert_eq!(frames(&trim(&data, 0, 1).unwrap()), vec![(RED, 10)]);
+        assert!(trim(&data, 2, 2).is_err());
+        assert!(trim(&data, 0, 4).is_err());
+    }
+
+    #[test]
+    fn invalid_gifs_are_errors() {
+        assert!(info(b"GIF89a").is_err());
+        assert!(reverse(b"not a gif").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Shrinking gifs by only storing what changes from one frame to the next.
+//!
+//! Every frame that is written covers the full canvas. Most of the time only a
+//! small part of it actually changes, so the optimizer crops each frame to the
+//! area that differs from what is on screen after the previous frame, and makes
+//! the pixels in that area that didn't change transparent. Runs of transparent
+//! pixels compress much better than the colors they replace.
+//!
+//! Drawing a transparent pixel keeps what is already there, so a frame can't make
+//! a pixel transparent again. If a frame needs that, the frame before it is
+//! written in full and disposed to the background, which clears the canvas.
+
+use gif::{DisposalMethod, Frame};
+use std::borrow::Cow;
+
+/// A color as it is shown on screen, fully transparent pixels are all the same.
+type Color = [u8; 4];
+
+const CLEAR: Color = [0, 0, 0, 0];
+
+/// Turns full canvas frames into cropped frames, one frame behind, since how a
+/// frame is disposed depends on the frame that follows it.
+pub struct Optimizer {
+    width: u16,
+    height: u16,
+    global_palette: Vec<u8>,
+    /// What is on screen before the pending frame is drawn
+    canvas: Vec<Color>,
+    /// The last frame that was pushed and what it looks like
+    pending: Option<(Frame<'static>, Vec<Color>)>,
+}
+
+impl Optimizer {
+    pub fn new(width: u16, height: u16, global_palette: &[u8]) -> Optimizer {
+        Optimizer {
+            width,
+            height,
+            global_palette: global_palette.to_vec(),
+            canvas: vec![CLEAR; width as usize * height as usize],
+            pending: None,
+        }
+    }
+
+    /// Adds the next full canvas frame and returns the previous one, optimized.
+    pub fn push(&mut self, frame: Frame) -> Option<Frame<'static>> {
+        let colors = self.colors(&frame);
+        let frame = Frame {
+            buffer: Cow::Owned(frame.buffer.into_owned()),
+            ..frame
+        };
+
+        let previous = self.pending.take().map(|(previous, previous_colors)| {
+            // pixels that become transparent can only be cleared by disposing the
+            // frame before them
+            let clear = previous_colors
+                .iter()
+                .zip(colors.iter())
+                .any(|(before, after)| before[3] != 0 && after[3] == 0);
+            self.optimize(previous, previous_colors, clear)
+        });
+        self.pending = Some((frame, colors));
+        previous
+    }
+
+    /// Returns the last frame, optimized.
+    pub fn finish(&mut self) -> Option<Frame<'static>> {
+        self.pending
+            .take()
+            .map(|(frame, colors)| self.optimize(frame, colors, false))
+    }
+
+    /// What the pixels of a full canvas frame look like.
+    fn colors(&self, frame: &Frame) -> Vec<Color> {
+        let palette = frame.palette.as_ref().unwrap_or(&self.global_palette);
+        frame
+            .buffer
+            .iter()
+            .map(|&index| {
+                if Some(index) == frame.transparent {
+                    return CLEAR;
+                }
+                match palette.get(index as usize * 3..index as usize * 3 + 3) {
+                    Some(rgb) => [rgb[0], rgb[1], rgb[2], 255],
+                    None => [0, 0, 0, 255],
+                }
+            })
+            .collect()
+    }
+
+    /// Crops `frame` to the area that differs from the canvas and makes unchanged
+    /// pixels in that area transparent. If `clear` is set the frame covers the
+    /// whole canvas and clears it when it's disposed.
+    fn optimize(&mut self, mut frame: Frame<'static>, colors: Vec<Color>, clear: bool) -> Frame<'static> {
+        let width = self.width as usize;
+        let changed = |i: usize| self.canvas[i] != colors[i];
+
+        let (left, top, right, bottom) = if clear {
+            (0, 0, width, self.height as usize)
+        } else {
+            let mut area: Option<(usize, usize, usize, usize)> = None;
+            for i in (0..colors.len()).filter(|&i| changed(i)) {
+                let (x, y) = (i % width, i / width);
+                area = Some(match area {
+                    None => (x, y, x + 1, y + 1),
+                    Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1)),
+                });
+            }
+            // a frame without changes still has to be written for its delay
+            area.unwrap_or((0, 0, 1.min(width), 1.min(self.height as usize)))
+        };
+
+        let transparent = frame
+            .transparent
+            .or_else(|| free_index(&mut frame, &self.global_palette));
+        let mut indices = Vec::with_capacity((right - left) * (bottom - top));
+        for y in top..bottom {
+            for x in left..right {
+                let i = y * width + x;
+                indices.push(match transparent {
+                    Some(transparent) if !changed(i) => transparent,
+                    _ => frame.buffer[i],
+                });
+            }
+        }
+
+        self.canvas = if clear { vec![CLEAR; colors.len()] } else { colors };
+
+        Frame {
+            left: left as u16,
+            top: top as u16,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+            buffer: Cow::Owned(indices),
+            transparent,
+            dispose: if clear {
+                DisposalMethod::Background
+            } else {
+                DisposalMethod::Keep
+            },
+            ..frame
+        }
+    }
+}
+
+/// Finds a palette index that no pixel of the frame uses, adding one to the
+/// palette if it isn't full yet. Frames that use the global palette get a copy of
+/// it as their local palette in that case.
+fn free_index(frame: &mut Frame, global_palette: &[u8]) -> Option<u8> {
+    let mut used = [false; 256];
+    for &index in frame.buffer.iter() {
+        used[index as usize] = true;
+    }
+
+    let colors = frame
+        .palette
+        .as_ref()
+        .map_or(global_palette.len(), |palette| palette.len())
+        / 3;
+    if let Some(unused) = (0..colors).find(|&i| !used[i]) {
+        return Some(unused as u8);
+    }
+    if colors >= 256 {
+        return None;
+    }
+
+    let mut palette = frame.palette.take().unwrap_or_else(|| global_palette.to_vec());
+    palette.extend_from_slice(&[0, 0, 0]);
+    frame.palette = Some(palette);
+    Some(colors as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Red, green and blue
+    const PALETTE: [u8; 9] = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+
+    /// A full canvas frame of 3x1 pixels with the global palette.
+    fn frame(indices: [u8; 3], transparent: Option<u8>) -> Frame<'static> {
+        Frame {
+            width: 3,
+            height: 1,
+            buffer: Cow::Owned(indices.to_vec()),
+            transparent,
+            ..Frame::default()
+        }
+    }
+
+    fn area(frame: &Frame) -> (u16, u16, u16, u16) {
+        (frame.left, frame.top, frame.width, frame.height)
+    }
+
+    #[test]
+    fn frames_are_cropped_to_what_changed() {
+        let mut optimizer = Optimizer::new(3, 1, &PALETTE);
+        assert!(optimizer.push(frame([0, 0, 0], None)).is_none());
+
+        let first = optimizer.push(frame([0, 1, 0], None)).unwrap();
+        assert_eq!(area(&first), (0, 0, 3, 1));
+        assert_eq!(first.dispose, DisposalMethod::Keep);
+
+        let second = optimizer.finish().unwrap();
+        assert_eq!(area(&second), (1, 0, 1, 1));
+        assert_eq!(&second.buffer[..], &[1]);
+    }
+
+    #[test]
+    fn unchanged_pixels_within_the_area_become_transparent() {
+        let mut optimizer = Optimizer::new(3, 1, &PALETTE);
+        optimizer.push(frame([0, 0, 0], None));
+        optimizer.push(frame([1, 0, 1], None));
+        let changed = optimizer.finish().unwrap();
+
+        let transparent = changed.transparent.unwrap();
+        assert_eq!(area(&changed), (0, 0, 3, 1));
+        assert_eq!(&changed.buffer[..], &[1, transparent, 1]);
+        assert!(changed.palette.is_none());
+    }
+
+    #[test]
+    fn frames_without_changes_are_still_written() {
+        let mut optimizer = Optimizer::new(3, 1, &PALETTE);
+        optimizer.push(frame([0, 1, 2], None));
+        optimizer.push(frame([0, 1, 2], None));
+        let unchanged = optimizer.finish().unwrap();
+
+        assert_eq!(area(&unchanged), (0, 0, 1, 1));
+    }
+
+    #[test]
+    fn pixels_that_become_transparent_clear_the_frame_before() {
+        let mut optimizer = Optimizer::new(3, 1, &PALETTE);
+        optimizer.push(frame([0, 1, 2], None));
+        let cleared = optimizer.push(frame([0, 1, 2], Some(2))).unwrap();
+        assert_eq!(area(&cleared), (0, 0, 3, 1));
+        assert_eq!(cleared.dispose, DisposalMethod::Background);
+
+        // the canvas is empty afterwards, so the next frame is drawn in full
+        let after = optimizer.finish().unwrap();
+        assert_eq!(area(&after), (0, 0, 2, 1));
+        assert_eq!(&after.buffer[..], &[0, 1]);
+    }
+
+    #[test]
+    fn transparent_pixels_can_be_filled_without_clearing() {
+        let mut optimizer = Optimizer::new(3, 1, &PALETTE);
+        optimizer.push(frame([0, 1, 2], Some(2)));
+        let first = optimizer.push(frame([0, 1, 2], None)).unwrap();
+        assert_eq!(first.dispose, DisposalMethod::Keep);
+        assert_eq!(area(&first), (0, 0, 2, 1));
+
+        let filled = optimizer.finish().unwrap();
+        assert_eq!(area(&filled), (2, 0, 1, 1));
+        assert_eq!(&filled.buffer[..], &[2]);
+    }
+
+    #[test]
+    fn full_palettes_get_an_extra_entry_for_transparency() {
+        let mut optimizer = Optimizer::new(3, 1, &PALETTE);
+        optimizer.push(frame([0, 0, 0], None));
+        optimizer.push(frame([0, 1, 2], None));
+        let changed = optimizer.finish().unwrap();
+
+        assert_eq!(changed.transparent, Some(3));
+        assert_eq!(changed.palette.as_ref().map(|palette| palette.len()), Some(12));
+        assert_eq!(&changed.buffer[..], &[1, 2]);
+    }
+}
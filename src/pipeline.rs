@@ -0,0 +1,226 @@
+//! Several transforms applied in a single pass.
+
+use encode::{gif_from_frames, EncodeOptions};
+use filters;
+use stats::{self, Phase};
+use timeline;
+use wasm_bindgen::prelude::*;
+use {collect_frames_with, decode_data, js_error, metadata, FrameData};
+
+/// A transform that is queued in a `Pipeline`.
+#[derive(Clone)]
+enum Operation {
+    Reverse,
+    Trim(u32, u32),
+    Decimate(u32),
+    Reorder(Vec<u32>, bool),
+    SetDelay(u32),
+    Speed(f32),
+    Dedupe,
+    Flip(bool, bool),
+    Resize(u16, u16),
+    Grayscale,
+    Sepia(f32),
+    Invert,
+    Adjust(i32, i32),
+    HslAdjust(f32, f32, f32),
+}
+
+impl Operation {
+    fn apply(&self, frames: &mut Vec<FrameData>) -> Result<(), String> {
+        match *self {
+            Operation::Reverse => frames.reverse(),
+            Operation::Trim(start, end) => timeline::trim(frames, start as usize, end as usize)?,
+            Operation::Decimate(0) => return Err("keep_every_n has to be at least 1".to_string()),
+            Operation::Decimate(n) => timeline::decimate(frames, n as usize),
+            Operation::Reorder(ref order, allow_duplicates) => {
+                let mut entries = timeline::entries(frames);
+                timeline::reorder(&mut entries, order, allow_duplicates)?;
+                *frames = timeline::arrange(std::mem::take(frames), &entries);
+            }
+            Operation::SetDelay(delay_ms) => {
+                let delay = timeline::delay_from_ms(delay_ms)?;
+                for frame in frames.iter_mut() {
+                    frame.delay = delay;
+                }
+            }
+            Operation::Speed(factor) => {
+                if !(factor > 0.0 && factor.is_finite()) {
+                    return Err(format!("speed factor has to be positive but is {}", factor));
+                }
+                timeline::change_speed(frames, factor);
+            }
+            Operation::Dedupe => timeline::remove_duplicates(frames),
+            Operation::Flip(horizontal, vertical) => {
+                for frame in frames.iter_mut() {
+                    filters::flip(frame, horizontal, vertical);
+                }
+            }
+            Operation::Resize(width, height) => {
+                if width == 0 || height == 0 {
+                    return Err(format!("can't resize to {}x{}", width, height));
+                }
+                for frame in frames.iter_mut() {
+                    filters::resize(frame, width, height);
+                }
+            }
+            Operation::Grayscale => {
+                for frame in frames.iter_mut() {
+                    filters::grayscale(frame);
+                }
+            }
+            Operation::Sepia(intensity) => {
+                for frame in frames.iter_mut() {
+                    filters::sepia(frame, intensity);
+                }
+            }
+            Operation::Invert => {
+                for frame in frames.iter_mut() {
+                    filters::invert(frame);
+                }
+            }
+            Operation::Adjust(brightness, contrast) => {
+                let lut = filters::brightness_contrast_lut(brightness, contrast);
+                for frame in frames.iter_mut() {
+                    filters::apply_lut(frame, &lut);
+                }
+            }
+            Operation::HslAdjust(hue_shift_deg, saturation, lightness) => {
+                for frame in frames.iter_mut() {
+                    filters::hsl_adjust(frame, hue_shift_deg, saturation, lightness);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A list of transforms that are applied one after the other.
+///
+/// Every method queues a transform and returns the pipeline, so calls can be
+/// chained: `new Pipeline().reverse().resize(320, 240).speed(2).run(data, options)`.
+/// `run()` decodes the gif once, applies all transforms to the composed frames and
+/// quantizes the colors only once when encoding the result. The parameters of the
+/// transforms are the same as for the corresponding `*_gif` functions, they are
+/// checked when the pipeline runs.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    operations: Vec<Operation>,
+}
+
+#[wasm_bindgen]
+impl Pipeline {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    fn then(mut self, operation: Operation) -> Pipeline {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn reverse(self) -> Pipeline {
+        self.then(Operation::Reverse)
+    }
+
+    pub fn trim(self, start_frame: u32, end_frame: u32) -> Pipeline {
+        self.then(Operation::Trim(start_frame, end_frame))
+    }
+
+    pub fn decimate(self, keep_every_n: u32) -> Pipeline {
+        self.then(Operation::Decimate(keep_every_n))
+    }
+
+    pub fn reorder(self, order: Vec<u32>, allow_duplicates: bool) -> Pipeline {
+        self.then(Operation::Reorder(order, allow_duplicates))
+    }
+
+    pub fn set_delay(self, delay_ms: u32) -> Pipeline {
+        self.then(Operation::SetDelay(delay_ms))
+    }
+
+    /// Plays the gif `factor` times as fast, values below 1 slow it down
+    pub fn speed(self, factor: f32) -> Pipeline {
+        self.then(Operation::Speed(factor))
+    }
+
+    pub fn dedupe(self) -> Pipeline {
+        self.then(Operation::Dedupe)
+    }
+
+    pub fn flip(self, horizontal: bool, vertical: bool) -> Pipeline {
+        self.then(Operation::Flip(horizontal, vertical))
+    }
+
+    /// Scales all frames to `width` x `height` pixels
+    pub fn resize(self, width: u16, height: u16) -> Pipeline {
+        self.then(Operation::Resize(width, height))
+    }
+
+    pub fn grayscale(self) -> Pipeline {
+        self.then(Operation::Grayscale)
+    }
+
+    pub fn sepia(self, intensity: f32) -> Pipeline {
+        self.then(Operation::Sepia(intensity))
+    }
+
+    pub fn invert(self) -> Pipeline {
+        self.then(Operation::Invert)
+    }
+
+    pub fn adjust(self, brightness: i32, contrast: i32) -> Pipeline {
+        self.then(Operation::Adjust(brightness, contrast))
+    }
+
+    pub fn hsl_adjust(self, hue_shift_deg: f32, saturation: f32, lightness: f32) -> Pipeline {
+        self.then(Operation::HslAdjust(hue_shift_deg, saturation, lightness))
+    }
+
+    /// Number of queued transforms
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Applies all transforms to the gif `data` and returns the encoded result.
+    /// The pipeline can be run again on other gifs.
+    pub fn run(&self, data: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, JsValue> {
+        console_error_panic_hook::set_once();
+        stats::start();
+
+        let mut reader = decode_data(data)?;
+        let (width, height, global_palette) = metadata(&reader);
+        let (mut frames, warnings) = collect_frames_with(&mut reader, width, height, &options.limits(), |_| {
+            options.check_cancelled()
+        })?;
+        options.add_warnings(warnings);
+
+        for operation in self.operations.iter() {
+            options.check_cancelled()?;
+            stats::time(Phase::Transform, || operation.apply(&mut frames)).map_err(|e| js_error(&e))?;
+        }
+
+        // resizing changes the size of the canvas
+        let width = frames.iter().map(|frame| frame.width).max().unwrap_or(width);
+        let height = frames.iter().map(|frame| frame.height).max().unwrap_or(height);
+
+        let options = options.with_source(data);
+        gif_from_frames(&frames, width, height, global_palette, &options, &|_| {})
+    }
+}
+
+impl Pipeline {
+    /// Applies all transforms to frames that were decoded already.
+    pub(crate) fn apply(&self, frames: &mut Vec<FrameData>) -> Result<(), String> {
+        for operation in self.operations.iter() {
+            operation.apply(frames)?;
+        }
+        Ok(())
+    }
+}
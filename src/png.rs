@@ -0,0 +1,544 @@
+//! A minimal PNG encoder for RGBA images and a decoder for all kinds of PNGs.
+//!
+//! The encoder only does what's needed to export frames: 8 bit RGBA, no
+//! interlacing. Every row is filtered with the filter that produces the smallest
+//! sum of absolute values, the usual heuristic recommended by the PNG
+//! specification. The decoder reads every color type, bit depth and interlacing
+//! and turns the pixels into 8 bit RGBA.
+
+use zlib;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Computes the CRC-32 checksum that's appended to every chunk.
+pub fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for part in parts {
+        for &byte in part.iter() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (!(crc & 1)).wrapping_add(1);
+                crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+/// Appends a chunk with the given type and payload to `output`.
+pub fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    output.extend_from_slice(&crc32(&[kind, data]).to_be_bytes());
+}
+
+/// The Paeth predictor from the PNG specification.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Applies `filter` to `row`. `previous` is the unfiltered row above, or all zeros
+/// for the first row.
+fn filter_row(filter: u8, row: &[u8], previous: &[u8], output: &mut Vec<u8>) {
+    const BPP: usize = 4;
+
+    output.push(filter);
+    for i in 0..row.len() {
+        let left = if i >= BPP { row[i - BPP] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= BPP { previous[i - BPP] } else { 0 };
+        output.push(row[i].wrapping_sub(predict(filter, left, up, up_left)));
+    }
+}
+
+/// The value a filter predicts for a byte from its neighbours.
+fn predict(filter: u8, left: u8, up: u8, up_left: u8) -> u8 {
+    match filter {
+        0 => 0,
+        1 => left,
+        2 => up,
+        3 => ((left as u16 + up as u16) / 2) as u8,
+        _ => paeth(left, up, up_left),
+    }
+}
+
+/// Filters all rows of an RGBA image and returns the data ready for compression.
+pub fn filter_image(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let row_length = width as usize * 4;
+    let mut filtered = Vec::with_capacity((row_length + 1) * height as usize);
+    let mut candidate = Vec::with_capacity(row_length + 1);
+    let zeros = vec![0; row_length];
+
+    for y in 0..height as usize {
+        let row = &rgba[y * row_length..(y + 1) * row_length];
+        let previous = if y > 0 {
+            &rgba[(y - 1) * row_length..y * row_length]
+        } else {
+            &zeros[..]
+        };
+
+        let mut best_filter = 0;
+        let mut best_score = u64::MAX;
+        for filter in 0..5 {
+            candidate.clear();
+            filter_row(filter, row, previous, &mut candidate);
+            let score: u64 = candidate[1..].iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+            if score < best_score {
+                best_score = score;
+                best_filter = filter;
+            }
+        }
+
+        filter_row(best_filter, row, previous, &mut filtered);
+    }
+
+    filtered
+}
+
+/// Builds the payload of the IHDR chunk for an 8 bit RGBA image.
+pub fn header(width: u32, height: u32) -> Vec<u8> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // bit depth 8, color type 6 (RGBA), deflate, adaptive filtering, no interlacing
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    ihdr
+}
+
+/// Encodes an RGBA image as PNG.
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut output = SIGNATURE.to_vec();
+
+    write_chunk(&mut output, b"IHDR", &header(width, height));
+    write_chunk(
+        &mut output,
+        b"IDAT",
+        &zlib::compress(&filter_image(width, height, rgba)),
+    );
+    write_chunk(&mut output, b"IEND", &[]);
+
+    output
+}
+
+/// Reads the size of a PNG from its IHDR chunk without decoding the image.
+pub fn size(data: &[u8]) -> Result<(u32, u32), String> {
+    match data.get(SIGNATURE.len() + 4..SIGNATURE.len() + 16) {
+        Some(header) if data.starts_with(&SIGNATURE) && &header[..4] == b"IHDR" => Ok((
+            u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            u32::from_be_bytes([header[8], header[9], header[10], header[11]]),
+        )),
+        _ => Err("the PNG has no IHDR chunk".to_string()),
+    }
+}
+
+/// A chunk of a PNG.
+pub struct Chunk<'a> {
+    pub kind: &'a [u8],
+    pub data: &'a [u8],
+}
+
+/// Splits the data after the signature into chunks, up to the IEND chunk. The
+/// checksums aren't verified.
+pub fn chunks(mut data: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    let mut chunks = Vec::new();
+    while data.len() >= 12 {
+        let length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        if data.len() < 12 + length {
+            return Err("unexpected end of data".to_string());
+        }
+
+        let chunk = Chunk {
+            kind: &data[4..8],
+            data: &data[8..8 + length],
+        };
+        if chunk.kind == b"IEND" {
+            break;
+        }
+        chunks.push(chunk);
+        data = &data[12 + length..];
+    }
+    Ok(chunks)
+}
+
+/// The passes of Adam7 interlacing as `(left, top, step x, step y)`.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// How the pixels of a PNG are stored, as described by its IHDR, PLTE and tRNS
+/// chunks.
+pub struct Format {
+    pub width: u32,
+    pub height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+    pub palette: Vec<u8>,
+    pub transparency: Option<Vec<u8>>,
+}
+
+impl Format {
+    /// Reads the format from the payload of the IHDR chunk. The palette and the
+    /// transparency have to be filled in from their own chunks.
+    pub fn from_header(header: &[u8]) -> Result<Format, String> {
+        if header.len() < 13 {
+            return Err("invalid IHDR chunk".to_string());
+        }
+
+        let format = Format {
+            width: u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            height: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            bit_depth: header[8],
+            color_type: header[9],
+            interlaced: header[12] == 1,
+            palette: Vec::new(),
+            transparency: None,
+        };
+
+        let valid = match format.color_type {
+            0 => matches!(format.bit_depth, 1 | 2 | 4 | 8 | 16),
+            3 => matches!(format.bit_depth, 1 | 2 | 4 | 8),
+            2 | 4 | 6 => matches!(format.bit_depth, 8 | 16),
+            _ => false,
+        };
+        if !valid {
+            return Err(format!(
+                "invalid combination of color type {} and bit depth {}",
+                format.color_type, format.bit_depth
+            ));
+        }
+        if header[10] != 0 || header[11] != 0 || header[12] > 1 {
+            return Err("unknown compression, filter or interlace method".to_string());
+        }
+
+        Ok(format)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Decodes the decompressed image data of an image of the given size to RGBA.
+    pub fn decode(&self, width: u32, height: u32, data: &[u8]) -> Result<Vec<u8>, String> {
+        let (width, height) = (width as usize, height as usize);
+        let bits_per_pixel = self.channels() * self.bit_depth as usize;
+        // filters look at the corresponding byte of the previous pixel
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+
+        let passes: &[(usize, usize, usize, usize)] = if self.interlaced { &ADAM7 } else { &[(0, 0, 1, 1)] };
+        let mut rgba = vec![0; width * height * 4];
+        let mut offset = 0;
+
+        for &(left, top, step_x, step_y) in passes {
+            let pass_width = width.saturating_sub(left).div_ceil(step_x);
+            let pass_height = height.saturating_sub(top).div_ceil(step_y);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+
+            let row_length = (pass_width * bits_per_pixel).div_ceil(8);
+            let size = (row_length + 1) * pass_height;
+            let filtered = data.get(offset..offset + size).ok_or("the image data is too short")?;
+            let rows = unfilter(filtered, row_length, bytes_per_pixel)?;
+            offset += size;
+
+            for (y, row) in rows.chunks_exact(row_length).enumerate() {
+                for x in 0..pass_width {
+                    let target = ((top + y * step_y) * width + left + x * step_x) * 4;
+                    rgba[target..target + 4].copy_from_slice(&self.color(row, x));
+                }
+            }
+        }
+
+        Ok(rgba)
+    }
+
+    /// Reads the sample with the given index from an unfiltered row.
+    fn sample(&self, row: &[u8], index: usize) -> u16 {
+        match self.bit_depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            depth => {
+                // samples are packed starting with the most significant bits
+                let bit = index * depth as usize;
+                let shift = 8 - depth as usize - bit % 8;
+                (row[bit / 8] >> shift) as u16 & ((1 << depth) - 1)
+            }
+        }
+    }
+
+    /// Scales a sample to 8 bits.
+    fn scale(&self, sample: u16) -> u8 {
+        match self.bit_depth {
+            16 => (sample >> 8) as u8,
+            8 => sample as u8,
+            depth => (sample as u32 * 255 / ((1 << depth) - 1)) as u8,
+        }
+    }
+
+    /// The RGBA color of pixel `x` in an unfiltered row.
+    fn color(&self, row: &[u8], x: usize) -> [u8; 4] {
+        let transparency = self.transparency.as_deref().unwrap_or(&[]);
+        // tRNS stores 16 bit samples for gray and RGB images
+        let transparent = |samples: &[u16]| {
+            self.transparency.is_some()
+                && samples
+                    .iter()
+                    .enumerate()
+                    .all(|(i, &sample)| transparency.get(i * 2..i * 2 + 2) == Some(&sample.to_be_bytes()[..]))
+        };
+
+        match self.color_type {
+            0 => {
+                let gray = self.sample(row, x);
+                let value = self.scale(gray);
+                [value, value, value, if transparent(&[gray]) { 0 } else { 255 }]
+            }
+            2 => {
+                let samples = [
+                    self.sample(row, x * 3),
+                    self.sample(row, x * 3 + 1),
+                    self.sample(row, x * 3 + 2),
+                ];
+                let alpha = if transparent(&samples) { 0 } else { 255 };
+                [
+                    self.scale(samples[0]),
+                    self.scale(samples[1]),
+                    self.scale(samples[2]),
+                    alpha,
+                ]
+            }
+            3 => {
+                let index = self.sample(row, x) as usize;
+                let color = self.palette.get(index * 3..index * 3 + 3).unwrap_or(&[0, 0, 0]);
+                [
+                    color[0],
+                    color[1],
+                    color[2],
+                    transparency.get(index).cloned().unwrap_or(255),
+                ]
+            }
+            4 => {
+                let value = self.scale(self.sample(row, x * 2));
+                [value, value, value, self.scale(self.sample(row, x * 2 + 1))]
+            }
+            _ => [
+                self.scale(self.sample(row, x * 4)),
+                self.scale(self.sample(row, x * 4 + 1)),
+                self.scale(self.sample(row, x * 4 + 2)),
+                self.scale(self.sample(row, x * 4 + 3)),
+            ],
+        }
+    }
+}
+
+/// Reverts the filters of an image, each row in `data` starts with its filter
+/// type.
+fn unfilter(data: &[u8], row_length: usize, bytes_per_pixel: usize) -> Result<Vec<u8>, String> {
+    let mut output = vec![0u8; data.len() / (row_length + 1) * row_length];
+
+    for (y, row) in data.chunks_exact(row_length + 1).enumerate() {
+        let filter = row[0];
+        if filter > 4 {
+            return Err(format!("unknown filter type {}", filter));
+        }
+
+        let start = y * row_length;
+        for i in 0..row_length {
+            let left = if i >= bytes_per_pixel {
+                output[start + i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let up = if y > 0 { output[start + i - row_length] } else { 0 };
+            let up_left = if y > 0 && i >= bytes_per_pixel {
+                output[start + i - row_length - bytes_per_pixel]
+            } else {
+                0
+            };
+            output[start + i] = row[i + 1].wrapping_add(predict(filter, left, up, up_left));
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zlib;
+
+    /// Builds a PNG from the fields of its IHDR, extra chunks and unfiltered rows.
+    fn build(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: u8,
+        interlace: u8,
+        extra: &[(&[u8; 4], &[u8])],
+        rows: &[u8],
+    ) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+
+        let mut output = SIGNATURE.to_vec();
+        write_chunk(&mut output, b"IHDR", &ihdr);
+        for &(kind, data) in extra {
+            write_chunk(&mut output, kind, data);
+        }
+        write_chunk(&mut output, b"IDAT", &zlib::compress(rows));
+        write_chunk(&mut output, b"IEND", &[]);
+        output
+    }
+
+    /// Decodes a PNG to its size and RGBA pixels.
+    fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+        let chunks = chunks(data.get(SIGNATURE.len()..).ok_or("not a PNG")?)?;
+        let mut format = Format::from_header(chunks.first().ok_or("no IHDR chunk")?.data)?;
+        let mut compressed = Vec::new();
+        for chunk in &chunks {
+            match chunk.kind {
+                b"PLTE" => format.palette = chunk.data.to_vec(),
+                b"tRNS" => format.transparency = Some(chunk.data.to_vec()),
+                b"IDAT" => compressed.extend_from_slice(chunk.data),
+                _ => {}
+            }
+        }
+        let rgba = format.decode(format.width, format.height, &zlib::decompress(&compressed)?)?;
+        Ok((format.width, format.height, rgba))
+    }
+
+    #[test]
+    fn crc32_matches_the_specification() {
+        assert_eq!(crc32(&[b"IEND"]), 0xae42_6082);
+        assert_eq!(crc32(&[b"IE", b"ND"]), 0xae42_6082);
+    }
+
+    #[test]
+    fn encoded_images_decode_to_the_same_pixels() {
+        let (width, height) = (13, 7);
+        let rgba: Vec<u8> = (0..width * height * 4).map(|i| (i * 37 % 251) as u8).collect();
+
+        let data = encode(width, height, &rgba);
+        assert_eq!(size(&data), Ok((width, height)));
+        assert_eq!(decode(&data), Ok((width, height, rgba)));
+    }
+
+    #[test]
+    fn size_rejects_data_without_a_header() {
+        assert!(size(b"GIF89a").is_err());
+        assert!(size(&SIGNATURE).is_err());
+    }
+
+    #[test]
+    fn packed_palette_indices_use_the_transparency() {
+        // indices 0, 1, 2 and 3 at 2 bits each
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9];
+        let data = build(
+            4,
+            1,
+            2,
+            3,
+            0,
+            &[(b"PLTE", &palette), (b"tRNS", &[0, 128])],
+            &[0, 0b0001_1011],
+        );
+        let (_, _, rgba) = decode(&data).unwrap();
+        assert_eq!(rgba, [255, 0, 0, 0, 0, 255, 0, 128, 0, 0, 255, 255, 9, 9, 9, 255]);
+    }
+
+    #[test]
+    fn low_bit_depths_are_scaled_to_8_bits() {
+        let data = build(4, 1, 2, 0, 0, &[], &[0, 0b0001_1011]);
+        let (_, _, rgba) = decode(&data).unwrap();
+        let grays: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(grays, [0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn sixteen_bit_samples_keep_their_high_byte() {
+        // two RGB pixels, the second one matches the transparent color
+        let rows = [0, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0, 1, 0, 2, 0, 3];
+        let data = build(2, 1, 16, 2, 0, &[(b"tRNS", &[0, 1, 0, 2, 0, 3])], &rows);
+        let (_, _, rgba) = decode(&data).unwrap();
+        assert_eq!(rgba, [0x12, 0x56, 0x9a, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn filters_are_reverted() {
+        // sub, up, average and paeth on a gray image with one byte per pixel
+        let rows = [1, 10, 5, 5, 2, 1, 1, 1, 3, 1, 1, 1, 4, 1, 1, 1];
+        let data = build(3, 4, 8, 0, 0, &[], &rows);
+        let (_, _, rgba) = decode(&data).unwrap();
+        let grays: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(grays, [10, 15, 20, 11, 16, 21, 6, 12, 17, 7, 13, 18]);
+    }
+
+    #[test]
+    fn interlaced_images_are_assembled_from_their_passes() {
+        let value = |x: u8, y: u8| (y * 3 + x) * 10;
+        let rows = [
+            // pass 1
+            0,
+            value(0, 0),
+            // pass 4
+            0,
+            value(2, 0),
+            // pass 5
+            0,
+            value(0, 2),
+            value(2, 2),
+            // pass 6
+            0,
+            value(1, 0),
+            0,
+            value(1, 2),
+            // pass 7
+            0,
+            value(0, 1),
+            value(1, 1),
+            value(2, 1),
+        ];
+        let data = build(3, 3, 8, 0, 1, &[], &rows);
+        let (_, _, rgba) = decode(&data).unwrap();
+        let grays: Vec<u8> = rgba.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(grays, [0, 10, 20, 30, 40, 50, 60, 70, 80]);
+    }
+
+    #[test]
+    fn invalid_data_is_an_error() {
+        assert!(Format::from_header(&[0, 0, 0, 1, 0, 0, 0, 1, 16, 3, 0, 0, 0]).is_err());
+        assert!(Format::from_header(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 2]).is_err());
+        assert!(decode(&build(2, 2, 8, 0, 0, &[], &[0, 1, 2])).is_err());
+        assert!(decode(&build(1, 1, 8, 0, 0, &[], &[5, 1])).is_err());
+
+        let data = encode(2, 2, &[0; 16]);
+        assert!(chunks(&data[SIGNATURE.len()..data.len() - 20]).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 // Import the wasm wrapper module generated by wasm-bindgen.
-import { reverse_gif } from 'gif';
+import { reverse_gif, EncodeOptions } from 'gif';
 
 // Ideally, this file would be written in typescript as well but something is
 // not working correctly. So for now at least this will be plain javascript.
@@ -9,24 +9,27 @@ import { reverse_gif } from 'gif';
 // the wasm module otherwise the import will fail.
 
 // We just forward the data to the main thread. The main thread will add the
-// gif to the progress module.
-const registerProgress = (id: string, name: string, numberOfFrames: number) => {
+// gif to the progress module. This is called twice per gif, once before the
+// frames are decoded and once before they are encoded again.
+const registerProgress = (id: string, name: string, numberOfFrames: number, phase: string) => {
   self.postMessage({
     type: 'register_progress',
     id,
     name,
-    numberOfFrames
+    numberOfFrames,
+    phase
   });
 }
 
-// This function will be called after every frame that was processed by the
-// wasm module. All we do here is pass the information through to the main
-// thread which will then update the progress bar.
-const reportProgress = (id: string, currentFrame: number) => {
+// This function will be called after every frame that was decoded or encoded
+// by the wasm module. All we do here is pass the information through to the
+// main thread which will then update the progress bar.
+const reportProgress = (id: string, currentFrame: number, phase: string) => {
   self.postMessage({
     type: 'report_progress',
     id,
-    currentFrame
+    currentFrame,
+    phase
   });
 }
 
@@ -35,8 +38,10 @@ self.addEventListener('message', async (event) => {
   try {
     const { id, name, buffer } = event.data;
 
-    // Reverse the gif.
-    const reversedBuffer = reverse_gif(id, name, buffer, registerProgress, reportProgress);
+    // Reverse the gif. The default options keep the loop count of the original.
+    const options = new EncodeOptions();
+    const reversedBuffer = reverse_gif(id, name, buffer, registerProgress, reportProgress, options);
+    options.free();
 
     // Tell the main thread that we're finished.
     self.postMessage({
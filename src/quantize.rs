@@ -0,0 +1,616 @@
+//! Reducing RGBA frames to the at most 256 colors a gif frame can have.
+//!
+//! Frames that already have few enough colors get an exact palette. All others are
+//! reduced with one of the algorithms in `Quantizer`. Pixels with an alpha value of
+//! 0 are transparent, all other pixels are treated as fully opaque.
+
+use color_quant::NeuQuant;
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// The algorithm that picks the palette of frames with more than 256 colors.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantizer {
+    /// A neural network that learns the colors of the image. Slow but gives the
+    /// best results for photos and gradients.
+    #[default]
+    NeuQuant,
+    /// Repeatedly splits the box of colors with the widest range. Fast and good
+    /// for images with large areas of flat color.
+    MedianCut,
+    /// Merges similar colors in an octree. The fastest algorithm, its palettes
+    /// are a bit coarser than the ones of the other two.
+    Octree,
+}
+
+/// How colors that are not in the palette are approximated.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dithering {
+    /// Every pixel gets the closest palette color, gradients show visible bands
+    #[default]
+    None,
+    /// Spreads the error of every pixel to its neighbours, gives the smoothest
+    /// gradients but the noise changes from frame to frame
+    FloydSteinberg,
+    /// Adds a fixed 8x8 pattern, the dots stay in place between frames which also
+    /// compresses better
+    Bayer,
+}
+
+/// The 8x8 Bayer threshold matrix with values from 0 to 63.
+#[rustfmt::skip]
+pub const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// A frame reduced to a palette.
+pub struct Quantized {
+    /// RGB triplets
+    pub palette: Vec<u8>,
+    /// One palette index per pixel
+    pub indices: Vec<u8>,
+    pub transparent: Option<u8>,
+}
+
+fn pack(r: u8, g: u8, b: u8) -> u32 {
+    (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+fn unpack(color: u32) -> [u8; 3] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
+
+/// Reduces an RGBA image that is `width` pixels wide to a palette of at most
+/// `palette_size` colors, which has to be between 2 and 256. The transparent color
+/// takes up one of them if the image has transparent pixels.
+pub fn quantize(
+    rgba: &[u8],
+    width: usize,
+    palette_size: usize,
+    quantizer: Quantizer,
+    dithering: Dithering,
+) -> Quantized {
+    let mut histogram: HashMap<u32, u32> = HashMap::new();
+    let mut has_transparency = false;
+    for pixel in rgba.chunks_exact(4) {
+        if pixel[3] == 0 {
+            has_transparency = true;
+        } else {
+            *histogram.entry(pack(pixel[0], pixel[1], pixel[2])).or_insert(0) += 1;
+        }
+    }
+
+    let max_colors = if has_transparency {
+        palette_size - 1
+    } else {
+        palette_size
+    };
+    let exact = histogram.len() <= max_colors;
+    let palette: Vec<[u8; 3]> = if exact {
+        let mut colors: Vec<u32> = histogram.keys().cloned().collect();
+        colors.sort_unstable();
+        colors.into_iter().map(unpack).collect()
+    } else {
+        let colors: Vec<(u32, u32)> = histogram.into_iter().collect();
+        match quantizer {
+            Quantizer::NeuQuant => neuquant(rgba, max_colors),
+            Quantizer::MedianCut => median_cut(colors, max_colors),
+            Quantizer::Octree => octree(&colors, max_colors),
+        }
+    };
+
+    map(rgba, width, palette, exact, has_transparency, dithering)
+}
+
+/// Maps an RGBA image that is `width` pixels wide to a fixed `palette` of at most
+/// 255 colors. Transparent pixels get an extra index after the palette.
+pub fn quantize_to(rgba: &[u8], width: usize, palette: &[[u8; 3]], dithering: Dithering) -> Quantized {
+    let colors: HashSet<u32> = palette.iter().map(|color| pack(color[0], color[1], color[2])).collect();
+    let mut has_transparency = false;
+    let mut exact = true;
+    for pixel in rgba.chunks_exact(4) {
+        if pixel[3] == 0 {
+            has_transparency = true;
+        } else {
+            exact = exact && colors.contains(&pack(pixel[0], pixel[1], pixel[2]));
+        }
+    }
+
+    map(rgba, width, palette.to_vec(), exact, has_transparency, dithering)
+}
+
+/// Looks up the palette index of every pixel. `exact` tells whether every opaque
+/// pixel has a color of the palette.
+fn map(
+    rgba: &[u8],
+    width: usize,
+    mut palette: Vec<[u8; 3]>,
+    exact: bool,
+    has_transparency: bool,
+    dithering: Dithering,
+) -> Quantized {
+    // there's nothing to dither if every color is in the palette
+    let transparent = palette.len() as u8;
+    let indices = match dithering {
+        Dithering::FloydSteinberg if !exact => map_floyd_steinberg(rgba, width, &palette, transparent),
+        Dithering::Bayer if !exact => map_bayer(rgba, width, &palette, transparent),
+        _ => map_closest(rgba, &palette, transparent),
+    };
+
+    let transparent = if has_transparency {
+        palette.push([0, 0, 0]);
+        Some(transparent)
+    } else {
+        None
+    };
+
+    Quantized {
+        palette: palette.concat(),
+        indices,
+        transparent,
+    }
+}
+
+/// Finds the closest palette entry for colors and remembers the result, since most
+/// images use the same colors over and over.
+struct Mapper<'a> {
+    palette: &'a [[u8; 3]],
+    cache: HashMap<u32, u8>,
+}
+
+impl<'a> Mapper<'a> {
+    fn new(palette: &'a [[u8; 3]]) -> Mapper<'a> {
+        Mapper {
+            palette,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn index_of(&mut self, color: [u8; 3]) -> u8 {
+        let palette = self.palette;
+        *self.cache.entry(pack(color[0], color[1], color[2])).or_insert_with(|| {
+            let distance =
+                |entry: &[u8; 3]| -> i32 { (0..3).map(|c| (entry[c] as i32 - color[c] as i32).pow(2)).sum() };
+            (0..palette.len()).min_by_key(|&i| distance(&palette[i])).unwrap_or(0) as u8
+        })
+    }
+}
+
+/// Maps every pixel to the closest palette color.
+fn map_closest(rgba: &[u8], palette: &[[u8; 3]], transparent: u8) -> Vec<u8> {
+    let mut mapper = Mapper::new(palette);
+    rgba.chunks_exact(4)
+        .map(|pixel| {
+            if pixel[3] == 0 {
+                transparent
+            } else {
+                mapper.index_of([pixel[0], pixel[1], pixel[2]])
+            }
+        })
+        .collect()
+}
+
+/// Maps pixels to palette colors and distributes the difference to the pixels to
+/// the right and below with the weights 7/16, 3/16, 5/16 and 1/16. Transparent
+/// pixels neither receive nor pass on any error.
+fn map_floyd_steinberg(rgba: &[u8], width: usize, palette: &[[u8; 3]], transparent: u8) -> Vec<u8> {
+    let mut mapper = Mapper::new(palette);
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+
+    // errors in 1/16 for the current and the next row, with one pixel of padding on
+    // both sides so the neighbours never have to be checked
+    let mut current = vec![[0i32; 3]; width + 2];
+    let mut next = vec![[0i32; 3]; width + 2];
+
+    for row in rgba.chunks_exact(width * 4) {
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            if pixel[3] == 0 {
+                indices.push(transparent);
+                continue;
+            }
+
+            let mut color = [0u8; 3];
+            for c in 0..3 {
+                color[c] = (pixel[c] as i32 + current[x + 1][c] / 16).clamp(0, 255) as u8;
+            }
+
+            let index = mapper.index_of(color);
+            indices.push(index);
+
+            let entry = palette[index as usize];
+            for c in 0..3 {
+                let error = color[c] as i32 - entry[c] as i32;
+                current[x + 2][c] += error * 7;
+                next[x][c] += error * 3;
+                next[x + 1][c] += error * 5;
+                next[x + 2][c] += error;
+            }
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        for error in next.iter_mut() {
+            *error = [0; 3];
+        }
+    }
+
+    indices
+}
+
+/// Maps pixels to palette colors after adding an offset from the Bayer matrix
+/// that depends on the position of the pixel.
+fn map_bayer(rgba: &[u8], width: usize, palette: &[[u8; 3]], transparent: u8) -> Vec<u8> {
+    let mut mapper = Mapper::new(palette);
+
+    // roughly the distance between two palette colors if they were spread evenly
+    let spread = 255.0 / (palette.len().max(2) as f32).cbrt();
+
+    rgba.chunks_exact(4)
+        .enumerate()
+        .map(|(i, pixel)| {
+            if pixel[3] == 0 {
+                return transparent;
+            }
+
+            let threshold = BAYER[(i / width) % 8][(i % width) % 8] as f32 / 64.0 - 0.5;
+            let offset = (threshold * spread) as i32;
+            let mut color = [0u8; 3];
+            for c in 0..3 {
+                color[c] = (pixel[c] as i32 + offset).clamp(0, 255) as u8;
+            }
+            mapper.index_of(color)
+        })
+        .collect()
+}
+
+/// Learns a palette of `max_colors` colors from the opaque pixels.
+fn neuquant(rgba: &[u8], max_colors: usize) -> Vec<[u8; 3]> {
+    let opaque: Vec<u8> = rgba
+        .chunks_exact(4)
+        .filter(|pixel| pixel[3] != 0)
+        .flat_map(|pixel| vec![pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+
+    NeuQuant::new(1, max_colors, &opaque)
+        .color_map_rgb()
+        .chunks_exact(3)
+        .map(|color| [color[0], color[1], color[2]])
+        .collect()
+}
+
+/// The pixel weighted average of a set of colors.
+fn average(colors: &[(u32, u32)]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    let mut count = 0u64;
+    for &(color, n) in colors {
+        let channels = unpack(color);
+        for c in 0..3 {
+            sum[c] += channels[c] as u64 * n as u64;
+        }
+        count += n as u64;
+    }
+
+    let count = count.max(1);
+    [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]
+}
+
+/// Returns the channel with the widest range of values and that range.
+fn widest_channel(colors: &[(u32, u32)]) -> (usize, u8) {
+    let mut min = [255u8; 3];
+    let mut max = [0u8; 3];
+    for &(color, _) in colors {
+        let channels = unpack(color);
+        for c in 0..3 {
+            min[c] = min[c].min(channels[c]);
+            max[c] = max[c].max(channels[c]);
+        }
+    }
+
+    (0..3)
+        .map(|c| (c, max[c] - min[c]))
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// Splits the colors into `max_colors` boxes by repeatedly cutting the box with the
+/// widest range at the median pixel of that range.
+fn median_cut(colors: Vec<(u32, u32)>, max_colors: usize) -> Vec<[u8; 3]> {
+    median_cut_boxes(colors, max_colors)
+        .iter()
+        .map(|colors| average(colors))
+        .collect()
+}
+
+/// The boxes of colors that `median_cut` averages.
+fn median_cut_boxes(colors: Vec<(u32, u32)>, max_colors: usize) -> Vec<Vec<(u32, u32)>> {
+    let mut boxes = vec![colors];
+
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (i, widest_channel(colors)))
+            .max_by_key(|&(_, (_, range))| range);
+
+        let (index, channel) = match widest {
+            Some((index, (channel, _))) => (index, channel),
+            None => break,
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|&(color, _)| unpack(color)[channel]);
+
+        let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+        let mut seen = 0;
+        let mut median = 1;
+        for (i, &(_, n)) in colors.iter().enumerate() {
+            seen += n as u64;
+            if seen * 2 >= total {
+                median = (i + 1).min(colors.len() - 1);
+                break;
+            }
+        }
+
+        let upper = colors.split_off(median);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+}
+
+/// Picks up to `max_colors` colors that represent the opaque pixels of the RGBA
+/// `images` best, the ones that cover the most pixels first. Only every `step`th
+/// pixel is looked at.
+#[cfg(feature = "wasm")]
+pub fn dominant_colors<'a, I>(images: I, step: usize, max_colors: usize) -> Vec<[u8; 3]>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut histogram: HashMap<u32, u32> = HashMap::new();
+    for rgba in images {
+        for pixel in rgba.chunks_exact(4).step_by(step.max(1)) {
+            if pixel[3] != 0 {
+                *histogram.entry(pack(pixel[0], pixel[1], pixel[2])).or_insert(0) += 1;
+            }
+        }
+    }
+    if histogram.is_empty() {
+        return Vec::new();
+    }
+
+    let mut boxes = median_cut_boxes(histogram.into_iter().collect(), max_colors);
+    boxes.sort_by_cached_key(|colors| std::cmp::Reverse(colors.iter().map(|&(_, n)| n as u64).sum::<u64>()));
+    boxes.iter().map(|colors| average(colors)).collect()
+}
+
+/// A node of the octree used by `octree`. Every node knows how many pixels and which
+/// color sum are below it, so any node can become a leaf.
+struct Node {
+    children: [usize; 8],
+    count: u64,
+    sum: [u64; 3],
+    leaf: bool,
+}
+
+impl Node {
+    fn new() -> Node {
+        Node {
+            children: [0; 8],
+            count: 0,
+            sum: [0; 3],
+            leaf: false,
+        }
+    }
+}
+
+/// Sorts the colors into an octree with one level per bit and merges the least
+/// used nodes, deepest first, until there are at most `max_colors` leaves.
+fn octree(colors: &[(u32, u32)], max_colors: usize) -> Vec<[u8; 3]> {
+    const DEPTH: usize = 8;
+
+    // index 0 is the root which is never anybody's child, so 0 also means no child
+    let mut nodes = vec![Node::new()];
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); DEPTH];
+    let mut leaves = 0;
+
+    for &(color, n) in colors {
+        let channels = unpack(color);
+        let mut node = 0;
+        for level in 0..=DEPTH {
+            nodes[node].count += n as u64;
+            for (sum, &channel) in nodes[node].sum.iter_mut().zip(channels.iter()) {
+                *sum += channel as u64 * n as u64;
+            }
+
+            if level == DEPTH {
+                nodes[node].leaf = true;
+                break;
+            }
+
+            let shift = 7 - level;
+            let child =
+                ((channels[0] >> shift) & 1) << 2 | ((channels[1] >> shift) & 1) << 1 | ((channels[2] >> shift) & 1);
+            if nodes[node].children[child as usize] == 0 {
+                nodes.push(Node::new());
+                let index = nodes.len() - 1;
+                nodes[node].children[child as usize] = index;
+                if level + 1 < DEPTH {
+                    levels[level + 1].push(index);
+                } else {
+                    leaves += 1;
+                }
+            }
+            node = nodes[node].children[child as usize];
+        }
+    }
+    levels[0].push(0);
+
+    // merging a node only changes the nodes below it, so the order within a level
+    // can be decided up front
+    for level in (0..DEPTH).rev() {
+        let mut candidates = levels[level].clone();
+        candidates.sort_unstable_by_key(|&node| nodes[node].count);
+
+        for node in candidates {
+            if leaves <= max_colors {
+                break;
+            }
+
+            let children = nodes[node].children.iter().filter(|&&child| child != 0).count();
+            nodes[node].children = [0; 8];
+            nodes[node].leaf = true;
+            leaves = leaves + 1 - children;
+        }
+    }
+
+    let mut palette = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.leaf {
+            let count = node.count.max(1);
+            palette.push([
+                (node.sum[0] / count) as u8,
+                (node.sum[1] / count) as u8,
+                (node.sum[2] / count) as u8,
+            ]);
+        } else {
+            stack.extend(node.children.iter().filter(|&&child| child != 0));
+        }
+    }
+
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK_AND_WHITE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+    /// An opaque image with `pixels` pixels of `color`.
+    fn filled(color: [u8; 3], pixels: usize) -> Vec<u8> {
+        [color[0], color[1], color[2], 255]
+            .iter()
+            .cloned()
+            .cycle()
+            .take(pixels * 4)
+            .collect()
+    }
+
+    fn histogram(colors: &[[u8; 3]]) -> Vec<(u32, u32)> {
+        colors
+            .iter()
+            .map(|color| (pack(color[0], color[1], color[2]), 1))
+            .collect()
+    }
+
+    fn sorted(mut colors: Vec<[u8; 3]>) -> Vec<[u8; 3]> {
+        colors.sort_unstable();
+        colors
+    }
+
+    #[test]
+    fn images_with_few_colors_keep_them_exactly() {
+        let mut rgba = filled([255, 0, 0], 2);
+        rgba.extend(filled([0, 0, 255], 2));
+        rgba.extend_from_slice(&[0, 0, 0, 0]);
+
+        let quantized = quantize(&rgba, 5, 256, Quantizer::MedianCut, Dithering::FloydSteinberg);
+        assert_eq!(quantized.palette, vec![0, 0, 255, 255, 0, 0, 0, 0, 0]);
+        assert_eq!(quantized.indices, vec![1, 1, 0, 0, 2]);
+        assert_eq!(quantized.transparent, Some(2));
+    }
+
+    #[test]
+    fn median_cut_splits_at_the_widest_channel() {
+        let colors = histogram(&[[0, 0, 0], [10, 0, 5], [200, 0, 0], [210, 0, 5]]);
+        let mut boxes: Vec<Vec<[u8; 3]>> = median_cut_boxes(colors, 2)
+            .into_iter()
+            .map(|colors| sorted(colors.into_iter().map(|(color, _)| unpack(color)).collect()))
+            .collect();
+        boxes.sort_unstable();
+
+        assert_eq!(boxes, vec![vec![[0, 0, 0], [10, 0, 5]], vec![[200, 0, 0], [210, 0, 5]]]);
+    }
+
+    #[test]
+    fn median_cut_stops_when_every_box_has_a_single_color() {
+        let colors = histogram(&[[0, 0, 0], [255, 255, 255]]);
+        assert_eq!(median_cut_boxes(colors.clone(), 16).len(), 2);
+        assert_eq!(sorted(median_cut(colors, 16)), BLACK_AND_WHITE.to_vec());
+    }
+
+    #[test]
+    fn octree_merges_similar_colors() {
+        let colors = histogram(&[[0, 0, 0], [1, 1, 1], [254, 254, 254], [255, 255, 255]]);
+        assert_eq!(sorted(octree(&colors, 2)), vec![[0, 0, 0], [254, 254, 254]]);
+    }
+
+    #[test]
+    fn octree_keeps_to_the_number_of_colors() {
+        let colors: Vec<[u8; 3]> = (0..200u32)
+            .map(|i| [(i * 37) as u8, (i * 91) as u8, (i * 13) as u8])
+            .collect();
+        for &max_colors in &[2, 16, 100] {
+            let palette = octree(&histogram(&colors), max_colors);
+            assert!(!palette.is_empty() && palette.len() <= max_colors);
+        }
+    }
+
+    #[test]
+    fn floyd_steinberg_mixes_palette_colors_for_colors_in_between() {
+        let indices = map_floyd_steinberg(&filled([128, 128, 128], 64), 8, &BLACK_AND_WHITE, 2);
+        let white = indices.iter().filter(|&&index| index == 1).count();
+        assert!((28..=36).contains(&white), "{} of 64 pixels are white", white);
+
+        // without dithering all of them end up white
+        assert!(map_closest(&filled([128, 128, 128], 64), &BLACK_AND_WHITE, 2)
+            .iter()
+            .all(|&index| index == 1));
+    }
+
+    #[test]
+    fn bayer_mixes_palette_colors_for_colors_in_between() {
+        let indices = map_bayer(&filled([128, 128, 128], 64), 8, &BLACK_AND_WHITE, 2);
+        let white = indices.iter().filter(|&&index| index == 1).count();
+        assert!((16..=48).contains(&white), "{} of 64 pixels are white", white);
+
+        assert!(map_bayer(&filled([0, 0, 0], 64), 8, &BLACK_AND_WHITE, 2)
+            .iter()
+            .all(|&index| index == 0));
+        assert!(map_bayer(&filled([255, 255, 255], 64), 8, &BLACK_AND_WHITE, 2)
+            .iter()
+            .all(|&index| index == 1));
+    }
+
+    #[test]
+    fn dithering_leaves_transparent_pixels_alone() {
+        let mut rgba = filled([128, 128, 128], 3);
+        rgba.extend_from_slice(&[128, 128, 128, 0]);
+
+        assert_eq!(map_floyd_steinberg(&rgba, 2, &BLACK_AND_WHITE, 2)[3], 2);
+        assert_eq!(map_bayer(&rgba, 2, &BLACK_AND_WHITE, 2)[3], 2);
+    }
+
+    #[test]
+    fn fixed_palettes_are_not_dithered_if_every_color_is_in_them() {
+        let mut rgba = filled([255, 255, 255], 2);
+        rgba.extend(filled([0, 0, 0], 2));
+
+        let quantized = quantize_to(&rgba, 2, &BLACK_AND_WHITE, Dithering::FloydSteinberg);
+        assert_eq!(quantized.indices, vec![1, 1, 0, 0]);
+        assert_eq!(quantized.transparent, None);
+    }
+}
@@ -0,0 +1,421 @@
+//! Fixing the defects that `validate_gif` finds where that's possible.
+//!
+//! The gif is rewritten block by block. Most fixes only change the structure of
+//! the gif, like appending the trailer. Frames that extend past the canvas are the
+//! exception, they are decoded, cropped and compressed again. Everything else is
+//! copied as it is, so the repaired gif doesn't lose any quality.
+
+use gif::{ColorOutput, DecodeOptions, Encoder, Frame};
+use js_error;
+use scan::{self, Block, RawFrame, APPLICATION, GRAPHIC_CONTROL};
+use std::borrow::Cow;
+use types::{JsRepairResult, RepairFix, RepairResult};
+use validate::loop_extension_problem;
+use wasm_bindgen::prelude::*;
+
+/// A change that was made to repair a gif, `code` is the one of the issue it fixes.
+struct Fix {
+    code: &'static str,
+    message: String,
+    frame: Option<usize>,
+}
+
+impl Fix {
+    fn new(code: &'static str, message: String, frame: Option<usize>) -> Fix {
+        Fix { code, message, frame }
+    }
+}
+
+/// Appends an extension with its sub-blocks to `out`.
+fn write_extension(out: &mut Vec<u8>, label: u8, sub_blocks: &[&[u8]]) {
+    out.extend_from_slice(&[0x21, label]);
+    for sub_block in sub_blocks {
+        out.push(sub_block.len() as u8);
+        out.extend_from_slice(sub_block);
+    }
+    out.push(0);
+}
+
+/// Rewrites the gif `data` without the defects that can be fixed.
+fn repair(data: &[u8]) -> Result<(Vec<u8>, Vec<Fix>), String> {
+    let header = scan::header(data)?;
+    let mut fixes = Vec::new();
+    let mut width = header.width;
+    let mut height = header.height;
+
+    let global_palette = &data[13..13 + header.global_palette_size * 3];
+    let mut out = data[..13 + global_palette.len()].to_vec();
+
+    // a canvas without pixels gets the size of the frames
+    if width == 0 || height == 0 {
+        for block in scan::blocks(data)?.filter_map(|block| block.ok()) {
+            if let Block::Image(image) = block {
+                let frame = RawFrame { control: None, image };
+                width = width.max(frame.left().saturating_add(frame.width()));
+                height = height.max(frame.top().saturating_add(frame.height()));
+            }
+        }
+        out[6..8].copy_from_slice(&width.to_le_bytes());
+        out[8..10].copy_from_slice(&height.to_le_bytes());
+        fixes.push(Fix::new(
+            "empty-canvas",
+            format!("resized the canvas to {}x{} pixels to fit the frames", width, height),
+            None,
+        ));
+    }
+
+    let mut blocks = scan::blocks(data)?;
+    let mut frames = 0;
+    let mut control: Option<Vec<u8>> = None;
+    // the delay of dropped frames in hundredths of a second, which is added to the
+    // next frame
+    let mut carried_delay: u16 = 0;
+    let mut trailer = false;
+    loop {
+        let start = blocks.offset();
+        let block = match blocks.next() {
+            Some(block) => block,
+            None => break,
+        };
+        match block {
+            Ok(Block::Extension {
+                label: GRAPHIC_CONTROL,
+                sub_blocks,
+            }) => {
+                let mut content = sub_blocks.first().map_or_else(Vec::new, |content| content.to_vec());
+                if content.len() != 4 {
+                    content.resize(4, 0);
+                    fixes.push(Fix::new(
+                        "invalid-graphic-control",
+                        "resized the graphic control extension to 4 bytes".to_string(),
+                        Some(frames),
+                    ));
+                }
+                control = Some(content);
+            }
+            Ok(Block::Extension {
+                label: APPLICATION,
+                sub_blocks,
+            }) => match loop_extension_problem(&sub_blocks) {
+                Some(_) => {
+                    // keep the loop count if it can be read at all, otherwise loop forever
+                    let loops = match sub_blocks.get(1) {
+                        Some(loops) if loops.len() >= 3 => [loops[1], loops[2]],
+                        _ => [0, 0],
+                    };
+                    write_extension(&mut out, APPLICATION, &[b"NETSCAPE2.0", &[1, loops[0], loops[1]]]);
+                    fixes.push(Fix::new(
+                        "invalid-loop-extension",
+                        format!(
+                            "rewrote the loop extension with a loop count of {}",
+                            u16::from_le_bytes(loops)
+                        ),
+                        None,
+                    ));
+                }
+                None => write_extension(&mut out, APPLICATION, &sub_blocks),
+            },
+            Ok(Block::Extension { label, sub_blocks }) => write_extension(&mut out, label, &sub_blocks),
+            Ok(Block::Image(image)) => {
+                let frame = RawFrame { control: None, image };
+                let outside = frame.left() as u32 + frame.width() as u32 > width as u32
+                    || frame.top() as u32 + frame.height() as u32 > height as u32;
+                let cropped = if outside {
+                    crop(&frame, global_palette, width, height).ok()
+                } else {
+                    None
+                };
+
+                let mut control = control.take();
+                if carried_delay > 0 {
+                    let content = control.get_or_insert_with(|| vec![0; 4]);
+                    let delay = u16::from_le_bytes([content[1], content[2]]).saturating_add(carried_delay);
+                    content[1..3].copy_from_slice(&delay.to_le_bytes());
+                    carried_delay = 0;
+                }
+
+                match cropped {
+                    // nothing of the frame is visible, only its delay is kept
+                    Some(None) => {
+                        carried_delay = control.map_or(0, |content| u16::from_le_bytes([content[1], content[2]]));
+                        fixes.push(Fix::new(
+                            "frame-outside-canvas",
+                            "dropped the frame because it lies completely outside the canvas".to_string(),
+                            Some(frames),
+                        ));
+                    }
+                    Some(Some(image)) => {
+                        if let Some(content) = control {
+                            write_extension(&mut out, GRAPHIC_CONTROL, &[&content]);
+                        }
+                        out.extend_from_slice(&image);
+                        fixes.push(Fix::new(
+                            "frame-outside-canvas",
+                            "cropped the frame to the canvas".to_string(),
+                            Some(frames),
+                        ));
+                    }
+                    // frames that can't be decoded are kept as they are
+                    None => {
+                        if let Some(content) = control {
+                            write_extension(&mut out, GRAPHIC_CONTROL, &[&content]);
+                        }
+                        out.extend_from_slice(image);
+                    }
+                }
+                frames += 1;
+            }
+            Ok(Block::Trailer) => trailer = true,
+            Err(_) if start == data.len() => {}
+            Err(e) => fixes.push(Fix::new(
+                "truncated",
+                format!("dropped the incomplete data from byte {} on: {}", start, e),
+                Some(frames),
+            )),
+        }
+    }
+
+    out.push(0x3b);
+    if !trailer {
+        fixes.push(Fix::new("missing-trailer", "appended the trailer".to_string(), None));
+    } else if blocks.offset() < data.len() {
+        fixes.push(Fix::new(
+            "data-after-trailer",
+            format!("dropped {} bytes after the trailer", data.len() - blocks.offset()),
+            None,
+        ));
+    }
+
+    Ok((out, fixes))
+}
+
+/// Crops a frame to the part that lies within a canvas of `width` x `height`
+/// pixels and returns its new image block, `None` if nothing of it is visible.
+fn crop(frame: &RawFrame, global_palette: &[u8], width: u16, height: u16) -> Result<Option<Vec<u8>>, String> {
+    if frame.left() >= width || frame.top() >= height {
+        return Ok(None);
+    }
+
+    // decode the frame on its own, as a gif with a canvas of the size of the frame
+    let mut single = b"GIF89a".to_vec();
+    single.extend_from_slice(&frame.width().to_le_bytes());
+    single.extend_from_slice(&frame.height().to_le_bytes());
+    single.extend_from_slice(&[
+        if global_palette.is_empty() {
+            0
+        } else {
+            0x80 | palette_bits(global_palette)
+        },
+        0,
+        0,
+    ]);
+    single.extend_from_slice(global_palette);
+    single.push(frame.image[0]);
+    single.extend_from_slice(&[0; 4]);
+    single.extend_from_slice(&frame.image[5..]);
+    single.push(0x3b);
+
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(&single[..]).map_err(|e| e.to_string())?;
+    let decoded = decoder
+        .read_next_frame()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "the frame has no pixels".to_string())?;
+
+    let visible_width = (width - frame.left()).min(frame.width());
+    let visible_height = (height - frame.top()).min(frame.height());
+    let indices: Vec<u8> = decoded
+        .buffer
+        .chunks_exact(frame.width() as usize)
+        .take(visible_height as usize)
+        .flat_map(|row| &row[..visible_width as usize])
+        .cloned()
+        .collect();
+    let cropped = Frame {
+        left: frame.left(),
+        top: frame.top(),
+        width: visible_width,
+        height: visible_height,
+        buffer: Cow::Owned(indices),
+        palette: decoded.palette.clone(),
+        ..Frame::default()
+    };
+
+    // the encoder writes a whole gif, only the image block of the frame is kept
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut encoded, width, height, global_palette).map_err(|e| e.to_string())?;
+        encoder.write_frame(&cropped).map_err(|e| e.to_string())?;
+    }
+    let frames = scan::raw_frames(&encoded)?;
+    let image = frames
+        .first()
+        .ok_or_else(|| "the frame couldn't be encoded".to_string())?
+        .image;
+
+    Ok(Some(image.to_vec()))
+}
+
+/// The size field of the packed flags for a palette, which holds 2^(n + 1) colors.
+fn palette_bits(palette: &[u8]) -> u8 {
+    let colors = palette.len() / 3;
+    (0..8).find(|&bits| 2 << bits >= colors).unwrap_or(7)
+}
+
+/// Fixes the defects of a gif that `validate_gif` reports where that's possible
+///
+/// Returns an object `{ data, fixes }` with the repaired gif and what was changed.
+/// Every fix has the `code` of the issue it fixes, a `message` and the `frame` it
+/// is about if any. These are fixed:
+///
+/// - a missing trailer is appended and anything after the trailer is dropped
+/// - incomplete data at the end of a truncated gif is dropped
+/// - loop extensions that can't be read are replaced by valid ones
+/// - graphic control extensions get the size of 4 bytes they should have
+/// - an empty canvas gets the size of the frames
+/// - frames that extend past the canvas are cropped to it, frames that lie
+///   completely outside of it are dropped and their delay is added to the next one
+///
+/// Frames without a palette and zero delays are left alone. Fails if `data` isn't
+/// a gif at all.
+#[wasm_bindgen]
+pub fn repair_gif(data: &[u8]) -> Result<JsRepairResult, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let (repaired, fixes) = repair(data).map_err(|e| js_error(&e))?;
+
+    Ok(RepairResult {
+        data: js_sys::Uint8Array::from(&repaired[..]),
+        fixes: fixes
+            .into_iter()
+            .map(|fix| RepairFix {
+                code: fix.code,
+                message: fix.message,
+                frame: fix.frame.map(|frame| frame as u32),
+            })
+            .collect(),
+    }
+    .into_object())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::Repeat;
+
+    /// A looping gif of 2x2 pixels with a frame of 2x2 pixels at every position and
+    /// delay next to it.
+    fn animation(frames: &[((u16, u16), u16)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2, &[0, 0, 0, 255, 255, 255]).unwrap();
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for &((left, top), delay) in frames {
+                encoder
+                    .write_frame(&Frame {
+                        left,
+                        top,
+                        width: 2,
+                        height: 2,
+                        delay,
+                        buffer: Cow::Owned(vec![0, 1, 1, 0]),
+                        ..Frame::default()
+                    })
+                    .unwrap();
+            }
+        }
+        data
+    }
+
+    /// The position, size and delay of every frame.
+    fn frames(data: &[u8]) -> Vec<(u16, u16, u16, u16, u16)> {
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let mut reader = options.read_info(data).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_next_frame().unwrap() {
+            frames.push((frame.left, frame.top, frame.width, frame.height, frame.delay));
+        }
+        frames
+    }
+
+    fn codes(fixes: &[Fix]) -> Vec<&'static str> {
+        fixes.iter().map(|fix| fix.code).collect()
+    }
+
+    #[test]
+    fn valid_gifs_are_unchanged() {
+        let data = animation(&[((0, 0), 10), ((0, 0), 20)]);
+        let (repaired, fixes) = repair(&data).unwrap();
+        assert_eq!(repaired, data);
+        assert!(fixes.is_empty());
+    }
+
+    #[test]
+    fn the_trailer_is_appended() {
+        let data = animation(&[((0, 0), 10)]);
+        let (repaired, fixes) = repair(&data[..data.len() - 1]).unwrap();
+        assert_eq!(repaired, data);
+        assert_eq!(codes(&fixes), vec!["missing-trailer"]);
+    }
+
+    #[test]
+    fn data_after_the_trailer_is_dropped() {
+        let mut data = animation(&[((0, 0), 10)]);
+        let length = data.len();
+        data.extend_from_slice(&[1, 2, 3]);
+        let (repaired, fixes) = repair(&data).unwrap();
+        assert_eq!(repaired, &data[..length]);
+        assert_eq!(codes(&fixes), vec!["data-after-trailer"]);
+    }
+
+    #[test]
+    fn truncated_frames_are_dropped() {
+        let data = animation(&[((0, 0), 10), ((0, 0), 20)]);
+        let (repaired, fixes) = repair(&data[..data.len() - 4]).unwrap();
+        assert_eq!(frames(&repaired), vec![(0, 0, 2, 2, 10)]);
+        assert_eq!(codes(&fixes), vec!["truncated", "missing-trailer"]);
+    }
+
+    #[test]
+    fn frames_are_cropped_to_the_canvas() {
+        let (repaired, fixes) = repair(&animation(&[((1, 1), 10)])).unwrap();
+        assert_eq!(frames(&repaired), vec![(1, 1, 1, 1, 10)]);
+        assert_eq!(codes(&fixes), vec!["frame-outside-canvas"]);
+    }
+
+    #[test]
+    fn frames_outside_of_the_canvas_pass_on_their_delay() {
+        let (repaired, fixes) = repair(&animation(&[((0, 0), 10), ((2, 0), 20), ((0, 0), 5)])).unwrap();
+        assert_eq!(frames(&repaired), vec![(0, 0, 2, 2, 10), (0, 0, 2, 2, 25)]);
+        assert_eq!(fixes[0].frame, Some(1));
+    }
+
+    #[test]
+    fn empty_canvases_get_the_size_of_the_frames() {
+        let mut data = animation(&[((0, 0), 10), ((1, 0), 10)]);
+        data[6..10].copy_from_slice(&[0; 4]);
+        let (repaired, fixes) = repair(&data).unwrap();
+        assert_eq!(&repaired[6..10], &[3, 0, 2, 0]);
+        assert_eq!(codes(&fixes), vec!["empty-canvas"]);
+    }
+
+    #[test]
+    fn loop_extensions_are_rewritten() {
+        let mut data = animation(&[((0, 0), 10)]);
+        let start = data.windows(11).position(|window| window == b"NETSCAPE2.0").unwrap();
+        // the sub-block with the loop count has an unknown id
+        data[start + 12] = 2;
+        let (repaired, fixes) = repair(&data).unwrap();
+        assert_eq!(repaired, animation(&[((0, 0), 10)]));
+        assert_eq!(codes(&fixes), vec!["invalid-loop-extension"]);
+    }
+
+    #[test]
+    fn palettes_hold_a_power_of_two_colors() {
+        assert_eq!(palette_bits(&[0; 2 * 3]), 0);
+        assert_eq!(palette_bits(&[0; 3 * 3]), 1);
+        assert_eq!(palette_bits(&[0; 256 * 3]), 7);
+    }
+}
@@ -0,0 +1,215 @@
+//! Reversing a gif without keeping every composed frame in memory.
+//!
+//! Decoding a gif means drawing frame after frame onto a canvas. To play that
+//! backwards it's enough to remember, for every frame, the pixels of the canvas
+//! it overwrote. Starting with the last state of the canvas, these pixels are
+//! put back frame by frame, which gives the canvas before each frame in reverse
+//! order. Drawing the frame onto it once more gives the composed frame.
+//!
+//! Besides a single canvas only the palette indices of the frames and the
+//! overwritten pixels are kept, which is usually a lot less than a full canvas
+//! per frame.
+//!
+//! Many gifs don't need any of that, since every frame paints the full canvas
+//! without transparent pixels and doesn't depend on the frames before it. Their
+//! frames are simply copied in reverse order without decoding them.
+
+use compose::{self, background_color, clear_color};
+use gif::{ColorOutput, DecodeOptions, DisposalMethod, Frame};
+use limits::Limits;
+use scan::{self, RawFrame};
+use std::borrow::Cow;
+use wasm_bindgen::prelude::*;
+use {js_error, lenient, FrameData};
+
+/// Returns the frames of the gif if every frame covers the full canvas and has no
+/// transparent color. Then how frames are disposed doesn't
+/// matter either, since the next frame paints over everything.
+pub(crate) fn opaque_frames(data: &[u8]) -> Option<Vec<RawFrame<'_>>> {
+    let header = scan::header(data).ok()?;
+    let frames = scan::raw_frames(data).ok()?;
+    let opaque = frames.iter().all(|frame| {
+        frame.left() == 0
+            && frame.top() == 0
+            && frame.width() == header.width
+            && frame.height() == header.height
+            && !frame.has_transparency()
+    });
+    if !opaque || frames.is_empty() {
+        return None;
+    }
+
+    Some(frames)
+}
+
+/// A frame together with what it changed on the canvas.
+struct Delta {
+    /// Position, timing, disposal, palette and pixels of the frame
+    frame: Frame<'static>,
+    /// The RGBA pixels of the canvas the frame or its disposal overwrote, in the
+    /// order `for_each_pixel` visits them
+    overwritten: Vec<u8>,
+}
+
+/// A decoded gif that hands out its composed frames from last to first.
+pub(crate) struct Reversed {
+    pub width: u16,
+    pub height: u16,
+    pub global_palette: Vec<u8>,
+    /// The state of the canvas after the frame that comes next has been disposed
+    canvas: Vec<u8>,
+    deltas: Vec<Delta>,
+    /// What lenient decoding stopped at
+    pub warnings: Vec<String>,
+}
+
+/// Decodes a gif and keeps what is needed to compose its frames in reverse.
+/// `after_frame` is called with the number of frames decoded so far after every
+/// frame, decoding stops as soon as it fails.
+pub(crate) fn decode<F>(data: &[u8], limits: &Limits, mut after_frame: F) -> Result<Reversed, JsValue>
+where
+    F: FnMut(usize) -> Result<(), JsValue>,
+{
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut reader = options.read_info(data).map_err(|e| js_error(&e.to_string()))?;
+
+    let width = reader.width();
+    let height = reader.height();
+    limits.check_size(width, height).map_err(|e| js_error(&e))?;
+    let global_palette = reader
+        .global_palette()
+        .map(|palette| palette.to_vec())
+        .unwrap_or_default();
+    let background = background_color(reader.global_palette(), reader.bg_color());
+
+    let mut canvas = vec![0; width as usize * height as usize * 4];
+    let mut deltas = Vec::new();
+    let mut warnings = Vec::new();
+    loop {
+        let mut frame = match reader.next_frame_info() {
+            Ok(Some(frame)) => frame.clone(),
+            Ok(None) => break,
+            Err(e) => {
+                warnings.push(lenient::stop(limits, deltas.len(), e).map_err(|e| js_error(&e))?);
+                break;
+            }
+        };
+        if !limits.keeps_frame(deltas.len() + 1).map_err(|e| js_error(&e))? {
+            break;
+        }
+        limits.check_size(frame.width, frame.height).map_err(|e| js_error(&e))?;
+        let mut indices = vec![0; reader.buffer_size()];
+        if let Err(e) = reader.read_into_buffer(&mut indices) {
+            warnings.push(lenient::stop(limits, deltas.len(), e).map_err(|e| js_error(&e))?);
+            break;
+        }
+        frame.buffer = Cow::Owned(indices);
+        let frame: Frame<'static> = frame;
+
+        if deltas.is_empty() {
+            let empty = clear_color(&frame, background);
+            for pixel in canvas.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&empty);
+            }
+        }
+
+        // disposing to the background clears pixels the frame didn't draw as well
+        let clears = frame.dispose == DisposalMethod::Background;
+        let mut overwritten = Vec::new();
+        for_each_pixel(&frame, width, height, |offset, index| {
+            if clears || Some(index) != frame.transparent {
+                overwritten.extend_from_slice(&canvas[offset..offset + 4]);
+            }
+        });
+
+        draw(&mut canvas, &frame, width, height, &global_palette);
+        match frame.dispose {
+            DisposalMethod::Background => {
+                let cleared = clear_color(&frame, background);
+                for_each_pixel(&frame, width, height, |offset, _| {
+                    canvas[offset..offset + 4].copy_from_slice(&cleared);
+                })
+            }
+            DisposalMethod::Previous => restore(&mut canvas, &frame, width, height, &overwritten, false),
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+
+        deltas.push(Delta { frame, overwritten });
+        after_frame(deltas.len())?;
+    }
+
+    Ok(Reversed {
+        width,
+        height,
+        global_palette,
+        canvas,
+        deltas,
+        warnings,
+    })
+}
+
+impl Reversed {
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+}
+
+impl Iterator for Reversed {
+    type Item = FrameData;
+
+    /// Returns the composed frames starting with the last one
+    fn next(&mut self) -> Option<FrameData> {
+        let Delta { frame, overwritten } = self.deltas.pop()?;
+
+        // undo the disposal and the drawing, which gives the canvas the frame was
+        // drawn on. frames disposed to the previous state have left it like that.
+        match frame.dispose {
+            DisposalMethod::Previous => {}
+            DisposalMethod::Background => {
+                restore(&mut self.canvas, &frame, self.width, self.height, &overwritten, true)
+            }
+            DisposalMethod::Any | DisposalMethod::Keep => {
+                restore(&mut self.canvas, &frame, self.width, self.height, &overwritten, false)
+            }
+        }
+
+        let mut rgba = self.canvas.clone();
+        draw(&mut rgba, &frame, self.width, self.height, &self.global_palette);
+        Some(FrameData {
+            width: self.width,
+            height: self.height,
+            rgba,
+            delay: frame.delay,
+        })
+    }
+}
+
+/// Calls `f` with the byte offset in the canvas and the palette index of every
+/// pixel of the frame that lies within the canvas, row by row.
+fn for_each_pixel<F: FnMut(usize, u8)>(frame: &Frame, width: u16, height: u16, mut f: F) {
+    for (start, row) in compose::visible_rows(frame, &frame.buffer, width, height) {
+        for (x, &index) in row.iter().enumerate() {
+            f((start + x) * 4, index);
+        }
+    }
+}
+
+/// Draws the pixels of the frame that aren't transparent onto the canvas.
+fn draw(canvas: &mut [u8], frame: &Frame, width: u16, height: u16, global_palette: &[u8]) {
+    let palette = frame.palette.as_ref().map_or(global_palette, |palette| &palette[..]);
+    compose::draw_frame(canvas, width, height, frame, &frame.buffer, palette, &mut Vec::new());
+}
+
+/// Puts the `overwritten` pixels back, either where the frame drew or, with
+/// `cleared` set, everywhere the frame covers.
+fn restore(canvas: &mut [u8], frame: &Frame, width: u16, height: u16, overwritten: &[u8], cleared: bool) {
+    let mut pixels = overwritten.chunks(4);
+    for_each_pixel(frame, width, height, |offset, index| {
+        if cleared || Some(index) != frame.transparent {
+            if let Some(pixel) = pixels.next() {
+                canvas[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+    });
+}
@@ -0,0 +1,367 @@
+//! A lightweight reader for the block structure of a gif.
+//!
+//! The gif crate always decompresses the image data of every frame it reads. A lot
+//! of questions about a gif, like how many frames it has, can be answered by just
+//! walking over its blocks and skipping the image data, which is a lot faster.
+
+/// Labels of the extensions that are interpreted.
+pub const GRAPHIC_CONTROL: u8 = 0xf9;
+#[cfg(feature = "wasm")]
+const COMMENT: u8 = 0xfe;
+pub const APPLICATION: u8 = 0xff;
+
+/// A block of the gif data stream.
+pub enum Block<'a> {
+    /// An extension with its label and the content of all its sub-blocks
+    Extension {
+        label: u8,
+        sub_blocks: Vec<&'a [u8]>,
+    },
+    /// A frame with its descriptor, local palette and compressed image data, which
+    /// isn't decompressed
+    #[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+    Image(&'a [u8]),
+    Trailer,
+}
+
+/// The header and logical screen descriptor at the start of a gif. Without the
+/// `wasm` feature it's only read to check the gif.
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+pub struct Header {
+    /// `87a` or `89a`
+    pub version: String,
+    pub width: u16,
+    pub height: u16,
+    /// Number of colors in the global palette, 0 if there is none
+    pub global_palette_size: usize,
+    /// Index of the color in the global palette that fills the canvas where no
+    /// frame has been drawn
+    pub background_color_index: u8,
+}
+
+/// Global properties of an animation and the timing of its frames.
+pub struct Summary {
+    pub width: u16,
+    pub height: u16,
+    /// Number of colors in the global palette, 0 if there is none
+    pub global_palette_size: usize,
+    /// Delay of every frame in hundredths of a second
+    pub delays: Vec<u16>,
+    /// The loop count stored in the NETSCAPE2.0 extension where 0 means forever,
+    /// `None` if the gif doesn't have the extension
+    pub repeat: Option<u16>,
+}
+
+impl Summary {
+    /// The loop count in the convention of `EncodeOptions::loop_count`: `0` plays the
+    /// animation once, `-1` repeats it forever.
+    pub fn loop_count(&self) -> i32 {
+        match self.repeat {
+            None => 0,
+            Some(0) => -1,
+            Some(count) => count as i32,
+        }
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+/// Size of a color table in bytes given the packed flags of a descriptor.
+fn palette_length(flags: u8) -> usize {
+    if flags & 0x80 != 0 {
+        3 * (2 << (flags & 0x07))
+    } else {
+        0
+    }
+}
+
+/// Reads the header of a gif.
+pub fn header(data: &[u8]) -> Result<Header, String> {
+    if data.len() < 13 || &data[..3] != b"GIF" {
+        return Err("not a gif".to_string());
+    }
+
+    Ok(Header {
+        version: String::from_utf8_lossy(&data[3..6]).into_owned(),
+        width: u16_at(data, 6),
+        height: u16_at(data, 8),
+        global_palette_size: palette_length(data[10]) / 3,
+        background_color_index: data[11],
+    })
+}
+
+/// Iterates over all blocks after the header. Stops after the trailer or the first
+/// error, e.g. when the data is truncated.
+pub struct Blocks<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+/// Returns an iterator over the blocks of the gif.
+pub fn blocks(data: &[u8]) -> Result<Blocks<'_>, String> {
+    if data.len() < 13 || &data[..3] != b"GIF" {
+        return Err("not a gif".to_string());
+    }
+
+    Ok(Blocks {
+        data,
+        offset: 13 + palette_length(data[10]),
+        done: false,
+    })
+}
+
+impl<'a> Blocks<'a> {
+    /// Position of the next block, right after the trailer once it was read.
+    #[cfg(feature = "wasm")]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .data
+            .get(self.offset)
+            .ok_or_else(|| format!("unexpected end of data at byte {}", self.offset))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.offset + count > self.data.len() {
+            return Err(format!("unexpected end of data at byte {}", self.data.len()));
+        }
+        let bytes = &self.data[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    /// Reads data sub-blocks up to and including the terminating empty block.
+    fn sub_blocks(&mut self) -> Result<Vec<&'a [u8]>, String> {
+        let mut blocks = Vec::new();
+        loop {
+            let length = self.byte()? as usize;
+            if length == 0 {
+                return Ok(blocks);
+            }
+            blocks.push(self.bytes(length)?);
+        }
+    }
+
+    fn next_block(&mut self) -> Result<Block<'a>, String> {
+        match self.byte()? {
+            0x21 => {
+                let label = self.byte()?;
+                let sub_blocks = self.sub_blocks()?;
+                Ok(Block::Extension { label, sub_blocks })
+            }
+            0x2c => {
+                let start = self.offset - 1;
+                let descriptor = self.bytes(9)?;
+
+                // skip the local palette, the minimum code size and the image data
+                self.bytes(palette_length(descriptor[8]))?;
+                self.byte()?;
+                self.sub_blocks()?;
+
+                Ok(Block::Image(&self.data[start..self.offset]))
+            }
+            0x3b => Ok(Block::Trailer),
+            other => Err(format!("unknown block 0x{:02x} at byte {}", other, self.offset - 1)),
+        }
+    }
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Result<Block<'a>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let block = self.next_block();
+        self.done = matches!(block, Ok(Block::Trailer) | Err(_));
+        Some(block)
+    }
+}
+
+/// Counts the frames of a gif without decoding them. Frames after a defect are
+/// not counted.
+#[cfg(feature = "wasm")]
+pub fn count_frames(data: &[u8]) -> usize {
+    match blocks(data) {
+        Ok(blocks) => blocks
+            .filter_map(|block| block.ok())
+            .filter(|block| matches!(block, Block::Image(_)))
+            .count(),
+        Err(_) => 0,
+    }
+}
+
+/// Reads the loop count from an application extension if it's a NETSCAPE2.0
+/// extension.
+fn netscape_loops(sub_blocks: &[&[u8]]) -> Option<u16> {
+    match sub_blocks {
+        [b"NETSCAPE2.0", loops, ..] if loops.len() >= 3 && loops[0] == 1 => Some(u16_at(loops, 1)),
+        _ => None,
+    }
+}
+
+/// Reads the size, palette size, frame delays and loop count of a gif without
+/// decoding its frames. Frames after a defect are left out.
+pub fn summary(data: &[u8]) -> Result<Summary, String> {
+    let mut summary = Summary {
+        width: 0,
+        height: 0,
+        global_palette_size: 0,
+        delays: Vec::new(),
+        repeat: None,
+    };
+
+    let mut delay = 0;
+    for block in blocks(data)?.filter_map(|block| block.ok()) {
+        match block {
+            Block::Extension {
+                label: GRAPHIC_CONTROL,
+                sub_blocks,
+            } => {
+                if let Some(control) = sub_blocks.first().filter(|control| control.len() >= 3) {
+                    delay = u16_at(control, 1);
+                }
+            }
+            Block::Extension {
+                label: APPLICATION,
+                sub_blocks,
+            } => {
+                summary.repeat = netscape_loops(&sub_blocks).or(summary.repeat);
+            }
+            Block::Image(_) => {
+                summary.delays.push(delay);
+                delay = 0;
+            }
+            _ => {}
+        }
+    }
+
+    summary.width = u16_at(data, 6);
+    summary.height = u16_at(data, 8);
+    summary.global_palette_size = palette_length(data[10]) / 3;
+
+    Ok(summary)
+}
+
+/// A frame as it is stored in the gif.
+#[cfg(feature = "wasm")]
+pub struct RawFrame<'a> {
+    /// The content of the graphic control extension that belongs to the frame
+    pub control: Option<&'a [u8]>,
+    /// The image block from the image separator up to the end of the image data
+    pub image: &'a [u8],
+}
+
+#[cfg(feature = "wasm")]
+impl<'a> RawFrame<'a> {
+    pub fn left(&self) -> u16 {
+        u16_at(self.image, 1)
+    }
+
+    pub fn top(&self) -> u16 {
+        u16_at(self.image, 3)
+    }
+
+    pub fn width(&self) -> u16 {
+        u16_at(self.image, 5)
+    }
+
+    pub fn height(&self) -> u16 {
+        u16_at(self.image, 7)
+    }
+
+    /// Whether one of the palette indices stands for a transparent pixel
+    pub fn has_transparency(&self) -> bool {
+        self.control.is_some_and(|control| control[0] & 0x01 != 0)
+    }
+
+    /// The palette index that stands for a transparent pixel, if any
+    pub fn transparent_index(&self) -> Option<u8> {
+        self.control
+            .filter(|_| self.has_transparency())
+            .map(|control| control[3])
+    }
+
+    /// Delay in hundredths of a second
+    pub fn delay(&self) -> u16 {
+        self.control.map_or(0, |control| u16_at(control, 1))
+    }
+
+    /// The disposal method as stored in the gif, `0` to `3` are defined
+    pub fn disposal(&self) -> u8 {
+        self.control.map_or(0, |control| (control[0] >> 2) & 0x07)
+    }
+
+    pub fn interlaced(&self) -> bool {
+        self.image[9] & 0x40 != 0
+    }
+
+    /// Number of colors in the local palette, 0 if there is none
+    pub fn local_palette_size(&self) -> usize {
+        palette_length(self.image[9]) / 3
+    }
+}
+
+/// Splits a gif into its frames without decoding them. Unlike the other functions
+/// a defect anywhere in the gif is an error.
+#[cfg(feature = "wasm")]
+pub fn raw_frames(data: &[u8]) -> Result<Vec<RawFrame<'_>>, String> {
+    split_frames(blocks(data)?)
+}
+
+/// Like `raw_frames` but frames after a defect are left out.
+#[cfg(feature = "wasm")]
+pub fn frames_until_defect(data: &[u8]) -> Result<Vec<RawFrame<'_>>, String> {
+    split_frames(blocks(data)?.map_while(|block| block.ok()).map(Ok))
+}
+
+/// Pairs every image block with the graphic control extension before it.
+#[cfg(feature = "wasm")]
+fn split_frames<'a, I: Iterator<Item = Result<Block<'a>, String>>>(blocks: I) -> Result<Vec<RawFrame<'a>>, String> {
+    let mut frames = Vec::new();
+    let mut control = None;
+    for block in blocks {
+        match block? {
+            Block::Extension {
+                label: GRAPHIC_CONTROL,
+                sub_blocks,
+            } => {
+                control = sub_blocks.first().cloned().filter(|control| control.len() >= 4);
+            }
+            Block::Image(image) => frames.push(RawFrame {
+                control: control.take(),
+                image,
+            }),
+            _ => {}
+        }
+    }
+    Ok(frames)
+}
+
+/// Extracts the text of all comment extensions. The specification asks for 7 bit
+/// ASCII but some tools write UTF-8, so the text is decoded as UTF-8 and invalid
+/// bytes are replaced.
+#[cfg(feature = "wasm")]
+pub fn comments(data: &[u8]) -> Result<Vec<String>, String> {
+    let comments = blocks(data)?
+        .filter_map(|block| match block {
+            Ok(Block::Extension {
+                label: COMMENT,
+                sub_blocks,
+            }) => Some(String::from_utf8_lossy(&sub_blocks.concat()).into_owned()),
+            _ => None,
+        })
+        .collect();
+
+    Ok(comments)
+}
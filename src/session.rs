@@ -0,0 +1,153 @@
+//! Editing a gif in several steps without decoding it again for every step.
+
+use encode::{gif_from_frames, EncodeOptions};
+use filters;
+use timeline;
+use types::JsDecodedFrame;
+use wasm_bindgen::prelude::*;
+use {collect_frames, decode_data, frame_to_js, js_error, metadata, scan, FrameData, Limits};
+
+/// A decoded gif that can be changed step by step and encoded whenever needed.
+///
+/// The gif is decoded once when the session is created. All methods work on the
+/// fully composed frames in memory, so interactive editors can apply and preview
+/// changes without paying for decoding every time. Call `free()` once the session
+/// isn't needed anymore.
+#[wasm_bindgen]
+pub struct GifSession {
+    width: u16,
+    height: u16,
+    global_palette: Vec<u8>,
+    loop_count: Option<i32>,
+    frames: Vec<FrameData>,
+    warnings: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl GifSession {
+    /// Decodes the gif `data` within `limits`
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &[u8], limits: &Limits) -> Result<GifSession, JsValue> {
+        console_error_panic_hook::set_once();
+
+        let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+        let mut reader = decode_data(data)?;
+        let (width, height, global_palette) = metadata(&reader);
+        let (frames, warnings) = collect_frames(&mut reader, width, height, limits)?;
+
+        Ok(GifSession {
+            width,
+            height,
+            global_palette,
+            loop_count: Some(summary.loop_count()),
+            frames,
+            warnings,
+        })
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns what lenient decoding stopped at when the gif was decoded, see
+    /// `Limits.lenient`
+    #[wasm_bindgen(unchecked_return_type = "string[]")]
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.clone()
+    }
+
+    /// Returns the frame at `index` as an object `{ width, height, delay, rgba }`
+    pub fn get_frame(&self, index: usize) -> Result<JsDecodedFrame, JsValue> {
+        self.frames.get(index).map(frame_to_js).ok_or_else(|| {
+            js_error(&format!(
+                "frame {} does not exist, the gif has {} frames",
+                index,
+                self.frames.len()
+            ))
+        })
+    }
+
+    /// Returns the frame that is on screen `ms` milliseconds into playback, see
+    /// `get_frame_at_time`
+    pub fn get_frame_at_time(&self, ms: f64) -> Result<JsDecodedFrame, JsValue> {
+        timeline::index_at_time(self.frames.iter().map(|frame| frame.delay), ms)
+            .map(|index| frame_to_js(&self.frames[index]))
+            .ok_or_else(|| js_error("the gif has no frames"))
+    }
+
+    pub fn reverse(&mut self) {
+        self.frames.reverse();
+    }
+
+    /// Keeps the frames from `start_frame` up to but not including `end_frame`
+    pub fn trim(&mut self, start_frame: usize, end_frame: usize) -> Result<(), JsValue> {
+        timeline::trim(&mut self.frames, start_frame, end_frame).map_err(|e| js_error(&e))
+    }
+
+    /// Keeps only every `keep_every_n`th frame, see `decimate_gif`
+    pub fn decimate(&mut self, keep_every_n: usize) -> Result<(), JsValue> {
+        if keep_every_n == 0 {
+            return Err(js_error("keep_every_n has to be at least 1"));
+        }
+
+        timeline::decimate(&mut self.frames, keep_every_n);
+        Ok(())
+    }
+
+    /// Puts the frames in the given order, see `reorder_gif`
+    pub fn reorder(&mut self, order: &[u32], allow_duplicates: bool) -> Result<(), JsValue> {
+        let mut entries = timeline::entries(&self.frames);
+        timeline::reorder(&mut entries, order, allow_duplicates).map_err(|e| js_error(&e))?;
+        self.frames = timeline::arrange(std::mem::take(&mut self.frames), &entries);
+        Ok(())
+    }
+
+    /// Sets the delay of all frames to `delay_ms`, which has to be a multiple of 10
+    pub fn set_delay(&mut self, delay_ms: u32) -> Result<(), JsValue> {
+        let delay = timeline::delay_from_ms(delay_ms).map_err(|e| js_error(&e))?;
+        for frame in self.frames.iter_mut() {
+            frame.delay = delay;
+        }
+        Ok(())
+    }
+
+    pub fn flip(&mut self, horizontal: bool, vertical: bool) {
+        for frame in self.frames.iter_mut() {
+            filters::flip(frame, horizontal, vertical);
+        }
+    }
+
+    pub fn grayscale(&mut self) {
+        for frame in self.frames.iter_mut() {
+            filters::grayscale(frame);
+        }
+    }
+
+    pub fn invert(&mut self) {
+        for frame in self.frames.iter_mut() {
+            filters::invert(frame);
+        }
+    }
+
+    /// Encodes the current state of the gif. The session can be changed and
+    /// encoded again afterwards.
+    pub fn encode(&self, options: &EncodeOptions) -> Result<Vec<u8>, JsValue> {
+        let options = options.with_default_loop_count(self.loop_count);
+        gif_from_frames(
+            &self.frames,
+            self.width,
+            self.height,
+            self.global_palette.clone(),
+            &options,
+            &|_| {},
+        )
+    }
+}
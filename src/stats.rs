@@ -0,0 +1,119 @@
+//! Measuring where the time of an operation goes.
+//!
+//! Decoding and encoding note how long every phase takes and how large the
+//! buffers get. Operations that transform a gif start over at zero, the numbers
+//! of the last one can be read with `take_stats()`.
+
+use std::cell::Cell;
+#[cfg(feature = "wasm")]
+use types::{JsOperationStats, OperationStats};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// A part of an operation that is timed separately.
+#[derive(Clone, Copy)]
+pub enum Phase {
+    /// Reading and decompressing frames
+    Decode,
+    /// Drawing frames onto the canvas
+    Compose,
+    /// Applying the transform to the decoded frames
+    #[cfg(feature = "wasm")]
+    Transform,
+    /// Reducing the colors of frames
+    #[cfg(feature = "wasm")]
+    Quantize,
+    /// Compressing and writing frames
+    #[cfg(feature = "wasm")]
+    Encode,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Stats {
+    /// Milliseconds spent in every phase, in the order of `Phase`
+    phases: [f64; 5],
+    /// Most bytes the decoded frames took up at the same time
+    peak_frame_bytes: usize,
+    /// Size of the written gif
+    #[cfg(feature = "wasm")]
+    output_bytes: usize,
+}
+
+thread_local! {
+    static STATS: Cell<Option<Stats>> = const { Cell::new(None) };
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance)]
+    fn now() -> f64;
+}
+
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+fn now() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64() * 1000.0)
+}
+
+fn update<F: FnOnce(&mut Stats)>(f: F) {
+    STATS.with(|stats| {
+        let mut current = stats.get().unwrap_or_default();
+        f(&mut current);
+        stats.set(Some(current));
+    });
+}
+
+/// Starts measuring a new operation.
+#[cfg(feature = "wasm")]
+pub fn start() {
+    STATS.with(|stats| stats.set(Some(Stats::default())));
+}
+
+/// Runs `f` and adds the time it takes to `phase`.
+pub fn time<T, F: FnOnce() -> T>(phase: Phase, f: F) -> T {
+    let start = now();
+    let result = f();
+    let elapsed = now() - start;
+    update(|stats| stats.phases[phase as usize] += elapsed);
+    result
+}
+
+/// Notes that the decoded frames take up `bytes` bytes at the moment.
+pub fn frame_bytes(bytes: usize) {
+    update(|stats| stats.peak_frame_bytes = stats.peak_frame_bytes.max(bytes));
+}
+
+/// Notes that `bytes` bytes of the gif were written.
+#[cfg(feature = "wasm")]
+pub fn output_bytes(bytes: usize) {
+    update(|stats| stats.output_bytes += bytes);
+}
+
+/// Returns what the last operation spent its time on and clears it
+///
+/// The object `{ decodeMs, composeMs, transformMs, quantizeMs, encodeMs,
+/// peakFrameBytes, outputBytes }` holds the milliseconds spent in every phase, the
+/// most bytes the decoded frames took up at the same time and the size of the
+/// written gif. Returns `undefined` if nothing was measured since the last call.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn take_stats() -> Option<JsOperationStats> {
+    let stats = STATS.with(|stats| stats.take())?;
+
+    let [decode_ms, compose_ms, transform_ms, quantize_ms, encode_ms] = stats.phases;
+    Some(
+        OperationStats {
+            decode_ms,
+            compose_ms,
+            transform_ms,
+            quantize_ms,
+            encode_ms,
+            peak_frame_bytes: stats.peak_frame_bytes as f64,
+            output_bytes: stats.output_bytes as f64,
+        }
+        .into_object(),
+    )
+}
@@ -0,0 +1,227 @@
+//! Decoding of gifs that arrive in chunks, e.g. from a `fetch` response body.
+
+use compose::{background_color, Compositor};
+use gif::{Block, Decoded, Frame, StreamingDecoder};
+use limits::Limits;
+use std::collections::VecDeque;
+use types::JsDecodedFrame;
+use wasm_bindgen::prelude::*;
+use {frame_to_js, js_error, lenient, FrameData};
+
+/// A gif decoder that is fed with chunks of data.
+///
+/// Call `push()` whenever new bytes arrive. Every frame that was completely
+/// received is composed and queued up, `next_frame()` hands them out in order as
+/// objects `{ width, height, delay, rgba }` just like `decode_frames`.
+#[wasm_bindgen]
+pub struct GifDecoderSession {
+    decoder: StreamingDecoder,
+    global_palette: Vec<u8>,
+    /// Index of the background color in the global palette
+    background_index: Option<u8>,
+    compositor: Option<Compositor>,
+    /// The frame that is currently decoded and its palette indices received so far
+    current: Option<Frame<'static>>,
+    indices: Vec<u8>,
+    frames: VecDeque<FrameData>,
+    decoded_frames: usize,
+    limits: Limits,
+    finished: bool,
+    /// What lenient decoding stopped at
+    warnings: Vec<String>,
+}
+
+impl Default for GifDecoderSession {
+    fn default() -> GifDecoderSession {
+        GifDecoderSession {
+            decoder: StreamingDecoder::new(),
+            global_palette: Vec::new(),
+            background_index: None,
+            compositor: None,
+            current: None,
+            indices: Vec::new(),
+            frames: VecDeque::new(),
+            decoded_frames: 0,
+            limits: Limits::default(),
+            finished: false,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GifDecoderSession {
+    /// Starts a session that decodes the frames within `limits`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(limits: &Limits) -> GifDecoderSession {
+        console_error_panic_hook::set_once();
+        GifDecoderSession {
+            limits: *limits,
+            ..GifDecoderSession::default()
+        }
+    }
+
+    /// Feeds the next chunk of the gif into the decoder. Throws if the data is not
+    /// a valid gif, unless decoding is lenient. Then the frames received so far are
+    /// kept, the session is finished and `warnings()` tells what went wrong.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), JsValue> {
+        let mut input = chunk;
+
+        while !self.finished {
+            // known once the header has been decoded, which happens before any frame
+            let (width, height) = (self.decoder.width(), self.decoder.height());
+            let (consumed, decoded) = match self.decoder.update(input) {
+                Ok(result) => result,
+                Err(e) => {
+                    let warning = lenient::stop(&self.limits, self.decoded_frames, e).map_err(|e| js_error(&e))?;
+                    self.warnings.push(warning);
+                    self.current = None;
+                    self.finished = true;
+                    break;
+                }
+            };
+            input = &input[consumed..];
+
+            match decoded {
+                // everything that was pushed so far is consumed
+                Decoded::Nothing => break,
+                Decoded::GlobalPalette(palette) => self.global_palette = palette,
+                Decoded::BackgroundColor(index) => self.background_index = Some(index),
+                Decoded::Frame(frame) => {
+                    // the header and the global palette come before the first frame
+                    if self.compositor.is_none() {
+                        self.limits.check_size(width, height).map_err(|e| js_error(&e))?;
+                        let global_palette = Some(&self.global_palette[..]).filter(|palette| !palette.is_empty());
+                        let background = background_color(global_palette, self.background_index.map(usize::from));
+                        self.compositor = Some(Compositor::new(width, height, &self.global_palette, background));
+                    }
+
+                    // frames that were fetched with `next_frame()` don't take up memory anymore
+                    if !self
+                        .limits
+                        .keeps_frame(self.decoded_frames + 1)
+                        .map_err(|e| js_error(&e))?
+                    {
+                        self.finished = true;
+                        break;
+                    }
+                    self.limits
+                        .check_size(frame.width, frame.height)
+                        .map_err(|e| js_error(&e))?;
+                    self.limits
+                        .check_memory(self.frames.len() + 1, width, height)
+                        .map_err(|e| js_error(&e))?;
+                    self.current = Some(frame.clone());
+                    self.indices.clear();
+                }
+                Decoded::Data(data) => self.indices.extend_from_slice(data),
+                Decoded::DataEnd => self.finish_frame()?,
+                Decoded::BlockStart(Block::Trailer) => self.finished = true,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Width of the gif, 0 until the header has been received
+    pub fn width(&self) -> u16 {
+        self.decoder.width()
+    }
+
+    /// Height of the gif, 0 until the header has been received
+    pub fn height(&self) -> u16 {
+        self.decoder.height()
+    }
+
+    /// Number of frames that were decoded and not yet fetched with `next_frame()`
+    pub fn frames_available(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Number of frames decoded so far
+    pub fn frames_decoded(&self) -> usize {
+        self.decoded_frames
+    }
+
+    /// Whether the end of the gif has been reached
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns what lenient decoding stopped at, see `Limits.lenient`
+    #[wasm_bindgen(unchecked_return_type = "string[]")]
+    pub fn warnings(&self) -> Vec<String> {
+        self.warnings.clone()
+    }
+
+    /// Returns the next decoded frame or `undefined` if there is none yet
+    pub fn next_frame(&mut self) -> Option<JsDecodedFrame> {
+        self.frames.pop_front().map(|frame| frame_to_js(&frame))
+    }
+}
+
+impl GifDecoderSession {
+    /// Composes the current frame from the palette indices received for it.
+    fn finish_frame(&mut self) -> Result<(), JsValue> {
+        let frame = match self.current.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let palette = match frame.palette {
+            Some(ref palette) => &palette[..],
+            None => &self.global_palette[..],
+        };
+        if palette.is_empty() {
+            return Err(js_error("no color table available for current frame"));
+        }
+
+        if let Some(ref mut compositor) = self.compositor {
+            let composed = if frame.interlaced {
+                compositor.compose(&frame, &deinterlace(&frame, &self.indices))
+            } else {
+                compositor.compose(&frame, &self.indices)
+            };
+            self.frames.push_back(composed);
+            self.decoded_frames += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Puts the palette `indices` of an interlaced frame back into the right row order.
+fn deinterlace(frame: &Frame, indices: &[u8]) -> Vec<u8> {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let mut rows = vec![0; width * height];
+
+    for (i, &index) in indices.iter().take(width * height).enumerate() {
+        rows[interlaced_row(i / width, height) * width + i % width] = index;
+    }
+
+    rows
+}
+
+/// Interlaced gifs store the rows in four passes: every 8th row starting with row
+/// 0, every 8th row starting with row 4, every 4th row starting with row 2 and
+/// finally every 2nd row starting with row 1. This maps the `n`th stored row to
+/// the row in the image.
+fn interlaced_row(n: usize, height: usize) -> usize {
+    let mut n = n;
+
+    for &(start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)].iter() {
+        let rows = if start < height {
+            (height - start).div_ceil(step)
+        } else {
+            0
+        };
+        if n < rows {
+            return start + n * step;
+        }
+        n -= rows;
+    }
+
+    n
+}
@@ -0,0 +1,177 @@
+//! Text rendering for captions.
+//!
+//! Text is rasterized into a coverage mask once and then blended onto every frame.
+//! The outlines of the glyphs are filled with 4×4 supersampling so the edges are
+//! smooth at any size. The built-in font is a subset of DejaVu Sans Bold with Latin,
+//! Greek and Cyrillic, other scripts need a font of their own.
+
+use filters;
+use font::Font;
+use FrameData;
+
+const SUPERSAMPLING: usize = 4;
+
+/// A grayscale coverage mask where 0 is transparent and 255 fully covered.
+pub struct Mask {
+    pub width: usize,
+    pub height: usize,
+    pub coverage: Vec<u8>,
+}
+
+impl Mask {
+    fn new(width: usize, height: usize) -> Mask {
+        Mask {
+            width,
+            height,
+            coverage: vec![0; width * height],
+        }
+    }
+}
+
+/// Rasterizes `text` with the glyphs of `font`. `size` is the distance from the
+/// top of the highest to the bottom of the lowest glyphs in pixels, lines are
+/// separated by `\n` and centered. Glyphs are placed one after another by their
+/// advance, without kerning or shaping.
+pub fn render(text: &str, size: u32, font: &Font) -> Mask {
+    let em_height = match font.ascender as i32 - font.descender as i32 {
+        height if height > 0 => height as f32,
+        _ => font.units_per_em as f32,
+    };
+    let scale = size.max(1) as f32 / em_height;
+    let line_height = (em_height + font.line_gap.max(0) as f32) * scale;
+    // curves are flattened finely enough that the error stays below a fifth of a pixel
+    let tolerance = 0.2 / scale;
+
+    let lines: Vec<Vec<u16>> = text
+        .lines()
+        .map(|line| line.chars().map(|c| font.glyph(c)).collect())
+        .collect();
+    let line_widths: Vec<f32> = lines
+        .iter()
+        .map(|line| line.iter().map(|&glyph| font.advance(glyph)).sum::<f32>() * scale)
+        .collect();
+    let width = line_widths.iter().cloned().fold(0.0, f32::max).ceil() as usize;
+    let height = match lines.len() {
+        0 => 0,
+        count => ((count - 1) as f32 * line_height + size.max(1) as f32).ceil() as usize,
+    };
+    let mut mask = Mask::new(width, height);
+
+    // the edges of all outlines in pixels as (x0, y0, x1, y1)
+    let mut edges = Vec::new();
+    for (row, line) in lines.iter().enumerate() {
+        let offset_x = (width as f32 - line_widths[row]) / 2.0;
+        let baseline = row as f32 * line_height + font.ascender as f32 * scale;
+        let mut pen = 0.0;
+        for &glyph in line {
+            for contour in font.outline(glyph, tolerance) {
+                let points: Vec<(f32, f32)> = contour
+                    .iter()
+                    .map(|&(x, y)| (offset_x + (pen + x) * scale, baseline - y * scale))
+                    .collect();
+                for (i, &(x0, y0)) in points.iter().enumerate() {
+                    let (x1, y1) = points[(i + 1) % points.len()];
+                    if y0 != y1 {
+                        edges.push((x0, y0, x1, y1));
+                    }
+                }
+            }
+            pen += font.advance(glyph);
+        }
+    }
+
+    // every sample row counts the samples that lie inside the outlines by the
+    // non-zero winding rule
+    let mut covered = vec![0u32; width * height];
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    let columns = (width * SUPERSAMPLING) as isize;
+    for sample_row in 0..height * SUPERSAMPLING {
+        let y = (sample_row as f32 + 0.5) / SUPERSAMPLING as f32;
+        crossings.clear();
+        for &(x0, y0, x1, y1) in &edges {
+            let (low, high, direction) = if y0 < y1 { (y0, y1, 1) } else { (y1, y0, -1) };
+            if y >= low && y < high {
+                crossings.push((x0 + (y - y0) / (y1 - y0) * (x1 - x0), direction));
+            }
+        }
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let row = &mut covered[sample_row / SUPERSAMPLING * width..][..width];
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            winding += pair[0].1;
+            if winding == 0 {
+                continue;
+            }
+            // the samples whose centers lie between the two crossings
+            let first = ((pair[0].0 * SUPERSAMPLING as f32 - 0.5).ceil() as isize).max(0);
+            let end = ((pair[1].0 * SUPERSAMPLING as f32 - 0.5).ceil() as isize).min(columns);
+            for column in first..end {
+                row[column as usize / SUPERSAMPLING] += 1;
+            }
+        }
+    }
+
+    let samples = (SUPERSAMPLING * SUPERSAMPLING) as u32;
+    for (coverage, &count) in mask.coverage.iter_mut().zip(&covered) {
+        *coverage = (count * 255 / samples) as u8;
+    }
+
+    mask
+}
+
+/// Grows a mask by `radius` pixels in every direction, used to draw outlines.
+pub fn dilate(mask: &Mask, radius: usize) -> Mask {
+    let width = mask.width + 2 * radius;
+    let height = mask.height + 2 * radius;
+    let mut dilated = Mask::new(width, height);
+    let r = radius as isize;
+
+    for y in 0..mask.height {
+        for x in 0..mask.width {
+            let value = mask.coverage[y * mask.width + x];
+            if value == 0 {
+                continue;
+            }
+
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    if dx * dx + dy * dy > r * r {
+                        continue;
+                    }
+                    let index = ((y as isize + r + dy) as usize) * width + (x as isize + r + dx) as usize;
+                    dilated.coverage[index] = dilated.coverage[index].max(value);
+                }
+            }
+        }
+    }
+
+    dilated
+}
+
+/// Blends `color` onto the frame wherever the mask covers it. The top left corner
+/// of the mask is placed at `left`/`top`, parts outside of the frame are skipped.
+pub fn draw(frame: &mut FrameData, mask: &Mask, left: i32, top: i32, color: [u8; 3]) {
+    let frame_width = frame.width as i32;
+    let frame_height = frame.height as i32;
+
+    for y in 0..mask.height as i32 {
+        let frame_y = top + y;
+        if frame_y < 0 || frame_y >= frame_height {
+            continue;
+        }
+
+        for x in 0..mask.width as i32 {
+            let frame_x = left + x;
+            if frame_x < 0 || frame_x >= frame_width {
+                continue;
+            }
+
+            let coverage = mask.coverage[(y * mask.width as i32 + x) as usize];
+            if coverage > 0 {
+                let index = ((frame_y * frame_width + frame_x) * 4) as usize;
+                filters::blend_pixel(&mut frame.rgba[index..index + 4], color, coverage);
+            }
+        }
+    }
+}
@@ -0,0 +1,767 @@
+//! Operations that change the order, number or timing of frames without
+//! touching their pixels.
+
+#[cfg(feature = "wasm")]
+use compose::FrameArena;
+#[cfg(feature = "wasm")]
+use filters;
+#[cfg(feature = "wasm")]
+use limits::Limits;
+use FrameData;
+
+/// Anything that is shown for a while, usually a frame.
+pub trait Timed {
+    fn delay(&self) -> u16;
+    fn set_delay(&mut self, delay: u16);
+}
+
+impl Timed for FrameData {
+    fn delay(&self) -> u16 {
+        self.delay
+    }
+
+    fn set_delay(&mut self, delay: u16) {
+        self.delay = delay;
+    }
+}
+
+/// Stands in for a frame while the sequence of frames is rearranged, so the
+/// operations don't have to move pixels around.
+#[cfg(feature = "wasm")]
+#[derive(Clone)]
+pub struct Entry {
+    /// Position of the frame in the original sequence
+    pub index: usize,
+    pub delay: u16,
+}
+
+#[cfg(feature = "wasm")]
+impl Timed for Entry {
+    fn delay(&self) -> u16 {
+        self.delay
+    }
+
+    fn set_delay(&mut self, delay: u16) {
+        self.delay = delay;
+    }
+}
+
+/// Returns one entry per frame in their current order.
+#[cfg(feature = "wasm")]
+pub fn entries<T: Timed>(frames: &[T]) -> Vec<Entry> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| Entry {
+            index,
+            delay: frame.delay(),
+        })
+        .collect()
+}
+
+/// Puts the frames into the order and gives them the delays of `entries`. Frames are
+/// only cloned if they are used again later on.
+#[cfg(feature = "wasm")]
+pub fn arrange<T: Timed + Clone>(frames: Vec<T>, entries: &[Entry]) -> Vec<T> {
+    let mut uses = vec![0; frames.len()];
+    for entry in entries {
+        uses[entry.index] += 1;
+    }
+
+    let mut source: Vec<Option<T>> = frames.into_iter().map(Some).collect();
+    entries
+        .iter()
+        .map(|entry| {
+            uses[entry.index] -= 1;
+            let mut frame = if uses[entry.index] == 0 {
+                source[entry.index].take().unwrap()
+            } else {
+                source[entry.index].clone().unwrap()
+            };
+            frame.set_delay(entry.delay);
+            frame
+        })
+        .collect()
+}
+
+/// Keeps what is shown from `start` up to `end` hundredths of a second into the
+/// animation. Frames that are only partly within that time are shortened to the
+/// part that is. `end` may lie beyond the end of the animation.
+#[cfg(feature = "wasm")]
+pub fn trim_time<T: Timed>(frames: &mut Vec<T>, start: u64, end: u64) -> Result<(), String> {
+    let total: u64 = frames.iter().map(|frame| playback_delay(frame.delay()) as u64).sum();
+    if start >= end || start >= total {
+        return Err(format!(
+            "can't keep the time from {}ms to {}ms of a gif that is {}ms long",
+            start * 10,
+            end * 10,
+            total * 10
+        ));
+    }
+
+    let mut frame_start = 0;
+    let mut kept = Vec::with_capacity(frames.len());
+    for mut frame in frames.drain(..) {
+        let frame_end = frame_start + playback_delay(frame.delay()) as u64;
+        let (from, to) = (frame_start.max(start), frame_end.min(end));
+        if from < to {
+            if from > frame_start || to < frame_end {
+                frame.set_delay((to - from) as u16);
+            }
+            kept.push(frame);
+        }
+        frame_start = frame_end;
+    }
+    *frames = kept;
+
+    Ok(())
+}
+
+/// Keeps the frames from `start` up to but not including `end`.
+pub fn trim<T>(frames: &mut Vec<T>, start: usize, end: usize) -> Result<(), String> {
+    if start >= end || end > frames.len() {
+        return Err(format!(
+            "invalid frame range {}..{}, the gif has {} frames",
+            start,
+            end,
+            frames.len()
+        ));
+    }
+
+    frames.truncate(end);
+    frames.drain(..start);
+    Ok(())
+}
+
+/// Plays the frames `times` times in a row.
+#[cfg(feature = "wasm")]
+pub fn repeat<T: Clone>(frames: &mut Vec<T>, times: usize) {
+    let once = frames.len();
+    for _ in 1..times {
+        frames.extend_from_within(..once);
+    }
+}
+
+/// Shows the first frame `first` and the last frame `last` hundredths of a second
+/// longer. A gif stores delays of at most 65535, longer holds repeat the frame.
+#[cfg(feature = "wasm")]
+pub fn hold<T: Timed + Clone>(frames: &mut Vec<T>, first: u32, last: u32) {
+    if frames.is_empty() {
+        return;
+    }
+
+    // the last frame comes first so the index of the first frame doesn't change
+    let end = frames.len() - 1;
+    hold_frame(frames, end, last);
+    hold_frame(frames, 0, first);
+}
+
+#[cfg(feature = "wasm")]
+fn hold_frame<T: Timed + Clone>(frames: &mut Vec<T>, index: usize, extra: u32) {
+    if extra == 0 {
+        return;
+    }
+
+    // the parts are about equally long so none of them is short enough for
+    // browsers to play it slower
+    let total = frames[index].delay() as u32 + extra;
+    let parts = total.div_ceil(u16::MAX as u32);
+    let delays = (0..parts).map(|i| (total / parts + if i < total % parts { 1 } else { 0 }) as u16);
+
+    let frame = frames[index].clone();
+    let held: Vec<T> = delays
+        .map(|delay| {
+            let mut part = frame.clone();
+            part.set_delay(delay);
+            part
+        })
+        .collect();
+    frames.splice(index..index + 1, held);
+}
+
+/// Resamples the frames to `fps` frames per second. Every new frame shows the frame
+/// that is on screen at its start, as browsers play it. Delays are whole
+/// hundredths of a second, so they alternate between the two closest to the frame
+/// rate to keep it on average, e.g. 3, 3 and 4 for 30 frames per second.
+#[cfg(feature = "wasm")]
+pub fn retarget<T: Timed + Clone>(frames: &mut Vec<T>, fps: f32) {
+    if frames.is_empty() {
+        return;
+    }
+    let total: u64 = frames.iter().map(|frame| playback_delay(frame.delay()) as u64).sum();
+
+    // whole frames only, so the last one isn't too short to be played at its delay
+    let interval = 100.0 / fps as f64;
+    let count = ((total as f64 / interval) as u64).max(1);
+
+    let mut resampled = Vec::with_capacity(count as usize);
+    let mut source = 0;
+    let mut source_end = playback_delay(frames[0].delay()) as u64;
+    let mut start = 0;
+    for position in 1..=count {
+        let end = if position == count {
+            total
+        } else {
+            (position as f64 * interval).round() as u64
+        };
+        while source_end <= start && source + 1 < frames.len() {
+            source += 1;
+            source_end += playback_delay(frames[source].delay()) as u64;
+        }
+
+        let mut frame = frames[source].clone();
+        frame.set_delay((end - start).min(u16::MAX as u64) as u16);
+        resampled.push(frame);
+        start = end;
+    }
+
+    *frames = resampled;
+}
+
+/// Reverses the frames from `start` up to but not including `end`, or up to the
+/// last frame if `end` is `None`. The frames around them keep their order.
+pub fn reverse_range<T>(frames: &mut [T], start: usize, end: Option<usize>) -> Result<(), String> {
+    let end = end.unwrap_or(frames.len());
+    if start >= end || end > frames.len() {
+        return Err(format!(
+            "invalid frame range {}..{}, the gif has {} frames",
+            start,
+            end,
+            frames.len()
+        ));
+    }
+
+    frames[start..end].reverse();
+    Ok(())
+}
+
+/// Keeps every `n`th frame, starting with the first one. The delays of the dropped
+/// frames are added to the previous kept frame so the total duration stays the same.
+#[cfg(feature = "wasm")]
+pub fn decimate<T: Timed>(frames: &mut Vec<T>, n: usize) {
+    let mut kept: Vec<T> = Vec::with_capacity(frames.len() / n + 1);
+
+    for (i, frame) in frames.drain(..).enumerate() {
+        if i % n == 0 {
+            kept.push(frame);
+        } else if let Some(previous) = kept.last_mut() {
+            let delay = previous.delay().saturating_add(frame.delay());
+            previous.set_delay(delay);
+        }
+    }
+
+    *frames = kept;
+}
+
+/// Rearranges the entries in the given `order`. Every value in `order` is the
+/// index of an entry in the original sequence.
+///
+/// Indices that are out of range are always rejected. Entries may appear more than
+/// once only if `allow_duplicates` is set, entries that are not part of `order` are
+/// dropped.
+#[cfg(feature = "wasm")]
+pub fn reorder(entries: &mut Vec<Entry>, order: &[u32], allow_duplicates: bool) -> Result<(), String> {
+    let mut used = vec![false; entries.len()];
+
+    for &index in order {
+        let index = index as usize;
+        if index >= entries.len() {
+            return Err(format!(
+                "frame {} does not exist, the gif has {} frames",
+                index,
+                entries.len()
+            ));
+        }
+        if used[index] && !allow_duplicates {
+            return Err(format!("frame {} is used more than once", index));
+        }
+        used[index] = true;
+    }
+
+    *entries = order.iter().map(|&index| entries[index as usize].clone()).collect();
+
+    Ok(())
+}
+
+/// Plays the frames `factor` times as fast by dividing their delays. Frames that
+/// had a delay keep at least 1/100s so they don't collapse into no delay at all.
+pub fn change_speed<T: Timed>(frames: &mut [T], factor: f32) {
+    for frame in frames.iter_mut() {
+        if frame.delay() > 0 {
+            let delay = (frame.delay() as f32 / factor).round().clamp(1.0, u16::MAX as f32) as u16;
+            frame.set_delay(delay);
+        }
+    }
+}
+
+/// Changes the speed gradually. `keyframes` are pairs of a frame position and the
+/// speed factor at that frame, the factors of the frames in between are
+/// interpolated linearly. Frames before the first and after the last keyframe keep
+/// the factor of that keyframe.
+#[cfg(feature = "wasm")]
+pub fn speed_ramp<T: Timed>(frames: &mut [T], keyframes: &[(usize, f32)]) -> Result<(), String> {
+    let mut keyframes = keyframes.to_vec();
+    keyframes.sort_by_key(|&(position, _)| position);
+
+    if keyframes.is_empty() {
+        return Err("at least one keyframe is needed".to_string());
+    }
+    for &(position, factor) in keyframes.iter() {
+        if position >= frames.len() {
+            return Err(format!(
+                "frame {} does not exist, the gif has {} frames",
+                position,
+                frames.len()
+            ));
+        }
+        if !(factor > 0.0 && factor.is_finite()) {
+            return Err(format!("speed factor has to be positive but is {}", factor));
+        }
+    }
+    if let Some(pair) = keyframes.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(format!("frame {} has more than one keyframe", pair[0].0));
+    }
+
+    for (i, frame) in frames.iter_mut().enumerate() {
+        let next = keyframes.iter().position(|&(position, _)| position >= i);
+        let factor = match next {
+            None => keyframes[keyframes.len() - 1].1,
+            Some(0) => keyframes[0].1,
+            Some(next) => {
+                let (start, from) = keyframes[next - 1];
+                let (end, to) = keyframes[next];
+                from + (to - from) * (i - start) as f32 / (end - start) as f32
+            }
+        };
+        change_speed(std::slice::from_mut(frame), factor);
+    }
+    Ok(())
+}
+
+/// How far every frame is faded for a fade in over the first `fade_in_ms` and a
+/// fade out over the last `fade_out_ms` milliseconds, from 0 for not at all to 1
+/// for completely. The first frame is faded completely for a fade in, the last one
+/// for a fade out.
+#[cfg(feature = "wasm")]
+pub fn fade_amounts<T: Timed>(frames: &[T], fade_in_ms: u32, fade_out_ms: u32) -> Vec<f32> {
+    let total: u32 = frames
+        .iter()
+        .map(|frame| playback_delay(frame.delay()) as u32 * 10)
+        .sum();
+
+    let mut start = 0;
+    frames
+        .iter()
+        .map(|frame| {
+            let end = start + playback_delay(frame.delay()) as u32 * 10;
+            let fade_in = if start < fade_in_ms {
+                1.0 - start as f32 / fade_in_ms as f32
+            } else {
+                0.0
+            };
+            let fade_out = if total - end < fade_out_ms {
+                1.0 - (total - end) as f32 / fade_out_ms as f32
+            } else {
+                0.0
+            };
+            start = end;
+            fade_in.max(fade_out)
+        })
+        .collect()
+}
+
+/// The index of the frame that is on screen `ms` milliseconds into the animation,
+/// given the delays of all frames. Times past the end show the last frame.
+/// Returns `None` if there are no frames.
+#[cfg(feature = "wasm")]
+pub fn index_at_time<I: IntoIterator<Item = u16>>(delays: I, ms: f64) -> Option<usize> {
+    let mut end = 0.0;
+    let mut last = None;
+    for (index, delay) in delays.into_iter().enumerate() {
+        end += playback_delay(delay) as f64 * 10.0;
+        if ms < end {
+            return Some(index);
+        }
+        last = Some(index);
+    }
+    last
+}
+
+/// The delay in hundredths of a second a frame is actually shown for. Browsers
+/// play delays below 2 as 10 since many old gifs rely on that, so a video made
+/// from the frames runs at the same speed.
+#[cfg(feature = "wasm")]
+pub fn playback_delay(delay: u16) -> u16 {
+    if delay < 2 {
+        10
+    } else {
+        delay
+    }
+}
+
+/// Converts a delay in milliseconds to the hundredths of a second stored in gifs.
+/// Fails if the delay can't be represented exactly.
+#[cfg(feature = "wasm")]
+pub fn delay_from_ms(ms: u32) -> Result<u16, String> {
+    if !ms.is_multiple_of(10) {
+        return Err(format!(
+            "delay of {}ms is not a multiple of 10ms, gifs can't store that",
+            ms
+        ));
+    }
+    if ms / 10 > u16::MAX as u32 {
+        return Err(format!(
+            "delay of {}ms is longer than the maximum of {}ms",
+            ms,
+            u16::MAX as u32 * 10
+        ));
+    }
+
+    Ok((ms / 10) as u16)
+}
+
+#[cfg(feature = "wasm")]
+fn is_same_image(a: &FrameData, b: &FrameData) -> bool {
+    a.width == b.width && a.height == b.height && a.rgba == b.rgba
+}
+
+/// Drops frames that look exactly like the frame before them. Their delays are
+/// added to the frame that is kept so the timing of the animation doesn't change.
+#[cfg(feature = "wasm")]
+pub fn remove_duplicates(frames: &mut Vec<FrameData>) {
+    let mut entries = entries(frames);
+    merge_duplicates(&mut entries, |a, b| is_same_image(&frames[a], &frames[b]));
+    *frames = arrange(std::mem::take(frames), &entries);
+}
+
+/// Like `remove_duplicates` for the entries of frames, `same` tells whether the
+/// frames with two indices look exactly alike.
+#[cfg(feature = "wasm")]
+pub fn merge_duplicates<F: Fn(usize, usize) -> bool>(entries: &mut Vec<Entry>, same: F) {
+    let mut kept: Vec<Entry> = Vec::with_capacity(entries.len());
+
+    for entry in entries.drain(..) {
+        match kept.last_mut() {
+            Some(previous) if same(previous.index, entry.index) => {
+                previous.delay = previous.delay.saturating_add(entry.delay);
+            }
+            _ => kept.push(entry),
+        }
+    }
+
+    *entries = kept;
+}
+
+/// Splits `delay` into `parts` delays that add up to the original delay.
+#[cfg(feature = "wasm")]
+fn split_delay(delay: u16, parts: u16) -> impl Iterator<Item = u16> {
+    (0..parts).map(move |i| delay / parts + if i < delay % parts { 1 } else { 0 })
+}
+
+/// Inserts up to `factor - 1` blended frames after every frame so the animation
+/// plays smoother. The delay of every original frame as browsers play it is shared
+/// with the frames that are inserted after it. Browsers play delays below 2cs
+/// slower, so a frame gets fewer inserted frames if its delay is too short to give
+/// every part at least 2cs. The last frame is blended into the first one because
+/// gifs usually loop.
+///
+/// The blended frames are added to the arena, the returned entries put them
+/// between the original frames. If they don't fit into the memory limit the
+/// factor is lowered until they do, an error is returned if not even one frame
+/// per original frame can be added.
+#[cfg(feature = "wasm")]
+pub fn interpolate(frames: &mut FrameArena, factor: u16, limits: &Limits) -> Result<Vec<Entry>, String> {
+    let original = frames.len();
+    if original < 2 || factor < 2 {
+        return Ok(frames.entries());
+    }
+
+    let mut factor = factor;
+    let parts = loop {
+        let parts: Vec<u16> = frames
+            .delays()
+            .iter()
+            .map(|&delay| factor.min(playback_delay(delay) / 2).max(1))
+            .collect();
+        let added = parts.iter().map(|&parts| parts as usize - 1).sum::<usize>();
+        match limits.check_memory(original + added, frames.width, frames.height) {
+            Ok(()) => {
+                frames.reserve(added);
+                break parts;
+            }
+            Err(error) if factor <= 2 => return Err(error),
+            Err(_) => factor -= 1,
+        }
+    };
+
+    let mut smoothed = Vec::with_capacity(original * factor as usize);
+    let mut mixed = Vec::new();
+    for (i, &parts) in parts.iter().enumerate() {
+        let next = (i + 1) % original;
+        let delay = playback_delay(frames.delays()[i]);
+
+        for (step, delay) in split_delay(delay, parts).enumerate() {
+            let index = if step == 0 {
+                i
+            } else {
+                mixed.clear();
+                mixed.extend_from_slice(frames.rgba(i));
+                filters::mix_into(&mut mixed, frames.rgba(next), step as f32 / parts as f32);
+                frames.push(&mixed, delay)
+            };
+            smoothed.push(Entry { index, delay });
+        }
+    }
+
+    Ok(smoothed)
+}
+
+/// Cross-fades the last `overlap` frames into the first `overlap` frames so the
+/// animation loops without a visible jump. The first frames are blended into the
+/// end of the animation and left out of the returned entries, which makes it
+/// `overlap` frames shorter.
+#[cfg(feature = "wasm")]
+pub fn crossfade_loop(frames: &mut FrameArena, overlap: usize) -> Result<Vec<Entry>, String> {
+    if overlap * 2 > frames.len() {
+        return Err(format!(
+            "an overlap of {} frames needs at least {} frames but the gif has {}",
+            overlap,
+            overlap * 2,
+            frames.len()
+        ));
+    }
+
+    let tail = frames.len() - overlap;
+    for i in 0..overlap {
+        // the blend gets closer to the start with every frame, the last one is
+        // followed by the first frame that isn't blended
+        let t = (i + 1) as f32 / (overlap + 1) as f32;
+        frames.blend(tail + i, i, t);
+    }
+
+    let mut entries = frames.entries();
+    entries.drain(..overlap);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame that is only told apart by its name.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Shown(char, u16);
+
+    impl Timed for Shown {
+        fn delay(&self) -> u16 {
+            self.1
+        }
+
+        fn set_delay(&mut self, delay: u16) {
+            self.1 = delay;
+        }
+    }
+
+    fn shown(frames: &[(char, u16)]) -> Vec<Shown> {
+        frames.iter().map(|&(name, delay)| Shown(name, delay)).collect()
+    }
+
+    #[cfg(feature = "wasm")]
+    fn placed(entries: &[Entry]) -> Vec<(usize, u16)> {
+        entries.iter().map(|entry| (entry.index, entry.delay)).collect()
+    }
+
+    #[test]
+    fn trim_keeps_the_range() {
+        let mut frames = shown(&[('a', 10), ('b', 10), ('c', 10)]);
+        trim(&mut frames, 1, 3).unwrap();
+        assert_eq!(frames, shown(&[('b', 10), ('c', 10)]));
+
+        assert!(trim(&mut frames, 1, 1).is_err());
+        assert!(trim(&mut frames, 0, 3).is_err());
+    }
+
+    #[test]
+    fn reverse_range_keeps_the_frames_around_it() {
+        let mut frames = shown(&[('a', 1), ('b', 2), ('c', 3), ('d', 4)]);
+        reverse_range(&mut frames, 1, Some(3)).unwrap();
+        assert_eq!(frames, shown(&[('a', 1), ('c', 3), ('b', 2), ('d', 4)]));
+
+        reverse_range(&mut frames, 2, None).unwrap();
+        assert_eq!(frames, shown(&[('a', 1), ('c', 3), ('d', 4), ('b', 2)]));
+        assert!(reverse_range(&mut frames, 0, Some(5)).is_err());
+    }
+
+    #[test]
+    fn change_speed_keeps_short_delays() {
+        let mut frames = shown(&[('a', 10), ('b', 1), ('c', 0)]);
+        change_speed(&mut frames, 4.0);
+        assert_eq!(frames, shown(&[('a', 3), ('b', 1), ('c', 0)]));
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn trim_time_shortens_the_frames_at_the_edges() {
+        let mut frames = shown(&[('a', 10), ('b', 10), ('c', 0)]);
+        trim_time(&mut frames, 5, 25).unwrap();
+        // the last frame is played for 10 although its delay is 0
+        assert_eq!(frames, shown(&[('a', 5), ('b', 10), ('c', 5)]));
+
+        assert!(trim_time(&mut frames, 20, 30).is_err());
+        assert!(trim_time(&mut frames, 10, 10).is_err());
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn arrange_follows_the_entries() {
+        let frames = shown(&[('a', 1), ('b', 2), ('c', 3)]);
+        let mut order = entries(&frames);
+        reorder(&mut order, &[2, 0, 2], true).unwrap();
+        assert_eq!(arrange(frames, &order), shown(&[('c', 3), ('a', 1), ('c', 3)]));
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn reorder_checks_the_indices() {
+        let mut order = entries(&shown(&[('a', 1), ('b', 2)]));
+        assert!(reorder(&mut order, &[0, 2], true).is_err());
+        assert!(reorder(&mut order, &[1, 1], false).is_err());
+
+        reorder(&mut order, &[1], false).unwrap();
+        assert_eq!(placed(&order), vec![(1, 2)]);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn repeat_plays_the_frames_again() {
+        let mut frames = shown(&[('a', 1), ('b', 2)]);
+        repeat(&mut frames, 3);
+        assert_eq!(
+            frames,
+            shown(&[('a', 1), ('b', 2), ('a', 1), ('b', 2), ('a', 1), ('b', 2)])
+        );
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn hold_splits_long_delays() {
+        let mut frames = shown(&[('a', 10), ('b', 10)]);
+        hold(&mut frames, 100_000, 5);
+        assert_eq!(frames, shown(&[('a', 50_005), ('a', 50_005), ('b', 15)]));
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn retarget_shows_the_frame_at_every_start() {
+        let mut frames = shown(&[('a', 10), ('b', 20)]);
+        retarget(&mut frames, 30.0);
+        let names: String = frames.iter().map(|frame| frame.0).collect();
+        let delays: Vec<u16> = frames.iter().map(|frame| frame.1).collect();
+        assert_eq!(names, "aaabbbbbb");
+        assert_eq!(delays, vec![3, 4, 3, 3, 4, 3, 3, 4, 3]);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn decimate_keeps_the_duration() {
+        let mut frames = shown(&[('a', 1), ('b', 2), ('c', 3), ('d', 4), ('e', 5)]);
+        decimate(&mut frames, 2);
+        assert_eq!(frames, shown(&[('a', 3), ('c', 7), ('e', 5)]));
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn speed_ramp_interpolates_between_keyframes() {
+        let mut frames = shown(&[('a', 12), ('b', 12), ('c', 12), ('d', 12)]);
+        speed_ramp(&mut frames, &[(2, 3.0), (0, 1.0)]).unwrap();
+        assert_eq!(frames, shown(&[('a', 12), ('b', 6), ('c', 4), ('d', 4)]));
+
+        assert!(speed_ramp(&mut frames, &[]).is_err());
+        assert!(speed_ramp(&mut frames, &[(4, 1.0)]).is_err());
+        assert!(speed_ramp(&mut frames, &[(0, 0.0)]).is_err());
+        assert!(speed_ramp(&mut frames, &[(1, 1.0), (1, 2.0)]).is_err());
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn fade_amounts_at_both_ends() {
+        let frames = shown(&[('a', 10), ('b', 10), ('c', 10), ('d', 10)]);
+        assert_eq!(fade_amounts(&frames, 200, 0), vec![1.0, 0.5, 0.0, 0.0]);
+        assert_eq!(fade_amounts(&frames, 0, 200), vec![0.0, 0.0, 0.5, 1.0]);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn index_at_time_uses_playback_delays() {
+        assert_eq!(index_at_time(vec![10, 0, 20], 0.0), Some(0));
+        assert_eq!(index_at_time(vec![10, 0, 20], 150.0), Some(1));
+        assert_eq!(index_at_time(vec![10, 0, 20], 200.0), Some(2));
+        assert_eq!(index_at_time(vec![10, 0, 20], 1000.0), Some(2));
+        assert_eq!(index_at_time(vec![], 0.0), None);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn delays_from_milliseconds() {
+        assert_eq!(delay_from_ms(120), Ok(12));
+        assert!(delay_from_ms(125).is_err());
+        assert!(delay_from_ms(655_360).is_err());
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn merge_duplicates_adds_up_the_delays() {
+        let mut entries = entries(&shown(&[('a', 1), ('a', 2), ('b', 3), ('a', 4)]));
+        let names = ['a', 'a', 'b', 'a'];
+        merge_duplicates(&mut entries, |a, b| names[a] == names[b]);
+        assert_eq!(placed(&entries), vec![(0, 3), (2, 3), (3, 4)]);
+    }
+
+    #[cfg(feature = "wasm")]
+    fn arena(frames: usize) -> FrameArena {
+        let mut arena = FrameArena::with_capacity(1, 1, frames);
+        for i in 0..frames {
+            arena.push(&[i as u8 * 50, 0, 0, 255], 10);
+        }
+        arena
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn interpolate_blends_into_the_next_frame() {
+        let mut frames = arena(2);
+        let entries = interpolate(&mut frames, 2, &Limits::default()).unwrap();
+        assert_eq!(placed(&entries), vec![(0, 5), (2, 5), (1, 5), (3, 5)]);
+        assert_eq!(frames.rgba(2), &[25, 0, 0, 255]);
+        // the last frame is blended into the first one
+        assert_eq!(frames.rgba(3), &[25, 0, 0, 255]);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn interpolate_lowers_the_factor_to_fit_into_memory() {
+        let limits = Limits {
+            max_memory: Some(5 * 4),
+            ..Limits::default()
+        };
+        let mut frames = arena(2);
+        assert_eq!(interpolate(&mut frames, 4, &limits).unwrap().len(), 4);
+
+        let limits = Limits {
+            max_memory: Some(3 * 4),
+            ..Limits::default()
+        };
+        let mut frames = arena(2);
+        assert!(interpolate(&mut frames, 4, &limits).is_err());
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn crossfade_loop_drops_the_blended_start() {
+        let mut frames = arena(4);
+        let entries = crossfade_loop(&mut frames, 1).unwrap();
+        assert_eq!(placed(&entries), vec![(1, 10), (2, 10), (3, 10)]);
+        assert!(crossfade_loop(&mut frames, 3).is_err());
+    }
+}
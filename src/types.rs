@@ -0,0 +1,591 @@
+//! The plain objects and callbacks that are passed between JavaScript and the
+//! module.
+//!
+//! Every object is declared once with `plain_object!`, which creates the Rust
+//! struct, its TypeScript interface, the code that converts between the two and a
+//! `Js` handle that exported functions take or return. The handle carries the name
+//! of the interface, so the generated `.d.ts` describes the objects instead of
+//! typing them as `any` and the fields can't drift apart from the code that reads
+//! or writes them. Callbacks are declared the same way with `callback!`.
+
+use std::fmt::Display;
+
+use js_error;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// A value that is written to a field of a plain object.
+pub trait IntoJs {
+    fn into_js(self) -> JsValue;
+}
+
+macro_rules! into_js {
+    ($($ty:ty),*) => {
+        $(impl IntoJs for $ty {
+            fn into_js(self) -> JsValue {
+                JsValue::from(self)
+            }
+        })*
+    };
+}
+
+into_js!(u8, u16, u32, i32, f64, bool, String, &'static str);
+into_js!(js_sys::Uint8Array, js_sys::Uint32Array, js_sys::Float64Array);
+
+/// A `Vec` becomes an array, typed arrays have to be created explicitly.
+impl<T: IntoJs> IntoJs for Vec<T> {
+    fn into_js(self) -> JsValue {
+        self.into_iter().map(IntoJs::into_js).collect::<js_sys::Array>().into()
+    }
+}
+
+/// A value that is read from the field `key` of a plain object.
+pub trait FromJs: Sized {
+    fn from_js(value: JsValue, key: &str) -> Result<Self, JsValue>;
+}
+
+macro_rules! from_js_integer {
+    ($($ty:ty),*) => {
+        $(impl FromJs for $ty {
+            fn from_js(value: JsValue, key: &str) -> Result<$ty, JsValue> {
+                value
+                    .as_f64()
+                    .filter(|&number| {
+                        number >= <$ty>::MIN as f64 && number <= <$ty>::MAX as f64 && number.fract() == 0.0
+                    })
+                    .map(|number| number as $ty)
+                    .ok_or_else(|| {
+                        js_error(&format!(
+                            "{} has to be a whole number between {} and {}",
+                            key,
+                            <$ty>::MIN,
+                            <$ty>::MAX
+                        ))
+                    })
+            }
+        })*
+    };
+}
+
+from_js_integer!(u8, u16, u32, i32);
+
+impl FromJs for f64 {
+    fn from_js(value: JsValue, key: &str) -> Result<f64, JsValue> {
+        value
+            .as_f64()
+            .ok_or_else(|| js_error(&format!("{} has to be a number", key)))
+    }
+}
+
+impl FromJs for bool {
+    fn from_js(value: JsValue, key: &str) -> Result<bool, JsValue> {
+        value
+            .as_bool()
+            .ok_or_else(|| js_error(&format!("{} has to be a boolean", key)))
+    }
+}
+
+impl FromJs for String {
+    fn from_js(value: JsValue, key: &str) -> Result<String, JsValue> {
+        value
+            .as_string()
+            .ok_or_else(|| js_error(&format!("{} has to be a string", key)))
+    }
+}
+
+impl FromJs for js_sys::Function {
+    fn from_js(value: JsValue, key: &str) -> Result<js_sys::Function, JsValue> {
+        value
+            .dyn_into()
+            .map_err(|_| js_error(&format!("{} has to be a function", key)))
+    }
+}
+
+/// Also accepts the `Uint8ClampedArray` of an `ImageData`, the result views the
+/// same memory.
+impl FromJs for js_sys::Uint8Array {
+    fn from_js(value: JsValue, key: &str) -> Result<js_sys::Uint8Array, JsValue> {
+        if let Some(clamped) = value.dyn_ref::<js_sys::Uint8ClampedArray>() {
+            return Ok(js_sys::Uint8Array::new_with_byte_offset_and_length(
+                &clamped.buffer(),
+                clamped.byte_offset(),
+                clamped.length(),
+            ));
+        }
+        value
+            .dyn_into()
+            .map_err(|_| js_error(&format!("{} has to be a Uint8Array", key)))
+    }
+}
+
+/// An `[r, g, b]` color.
+impl FromJs for [u8; 3] {
+    fn from_js(value: JsValue, key: &str) -> Result<[u8; 3], JsValue> {
+        let invalid = || js_error(&format!("{} has to be an array of [r, g, b] colors", key));
+        let channels = value.dyn_into::<js_sys::Array>().map_err(|_| invalid())?;
+        if channels.length() != 3 {
+            return Err(invalid());
+        }
+        let mut rgb = [0; 3];
+        for (c, channel) in channels.iter().enumerate() {
+            rgb[c] = u8::from_js(channel, key).map_err(|_| invalid())?;
+        }
+        Ok(rgb)
+    }
+}
+
+impl<T: FromJs> FromJs for Vec<T> {
+    fn from_js(value: JsValue, key: &str) -> Result<Vec<T>, JsValue> {
+        let array = value
+            .dyn_into::<js_sys::Array>()
+            .map_err(|_| js_error(&format!("{} has to be an array", key)))?;
+        array.iter().map(|item| T::from_js(item, key)).collect()
+    }
+}
+
+/// Returns the property `key` of a plain object, `undefined` and `null` count as
+/// missing.
+pub fn field(object: &JsValue, key: &str) -> Option<JsValue> {
+    js_sys::Reflect::get(object, &JsValue::from(key))
+        .ok()
+        .filter(|value| !value.is_undefined() && !value.is_null())
+}
+
+fn read<T: FromJs>(object: &JsValue, key: &str) -> Result<Option<T>, JsValue> {
+    field(object, key).map(|value| T::from_js(value, key)).transpose()
+}
+
+fn write(object: &js_sys::Object, key: &str, value: JsValue) {
+    js_sys::Reflect::set(object, &JsValue::from(key), &value).unwrap();
+}
+
+/// Checks that a number that was read from a plain object is between `min` and
+/// `max`, for fields with a smaller range than their type.
+pub fn within<T: PartialOrd + Display>(value: Option<T>, key: &str, min: T, max: T) -> Result<Option<T>, JsValue> {
+    match value {
+        Some(value) if value < min || value > max => Err(js_error(&format!(
+            "{} has to be a whole number between {} and {}",
+            key, min, max
+        ))),
+        value => Ok(value),
+    }
+}
+
+/// Declares a plain object with its TypeScript interface.
+///
+/// `Name as JsName: "Name"` creates the struct `Name`, the handle `JsName` and the
+/// interface `Name`. Every field is written as `name "jsName": Type => "ts type"`,
+/// `optional` after the JavaScript name makes it an `Option` and `?` in TypeScript.
+/// Objects that are passed to JavaScript start with `output` and get
+/// `into_object`, the ones that are passed in start with `input` and get
+/// `from_js`. Doc comments end up in both places.
+macro_rules! plain_object {
+    (@type $ty:ty) => { $ty };
+    (@type $ty:ty, optional) => { Option<$ty> };
+
+    (@marker) => { "" };
+    (@marker optional) => { "?" };
+
+    (@write $object:ident, $key:literal, $value:expr) => {
+        write(&$object, $key, IntoJs::into_js($value));
+    };
+    (@write $object:ident, $key:literal, $value:expr, optional) => {
+        if let Some(value) = $value {
+            write(&$object, $key, IntoJs::into_js(value));
+        }
+    };
+
+    (@read $object:ident, $name:literal, $key:literal) => {
+        read($object, $key)?.ok_or_else(|| js_error(&format!("{} needs {}", $name, $key)))?
+    };
+    (@read $object:ident, $name:literal, $key:literal, optional) => {
+        read($object, $key)?
+    };
+
+    (@declare
+        $(#[doc = $doc:literal] $(#[doc = $more_doc:literal])*)?
+        $name:ident as $handle:ident: $ts_name:literal {
+            $(
+                $(#[doc = $field_doc:literal] $(#[doc = $more_field_doc:literal])*)?
+                $field:ident $key:literal $($optional:ident)?: $ty:ty => $ts:literal,
+            )*
+        }
+    ) => {
+        $(#[doc = $doc] $(#[doc = $more_doc])*)?
+        pub struct $name {
+            $(
+                $(#[doc = $field_doc] $(#[doc = $more_field_doc])*)?
+                pub $field: plain_object!(@type $ty $(, $optional)?),
+            )*
+        }
+
+        #[wasm_bindgen]
+        extern "C" {
+            #[wasm_bindgen(typescript_type = $ts_name)]
+            pub type $handle;
+        }
+
+        #[wasm_bindgen(typescript_custom_section)]
+        const _: &'static str = concat!(
+            $("/**", $doc, $($more_doc,)* " */\n",)?
+            "export interface ", $ts_name, " {\n",
+            $(
+                $("    /**", $field_doc, $($more_field_doc,)* " */\n",)?
+                "    ", $key, plain_object!(@marker $($optional)?), ": ", $ts, ";\n",
+            )*
+            "}\n",
+        );
+    };
+
+    (
+        $(#[doc = $doc:literal])*
+        output $name:ident as $handle:ident: $ts_name:literal { $($fields:tt)* }
+    ) => {
+        plain_object!(@declare $(#[doc = $doc])* $name as $handle: $ts_name { $($fields)* });
+        plain_object!(@output $name as $handle { $($fields)* });
+    };
+    (
+        $(#[doc = $doc:literal])*
+        input $name:ident as $handle:ident: $ts_name:literal { $($fields:tt)* }
+    ) => {
+        plain_object!(@declare $(#[doc = $doc])* $name as $handle: $ts_name { $($fields)* });
+        plain_object!(@input $name: $ts_name { $($fields)* });
+    };
+
+    (@output $name:ident as $handle:ident {
+        $(
+            $(#[doc = $field_doc:literal])*
+            $field:ident $key:literal $($optional:ident)?: $ty:ty => $ts:literal,
+        )*
+    }) => {
+        impl $name {
+            pub fn into_object(self) -> $handle {
+                let object = js_sys::Object::new();
+                $(plain_object!(@write object, $key, self.$field $(, $optional)?);)*
+                object.unchecked_into()
+            }
+        }
+
+        /// Objects can be fields of other objects.
+        impl IntoJs for $name {
+            fn into_js(self) -> JsValue {
+                self.into_object().into()
+            }
+        }
+    };
+    (@input $name:ident: $ts_name:literal {
+        $(
+            $(#[doc = $field_doc:literal])*
+            $field:ident $key:literal $($optional:ident)?: $ty:ty => $ts:literal,
+        )*
+    }) => {
+        impl $name {
+            pub fn from_js(object: &JsValue) -> Result<$name, JsValue> {
+                Ok($name {
+                    $($field: plain_object!(@read object, $ts_name, $key $(, $optional)?),)*
+                })
+            }
+        }
+    };
+}
+
+/// Declares the type of a callback with its TypeScript definition.
+///
+/// `Name: "Name" = "(...) => ..."` creates the handle `Name`, which can be used as
+/// the `js_sys::Function` it is.
+macro_rules! callback {
+    (
+        $(#[doc = $doc:literal] $(#[doc = $more_doc:literal])*)?
+        $handle:ident: $ts_name:literal = $ts:literal;
+    ) => {
+        #[wasm_bindgen]
+        extern "C" {
+            $(#[doc = $doc] $(#[doc = $more_doc])*)?
+            #[wasm_bindgen(typescript_type = $ts_name, extends = js_sys::Function)]
+            pub type $handle;
+        }
+
+        #[wasm_bindgen(typescript_custom_section)]
+        const _: &'static str = concat!(
+            $("/**", $doc, $($more_doc,)* " */\n",)?
+            "export type ", $ts_name, " = ", $ts, ";\n",
+        );
+    };
+}
+
+plain_object! {
+    /// Metadata of a gif as returned by `get_metadata`.
+    output GifMetadata as JsGifMetadata: "GifMetadata" {
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+        frame_count "frameCount": u32 => "number",
+        /// Delay of every frame in milliseconds
+        delays "delays": Vec<u32> => "number[]",
+        /// Sum of all delays in milliseconds
+        duration "duration": u32 => "number",
+        /// `0` if the gif plays once, `-1` if it loops forever, otherwise the number of repetitions
+        loop_count "loopCount": i32 => "number",
+        global_palette_size "globalPaletteSize": u32 => "number",
+    }
+}
+
+plain_object! {
+    /// A fully composed frame.
+    output DecodedFrame as JsDecodedFrame: "DecodedFrame" {
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+        /// Delay in hundredths of a second
+        delay "delay": u16 => "number",
+        rgba "rgba": js_sys::Uint8Array => "Uint8Array",
+    }
+}
+
+plain_object! {
+    /// A frame that is encoded, `rgba` can be taken from an `ImageData` directly.
+    input FrameInput as JsFrameInput: "FrameInput" {
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+        /// Delay in hundredths of a second, `0` if left out
+        delay "delay" optional: f64 => "number",
+        rgba "rgba": js_sys::Uint8Array => "Uint8Array | Uint8ClampedArray",
+    }
+}
+
+plain_object! {
+    /// All frames of a gif packed into one buffer, see `export_raw_frames`.
+    output RawFrames as JsRawFrames: "RawFrames" {
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+        format "format": &'static str => "\"RGBA\"",
+        frame_count "frameCount": u32 => "number",
+        /// Number of bytes per frame
+        frame_size "frameSize": u32 => "number",
+        data "data": js_sys::Uint8Array => "Uint8Array",
+        /// Start of every frame in microseconds
+        timestamps "timestamps": js_sys::Float64Array => "Float64Array",
+        /// Length of every frame in microseconds
+        durations "durations": js_sys::Float64Array => "Float64Array",
+        /// Total length in microseconds
+        duration "duration": f64 => "number",
+        /// What lenient decoding stopped at, see `Limits.lenient`
+        warnings "warnings": Vec<String> => "string[]",
+    }
+}
+
+callback! {
+    /// Called at the start of the decoding and the encoding phase of a job.
+    RegisterPhase: "RegisterPhase" =
+        "(id: string, name: string, frames: number, phase: \"decoding\" | \"encoding\") => void";
+}
+
+callback! {
+    /// Called after every frame that was decoded or written.
+    ReportProgress: "ReportProgress" = "(id: string, processed: number, phase: \"decoding\" | \"encoding\") => void";
+}
+
+callback! {
+    /// Changes the composed frame `index` of a gif, see `filter_gif`. `rgba` may be
+    /// changed in place or a new array of the same length may be returned.
+    FrameFilter: "FrameFilter" =
+        "(rgba: Uint8Array, width: number, height: number, index: number) => Uint8Array | Uint8ClampedArray | void";
+}
+
+plain_object! {
+    /// Where the time of the last operation went, see `take_stats`.
+    output OperationStats as JsOperationStats: "OperationStats" {
+        decode_ms "decodeMs": f64 => "number",
+        compose_ms "composeMs": f64 => "number",
+        transform_ms "transformMs": f64 => "number",
+        quantize_ms "quantizeMs": f64 => "number",
+        encode_ms "encodeMs": f64 => "number",
+        /// Most bytes the decoded frames took up at the same time
+        peak_frame_bytes "peakFrameBytes": f64 => "number",
+        /// Size of the written gif in bytes
+        output_bytes "outputBytes": f64 => "number",
+    }
+}
+
+plain_object! {
+    /// What went wrong in an operation, see `set_error_handler`.
+    output ErrorReport as JsErrorReport: "ErrorReport" {
+        /// The id of the job that failed, if any
+        id "id" optional: String => "string",
+        /// The name of the job that failed, if any
+        name "name" optional: String => "string",
+        /// What the job was doing: `"decode"`, `"transform"` or `"encode"`
+        operation "operation" optional: &'static str => "string",
+        /// The index of the frame that was decoded or encoded
+        frame "frame" optional: u32 => "number",
+        message "message": String => "string",
+        /// Whether trying again differently can succeed, e.g. with lenient decoding
+        recoverable "recoverable": bool => "boolean",
+    }
+}
+
+callback! {
+    /// Receives a report for every error, see `set_error_handler`.
+    ErrorHandler: "ErrorHandler" = "(report: ErrorReport) => void";
+}
+
+plain_object! {
+    /// How a frame is stored in a gif, see `frame_info`.
+    output FrameInfo as JsFrameInfo: "FrameInfo" {
+        left "left": u16 => "number",
+        top "top": u16 => "number",
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+        /// Delay in hundredths of a second
+        delay "delay": u16 => "number",
+        disposal "disposal": &'static str => "\"any\" | \"keep\" | \"background\" | \"previous\"",
+        interlaced "interlaced": bool => "boolean",
+        /// Number of colors in the local palette, `0` if the frame uses the global palette
+        local_palette_size "localPaletteSize": u32 => "number",
+        /// The palette index of transparent pixels, left out if the frame has none
+        transparent_index "transparentIndex" optional: u8 => "number",
+    }
+}
+
+plain_object! {
+    /// A problem that `validate_gif` found in a gif.
+    output ValidationIssue as JsValidationIssue: "ValidationIssue" {
+        /// Errors keep the gif from being decoded completely, warnings are decoded by most viewers
+        severity "severity": &'static str => "\"error\" | \"warning\"",
+        /// What is wrong: `"not-a-gif"`, `"empty-canvas"`, `"truncated"`, `"unknown-block"`,
+        /// `"missing-trailer"`, `"data-after-trailer"`, `"no-frames"`, `"missing-palette"`,
+        /// `"frame-outside-canvas"`, `"zero-delay"`, `"invalid-graphic-control"` or
+        /// `"invalid-loop-extension"`
+        code "code": &'static str => "string",
+        message "message": String => "string",
+        /// The index of the frame the problem is about, if any
+        frame "frame" optional: u32 => "number",
+    }
+}
+
+plain_object! {
+    /// The result of `validate_gif`.
+    output ValidationReport as JsValidationReport: "ValidationReport" {
+        /// Whether there are no errors, warnings don't count
+        valid "valid": bool => "boolean",
+        issues "issues": Vec<ValidationIssue> => "ValidationIssue[]",
+    }
+}
+
+plain_object! {
+    /// A change that `repair_gif` made to a gif.
+    output RepairFix as JsRepairFix: "RepairFix" {
+        /// The `code` of the `ValidationIssue` that was fixed
+        code "code": &'static str => "string",
+        message "message": String => "string",
+        /// The index of the frame that was changed, if any
+        frame "frame" optional: u32 => "number",
+    }
+}
+
+plain_object! {
+    /// The result of `repair_gif`.
+    output RepairResult as JsRepairResult: "RepairResult" {
+        /// The repaired gif
+        data "data": js_sys::Uint8Array => "Uint8Array",
+        fixes "fixes": Vec<RepairFix> => "RepairFix[]",
+    }
+}
+
+plain_object! {
+    /// How often every value from 0 to 255 occurs in a frame, see `frame_histograms`.
+    /// Has `red`, `green` and `blue` for `HistogramKind.Rgb` and `luma` for
+    /// `HistogramKind.Luma`.
+    output FrameHistogram as JsFrameHistogram: "FrameHistogram" {
+        red "red" optional: js_sys::Uint32Array => "Uint32Array",
+        green "green" optional: js_sys::Uint32Array => "Uint32Array",
+        blue "blue" optional: js_sys::Uint32Array => "Uint32Array",
+        luma "luma" optional: js_sys::Uint32Array => "Uint32Array",
+    }
+}
+
+plain_object! {
+    /// Where a frame is on a sprite sheet, see `to_spritesheet`.
+    output SpriteFrame as JsSpriteFrame: "SpriteFrame" {
+        x "x": u32 => "number",
+        y "y": u32 => "number",
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+        /// Delay in milliseconds
+        delay "delay": u32 => "number",
+    }
+}
+
+plain_object! {
+    /// All frames of a gif tiled into one PNG, see `to_spritesheet`.
+    output SpriteSheet as JsSpriteSheet: "SpriteSheet" {
+        png "png": js_sys::Uint8Array => "Uint8Array",
+        width "width": u32 => "number",
+        height "height": u32 => "number",
+        columns "columns": u32 => "number",
+        rows "rows": u32 => "number",
+        frame_width "frameWidth": u16 => "number",
+        frame_height "frameHeight": u16 => "number",
+        frames "frames": Vec<SpriteFrame> => "SpriteFrame[]",
+        /// What lenient decoding stopped at, see `Limits.lenient`
+        warnings "warnings": Vec<String> => "string[]",
+    }
+}
+
+callback! {
+    /// Receives log messages, see `set_log_sink`.
+    LogSink: "LogSink" = "(level: \"error\" | \"info\" | \"debug\", message: string) => void";
+}
+
+plain_object! {
+    /// How fast a gif plays at a frame, see `speed_ramp_gif`.
+    input SpeedKeyframe as JsSpeedKeyframe: "SpeedKeyframe" {
+        /// Position of the frame, starting at 0
+        frame "frame": u32 => "number",
+        /// `2` plays twice as fast, `0.5` half as fast
+        speed "speed": f64 => "number",
+    }
+}
+
+plain_object! {
+    /// A rectangle within a frame in pixels, see `pixelate_gif`.
+    input Region as JsRegion: "Region" {
+        x "x": u16 => "number",
+        y "y": u16 => "number",
+        width "width": u16 => "number",
+        height "height": u16 => "number",
+    }
+}
+
+plain_object! {
+    /// The fields of `EncodeOptions` as a plain object, see `EncodeOptions.fromObject`.
+    input EncodeOptionsInit as JsEncodeOptionsInit: "EncodeOptionsInit" {
+        loop_count "loopCount" optional: i32 => "number | null",
+        quantizer "quantizer" optional: u32 => "Quantizer",
+        max_colors "maxColors" optional: u16 => "number | null",
+        dithering "dithering" optional: u32 => "Dithering",
+        optimize "optimize" optional: bool => "boolean",
+        /// From 0 for lossless up to 200
+        lossy "lossy" optional: u8 => "number",
+        interlaced "interlaced" optional: bool => "boolean",
+        /// A color as `0xRRGGBB` that is written as transparent
+        transparent_color "transparentColor" optional: u32 => "number | null",
+        /// Up to 255 colors that all frames are mapped to, see `EncodeOptions.set_palette`
+        palette "palette" optional: Vec<[u8; 3]> => "[number, number, number][] | null",
+        comment "comment" optional: String => "string | null",
+        keep_output "keepOutput" optional: bool => "boolean",
+        chunk_callback "chunkCallback" optional: js_sys::Function => "((chunk: Uint8Array) => void) | null",
+        cancel_check "cancelCheck" optional: js_sys::Function => "(() => unknown) | null",
+    }
+}
+
+plain_object! {
+    /// The fields of `CaptionOptions` as a plain object, see `CaptionOptions.fromObject`.
+    input CaptionOptionsInit as JsCaptionOptionsInit: "CaptionOptionsInit" {
+        size "size" optional: u16 => "number",
+        color "color" optional: u32 => "number",
+        outline_color "outlineColor" optional: u32 => "number",
+        outline_width "outlineWidth" optional: u16 => "number",
+        position "position" optional: u32 => "CaptionPosition",
+        margin "margin" optional: u16 => "number",
+        font "font" optional: js_sys::Uint8Array => "Uint8Array",
+    }
+}
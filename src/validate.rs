@@ -0,0 +1,337 @@
+//! Checking gifs for defects before they are processed.
+//!
+//! Decoding stops at the first defect with a message that is hard to act on.
+//! `validate_gif` walks over the blocks of a gif instead and collects everything
+//! that is wrong with it, so upload forms can tell users what to fix.
+
+use scan::{self, Block, RawFrame, APPLICATION, GRAPHIC_CONTROL};
+use types::{JsValidationReport, ValidationIssue, ValidationReport};
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    /// The gif can't be decoded completely
+    Error,
+    /// Most viewers show the gif anyway, but probably not as intended
+    Warning,
+}
+
+/// A problem with a gif.
+struct Issue {
+    severity: Severity,
+    code: &'static str,
+    message: String,
+    frame: Option<usize>,
+}
+
+impl Issue {
+    fn error(code: &'static str, message: String, frame: Option<usize>) -> Issue {
+        Issue {
+            severity: Severity::Error,
+            code,
+            message,
+            frame,
+        }
+    }
+
+    fn warning(code: &'static str, message: String, frame: Option<usize>) -> Issue {
+        Issue {
+            severity: Severity::Warning,
+            code,
+            message,
+            frame,
+        }
+    }
+}
+
+/// Collects the problems of the gif `data`.
+fn check(data: &[u8]) -> Vec<Issue> {
+    let header = match scan::header(data) {
+        Ok(header) => header,
+        Err(e) => return vec![Issue::error("not-a-gif", e, None)],
+    };
+
+    let mut issues = Vec::new();
+    if header.width == 0 || header.height == 0 {
+        issues.push(Issue::error(
+            "empty-canvas",
+            format!("the canvas is {}x{} pixels", header.width, header.height),
+            None,
+        ));
+    }
+
+    let mut blocks = match scan::blocks(data) {
+        Ok(blocks) => blocks,
+        Err(e) => return vec![Issue::error("not-a-gif", e, None)],
+    };
+    let mut frames = 0;
+    let mut control = None;
+    let mut trailer = false;
+    let mut truncated = false;
+    loop {
+        let start = blocks.offset();
+        let block = match blocks.next() {
+            Some(block) => block,
+            None => break,
+        };
+        match block {
+            Ok(Block::Extension {
+                label: GRAPHIC_CONTROL,
+                sub_blocks,
+            }) => {
+                control = sub_blocks.first().cloned();
+                if control.is_none_or(|control| control.len() != 4) {
+                    issues.push(Issue::warning(
+                        "invalid-graphic-control",
+                        "the graphic control extension doesn't have 4 bytes".to_string(),
+                        Some(frames),
+                    ));
+                }
+            }
+            Ok(Block::Extension {
+                label: APPLICATION,
+                sub_blocks,
+            }) => {
+                if let Some(message) = loop_extension_problem(&sub_blocks) {
+                    issues.push(Issue::warning("invalid-loop-extension", message, None));
+                }
+            }
+            Ok(Block::Extension { .. }) => {}
+            Ok(Block::Image(image)) => {
+                let frame = RawFrame {
+                    control: control.take().filter(|control| control.len() >= 4),
+                    image,
+                };
+                check_frame(
+                    &frame,
+                    frames,
+                    header.width,
+                    header.height,
+                    header.global_palette_size,
+                    &mut issues,
+                );
+                frames += 1;
+            }
+            Ok(Block::Trailer) => trailer = true,
+            // the data ending right after a block is only missing the trailer
+            Err(_) if start == data.len() => {
+                issues.push(Issue::warning(
+                    "missing-trailer",
+                    "the gif doesn't end with a trailer".to_string(),
+                    None,
+                ));
+            }
+            Err(e) => {
+                let code = if e.starts_with("unexpected end") {
+                    "truncated"
+                } else {
+                    "unknown-block"
+                };
+                issues.push(Issue::error(code, e, Some(frames)));
+                truncated = true;
+            }
+        }
+    }
+
+    if trailer && blocks.offset() < data.len() {
+        let after = &data[blocks.offset()..];
+        // some encoders pad the file with zeros, anything else is most likely
+        // frames that were appended after the gif had ended
+        if after.iter().any(|&byte| byte != 0) {
+            issues.push(Issue::warning(
+                "data-after-trailer",
+                format!("{} bytes after the trailer are ignored", after.len()),
+                None,
+            ));
+        }
+    }
+    if frames == 0 && !truncated {
+        issues.push(Issue::error(
+            "no-frames",
+            "the gif doesn't contain any frames".to_string(),
+            None,
+        ));
+    }
+
+    issues
+}
+
+/// Checks the size, position, palette and delay of a frame.
+fn check_frame(
+    frame: &RawFrame,
+    index: usize,
+    width: u16,
+    height: u16,
+    global_palette_size: usize,
+    issues: &mut Vec<Issue>,
+) {
+    let right = frame.left() as u32 + frame.width() as u32;
+    let bottom = frame.top() as u32 + frame.height() as u32;
+    if right > width as u32 || bottom > height as u32 {
+        issues.push(Issue::warning(
+            "frame-outside-canvas",
+            format!(
+                "the frame of {}x{} pixels at {},{} extends past the canvas of {}x{} pixels",
+                frame.width(),
+                frame.height(),
+                frame.left(),
+                frame.top(),
+                width,
+                height
+            ),
+            Some(index),
+        ));
+    }
+
+    let local_palette = frame.image[9] & 0x80 != 0;
+    if !local_palette && global_palette_size == 0 {
+        issues.push(Issue::error(
+            "missing-palette",
+            "the frame has no palette and there is no global palette".to_string(),
+            Some(index),
+        ));
+    }
+
+    if frame.control.is_some_and(|control| control[1] == 0 && control[2] == 0) {
+        issues.push(Issue::warning(
+            "zero-delay",
+            "the frame has no delay, browsers show it for 1/10s instead".to_string(),
+            Some(index),
+        ));
+    }
+}
+
+/// Describes what is wrong with an application extension that claims to hold the
+/// loop count, `None` for valid loop extensions and other applications.
+pub fn loop_extension_problem(sub_blocks: &[&[u8]]) -> Option<String> {
+    match sub_blocks {
+        [b"NETSCAPE2.0", loops] | [b"ANIMEXTS1.0", loops] if loops.len() == 3 && loops[0] == 1 => None,
+        [b"NETSCAPE2.0", ..] | [b"ANIMEXTS1.0", ..] => {
+            Some("the loop extension doesn't hold a loop count in the expected format".to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Checks a gif for defects without decoding it
+///
+/// Returns an object `{ valid, issues }` where every issue has a `severity` of
+/// `"error"` or `"warning"`, a `code` like `"missing-trailer"`, a `message` and the
+/// `frame` it is about if any. The gif is `valid` if there are no errors, i.e. all
+/// its frames can be decoded. Warnings point out things like frames without a
+/// delay that most viewers display differently than intended.
+#[wasm_bindgen]
+pub fn validate_gif(data: &[u8]) -> JsValidationReport {
+    let issues = check(data);
+
+    ValidationReport {
+        valid: issues.iter().all(|issue| issue.severity != Severity::Error),
+        issues: issues
+            .into_iter()
+            .map(|issue| ValidationIssue {
+                severity: match issue.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                },
+                code: issue.code,
+                message: issue.message,
+                frame: issue.frame.map(|frame| frame as u32),
+            })
+            .collect(),
+    }
+    .into_object()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::{Encoder, Frame, Repeat};
+    use std::borrow::Cow;
+
+    /// A looping gif of 2x2 pixels with a frame of 2x2 pixels at every position and
+    /// delay next to it.
+    fn animation(frames: &[((u16, u16), u16)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut data, 2, 2, &[0, 0, 0, 255, 255, 255]).unwrap();
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for &((left, top), delay) in frames {
+                encoder
+                    .write_frame(&Frame {
+                        left,
+                        top,
+                        width: 2,
+                        height: 2,
+                        delay,
+                        buffer: Cow::Owned(vec![0, 1, 1, 0]),
+                        ..Frame::default()
+                    })
+                    .unwrap();
+            }
+        }
+        data
+    }
+
+    fn codes(data: &[u8]) -> Vec<&'static str> {
+        check(data).iter().map(|issue| issue.code).collect()
+    }
+
+    #[test]
+    fn valid_gifs_have_no_issues() {
+        assert!(codes(&animation(&[((0, 0), 10), ((0, 0), 20)])).is_empty());
+    }
+
+    #[test]
+    fn other_data_is_not_a_gif() {
+        assert_eq!(codes(b"not a gif"), vec!["not-a-gif"]);
+    }
+
+    #[test]
+    fn a_missing_trailer_is_a_warning() {
+        let data = animation(&[((0, 0), 10)]);
+        let issues = check(&data[..data.len() - 1]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].code, "missing-trailer");
+        assert!(issues[0].severity == Severity::Warning);
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let data = animation(&[((0, 0), 10), ((0, 0), 10)]);
+        let issues = check(&data[..data.len() - 4]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].code, "truncated");
+        assert!(issues[0].severity == Severity::Error);
+        assert_eq!(issues[0].frame, Some(1));
+    }
+
+    #[test]
+    fn padding_after_the_trailer_is_fine() {
+        let mut data = animation(&[((0, 0), 10)]);
+        data.extend_from_slice(&[0, 0]);
+        assert!(codes(&data).is_empty());
+
+        data.push(1);
+        assert_eq!(codes(&data), vec!["data-after-trailer"]);
+    }
+
+    #[test]
+    fn frames_are_checked() {
+        let issues = check(&animation(&[((0, 0), 10), ((1, 0), 10), ((0, 0), 0)]));
+        let found: Vec<_> = issues.iter().map(|issue| (issue.code, issue.frame)).collect();
+        assert_eq!(found, vec![("frame-outside-canvas", Some(1)), ("zero-delay", Some(2))]);
+    }
+
+    #[test]
+    fn gifs_without_frames_are_errors() {
+        assert_eq!(codes(&animation(&[])), vec!["no-frames"]);
+    }
+
+    #[test]
+    fn loop_extensions_need_a_loop_count() {
+        assert_eq!(loop_extension_problem(&[b"NETSCAPE2.0", &[1, 0, 0]]), None);
+        assert_eq!(loop_extension_problem(&[b"XMP DataXMP", &[1, 2]]), None);
+        assert!(loop_extension_problem(&[b"NETSCAPE2.0", &[1, 0]]).is_some());
+        assert!(loop_extension_problem(&[b"ANIMEXTS1.0"]).is_some());
+    }
+}
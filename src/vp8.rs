@@ -0,0 +1,2361 @@
+//! Lossy WebP images, which are key frames of the VP8 video format.
+//!
+//! The image is converted to YUV with the chroma planes at half the resolution and
+//! split into macroblocks of 16x16 pixels. Every macroblock is predicted from the
+//! pixels above and to the left of it, and the difference to the prediction is
+//! stored as quantized DCT coefficients. All of it is written with a boolean
+//! entropy coder, whose probabilities depend on what was coded around it.
+//!
+//! The encoder keeps things simple: macroblocks are predicted as a whole with the
+//! best of the four 16x16 modes and the probabilities of the coefficients are
+//! adapted to the image once. Intra prediction uses the pixels from before the
+//! loop filter, so the encoder can turn the filter on without running it.
+//!
+//! The decoder handles everything a key frame can use: 4x4 subblock prediction,
+//! segments, several token partitions and both loop filters.
+
+/// The largest magnitude of a quantized coefficient
+const MAX_LEVEL: i32 = 2048 + 66;
+
+/// The positions of the coefficients of a 4x4 block in the order they're coded
+const ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+/// The band of every coded position, which picks the probabilities. The extra
+/// entry is for the position after the last one.
+const BANDS: [usize; 17] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7, 0];
+
+/// Block types of the coefficient probabilities
+const TYPE_Y_AFTER_Y2: usize = 0;
+const TYPE_Y2: usize = 1;
+const TYPE_CHROMA: usize = 2;
+const TYPE_Y_WITH_DC: usize = 3;
+
+/// The ways whole blocks are predicted, numbered like the subblock modes that they
+/// stand in for
+const DC_PRED: u8 = 0;
+const TM_PRED: u8 = 1;
+const V_PRED: u8 = 2;
+const H_PRED: u8 = 3;
+
+/// The diagonal modes of 4x4 subblocks, the last one is HU_PRED
+const RD_PRED: u8 = 4;
+const VR_PRED: u8 = 5;
+const LD_PRED: u8 = 6;
+const VL_PRED: u8 = 7;
+const HD_PRED: u8 = 8;
+
+/// Luma is predicted in 4x4 subblocks that have their own modes
+const B_PRED: u8 = 10;
+
+/// The tree of the subblock modes. Positive entries point to the next pair of
+/// branches, the others are negated modes.
+const SUBBLOCK_MODE_TREE: [i8; 18] = [0, 1, -1, 2, -2, 3, 4, 6, -3, 5, -4, -5, -6, 7, -7, 8, -8, -9];
+
+/// The probabilities of the extra bits of the large coefficient categories 3 to 6
+const CATEGORY_PROBABILITIES: [&[u8]; 4] = [
+    &[173, 148, 140],
+    &[176, 155, 140, 135],
+    &[180, 157, 141, 134, 130],
+    &[254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129],
+];
+
+type TokenProbabilities = [[[[u8; 11]; 3]; 8]; 4];
+
+/// Writes bits that have a known probability of being 0 in about as much space as
+/// their entropy, the arithmetic coder of VP8.
+struct BoolEncoder {
+    output: Vec<u8>,
+    range: u32,
+    bottom: u32,
+    /// Number of shifts until the next byte is written
+    bit_count: u32,
+}
+
+impl BoolEncoder {
+    fn new() -> BoolEncoder {
+        BoolEncoder {
+            output: Vec::new(),
+            range: 255,
+            bottom: 0,
+            bit_count: 24,
+        }
+    }
+
+    /// Writes `bit`, which is 0 with a probability of `probability / 256`.
+    fn put(&mut self, bit: bool, probability: u8) {
+        let split = 1 + (((self.range - 1) * probability as u32) >> 8);
+        if bit {
+            self.bottom = self.bottom.wrapping_add(split);
+            self.range -= split;
+        } else {
+            self.range = split;
+        }
+
+        while self.range < 128 {
+            self.range <<= 1;
+            if self.bottom & (1 << 31) != 0 {
+                self.carry();
+            }
+            self.bottom <<= 1;
+            self.bit_count -= 1;
+            if self.bit_count == 0 {
+                self.output.push((self.bottom >> 24) as u8);
+                self.bottom &= (1 << 24) - 1;
+                self.bit_count = 8;
+            }
+        }
+    }
+
+    /// Writes the lowest `bits` bits of `value`, the highest one first.
+    fn put_literal(&mut self, value: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.put((value >> bit) & 1 == 1, 128);
+        }
+    }
+
+    /// Adds one to the bytes written so far.
+    fn carry(&mut self) {
+        for byte in self.output.iter_mut().rev() {
+            if *byte == 255 {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                return;
+            }
+        }
+    }
+
+    /// Pushes out the bits that are still pending.
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..32 {
+            self.put(false, 128);
+        }
+        self.output
+    }
+}
+
+/// `a * sqrt(2) * cos(pi / 8)`
+fn multiply_cos(a: i32) -> i32 {
+    ((a * 20091) >> 16) + a
+}
+
+/// `a * sqrt(2) * sin(pi / 8)`
+fn multiply_sin(a: i32) -> i32 {
+    (a * 35468) >> 16
+}
+
+/// Turns 4x4 residuals into DCT coefficients, both in raster order.
+fn forward_dct(residuals: &[i32; 16]) -> [i32; 16] {
+    let mut rows = [0; 16];
+    for i in 0..4 {
+        let row = &residuals[i * 4..i * 4 + 4];
+        let a0 = row[0] + row[3];
+        let a1 = row[1] + row[2];
+        let a2 = row[1] - row[2];
+        let a3 = row[0] - row[3];
+        rows[i * 4] = (a0 + a1) * 8;
+        rows[i * 4 + 1] = (a2 * 2217 + a3 * 5352 + 1812) >> 9;
+        rows[i * 4 + 2] = (a0 - a1) * 8;
+        rows[i * 4 + 3] = (a3 * 2217 - a2 * 5352 + 937) >> 9;
+    }
+
+    let mut coefficients = [0; 16];
+    for i in 0..4 {
+        let a0 = rows[i] + rows[12 + i];
+        let a1 = rows[4 + i] + rows[8 + i];
+        let a2 = rows[4 + i] - rows[8 + i];
+        let a3 = rows[i] - rows[12 + i];
+        coefficients[i] = (a0 + a1 + 7) >> 4;
+        coefficients[4 + i] = ((a2 * 2217 + a3 * 5352 + 12000) >> 16) + (a3 != 0) as i32;
+        coefficients[8 + i] = (a0 - a1 + 7) >> 4;
+        coefficients[12 + i] = (a3 * 2217 - a2 * 5352 + 51000) >> 16;
+    }
+    coefficients
+}
+
+/// Turns DCT coefficients back into residuals exactly like decoders do.
+fn inverse_dct(coefficients: &[i32; 16]) -> [i32; 16] {
+    let mut columns = [0; 16];
+    for i in 0..4 {
+        let a = coefficients[i] + coefficients[8 + i];
+        let b = coefficients[i] - coefficients[8 + i];
+        let c = multiply_sin(coefficients[4 + i]) - multiply_cos(coefficients[12 + i]);
+        let d = multiply_cos(coefficients[4 + i]) + multiply_sin(coefficients[12 + i]);
+        columns[i] = a + d;
+        columns[4 + i] = b + c;
+        columns[8 + i] = b - c;
+        columns[12 + i] = a - d;
+    }
+
+    let mut residuals = [0; 16];
+    for i in 0..4 {
+        let row = &columns[i * 4..i * 4 + 4];
+        let a = row[0] + row[2];
+        let b = row[0] - row[2];
+        let c = multiply_sin(row[1]) - multiply_cos(row[3]);
+        let d = multiply_cos(row[1]) + multiply_sin(row[3]);
+        residuals[i * 4] = (a + d + 4) >> 3;
+        residuals[i * 4 + 1] = (b + c + 4) >> 3;
+        residuals[i * 4 + 2] = (b - c + 4) >> 3;
+        residuals[i * 4 + 3] = (a - d + 4) >> 3;
+    }
+    residuals
+}
+
+/// The Walsh-Hadamard transform of the DC coefficients of the 16 luma blocks of a
+/// macroblock, which compresses the DC of smooth areas further.
+fn forward_wht(dcs: &[i32; 16]) -> [i32; 16] {
+    let mut rows = [0; 16];
+    for i in 0..4 {
+        let row = &dcs[i * 4..i * 4 + 4];
+        let a0 = row[0] + row[2];
+        let a1 = row[1] + row[3];
+        let a2 = row[1] - row[3];
+        let a3 = row[0] - row[2];
+        rows[i * 4] = a0 + a1;
+        rows[i * 4 + 1] = a3 + a2;
+        rows[i * 4 + 2] = a3 - a2;
+        rows[i * 4 + 3] = a0 - a1;
+    }
+
+    let mut coefficients = [0; 16];
+    for i in 0..4 {
+        let a0 = rows[i] + rows[8 + i];
+        let a1 = rows[4 + i] + rows[12 + i];
+        let a2 = rows[4 + i] - rows[12 + i];
+        let a3 = rows[i] - rows[8 + i];
+        coefficients[i] = (a0 + a1) >> 1;
+        coefficients[4 + i] = (a3 + a2) >> 1;
+        coefficients[8 + i] = (a3 - a2) >> 1;
+        coefficients[12 + i] = (a0 - a1) >> 1;
+    }
+    coefficients
+}
+
+/// Turns the Walsh-Hadamard coefficients back into the DC coefficients of the
+/// luma blocks exactly like decoders do.
+fn inverse_wht(coefficients: &[i32; 16]) -> [i32; 16] {
+    let mut columns = [0; 16];
+    for i in 0..4 {
+        let a0 = coefficients[i] + coefficients[12 + i];
+        let a1 = coefficients[4 + i] + coefficients[8 + i];
+        let a2 = coefficients[4 + i] - coefficients[8 + i];
+        let a3 = coefficients[i] - coefficients[12 + i];
+        columns[i] = a0 + a1;
+        columns[8 + i] = a0 - a1;
+        columns[4 + i] = a3 + a2;
+        columns[12 + i] = a3 - a2;
+    }
+
+    let mut dcs = [0; 16];
+    for i in 0..4 {
+        let row = &columns[i * 4..i * 4 + 4];
+        let dc = row[0] + 3;
+        let a0 = dc + row[3];
+        let a1 = row[1] + row[2];
+        let a2 = row[1] - row[2];
+        let a3 = dc - row[3];
+        dcs[i * 4] = (a0 + a1) >> 3;
+        dcs[i * 4 + 1] = (a3 + a2) >> 3;
+        dcs[i * 4 + 2] = (a0 - a1) >> 3;
+        dcs[i * 4 + 3] = (a3 - a2) >> 3;
+    }
+    dcs
+}
+
+/// A plane of samples with its size rounded up to whole macroblocks.
+struct Plane {
+    width: usize,
+    height: usize,
+    samples: Vec<u8>,
+}
+
+impl Plane {
+    fn new(width: usize, height: usize) -> Plane {
+        Plane {
+            width,
+            height,
+            samples: vec![0; width * height],
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> u8 {
+        self.samples[y * self.width + x]
+    }
+}
+
+/// The samples next to a block that it's predicted from.
+///
+/// Decoders fill in 127 for samples above the image and 129 for samples left of
+/// it, the sample in the corner is 127 in the first row and 129 in the others.
+struct Edges {
+    /// The samples above the block, and the four samples after them for luma
+    above: [u8; 20],
+    left: [u8; 16],
+    top_left: u8,
+    has_above: bool,
+    has_left: bool,
+}
+
+impl Edges {
+    /// The edges of the `size` x `size` block at `x`, `y` of `plane`.
+    fn of(plane: &Plane, x: usize, y: usize, size: usize) -> Edges {
+        let mut edges = Edges {
+            above: [127; 20],
+            left: [129; 16],
+            top_left: if y == 0 { 127 } else { 129 },
+            has_above: y > 0,
+            has_left: x > 0,
+        };
+
+        if y > 0 {
+            for i in 0..size {
+                edges.above[i] = plane.at(x + i, y - 1);
+            }
+            // the last block of a row repeats its last sample to the right
+            for i in size..size + 4 {
+                let column = if x + size < plane.width { x + i } else { x + size - 1 };
+                edges.above[i] = plane.at(column, y - 1);
+            }
+        }
+        if x > 0 {
+            for i in 0..size {
+                edges.left[i] = plane.at(x - 1, y + i);
+            }
+            if y > 0 {
+                edges.top_left = plane.at(x - 1, y - 1);
+            }
+        }
+        edges
+    }
+}
+
+/// Predicts a `size` x `size` block from its edges with one of the whole block
+/// modes.
+fn predict(edges: &Edges, size: usize, mode: u8, prediction: &mut [u8]) {
+    match mode {
+        DC_PRED => {
+            let shift = size.trailing_zeros();
+            let above: u32 = edges.above[..size].iter().map(|&sample| sample as u32).sum();
+            let left: u32 = edges.left[..size].iter().map(|&sample| sample as u32).sum();
+            let dc = match (edges.has_above, edges.has_left) {
+                (true, true) => (above + left + size as u32) >> (shift + 1),
+                (true, false) => (above + size as u32 / 2) >> shift,
+                (false, true) => (left + size as u32 / 2) >> shift,
+                (false, false) => 128,
+            };
+            prediction[..size * size].fill(dc as u8);
+        }
+        V_PRED => {
+            for row in prediction[..size * size].chunks_exact_mut(size) {
+                row.copy_from_slice(&edges.above[..size]);
+            }
+        }
+        H_PRED => {
+            for (row, &left) in prediction[..size * size].chunks_exact_mut(size).zip(edges.left.iter()) {
+                row.fill(left);
+            }
+        }
+        _ => {
+            for (row, &left) in prediction[..size * size].chunks_exact_mut(size).zip(edges.left.iter()) {
+                for (sample, &above) in row.iter_mut().zip(edges.above.iter()) {
+                    *sample = (left as i32 + above as i32 - edges.top_left as i32).clamp(0, 255) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// The step sizes of the DC and AC coefficients of every kind of block.
+struct Quantizer {
+    y: [i32; 2],
+    y2: [i32; 2],
+    chroma: [i32; 2],
+}
+
+impl Quantizer {
+    /// The step sizes for a quantizer index from 0 to 127 and the differences to
+    /// it for the Y DC, Y2 DC, Y2 AC, chroma DC and chroma AC coefficients.
+    fn new(index: i32, deltas: &[i32; 5]) -> Quantizer {
+        let dc = |delta: i32, max: i32| DC_QUANTIZER[(index + delta).clamp(0, max) as usize] as i32;
+        let ac = |delta: i32| AC_QUANTIZER[(index + delta).clamp(0, 127) as usize] as i32;
+        Quantizer {
+            y: [dc(deltas[0], 127), ac(0)],
+            y2: [dc(deltas[1], 127) * 2, (ac(deltas[2]) * 155 / 100).max(8)],
+            chroma: [dc(deltas[3], 117), ac(deltas[4])],
+        }
+    }
+}
+
+/// Quantizes a coefficient. `rounding` is how many eighths of a step are added
+/// before rounding down, less than half a step sets more small coefficients to 0.
+fn quantize(coefficient: i32, step: i32, rounding: i32) -> i16 {
+    let level = ((coefficient.abs() * 8 + step * rounding) / (step * 8)).min(MAX_LEVEL);
+    (if coefficient < 0 { -level } else { level }) as i16
+}
+
+/// The result of encoding a macroblock, its modes and quantized coefficients.
+struct Macroblock {
+    luma_mode: u8,
+    chroma_mode: u8,
+    /// The Walsh-Hadamard coefficients of the DC of the luma blocks in zigzag order
+    y2: [i16; 16],
+    /// The coefficients of the 16 luma blocks, which start at the second one, and of
+    /// the 4 U and the 4 V blocks in zigzag order
+    blocks: [[i16; 16]; 24],
+}
+
+impl Macroblock {
+    /// Whether all coefficients are 0, so the macroblock can be skipped.
+    fn is_empty(&self) -> bool {
+        self.y2.iter().all(|&level| level == 0) && self.blocks.iter().all(|block| block.iter().all(|&level| level == 0))
+    }
+}
+
+/// Adds the residuals of dequantized DCT coefficients to a prediction and writes
+/// the result to the 4x4 block at `x`, `y` of `plane`. The prediction has `stride`
+/// samples per row.
+fn reconstruct(plane: &mut Plane, x: usize, y: usize, prediction: &[u8], stride: usize, coefficients: &[i32; 16]) {
+    let residuals = inverse_dct(coefficients);
+    for row in 0..4 {
+        for column in 0..4 {
+            let predicted = prediction[row * stride + column] as i32;
+            let sample = (predicted + residuals[row * 4 + column]).clamp(0, 255) as u8;
+            plane.samples[(y + row) * plane.width + x + column] = sample;
+        }
+    }
+}
+
+/// The sum of squared differences between a block of `plane` and a prediction.
+fn distortion(plane: &Plane, x: usize, y: usize, size: usize, prediction: &[u8]) -> u64 {
+    let mut sum = 0;
+    for row in 0..size {
+        let source = &plane.samples[(y + row) * plane.width + x..][..size];
+        for (&a, &b) in source.iter().zip(&prediction[row * size..(row + 1) * size]) {
+            sum += ((a as i32 - b as i32) * (a as i32 - b as i32)) as u64;
+        }
+    }
+    sum
+}
+
+/// Encodes the 4x4 block at `x`, `y` of `source` on top of `prediction` of the
+/// enclosing `size` x `size` block at `origin`. The reconstructed block is written
+/// to `reconstructed` like decoders will see it. Returns the DCT coefficients if
+/// `dc` is `None`, otherwise `dc` is used as the DC coefficient and only the AC
+/// coefficients are quantized.
+fn encode_block(
+    source: &Plane,
+    reconstructed: &mut Plane,
+    origin: (usize, usize),
+    offset: (usize, usize),
+    size: usize,
+    prediction: &[u8],
+    steps: [i32; 2],
+    levels: &mut [i16; 16],
+    dc: Option<i32>,
+) -> i32 {
+    let (x, y) = (origin.0 + offset.0, origin.1 + offset.1);
+    let mut residuals = [0; 16];
+    for row in 0..4 {
+        for column in 0..4 {
+            let predicted = prediction[(offset.1 + row) * size + offset.0 + column] as i32;
+            residuals[row * 4 + column] = source.at(x + column, y + row) as i32 - predicted;
+        }
+    }
+    let coefficients = forward_dct(&residuals);
+
+    let mut dequantized = [0; 16];
+    let first = match dc {
+        Some(dc) => {
+            dequantized[0] = dc;
+            1
+        }
+        None => 0,
+    };
+    for i in first..16 {
+        let (step, rounding) = if i == 0 { (steps[0], 4) } else { (steps[1], 3) };
+        levels[i] = quantize(coefficients[ZIGZAG[i]], step, rounding);
+        dequantized[ZIGZAG[i]] = levels[i] as i32 * step;
+    }
+
+    reconstruct(
+        reconstructed,
+        x,
+        y,
+        &prediction[offset.1 * size + offset.0..],
+        size,
+        &dequantized,
+    );
+    coefficients[0]
+}
+
+/// Picks the whole block mode whose prediction is closest to the source samples of
+/// every plane and returns it together with the predictions.
+fn best_mode(sources: &[&Plane], edges: &[Edges], x: usize, y: usize, size: usize) -> (u8, Vec<Vec<u8>>) {
+    let mut best: Option<(u64, u8, Vec<Vec<u8>>)> = None;
+    for mode in [DC_PRED, V_PRED, H_PRED, TM_PRED] {
+        let predictions: Vec<Vec<u8>> = edges
+            .iter()
+            .map(|edges| {
+                let mut prediction = vec![0; size * size];
+                predict(edges, size, mode, &mut prediction);
+                prediction
+            })
+            .collect();
+        let error = sources
+            .iter()
+            .zip(predictions.iter())
+            .map(|(plane, prediction)| distortion(plane, x, y, size, prediction))
+            .sum();
+        if best.as_ref().is_none_or(|&(best_error, _, _)| error < best_error) {
+            best = Some((error, mode, predictions));
+        }
+    }
+    let (_, mode, predictions) = best.unwrap();
+    (mode, predictions)
+}
+
+/// Predicts and quantizes the macroblock at `mb_x`, `mb_y` and writes what
+/// decoders will reconstruct from it to `reconstructed`.
+fn encode_macroblock(
+    source: &[Plane; 3],
+    reconstructed: &mut [Plane; 3],
+    mb_x: usize,
+    mb_y: usize,
+    quantizer: &Quantizer,
+) -> Macroblock {
+    let mut macroblock = Macroblock {
+        luma_mode: DC_PRED,
+        chroma_mode: DC_PRED,
+        y2: [0; 16],
+        blocks: [[0; 16]; 24],
+    };
+
+    // the DC coefficients of the luma blocks are coded together, so they have to be
+    // known before any block can be reconstructed
+    let (x, y) = (mb_x * 16, mb_y * 16);
+    let edges = Edges::of(&reconstructed[0], x, y, 16);
+    let (mode, predictions) = best_mode(&[&source[0]], &[edges], x, y, 16);
+    macroblock.luma_mode = mode;
+    let mut dcs = [0; 16];
+    for (block, dc) in dcs.iter_mut().enumerate() {
+        let mut residuals = [0; 16];
+        for row in 0..4 {
+            for column in 0..4 {
+                let (px, py) = ((block % 4) * 4 + column, (block / 4) * 4 + row);
+                residuals[row * 4 + column] = source[0].at(x + px, y + py) as i32 - predictions[0][py * 16 + px] as i32;
+            }
+        }
+        *dc = forward_dct(&residuals)[0];
+    }
+
+    let wht = forward_wht(&dcs);
+    let mut dequantized = [0; 16];
+    for i in 0..16 {
+        let step = quantizer.y2[(i > 0) as usize];
+        macroblock.y2[i] = quantize(wht[ZIGZAG[i]], step, 4);
+        dequantized[ZIGZAG[i]] = macroblock.y2[i] as i32 * step;
+    }
+    let dcs = inverse_wht(&dequantized);
+    for (block, &dc) in dcs.iter().enumerate() {
+        encode_block(
+            &source[0],
+            &mut reconstructed[0],
+            (x, y),
+            ((block % 4) * 4, (block / 4) * 4),
+            16,
+            &predictions[0],
+            quantizer.y,
+            &mut macroblock.blocks[block],
+            Some(dc),
+        );
+    }
+
+    // both chroma planes share a mode
+    let (x, y) = (mb_x * 8, mb_y * 8);
+    let edges = [
+        Edges::of(&reconstructed[1], x, y, 8),
+        Edges::of(&reconstructed[2], x, y, 8),
+    ];
+    let (mode, predictions) = best_mode(&[&source[1], &source[2]], &edges, x, y, 8);
+    macroblock.chroma_mode = mode;
+    for plane in 0..2 {
+        for block in 0..4 {
+            encode_block(
+                &source[plane + 1],
+                &mut reconstructed[plane + 1],
+                (x, y),
+                ((block % 2) * 4, (block / 2) * 4),
+                8,
+                &predictions[plane],
+                quantizer.chroma,
+                &mut macroblock.blocks[16 + plane * 4 + block],
+                None,
+            );
+        }
+    }
+
+    macroblock
+}
+
+/// A probability that a bit of the coefficients is coded with.
+#[derive(Clone, Copy)]
+enum Probability {
+    /// The node of the token tree for a band and a context, which adapts to the image
+    Token(usize, usize, usize),
+    Fixed(u8),
+}
+
+/// Codes the coefficients of a block in zigzag order from `first` on with `put`.
+/// Returns whether any of them isn't 0, which is the context of the blocks to the
+/// right and below.
+fn put_coefficients(put: &mut dyn FnMut(Probability, bool), context: usize, first: usize, levels: &[i16; 16]) -> bool {
+    let last = match (first..16).rev().find(|&i| levels[i] != 0) {
+        Some(last) => last,
+        None => {
+            put(Probability::Token(BANDS[first], context, 0), false);
+            return false;
+        }
+    };
+
+    let mut n = first;
+    let mut context = context;
+    put(Probability::Token(BANDS[n], context, 0), true);
+    loop {
+        let value = levels[n].unsigned_abs() as i32;
+        let band = BANDS[n];
+        // a zero is never the last coefficient, so no end of block follows it
+        put(Probability::Token(band, context, 1), value != 0);
+        if value == 0 {
+            n += 1;
+            context = 0;
+            continue;
+        }
+
+        put(Probability::Token(band, context, 2), value > 1);
+        if value > 1 {
+            put_large_value(put, band, context, value);
+        }
+        put(Probability::Fixed(128), levels[n] < 0);
+
+        context = if value == 1 { 1 } else { 2 };
+        n += 1;
+        if n == 16 {
+            return true;
+        }
+        put(Probability::Token(BANDS[n], context, 0), n <= last);
+        if n > last {
+            return true;
+        }
+    }
+}
+
+/// Codes a coefficient of at least 2 with the token tree and extra bits.
+fn put_large_value(put: &mut dyn FnMut(Probability, bool), band: usize, context: usize, value: i32) {
+    let node = |node| Probability::Token(band, context, node);
+    if value <= 4 {
+        put(node(3), false);
+        put(node(4), value > 2);
+        if value > 2 {
+            put(node(5), value == 4);
+        }
+    } else if value <= 10 {
+        put(node(3), true);
+        put(node(6), false);
+        put(node(7), value > 6);
+        if value <= 6 {
+            put(Probability::Fixed(159), value == 6);
+        } else {
+            put(Probability::Fixed(165), (value - 7) & 2 != 0);
+            put(Probability::Fixed(145), (value - 7) & 1 != 0);
+        }
+    } else {
+        put(node(3), true);
+        put(node(6), true);
+        let category = match value {
+            11..=18 => 0,
+            19..=34 => 1,
+            35..=66 => 2,
+            _ => 3,
+        };
+        put(node(8), category >= 2);
+        put(node(9 + category / 2), category % 2 == 1);
+        let extra = value - (3 + (8 << category));
+        let probabilities = CATEGORY_PROBABILITIES[category];
+        for (i, &probability) in probabilities.iter().enumerate() {
+            put(
+                Probability::Fixed(probability),
+                (extra >> (probabilities.len() - 1 - i)) & 1 == 1,
+            );
+        }
+    }
+}
+
+/// Whether the blocks next to a macroblock have coefficients, which picks the
+/// probabilities of its blocks.
+#[derive(Clone, Copy, Default)]
+struct Contexts {
+    y2: bool,
+    y: [bool; 4],
+    chroma: [[bool; 2]; 2],
+}
+
+/// Codes the coefficients of all macroblocks with `put`, which gets the block type
+/// together with every bit. Macroblocks without coefficients are skipped if
+/// `skip` is set.
+fn put_tokens<F>(macroblocks: &[Macroblock], mb_width: usize, skip: bool, mut put: F)
+where
+    F: FnMut(usize, Probability, bool),
+{
+    let mut above = vec![Contexts::default(); mb_width];
+    for row in macroblocks.chunks(mb_width) {
+        let mut left = Contexts::default();
+        for (macroblock, above) in row.iter().zip(above.iter_mut()) {
+            if skip && macroblock.is_empty() {
+                *above = Contexts::default();
+                left = Contexts::default();
+                continue;
+            }
+
+            let context = above.y2 as usize + left.y2 as usize;
+            let coded = put_coefficients(&mut |p, bit| put(TYPE_Y2, p, bit), context, 0, &macroblock.y2);
+            above.y2 = coded;
+            left.y2 = coded;
+
+            for (block, levels) in macroblock.blocks[..16].iter().enumerate() {
+                let (x, y) = (block % 4, block / 4);
+                let context = above.y[x] as usize + left.y[y] as usize;
+                let coded = put_coefficients(&mut |p, bit| put(TYPE_Y_AFTER_Y2, p, bit), context, 1, levels);
+                above.y[x] = coded;
+                left.y[y] = coded;
+            }
+
+            for (plane, blocks) in macroblock.blocks[16..].chunks(4).enumerate() {
+                for (block, levels) in blocks.iter().enumerate() {
+                    let (x, y) = (block % 2, block / 2);
+                    let context = above.chroma[plane][x] as usize + left.chroma[plane][y] as usize;
+                    let coded = put_coefficients(&mut |p, bit| put(TYPE_CHROMA, p, bit), context, 0, levels);
+                    above.chroma[plane][x] = coded;
+                    left.chroma[plane][y] = coded;
+                }
+            }
+        }
+    }
+}
+
+/// The number of bits it takes to code `zeros` and `ones` with `probability`.
+fn cost(zeros: u32, ones: u32, probability: u8) -> f64 {
+    let p = probability as f64 / 256.0;
+    -(zeros as f64 * p.log2() + ones as f64 * (1.0 - p).log2())
+}
+
+/// Adapts the coefficient probabilities to how often every bit was 0 or 1 where
+/// that saves more than it costs to send the new probability.
+fn adapted_probabilities(counts: &[[[[[u32; 2]; 11]; 3]; 8]; 4]) -> TokenProbabilities {
+    let mut probabilities = COEFFICIENT_PROBABILITIES;
+    for t in 0..4 {
+        for b in 0..8 {
+            for c in 0..3 {
+                for n in 0..11 {
+                    let [zeros, ones] = counts[t][b][c][n];
+                    if zeros + ones == 0 {
+                        continue;
+                    }
+                    let old = COEFFICIENT_PROBABILITIES[t][b][c][n];
+                    let new =
+                        ((zeros as u64 * 255 + (zeros + ones) as u64 / 2) / (zeros + ones) as u64).clamp(1, 255) as u8;
+                    let update = COEFFICIENT_UPDATE_PROBABILITIES[t][b][c][n];
+                    let savings =
+                        cost(zeros, ones, old) - cost(zeros, ones, new) - 8.0 - cost(0, 1, update) + cost(1, 0, update);
+                    if savings > 0.0 {
+                        probabilities[t][b][c][n] = new;
+                    }
+                }
+            }
+        }
+    }
+    probabilities
+}
+
+/// Converts RGBA pixels to YUV planes with the size rounded up to whole
+/// macroblocks, repeating the last column and row.
+fn to_yuv(width: usize, height: usize, rgba: &[u8]) -> [Plane; 3] {
+    let mb_width = width.div_ceil(16);
+    let mb_height = height.div_ceil(16);
+    let mut y_plane = Plane::new(mb_width * 16, mb_height * 16);
+    let mut u_plane = Plane::new(mb_width * 8, mb_height * 8);
+    let mut v_plane = Plane::new(mb_width * 8, mb_height * 8);
+    let pixel = |x: usize, y: usize| {
+        let offset = (y.min(height - 1) * width + x.min(width - 1)) * 4;
+        [rgba[offset] as i32, rgba[offset + 1] as i32, rgba[offset + 2] as i32]
+    };
+
+    for y in 0..y_plane.height {
+        for x in 0..y_plane.width {
+            let [r, g, b] = pixel(x, y);
+            y_plane.samples[y * y_plane.width + x] =
+                ((16839 * r + 33059 * g + 6420 * b + (1 << 15) + (16 << 16)) >> 16) as u8;
+        }
+    }
+
+    // chroma is the average of 2x2 pixels
+    for y in 0..u_plane.height {
+        for x in 0..u_plane.width {
+            let mut sum = [0; 3];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let rgb = pixel(x * 2 + dx, y * 2 + dy);
+                for c in 0..3 {
+                    sum[c] += rgb[c];
+                }
+            }
+            let [r, g, b] = sum;
+            let chroma = |value: i32| ((value + (1 << 17) + (128 << 18)) >> 18).clamp(0, 255) as u8;
+            u_plane.samples[y * u_plane.width + x] = chroma(-9719 * r - 19081 * g + 28800 * b);
+            v_plane.samples[y * v_plane.width + x] = chroma(28800 * r - 24116 * g - 4684 * b);
+        }
+    }
+
+    [y_plane, u_plane, v_plane]
+}
+
+/// Encodes RGBA pixels as a VP8 key frame, which makes up the `VP8 ` chunk of a
+/// lossy WebP. Transparency is left out. `quality` ranges from 0 for the smallest
+/// files to 100 for the best looking images.
+pub fn encode(width: u32, height: u32, rgba: &[u8], quality: u8) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 || width >= 1 << 14 || height >= 1 << 14 {
+        return Err(format!("lossy WebP images can't be {}x{} pixels", width, height));
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let source = to_yuv(width, height, rgba);
+    let mut reconstructed = [
+        Plane::new(source[0].width, source[0].height),
+        Plane::new(source[1].width, source[1].height),
+        Plane::new(source[2].width, source[2].height),
+    ];
+    let mb_width = width.div_ceil(16);
+    let mb_height = height.div_ceil(16);
+
+    let index = (100 - quality.min(100) as i32) * 127 / 100;
+    let quantizer = Quantizer::new(index, &[0; 5]);
+    let mut macroblocks = Vec::with_capacity(mb_width * mb_height);
+    for mb_y in 0..mb_height {
+        for mb_x in 0..mb_width {
+            macroblocks.push(encode_macroblock(&source, &mut reconstructed, mb_x, mb_y, &quantizer));
+        }
+    }
+
+    let skipped = macroblocks.iter().filter(|macroblock| macroblock.is_empty()).count();
+    let skip = skipped > 0;
+    let mut counts = [[[[[0u32; 2]; 11]; 3]; 8]; 4];
+    put_tokens(&macroblocks, mb_width, skip, |t, probability, bit| {
+        if let Probability::Token(b, c, n) = probability {
+            counts[t][b][c][n][bit as usize] += 1;
+        }
+    });
+    let probabilities = adapted_probabilities(&counts);
+
+    // the first partition holds the frame header and the modes of the macroblocks
+    let mut header = BoolEncoder::new();
+    // color space and clamping
+    header.put_literal(0, 2);
+    // no segmentation
+    header.put_literal(0, 1);
+    // the normal loop filter, its level and sharpness, no level adjustments
+    header.put_literal(0, 1);
+    header.put_literal((index as u32 / 2).min(63), 6);
+    header.put_literal(0, 3);
+    header.put_literal(0, 1);
+    // a single partition for the coefficients
+    header.put_literal(0, 2);
+    // the quantizer index without any differences for the kinds of blocks
+    header.put_literal(index as u32, 7);
+    header.put_literal(0, 5);
+    // whether the probabilities are kept for the next frame
+    header.put_literal(0, 1);
+    for t in 0..4 {
+        for b in 0..8 {
+            for c in 0..3 {
+                for n in 0..11 {
+                    let probability = probabilities[t][b][c][n];
+                    let update = probability != COEFFICIENT_PROBABILITIES[t][b][c][n];
+                    header.put(update, COEFFICIENT_UPDATE_PROBABILITIES[t][b][c][n]);
+                    if update {
+                        header.put_literal(probability as u32, 8);
+                    }
+                }
+            }
+        }
+    }
+
+    let skip_probability = ((macroblocks.len() - skipped) * 255 / macroblocks.len()).clamp(1, 254) as u8;
+    header.put(skip, 128);
+    if skip {
+        header.put_literal(skip_probability as u32, 8);
+    }
+    for macroblock in &macroblocks {
+        if skip {
+            header.put(macroblock.is_empty(), skip_probability);
+        }
+        // whole block modes, never the one for 4x4 subblocks
+        header.put(true, 145);
+        match macroblock.luma_mode {
+            DC_PRED | V_PRED => {
+                header.put(false, 156);
+                header.put(macroblock.luma_mode == V_PRED, 163);
+            }
+            mode => {
+                header.put(true, 156);
+                header.put(mode == TM_PRED, 128);
+            }
+        }
+        header.put(macroblock.chroma_mode != DC_PRED, 142);
+        if macroblock.chroma_mode != DC_PRED {
+            header.put(macroblock.chroma_mode != V_PRED, 114);
+            if macroblock.chroma_mode != V_PRED {
+                header.put(macroblock.chroma_mode == TM_PRED, 183);
+            }
+        }
+    }
+    let header = header.finish();
+    if header.len() >= 1 << 19 {
+        return Err(format!("{}x{} pixels are too many for a lossy WebP", width, height));
+    }
+
+    let mut tokens = BoolEncoder::new();
+    put_tokens(&macroblocks, mb_width, skip, |t, probability, bit| match probability {
+        Probability::Token(b, c, n) => tokens.put(bit, probabilities[t][b][c][n]),
+        Probability::Fixed(probability) => tokens.put(bit, probability),
+    });
+    let tokens = tokens.finish();
+
+    let mut output = Vec::with_capacity(10 + header.len() + tokens.len());
+    // a key frame of version 0 that is shown, and the size of the first partition
+    let tag = (header.len() as u32) << 5 | 1 << 4;
+    output.extend_from_slice(&tag.to_le_bytes()[..3]);
+    output.extend_from_slice(&[0x9d, 0x01, 0x2a]);
+    output.extend_from_slice(&(width as u16).to_le_bytes());
+    output.extend_from_slice(&(height as u16).to_le_bytes());
+    output.extend_from_slice(&header);
+    output.extend_from_slice(&tokens);
+    Ok(output)
+}
+
+/// Reads bits written by `BoolEncoder`. Reading past the end gives zeros.
+struct BoolDecoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    value: u32,
+    range: u32,
+    /// Number of shifts since the last byte was read
+    bit_count: u32,
+}
+
+impl<'a> BoolDecoder<'a> {
+    fn new(data: &'a [u8]) -> BoolDecoder<'a> {
+        let mut decoder = BoolDecoder {
+            data,
+            position: 0,
+            value: 0,
+            range: 255,
+            bit_count: 0,
+        };
+        decoder.value = decoder.next_byte() << 8 | decoder.next_byte();
+        decoder
+    }
+
+    fn next_byte(&mut self) -> u32 {
+        let byte = self.data.get(self.position).map_or(0, |&byte| byte as u32);
+        self.position += 1;
+        byte
+    }
+
+    /// Reads a bit that is 0 with a probability of `probability / 256`.
+    fn get(&mut self, probability: u8) -> bool {
+        let split = 1 + (((self.range - 1) * probability as u32) >> 8);
+        let bit = self.value >= split << 8;
+        if bit {
+            self.range -= split;
+            self.value -= split << 8;
+        } else {
+            self.range = split;
+        }
+
+        while self.range < 128 {
+            self.value <<= 1;
+            self.range <<= 1;
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.bit_count = 0;
+                self.value |= self.next_byte();
+            }
+        }
+        bit
+    }
+
+    /// Reads an unsigned value of `bits` bits, the highest one first.
+    fn get_literal(&mut self, bits: u32) -> u32 {
+        (0..bits).fold(0, |value, _| value << 1 | self.get(128) as u32)
+    }
+
+    /// Reads a value of `bits` bits that is followed by its sign.
+    fn get_signed(&mut self, bits: u32) -> i32 {
+        let value = self.get_literal(bits) as i32;
+        if self.get(128) {
+            -value
+        } else {
+            value
+        }
+    }
+
+    /// Reads a signed value of `bits` bits if a flag says that it's there.
+    fn get_optional_signed(&mut self, bits: u32) -> i32 {
+        if self.get(128) {
+            self.get_signed(bits)
+        } else {
+            0
+        }
+    }
+}
+
+/// Up to four groups of macroblocks with their own quantizer and filter level.
+struct Segmentation {
+    enabled: bool,
+    update_map: bool,
+    /// Whether the values replace the ones of the frame instead of being added
+    absolute: bool,
+    quantizer: [i32; 4],
+    filter_level: [i32; 4],
+    tree_probabilities: [u8; 3],
+}
+
+impl Segmentation {
+    fn read(header: &mut BoolDecoder) -> Segmentation {
+        let mut segmentation = Segmentation {
+            enabled: header.get(128),
+            update_map: false,
+            absolute: true,
+            quantizer: [0; 4],
+            filter_level: [0; 4],
+            tree_probabilities: [255; 3],
+        };
+        if !segmentation.enabled {
+            return segmentation;
+        }
+
+        segmentation.update_map = header.get(128);
+        if header.get(128) {
+            segmentation.absolute = header.get(128);
+            for quantizer in &mut segmentation.quantizer {
+                *quantizer = header.get_optional_signed(7);
+            }
+            for level in &mut segmentation.filter_level {
+                *level = header.get_optional_signed(6);
+            }
+        }
+        if segmentation.update_map {
+            for probability in &mut segmentation.tree_probabilities {
+                if header.get(128) {
+                    *probability = header.get_literal(8) as u8;
+                }
+            }
+        }
+        segmentation
+    }
+
+    /// The value of `segment` on top of the value of the whole frame.
+    fn apply(&self, frame: i32, values: &[i32; 4], segment: usize) -> i32 {
+        match (self.enabled, self.absolute) {
+            (false, _) => frame,
+            (true, true) => values[segment],
+            (true, false) => frame + values[segment],
+        }
+    }
+}
+
+/// How strongly the edges of a macroblock are smoothed by the loop filter.
+#[derive(Clone, Copy)]
+struct FilterStrength {
+    /// The largest difference across an edge that is filtered, 0 for none, which
+    /// is raised by 4 on the edges between macroblocks
+    limit: i32,
+    /// The largest difference between samples on the same side of an edge
+    interior: i32,
+    /// Above this difference next to an edge only the closest samples are changed
+    hev_threshold: i32,
+    /// Whether the edges between the blocks inside the macroblock are filtered too
+    inner: bool,
+}
+
+impl FilterStrength {
+    fn new(level: i32, sharpness: i32, inner: bool) -> FilterStrength {
+        let level = level.clamp(0, 63);
+        let mut interior = level;
+        if sharpness > 0 {
+            interior >>= if sharpness > 4 { 2 } else { 1 };
+            interior = interior.min(9 - sharpness);
+        }
+        let interior = interior.max(1);
+        FilterStrength {
+            limit: if level == 0 { 0 } else { 2 * level + interior },
+            interior,
+            hev_threshold: match level {
+                40.. => 2,
+                15.. => 1,
+                _ => 0,
+            },
+            inner,
+        }
+    }
+}
+
+/// Reads the prediction modes of a macroblock: the luma mode, the modes of the
+/// subblocks if it's `B_PRED` and the chroma mode. `above` and `left` are the modes
+/// of the neighboring subblocks, which are updated for the next macroblocks.
+fn read_modes(header: &mut BoolDecoder, above: &mut [u8; 4], left: &mut [u8; 4]) -> (u8, [u8; 16], u8) {
+    let mut subblocks = [DC_PRED; 16];
+    let luma_mode = if !header.get(145) {
+        for y in 0..4 {
+            for x in 0..4 {
+                let probabilities = &SUBBLOCK_MODE_PROBABILITIES[above[x] as usize][left[y] as usize];
+                let mut i = SUBBLOCK_MODE_TREE[header.get(probabilities[0]) as usize];
+                while i > 0 {
+                    i = SUBBLOCK_MODE_TREE[2 * i as usize + header.get(probabilities[i as usize]) as usize];
+                }
+                let mode = -i as u8;
+                subblocks[y * 4 + x] = mode;
+                above[x] = mode;
+                left[y] = mode;
+            }
+        }
+        B_PRED
+    } else {
+        let mode = if header.get(156) {
+            if header.get(128) {
+                TM_PRED
+            } else {
+                H_PRED
+            }
+        } else if header.get(163) {
+            V_PRED
+        } else {
+            DC_PRED
+        };
+        *above = [mode; 4];
+        *left = [mode; 4];
+        mode
+    };
+
+    let chroma_mode = if !header.get(142) {
+        DC_PRED
+    } else if !header.get(114) {
+        V_PRED
+    } else if header.get(183) {
+        TM_PRED
+    } else {
+        H_PRED
+    };
+    (luma_mode, subblocks, chroma_mode)
+}
+
+/// Reads the coefficients of a block in zigzag order from `first` on and stores them
+/// dequantized in raster order. Returns the position after the last one that was
+/// coded, which is more than `first` if any were.
+fn read_coefficients(
+    reader: &mut BoolDecoder,
+    probabilities: &[[[u8; 11]; 3]; 8],
+    context: usize,
+    first: usize,
+    steps: [i32; 2],
+    block: &mut [i32; 16],
+) -> usize {
+    let mut n = first;
+    let mut p = &probabilities[BANDS[n]][context];
+    while n < 16 {
+        if !reader.get(p[0]) {
+            return n;
+        }
+        while !reader.get(p[1]) {
+            n += 1;
+            if n == 16 {
+                return 16;
+            }
+            p = &probabilities[BANDS[n]][0];
+        }
+
+        let value = if !reader.get(p[2]) {
+            p = &probabilities[BANDS[n + 1]][1];
+            1
+        } else {
+            let value = read_large_value(reader, p);
+            p = &probabilities[BANDS[n + 1]][2];
+            value
+        };
+        let value = if reader.get(128) { -value } else { value };
+        block[ZIGZAG[n]] = value * steps[(n > 0) as usize];
+        n += 1;
+    }
+    16
+}
+
+/// Reads a coefficient of at least 2 from the token tree and extra bits.
+fn read_large_value(reader: &mut BoolDecoder, p: &[u8; 11]) -> i32 {
+    if !reader.get(p[3]) {
+        if !reader.get(p[4]) {
+            2
+        } else {
+            3 + reader.get(p[5]) as i32
+        }
+    } else if !reader.get(p[6]) {
+        if !reader.get(p[7]) {
+            5 + reader.get(159) as i32
+        } else {
+            7 + 2 * reader.get(165) as i32 + reader.get(145) as i32
+        }
+    } else {
+        let high = reader.get(p[8]) as usize;
+        let category = 2 * high + reader.get(p[9 + high]) as usize;
+        let extra = CATEGORY_PROBABILITIES[category]
+            .iter()
+            .fold(0, |value, &probability| value << 1 | reader.get(probability) as i32);
+        extra + 3 + (8 << category)
+    }
+}
+
+impl Edges {
+    /// The edges of the 4x4 subblock at `x`, `y` inside the macroblock whose luma
+    /// starts at `origin`. Subblocks on the right of the lower rows continue with
+    /// the samples above and to the right of the macroblock, like the top row.
+    fn of_subblock(plane: &Plane, origin: (usize, usize), x: usize, y: usize) -> Edges {
+        let mut edges = Edges::of(plane, origin.0 + x, origin.1 + y, 4);
+        if x == 12 && y > 0 {
+            let top_right = Edges::of(plane, origin.0 + 12, origin.1, 4).above;
+            edges.above[4..8].copy_from_slice(&top_right[4..8]);
+        }
+        edges
+    }
+}
+
+/// Predicts a 4x4 subblock from its edges with any of the subblock modes.
+fn predict_subblock(edges: &Edges, mode: u8, prediction: &mut [u8; 16]) {
+    // the samples from the bottom left around the corner to the top right
+    let mut e = [0; 13];
+    for (sample, &left) in e[..4].iter_mut().zip(edges.left[..4].iter().rev()) {
+        *sample = left;
+    }
+    e[4] = edges.top_left;
+    e[5..].copy_from_slice(&edges.above[..8]);
+    let a2 = |a: usize, b: usize| ((e[a] as u32 + e[b] as u32 + 1) >> 1) as u8;
+    let a3 = |a: usize, b: usize, c: usize| ((e[a] as u32 + 2 * e[b] as u32 + e[c] as u32 + 2) >> 2) as u8;
+
+    *prediction = match mode {
+        DC_PRED => {
+            let sum: u32 = e[..4].iter().chain(&e[5..9]).map(|&sample| sample as u32).sum();
+            [((sum + 4) >> 3) as u8; 16]
+        }
+        TM_PRED => {
+            let mut prediction = [0; 16];
+            for (i, sample) in prediction.iter_mut().enumerate() {
+                let value = edges.left[i / 4] as i32 + edges.above[i % 4] as i32 - edges.top_left as i32;
+                *sample = value.clamp(0, 255) as u8;
+            }
+            prediction
+        }
+        V_PRED => {
+            let row = [a3(4, 5, 6), a3(5, 6, 7), a3(6, 7, 8), a3(7, 8, 9)];
+            let mut prediction = [0; 16];
+            for chunk in prediction.chunks_exact_mut(4) {
+                chunk.copy_from_slice(&row);
+            }
+            prediction
+        }
+        H_PRED => {
+            let rows = [a3(4, 3, 2), a3(3, 2, 1), a3(2, 1, 0), a3(1, 0, 0)];
+            let mut prediction = [0; 16];
+            for (i, sample) in prediction.iter_mut().enumerate() {
+                *sample = rows[i / 4];
+            }
+            prediction
+        }
+        RD_PRED => {
+            let mut prediction = [0; 16];
+            for (i, sample) in prediction.iter_mut().enumerate() {
+                let d = 3 + i % 4 - i / 4;
+                *sample = a3(d, d + 1, d + 2);
+            }
+            prediction
+        }
+        LD_PRED => {
+            let mut prediction = [0; 16];
+            for (i, sample) in prediction.iter_mut().enumerate() {
+                let d = 5 + i % 4 + i / 4;
+                *sample = a3(d, d + 1, (d + 2).min(12));
+            }
+            prediction
+        }
+        VR_PRED => [
+            a2(4, 5),
+            a2(5, 6),
+            a2(6, 7),
+            a2(7, 8),
+            a3(3, 4, 5),
+            a3(4, 5, 6),
+            a3(5, 6, 7),
+            a3(6, 7, 8),
+            a3(2, 3, 4),
+            a2(4, 5),
+            a2(5, 6),
+            a2(6, 7),
+            a3(1, 2, 3),
+            a3(3, 4, 5),
+            a3(4, 5, 6),
+            a3(5, 6, 7),
+        ],
+        VL_PRED => [
+            a2(5, 6),
+            a2(6, 7),
+            a2(7, 8),
+            a2(8, 9),
+            a3(5, 6, 7),
+            a3(6, 7, 8),
+            a3(7, 8, 9),
+            a3(8, 9, 10),
+            a2(6, 7),
+            a2(7, 8),
+            a2(8, 9),
+            a3(9, 10, 11),
+            a3(6, 7, 8),
+            a3(7, 8, 9),
+            a3(8, 9, 10),
+            a3(10, 11, 12),
+        ],
+        HD_PRED => [
+            a2(3, 4),
+            a3(3, 4, 5),
+            a3(4, 5, 6),
+            a3(5, 6, 7),
+            a2(2, 3),
+            a3(2, 3, 4),
+            a2(3, 4),
+            a3(3, 4, 5),
+            a2(1, 2),
+            a3(1, 2, 3),
+            a2(2, 3),
+            a3(2, 3, 4),
+            a2(0, 1),
+            a3(0, 1, 2),
+            a2(1, 2),
+            a3(1, 2, 3),
+        ],
+        // HU_PRED
+        _ => [
+            a2(3, 2),
+            a3(3, 2, 1),
+            a2(2, 1),
+            a3(2, 1, 0),
+            a2(2, 1),
+            a3(2, 1, 0),
+            a2(1, 0),
+            a3(1, 0, 0),
+            a2(1, 0),
+            a3(1, 0, 0),
+            e[0],
+            e[0],
+            e[0],
+            e[0],
+            e[0],
+            e[0],
+        ],
+    };
+}
+
+/// Whether the differences across the edge before sample `i` are small enough
+/// for the edge to be smoothed.
+fn needs_filter(samples: &[u8], i: usize, step: usize, limit: i32) -> bool {
+    let [p1, p0, q0, q1] = [i - 2 * step, i - step, i, i + step].map(|i| samples[i] as i32);
+    4 * (p0 - q0).abs() + (p1 - q1).abs() <= 2 * limit + 1
+}
+
+/// Like `needs_filter`, but the samples on either side have to be close to each
+/// other too.
+fn needs_normal_filter(samples: &[u8], i: usize, step: usize, limit: i32, interior: i32) -> bool {
+    let s = |k: isize| samples[(i as isize + k * step as isize) as usize] as i32;
+    needs_filter(samples, i, step, limit)
+        && [(-4, -3), (-3, -2), (-2, -1), (3, 2), (2, 1), (1, 0)]
+            .iter()
+            .all(|&(a, b)| (s(a) - s(b)).abs() <= interior)
+}
+
+/// Whether the variance next to the edge before sample `i` is high.
+fn high_variance(samples: &[u8], i: usize, step: usize, threshold: i32) -> bool {
+    let [p1, p0, q0, q1] = [i - 2 * step, i - step, i, i + step].map(|i| samples[i] as i32);
+    (p1 - p0).abs() > threshold || (q1 - q0).abs() > threshold
+}
+
+fn clip_signed(value: i32, bound: i32) -> i32 {
+    value.clamp(-bound, bound - 1)
+}
+
+fn clip_sample(value: i32) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+/// Moves the two samples next to the edge before `i` towards each other.
+fn filter_two(samples: &mut [u8], i: usize, step: usize) {
+    let [p1, p0, q0, q1] = [i - 2 * step, i - step, i, i + step].map(|i| samples[i] as i32);
+    let a = 3 * (q0 - p0) + clip_signed(p1 - q1, 128);
+    samples[i - step] = clip_sample(p0 + clip_signed((a + 3) >> 3, 16));
+    samples[i] = clip_sample(q0 - clip_signed((a + 4) >> 3, 16));
+}
+
+/// Smooths the four samples next to the edge before `i`, inside macroblocks.
+fn filter_four(samples: &mut [u8], i: usize, step: usize) {
+    let [p1, p0, q0, q1] = [i - 2 * step, i - step, i, i + step].map(|i| samples[i] as i32);
+    let a = 3 * (q0 - p0);
+    let a1 = clip_signed((a + 4) >> 3, 16);
+    let a2 = clip_signed((a + 3) >> 3, 16);
+    let a3 = (a1 + 1) >> 1;
+    samples[i - 2 * step] = clip_sample(p1 + a3);
+    samples[i - step] = clip_sample(p0 + a2);
+    samples[i] = clip_sample(q0 - a1);
+    samples[i + step] = clip_sample(q1 - a3);
+}
+
+/// Smooths the six samples next to the edge before `i`, between macroblocks.
+fn filter_six(samples: &mut [u8], i: usize, step: usize) {
+    let [p2, p1, p0, q0, q1, q2] =
+        [i - 3 * step, i - 2 * step, i - step, i, i + step, i + 2 * step].map(|i| samples[i] as i32);
+    let a = clip_signed(3 * (q0 - p0) + clip_signed(p1 - q1, 128), 128);
+    let a1 = (27 * a + 63) >> 7;
+    let a2 = (18 * a + 63) >> 7;
+    let a3 = (9 * a + 63) >> 7;
+    samples[i - 3 * step] = clip_sample(p2 + a3);
+    samples[i - 2 * step] = clip_sample(p1 + a2);
+    samples[i - step] = clip_sample(p0 + a1);
+    samples[i] = clip_sample(q0 - a1);
+    samples[i + step] = clip_sample(q1 - a2);
+    samples[i + 2 * step] = clip_sample(q2 - a3);
+}
+
+/// Filters `length` samples along an edge of `plane` that starts at `x`, `y`. The
+/// edge runs down if `vertical` is set, otherwise to the right.
+fn filter_edge(
+    plane: &mut Plane,
+    x: usize,
+    y: usize,
+    length: usize,
+    vertical: bool,
+    strength: &FilterStrength,
+    simple: bool,
+    macroblock_edge: bool,
+) {
+    let (step, along) = if vertical { (1, plane.width) } else { (plane.width, 1) };
+    let limit = strength.limit + if macroblock_edge { 4 } else { 0 };
+    let samples = &mut plane.samples;
+    for k in 0..length {
+        let i = y * plane.width + x + k * along;
+        if simple {
+            if needs_filter(samples, i, step, limit) {
+                filter_two(samples, i, step);
+            }
+        } else if needs_normal_filter(samples, i, step, limit, strength.interior) {
+            if high_variance(samples, i, step, strength.hev_threshold) {
+                filter_two(samples, i, step);
+            } else if macroblock_edge {
+                filter_six(samples, i, step);
+            } else {
+                filter_four(samples, i, step);
+            }
+        }
+    }
+}
+
+/// Smooths the edges between blocks of the whole frame. The simple filter only
+/// touches luma.
+fn loop_filter(planes: &mut [Plane; 3], strengths: &[FilterStrength], mb_width: usize, simple: bool) {
+    let planes_filtered = if simple { 1 } else { 3 };
+    for (i, strength) in strengths.iter().enumerate() {
+        if strength.limit == 0 {
+            continue;
+        }
+        let (mb_x, mb_y) = (i % mb_width, i / mb_width);
+        for (p, plane) in planes[..planes_filtered].iter_mut().enumerate() {
+            let size = if p == 0 { 16 } else { 8 };
+            let (x, y) = (mb_x * size, mb_y * size);
+            if mb_x > 0 {
+                filter_edge(plane, x, y, size, true, strength, simple, true);
+            }
+            if strength.inner {
+                for offset in (4..size).step_by(4) {
+                    filter_edge(plane, x + offset, y, size, true, strength, simple, false);
+                }
+            }
+            if mb_y > 0 {
+                filter_edge(plane, x, y, size, false, strength, simple, true);
+            }
+            if strength.inner {
+                for offset in (4..size).step_by(4) {
+                    filter_edge(plane, x, y + offset, size, false, strength, simple, false);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes a VP8 key frame into its Y, U and V planes, whose size is rounded up to
+/// whole macroblocks.
+fn decode_yuv(data: &[u8]) -> Result<(u32, u32, [Plane; 3]), String> {
+    if data.len() < 10 {
+        return Err("unexpected end of data".to_string());
+    }
+    let tag = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16;
+    if tag & 1 != 0 {
+        return Err("a lossy WebP image is not a key frame".to_string());
+    }
+    if data[3..6] != [0x9d, 0x01, 0x2a] {
+        return Err("invalid VP8 start code".to_string());
+    }
+    let width = (u16::from_le_bytes([data[6], data[7]]) & 0x3fff) as usize;
+    let height = (u16::from_le_bytes([data[8], data[9]]) & 0x3fff) as usize;
+    if width == 0 || height == 0 {
+        return Err("a lossy WebP image has no pixels".to_string());
+    }
+    let first_size = (tag >> 5) as usize;
+    let data = &data[10..];
+    if data.len() < first_size {
+        return Err("unexpected end of data".to_string());
+    }
+
+    let mut header = BoolDecoder::new(&data[..first_size]);
+    // color space and clamping, which is always done
+    header.get_literal(2);
+    let segmentation = Segmentation::read(&mut header);
+    let simple_filter = header.get(128);
+    let filter_level = header.get_literal(6) as i32;
+    let sharpness = header.get_literal(3) as i32;
+    // the adjustments of the filter level for intra frames and 4x4 subblocks
+    let mut filter_deltas = (0, 0);
+    if header.get(128) && header.get(128) {
+        let frames: Vec<i32> = (0..4).map(|_| header.get_optional_signed(6)).collect();
+        let modes: Vec<i32> = (0..4).map(|_| header.get_optional_signed(6)).collect();
+        filter_deltas = (frames[0], modes[0]);
+    }
+    let partition_count = 1 << header.get_literal(2);
+    let base_index = header.get_literal(7) as i32;
+    let mut deltas = [0; 5];
+    for delta in &mut deltas {
+        *delta = header.get_optional_signed(4);
+    }
+    // whether the probabilities are kept for the next frame
+    header.get(128);
+    let mut probabilities = COEFFICIENT_PROBABILITIES;
+    for t in 0..4 {
+        for b in 0..8 {
+            for c in 0..3 {
+                for n in 0..11 {
+                    if header.get(COEFFICIENT_UPDATE_PROBABILITIES[t][b][c][n]) {
+                        probabilities[t][b][c][n] = header.get_literal(8) as u8;
+                    }
+                }
+            }
+        }
+    }
+    let skip_probability = if header.get(128) {
+        Some(header.get_literal(8) as u8)
+    } else {
+        None
+    };
+
+    // the sizes of all partitions but the last one come first
+    let data = &data[first_size..];
+    let sizes_length = 3 * (partition_count - 1);
+    if data.len() < sizes_length {
+        return Err("unexpected end of data".to_string());
+    }
+    let (sizes, mut data) = data.split_at(sizes_length);
+    let mut partitions = Vec::with_capacity(partition_count);
+    for i in 0..partition_count {
+        let size = match sizes.get(i * 3..i * 3 + 3) {
+            Some(size) => size[0] as usize | (size[1] as usize) << 8 | (size[2] as usize) << 16,
+            None => data.len(),
+        };
+        if data.len() < size {
+            return Err("unexpected end of data".to_string());
+        }
+        let (partition, rest) = data.split_at(size);
+        partitions.push(BoolDecoder::new(partition));
+        data = rest;
+    }
+
+    let quantizers: Vec<Quantizer> = (0..4)
+        .map(|segment| {
+            Quantizer::new(
+                segmentation.apply(base_index, &segmentation.quantizer, segment),
+                &deltas,
+            )
+        })
+        .collect();
+    let mb_width = width.div_ceil(16);
+    let mb_height = height.div_ceil(16);
+    let mut planes = [
+        Plane::new(mb_width * 16, mb_height * 16),
+        Plane::new(mb_width * 8, mb_height * 8),
+        Plane::new(mb_width * 8, mb_height * 8),
+    ];
+    let mut strengths = Vec::with_capacity(mb_width * mb_height);
+    let mut above_modes = vec![[DC_PRED; 4]; mb_width];
+    let mut above_contexts = vec![Contexts::default(); mb_width];
+
+    for mb_y in 0..mb_height {
+        let mut left_modes = [DC_PRED; 4];
+        let mut left = Contexts::default();
+        let tokens = &mut partitions[mb_y % partition_count];
+        for mb_x in 0..mb_width {
+            let segment = if segmentation.update_map {
+                let p = segmentation.tree_probabilities;
+                if !header.get(p[0]) {
+                    header.get(p[1]) as usize
+                } else {
+                    2 + header.get(p[2]) as usize
+                }
+            } else {
+                0
+            };
+            let skip = skip_probability.is_some_and(|probability| header.get(probability));
+            let (luma_mode, subblock_modes, chroma_mode) =
+                read_modes(&mut header, &mut above_modes[mb_x], &mut left_modes);
+            let subblocks = luma_mode == B_PRED;
+
+            // the luma blocks, then the U and V blocks
+            let mut blocks = [[0; 16]; 24];
+            let quantizer = &quantizers[segment];
+            let above = &mut above_contexts[mb_x];
+            if skip {
+                let y2 = (above.y2, left.y2);
+                *above = Contexts::default();
+                left = Contexts::default();
+                if subblocks {
+                    above.y2 = y2.0;
+                    left.y2 = y2.1;
+                }
+            } else {
+                let (first, block_type) = if subblocks {
+                    (0, TYPE_Y_WITH_DC)
+                } else {
+                    let mut y2 = [0; 16];
+                    let context = above.y2 as usize + left.y2 as usize;
+                    let coded =
+                        read_coefficients(tokens, &probabilities[TYPE_Y2], context, 0, quantizer.y2, &mut y2) > 0;
+                    above.y2 = coded;
+                    left.y2 = coded;
+                    for (block, dc) in blocks.iter_mut().zip(inverse_wht(&y2).iter()) {
+                        block[0] = *dc;
+                    }
+                    (1, TYPE_Y_AFTER_Y2)
+                };
+
+                for (i, block) in blocks[..16].iter_mut().enumerate() {
+                    let (x, y) = (i % 4, i / 4);
+                    let context = above.y[x] as usize + left.y[y] as usize;
+                    let end = read_coefficients(tokens, &probabilities[block_type], context, first, quantizer.y, block);
+                    above.y[x] = end > first;
+                    left.y[y] = end > first;
+                }
+                for (plane, blocks) in blocks[16..].chunks_mut(4).enumerate() {
+                    for (i, block) in blocks.iter_mut().enumerate() {
+                        let (x, y) = (i % 2, i / 2);
+                        let context = above.chroma[plane][x] as usize + left.chroma[plane][y] as usize;
+                        let end =
+                            read_coefficients(tokens, &probabilities[TYPE_CHROMA], context, 0, quantizer.chroma, block);
+                        above.chroma[plane][x] = end > 0;
+                        left.chroma[plane][y] = end > 0;
+                    }
+                }
+            }
+
+            let coded = blocks
+                .iter()
+                .any(|block| block.iter().any(|&coefficient| coefficient != 0));
+            let mut level = segmentation.apply(filter_level, &segmentation.filter_level, segment) + filter_deltas.0;
+            if subblocks {
+                level += filter_deltas.1;
+            }
+            strengths.push(FilterStrength::new(level, sharpness, subblocks || coded));
+
+            let (x, y) = (mb_x * 16, mb_y * 16);
+            if subblocks {
+                for (i, block) in blocks[..16].iter().enumerate() {
+                    let (bx, by) = ((i % 4) * 4, (i / 4) * 4);
+                    let mut prediction = [0; 16];
+                    predict_subblock(
+                        &Edges::of_subblock(&planes[0], (x, y), bx, by),
+                        subblock_modes[i],
+                        &mut prediction,
+                    );
+                    reconstruct(&mut planes[0], x + bx, y + by, &prediction, 4, block);
+                }
+            } else {
+                let mut prediction = [0; 256];
+                predict(&Edges::of(&planes[0], x, y, 16), 16, luma_mode, &mut prediction);
+                for (i, block) in blocks[..16].iter().enumerate() {
+                    let (bx, by) = ((i % 4) * 4, (i / 4) * 4);
+                    reconstruct(&mut planes[0], x + bx, y + by, &prediction[by * 16 + bx..], 16, block);
+                }
+            }
+
+            let (x, y) = (mb_x * 8, mb_y * 8);
+            for (plane, blocks) in planes[1..].iter_mut().zip(blocks[16..].chunks(4)) {
+                let mut prediction = [0; 64];
+                predict(&Edges::of(plane, x, y, 8), 8, chroma_mode, &mut prediction);
+                for (i, block) in blocks.iter().enumerate() {
+                    let (bx, by) = ((i % 2) * 4, (i / 2) * 4);
+                    reconstruct(plane, x + bx, y + by, &prediction[by * 8 + bx..], 8, block);
+                }
+            }
+        }
+    }
+
+    // a frame level of 0 turns the filter off, whatever the segments say
+    if filter_level > 0 {
+        loop_filter(&mut planes, &strengths, mb_width, simple_filter);
+    }
+    Ok((width as u32, height as u32, planes))
+}
+
+/// Converts YUV planes to RGBA pixels. Every chroma sample is interpolated from the
+/// four closest ones with weights of 9, 3, 3 and 1 sixteenths, rounded the same way
+/// as libwebp does.
+fn to_rgba(width: usize, height: usize, planes: &[Plane; 3]) -> Vec<u8> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    // the closest chroma sample of a pixel, and the other one in that direction
+    let neighbors = |position: usize, last: usize| {
+        let near = position / 2;
+        let other = if position == 0 {
+            0
+        } else if position % 2 == 1 {
+            (near + 1).min(last)
+        } else {
+            near - 1
+        };
+        (near, other)
+    };
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let (near_y, other_y) = neighbors(y, chroma_height - 1);
+        for x in 0..width {
+            let (near_x, other_x) = neighbors(x, chroma_width - 1);
+            let chroma = |plane: &Plane| {
+                let near = plane.at(near_x, near_y) as u32;
+                let vertical = plane.at(near_x, other_y) as u32;
+                // the first and last column only interpolate vertically
+                if x == 0 || near_x == other_x {
+                    return ((3 * near + vertical + 2) >> 2) as i32;
+                }
+                let horizontal = plane.at(other_x, near_y) as u32;
+                let far = plane.at(other_x, other_y) as u32;
+                ((((far + 3 * horizontal + 3 * vertical + near + 8) >> 3) + near) >> 1) as i32
+            };
+            let (u, v) = (chroma(&planes[1]), chroma(&planes[2]));
+
+            let multiply = |value: i32, factor: i32| (value * factor) >> 8;
+            let luma = multiply(planes[0].at(x, y) as i32, 19077);
+            let clip = |value: i32| (value >> 6).clamp(0, 255) as u8;
+            rgba.extend_from_slice(&[
+                clip(luma + multiply(v, 26149) - 14234),
+                clip(luma - multiply(u, 6419) - multiply(v, 13320) + 8708),
+                clip(luma + multiply(u, 33050) - 17685),
+                255,
+            ]);
+        }
+    }
+    rgba
+}
+
+/// Decodes the `VP8 ` chunk of a lossy WebP into opaque RGBA pixels.
+pub fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    let (width, height, planes) = decode_yuv(data)?;
+    Ok((width, height, to_rgba(width as usize, height as usize, &planes)))
+}
+
+/// The step sizes of DC coefficients for every quantizer index
+const DC_QUANTIZER: [u8; 128] = [
+    4, 5, 6, 7, 8, 9, 10, 10, 11, 12, 13, 14, 15, 16, 17, 17, 18, 19, 20, 20, 21, 21, 22, 22, 23, 23, 24, 25, 25, 26,
+    27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 46, 47, 48, 49, 50, 51, 52, 53,
+    54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 76, 77, 78, 79, 80, 81,
+    82, 83, 84, 85, 86, 87, 88, 89, 91, 93, 95, 96, 98, 100, 101, 102, 104, 106, 108, 110, 112, 114, 116, 118, 122,
+    124, 126, 128, 130, 132, 134, 136, 138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+/// The step sizes of AC coefficients for every quantizer index
+const AC_QUANTIZER: [u16; 128] = [
+    4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33,
+    34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 60, 62, 64, 66,
+    68, 70, 72, 74, 76, 78, 80, 82, 84, 86, 88, 90, 92, 94, 96, 98, 100, 102, 104, 106, 108, 110, 112, 114, 116, 119,
+    122, 125, 128, 131, 134, 137, 140, 143, 146, 149, 152, 155, 158, 161, 164, 167, 170, 173, 177, 181, 185, 189, 193,
+    197, 201, 205, 209, 213, 217, 221, 225, 229, 234, 239, 245, 249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+/// The probabilities of the token tree nodes that coefficients start out with, by
+/// block type, band and context
+const COEFFICIENT_PROBABILITIES: [[[[u8; 11]; 3]; 8]; 4] = [
+    [
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [253, 136, 254, 255, 228, 219, 128, 128, 128, 128, 128],
+            [189, 129, 242, 255, 227, 213, 255, 219, 128, 128, 128],
+            [106, 126, 227, 252, 214, 209, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 98, 248, 255, 236, 226, 255, 255, 128, 128, 128],
+            [181, 133, 238, 254, 221, 234, 255, 154, 128, 128, 128],
+            [78, 134, 202, 247, 198, 180, 255, 219, 128, 128, 128],
+        ],
+        [
+            [1, 185, 249, 255, 243, 255, 128, 128, 128, 128, 128],
+            [184, 150, 247, 255, 236, 224, 128, 128, 128, 128, 128],
+            [77, 110, 216, 255, 236, 230, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 101, 251, 255, 241, 255, 128, 128, 128, 128, 128],
+            [170, 139, 241, 252, 236, 209, 255, 255, 128, 128, 128],
+            [37, 116, 196, 243, 228, 255, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 204, 254, 255, 245, 255, 128, 128, 128, 128, 128],
+            [207, 160, 250, 255, 238, 128, 128, 128, 128, 128, 128],
+            [102, 103, 231, 255, 211, 171, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 152, 252, 255, 240, 255, 128, 128, 128, 128, 128],
+            [177, 135, 243, 255, 234, 225, 128, 128, 128, 128, 128],
+            [80, 129, 211, 255, 194, 224, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [246, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [255, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [198, 35, 237, 223, 193, 187, 162, 160, 145, 155, 62],
+            [131, 45, 198, 221, 172, 176, 220, 157, 252, 221, 1],
+            [68, 47, 146, 208, 149, 167, 221, 162, 255, 223, 128],
+        ],
+        [
+            [1, 149, 241, 255, 221, 224, 255, 255, 128, 128, 128],
+            [184, 141, 234, 253, 222, 220, 255, 199, 128, 128, 128],
+            [81, 99, 181, 242, 176, 190, 249, 202, 255, 255, 128],
+        ],
+        [
+            [1, 129, 232, 253, 214, 197, 242, 196, 255, 255, 128],
+            [99, 121, 210, 250, 201, 198, 255, 202, 128, 128, 128],
+            [23, 91, 163, 242, 170, 187, 247, 210, 255, 255, 128],
+        ],
+        [
+            [1, 200, 246, 255, 234, 255, 128, 128, 128, 128, 128],
+            [109, 178, 241, 255, 231, 245, 255, 255, 128, 128, 128],
+            [44, 130, 201, 253, 205, 192, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 132, 239, 251, 219, 209, 255, 165, 128, 128, 128],
+            [94, 136, 225, 251, 218, 190, 255, 255, 128, 128, 128],
+            [22, 100, 174, 245, 186, 161, 255, 199, 128, 128, 128],
+        ],
+        [
+            [1, 182, 249, 255, 232, 235, 128, 128, 128, 128, 128],
+            [124, 143, 241, 255, 227, 234, 128, 128, 128, 128, 128],
+            [35, 77, 181, 251, 193, 211, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 157, 247, 255, 236, 231, 255, 255, 128, 128, 128],
+            [121, 141, 235, 255, 225, 227, 255, 255, 128, 128, 128],
+            [45, 99, 188, 251, 195, 217, 255, 224, 128, 128, 128],
+        ],
+        [
+            [1, 1, 251, 255, 213, 255, 128, 128, 128, 128, 128],
+            [203, 1, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [137, 1, 177, 255, 224, 255, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [253, 9, 248, 251, 207, 208, 255, 192, 128, 128, 128],
+            [175, 13, 224, 243, 193, 185, 249, 198, 255, 255, 128],
+            [73, 17, 171, 221, 161, 179, 236, 167, 255, 234, 128],
+        ],
+        [
+            [1, 95, 247, 253, 212, 183, 255, 255, 128, 128, 128],
+            [239, 90, 244, 250, 211, 209, 255, 255, 128, 128, 128],
+            [155, 77, 195, 248, 188, 195, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 24, 239, 251, 218, 219, 255, 205, 128, 128, 128],
+            [201, 51, 219, 255, 196, 186, 128, 128, 128, 128, 128],
+            [69, 46, 190, 239, 201, 218, 255, 228, 128, 128, 128],
+        ],
+        [
+            [1, 191, 251, 255, 255, 128, 128, 128, 128, 128, 128],
+            [223, 165, 249, 255, 213, 255, 128, 128, 128, 128, 128],
+            [141, 124, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 16, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [190, 36, 230, 255, 236, 255, 128, 128, 128, 128, 128],
+            [149, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 226, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [247, 192, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [240, 128, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 134, 252, 255, 255, 128, 128, 128, 128, 128, 128],
+            [213, 62, 250, 255, 255, 128, 128, 128, 128, 128, 128],
+            [55, 93, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [202, 24, 213, 235, 186, 191, 220, 160, 240, 175, 255],
+            [126, 38, 182, 232, 169, 184, 228, 174, 255, 187, 128],
+            [61, 46, 138, 219, 151, 178, 240, 170, 255, 216, 128],
+        ],
+        [
+            [1, 112, 230, 250, 199, 191, 247, 159, 255, 255, 128],
+            [166, 109, 228, 252, 211, 215, 255, 174, 128, 128, 128],
+            [39, 77, 162, 232, 172, 180, 245, 178, 255, 255, 128],
+        ],
+        [
+            [1, 52, 220, 246, 198, 199, 249, 220, 255, 255, 128],
+            [124, 74, 191, 243, 183, 193, 250, 221, 255, 255, 128],
+            [24, 71, 130, 219, 154, 170, 243, 182, 255, 255, 128],
+        ],
+        [
+            [1, 182, 225, 249, 219, 240, 255, 224, 128, 128, 128],
+            [149, 150, 226, 252, 216, 205, 255, 171, 128, 128, 128],
+            [28, 108, 170, 242, 183, 194, 254, 223, 255, 255, 128],
+        ],
+        [
+            [1, 81, 230, 252, 204, 203, 255, 192, 128, 128, 128],
+            [123, 102, 209, 247, 188, 196, 255, 233, 128, 128, 128],
+            [20, 95, 153, 243, 164, 173, 255, 203, 128, 128, 128],
+        ],
+        [
+            [1, 222, 248, 255, 216, 213, 128, 128, 128, 128, 128],
+            [168, 175, 246, 252, 235, 205, 255, 255, 128, 128, 128],
+            [47, 116, 215, 255, 211, 212, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 121, 236, 253, 212, 214, 255, 255, 128, 128, 128],
+            [141, 84, 213, 252, 201, 202, 255, 219, 128, 128, 128],
+            [42, 80, 160, 240, 162, 185, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [244, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [238, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+];
+
+/// The probabilities that frame headers change a coefficient probability
+const COEFFICIENT_UPDATE_PROBABILITIES: [[[[u8; 11]; 3]; 8]; 4] = [
+    [
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [176, 246, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 241, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 244, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 246, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [239, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 254, 255, 255, 255, 255, 255, 255],
+            [250, 255, 254, 255, 254, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [217, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [225, 252, 241, 253, 255, 255, 254, 255, 255, 255, 255],
+            [234, 250, 241, 250, 253, 255, 253, 254, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [238, 253, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [247, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [186, 251, 250, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 251, 244, 254, 255, 255, 255, 255, 255, 255, 255],
+            [251, 251, 243, 253, 254, 255, 254, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [236, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 253, 253, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [248, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 254, 252, 254, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 249, 253, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [246, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 254, 251, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [245, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 252, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+];
+
+/// The probabilities of the subblock mode tree nodes, by the modes of the subblocks
+/// above and to the left
+const SUBBLOCK_MODE_PROBABILITIES: [[[u8; 9]; 10]; 10] = [
+    [
+        [231, 120, 48, 89, 115, 113, 120, 152, 112],
+        [152, 179, 64, 126, 170, 118, 46, 70, 95],
+        [175, 69, 143, 80, 85, 82, 72, 155, 103],
+        [56, 58, 10, 171, 218, 189, 17, 13, 152],
+        [114, 26, 17, 163, 44, 195, 21, 10, 173],
+        [121, 24, 80, 195, 26, 62, 44, 64, 85],
+        [144, 71, 10, 38, 171, 213, 144, 34, 26],
+        [170, 46, 55, 19, 136, 160, 33, 206, 71],
+        [63, 20, 8, 114, 114, 208, 12, 9, 226],
+        [81, 40, 11, 96, 182, 84, 29, 16, 36],
+    ],
+    [
+        [134, 183, 89, 137, 98, 101, 106, 165, 148],
+        [72, 187, 100, 130, 157, 111, 32, 75, 80],
+        [66, 102, 167, 99, 74, 62, 40, 234, 128],
+        [41, 53, 9, 178, 241, 141, 26, 8, 107],
+        [74, 43, 26, 146, 73, 166, 49, 23, 157],
+        [65, 38, 105, 160, 51, 52, 31, 115, 128],
+        [104, 79, 12, 27, 217, 255, 87, 17, 7],
+        [87, 68, 71, 44, 114, 51, 15, 186, 23],
+        [47, 41, 14, 110, 182, 183, 21, 17, 194],
+        [66, 45, 25, 102, 197, 189, 23, 18, 22],
+    ],
+    [
+        [88, 88, 147, 150, 42, 46, 45, 196, 205],
+        [43, 97, 183, 117, 85, 38, 35, 179, 61],
+        [39, 53, 200, 87, 26, 21, 43, 232, 171],
+        [56, 34, 51, 104, 114, 102, 29, 93, 77],
+        [39, 28, 85, 171, 58, 165, 90, 98, 64],
+        [34, 22, 116, 206, 23, 34, 43, 166, 73],
+        [107, 54, 32, 26, 51, 1, 81, 43, 31],
+        [68, 25, 106, 22, 64, 171, 36, 225, 114],
+        [34, 19, 21, 102, 132, 188, 16, 76, 124],
+        [62, 18, 78, 95, 85, 57, 50, 48, 51],
+    ],
+    [
+        [193, 101, 35, 159, 215, 111, 89, 46, 111],
+        [60, 148, 31, 172, 219, 228, 21, 18, 111],
+        [112, 113, 77, 85, 179, 255, 38, 120, 114],
+        [40, 42, 1, 196, 245, 209, 10, 25, 109],
+        [88, 43, 29, 140, 166, 213, 37, 43, 154],
+        [61, 63, 30, 155, 67, 45, 68, 1, 209],
+        [100, 80, 8, 43, 154, 1, 51, 26, 71],
+        [142, 78, 78, 16, 255, 128, 34, 197, 171],
+        [41, 40, 5, 102, 211, 183, 4, 1, 221],
+        [51, 50, 17, 168, 209, 192, 23, 25, 82],
+    ],
+    [
+        [138, 31, 36, 171, 27, 166, 38, 44, 229],
+        [67, 87, 58, 169, 82, 115, 26, 59, 179],
+        [63, 59, 90, 180, 59, 166, 93, 73, 154],
+        [40, 40, 21, 116, 143, 209, 34, 39, 175],
+        [47, 15, 16, 183, 34, 223, 49, 45, 183],
+        [46, 17, 33, 183, 6, 98, 15, 32, 183],
+        [57, 46, 22, 24, 128, 1, 54, 17, 37],
+        [65, 32, 73, 115, 28, 128, 23, 128, 205],
+        [40, 3, 9, 115, 51, 192, 18, 6, 223],
+        [87, 37, 9, 115, 59, 77, 64, 21, 47],
+    ],
+    [
+        [104, 55, 44, 218, 9, 54, 53, 130, 226],
+        [64, 90, 70, 205, 40, 41, 23, 26, 57],
+        [54, 57, 112, 184, 5, 41, 38, 166, 213],
+        [30, 34, 26, 133, 152, 116, 10, 32, 134],
+        [39, 19, 53, 221, 26, 114, 32, 73, 255],
+        [31, 9, 65, 234, 2, 15, 1, 118, 73],
+        [75, 32, 12, 51, 192, 255, 160, 43, 51],
+        [88, 31, 35, 67, 102, 85, 55, 186, 85],
+        [56, 21, 23, 111, 59, 205, 45, 37, 192],
+        [55, 38, 70, 124, 73, 102, 1, 34, 98],
+    ],
+    [
+        [125, 98, 42, 88, 104, 85, 117, 175, 82],
+        [95, 84, 53, 89, 128, 100, 113, 101, 45],
+        [75, 79, 123, 47, 51, 128, 81, 171, 1],
+        [57, 17, 5, 71, 102, 57, 53, 41, 49],
+        [38, 33, 13, 121, 57, 73, 26, 1, 85],
+        [41, 10, 67, 138, 77, 110, 90, 47, 114],
+        [115, 21, 2, 10, 102, 255, 166, 23, 6],
+        [101, 29, 16, 10, 85, 128, 101, 196, 26],
+        [57, 18, 10, 102, 102, 213, 34, 20, 43],
+        [117, 20, 15, 36, 163, 128, 68, 1, 26],
+    ],
+    [
+        [102, 61, 71, 37, 34, 53, 31, 243, 192],
+        [69, 60, 71, 38, 73, 119, 28, 222, 37],
+        [68, 45, 128, 34, 1, 47, 11, 245, 171],
+        [62, 17, 19, 70, 146, 85, 55, 62, 70],
+        [37, 43, 37, 154, 100, 163, 85, 160, 1],
+        [63, 9, 92, 136, 28, 64, 32, 201, 85],
+        [75, 15, 9, 9, 64, 255, 184, 119, 16],
+        [86, 6, 28, 5, 64, 255, 25, 248, 1],
+        [56, 8, 17, 132, 137, 255, 55, 116, 128],
+        [58, 15, 20, 82, 135, 57, 26, 121, 40],
+    ],
+    [
+        [164, 50, 31, 137, 154, 133, 25, 35, 218],
+        [51, 103, 44, 131, 131, 123, 31, 6, 158],
+        [86, 40, 64, 135, 148, 224, 45, 183, 128],
+        [22, 26, 17, 131, 240, 154, 14, 1, 209],
+        [45, 16, 21, 91, 64, 222, 7, 1, 197],
+        [56, 21, 39, 155, 60, 138, 23, 102, 213],
+        [83, 12, 13, 54, 192, 255, 68, 47, 28],
+        [85, 26, 85, 85, 128, 128, 32, 146, 171],
+        [18, 11, 7, 63, 144, 171, 4, 4, 246],
+        [35, 27, 10, 146, 174, 171, 12, 26, 128],
+    ],
+    [
+        [190, 80, 35, 99, 180, 80, 126, 54, 45],
+        [85, 126, 47, 87, 176, 51, 41, 20, 32],
+        [101, 75, 128, 139, 118, 146, 116, 128, 85],
+        [56, 41, 15, 176, 236, 85, 37, 9, 62],
+        [71, 30, 17, 119, 118, 255, 17, 18, 138],
+        [101, 38, 60, 138, 55, 70, 43, 26, 142],
+        [146, 36, 19, 30, 171, 255, 97, 27, 20],
+        [138, 45, 61, 62, 219, 1, 81, 188, 64],
+        [32, 41, 20, 117, 151, 142, 20, 21, 163],
+        [112, 19, 12, 61, 195, 128, 48, 4, 24],
+    ],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A smooth RGBA image, the kind of content lossy compression is made for.
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                rgba.extend_from_slice(&[
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    ((x + y) * 2) as u8,
+                    255,
+                ]);
+            }
+        }
+        rgba
+    }
+
+    /// The mean absolute difference of the color channels of two images.
+    fn mean_error(a: &[u8], b: &[u8]) -> f64 {
+        let sum: u64 = a
+            .chunks_exact(4)
+            .zip(b.chunks_exact(4))
+            .flat_map(|(a, b)| (0..3).map(move |i| (a[i] as i64 - b[i] as i64).unsigned_abs()))
+            .sum();
+        sum as f64 / (a.len() / 4 * 3) as f64
+    }
+
+    #[test]
+    fn encoded_images_decode_close_to_the_source() {
+        // a size that isn't a multiple of the macroblock size
+        let (width, height) = (37, 21);
+        let rgba = gradient(width, height);
+
+        let data = encode(width as u32, height as u32, &rgba, 90).unwrap();
+        let (decoded_width, decoded_height, decoded) = decode(&data).unwrap();
+        assert_eq!((decoded_width, decoded_height), (width as u32, height as u32));
+        assert_eq!(decoded.len(), rgba.len());
+        assert!(decoded.chunks_exact(4).all(|pixel| pixel[3] == 255));
+        assert!(mean_error(&rgba, &decoded) < 3.0, "{}", mean_error(&rgba, &decoded));
+    }
+
+    #[test]
+    fn lower_quality_makes_smaller_images() {
+        let rgba: Vec<u8> = (0..64 * 64 * 4).map(|i| (i * 7919 % 256) as u8).collect();
+        let small = encode(64, 64, &rgba, 10).unwrap();
+        let large = encode(64, 64, &rgba, 95).unwrap();
+        assert!(small.len() < large.len());
+
+        let (_, _, low) = decode(&small).unwrap();
+        let (_, _, high) = decode(&large).unwrap();
+        assert!(mean_error(&rgba, &high) < mean_error(&rgba, &low));
+    }
+
+    #[test]
+    fn images_from_libwebp_are_decoded() {
+        // an 8x8 image of the color (200, 100, 50), written by libwebp at quality 90
+        let data = [
+            0xd0, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x08, 0x00, 0x08, 0x00, 0x00, 0xc0, 0x12, 0x25, 0xa0, 0x02, 0x74, 0xba,
+            0x01, 0xf8, 0x00, 0x03, 0xb0, 0x00, 0xfe, 0xda, 0x26, 0xff, 0xee, 0xf3, 0x7e, 0xd3, 0xd7, 0xb4, 0xf5, 0xfd,
+            0x4c, 0xff, 0xf8, 0xca, 0x9f, 0x20, 0x3f, 0xe3, 0x2a, 0x7f, 0xc5, 0xcc, 0x00, 0x00,
+        ];
+        let (width, height, rgba) = decode(&data).unwrap();
+        assert_eq!((width, height), (8, 8));
+        for pixel in rgba.chunks_exact(4) {
+            assert!(
+                (pixel[0] as i32 - 201).abs() <= 1 && pixel[1] == 100 && pixel[2] == 50,
+                "{:?}",
+                pixel
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_images_are_errors() {
+        assert!(encode(0, 4, &[], 50).is_err());
+        assert!(encode(1 << 14, 1, &vec![0; 4 << 14], 50).is_err());
+
+        let data = encode(16, 16, &gradient(16, 16), 50).unwrap();
+        assert!(decode(&data).is_ok());
+        assert!(decode(&data[..8]).is_err());
+
+        // an interframe and a broken start code
+        let mut interframe = data.clone();
+        interframe[0] |= 1;
+        assert!(decode(&interframe).is_err());
+        let mut start_code = data;
+        start_code[3] = 0;
+        assert!(decode(&start_code).is_err());
+    }
+}
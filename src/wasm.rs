@@ -0,0 +1,2783 @@
+//! The functions and types that are exported to JavaScript.
+//!
+//! Everything here is built on `JsValue` and only compiled with the `wasm`
+//! feature. The decoding and encoding underneath lives in the other modules and
+//! works without it, see `native`.
+
+use compose::{Compositor, FrameArena};
+use encode::{gif_from_frames, gif_from_iter, gif_from_raw_frames, gif_from_stream};
+use gif::{ColorOutput, DecodeOptions, Decoder};
+use stats::Phase;
+use std::io::Cursor;
+use std::rc::Rc;
+use std::vec::Vec;
+use types::{FrameFilter, RegisterPhase, ReportProgress};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use {
+    apng, errors, filters, font, indexed, jpeg, logging, png, quantize, reverse, scan, stats, text, timeline, types,
+    webp,
+};
+use {compose_frames, decode_indexed, metadata, read_frames, rgb, Animation, EncodeOptions, FrameData, Limits};
+
+pub use encode::GifBuffer;
+pub use errors::set_error_handler;
+pub use iterator::FrameIterator;
+pub use job::GifJob;
+pub use logging::{set_log_level, set_log_sink, LogLevel};
+pub use pipeline::Pipeline;
+pub use repair::repair_gif;
+pub use session::GifSession;
+pub use stats::take_stats;
+pub use stream::GifDecoderSession;
+pub use validate::validate_gif;
+
+/// Phases passed to the progress callbacks. A transform first decodes all frames
+/// and then encodes the result.
+const DECODING: &str = "decoding";
+const ENCODING: &str = "encoding";
+
+/// Tuples are apparently not supported by wasm-bindgen atm
+/// so we'll use our own datastructure.
+#[wasm_bindgen]
+pub struct Dimension {
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Everything the header of a gif tells about it, see `get_info`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct GifInfo {
+    pub width: u16,
+    pub height: u16,
+    /// The version of the gif format, `87a` or `89a`
+    pub version: String,
+    pub has_global_palette: bool,
+    /// Number of colors in the global palette, 0 if there is none
+    pub global_palette_size: u16,
+    /// Index of the color in the global palette that is shown where no frame has
+    /// been drawn
+    pub background_color_index: u8,
+}
+
+/// Where a caption is placed vertically, it is always centered horizontally.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaptionPosition {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// How `fit_gif` fits a gif into a size with a different aspect ratio.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FitMode {
+    /// Scales the gif to fit inside and fills the bars on two sides
+    Contain,
+    /// Scales the gif to fill the whole size and crops what sticks out
+    Cover,
+}
+
+/// Where `resize_canvas` puts a gif on the new canvas.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// The position of the top left corner of something of size `width` ×
+    /// `height` on a canvas of size `canvas_width` × `canvas_height`.
+    fn place(self, width: u16, height: u16, canvas_width: u16, canvas_height: u16) -> (i32, i32) {
+        let free_width = canvas_width as i32 - width as i32;
+        let free_height = canvas_height as i32 - height as i32;
+        let left = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => free_width / 2,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => free_width,
+        };
+        let top = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+            Anchor::Left | Anchor::Center | Anchor::Right => free_height / 2,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => free_height,
+        };
+        (left, top)
+    }
+}
+
+/// Which histograms `frame_histograms` counts.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HistogramKind {
+    /// One histogram for each of the red, green and blue channels
+    Rgb,
+    /// A single histogram of the luminance with the BT.709 weights
+    Luma,
+}
+
+/// Options for `caption_gif`. Colors are given as `0xRRGGBB`.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct CaptionOptions {
+    /// Height of a line of text in pixels
+    pub size: u32,
+    pub color: u32,
+    pub outline_color: u32,
+    /// Width of the outline in pixels, 0 disables the outline
+    pub outline_width: u32,
+    pub position: CaptionPosition,
+    /// Distance between the caption and the top or bottom edge in pixels
+    pub margin: u32,
+    font: Option<Rc<font::Font>>,
+}
+
+#[wasm_bindgen]
+impl CaptionOptions {
+    /// White text with a black outline at the bottom of the gif
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CaptionOptions {
+        CaptionOptions::default()
+    }
+
+    /// Creates options from a plain object like `{ size: 32, color: 0xffff00 }`.
+    /// Fields that are left out keep their defaults.
+    #[wasm_bindgen(js_name = fromObject)]
+    pub fn from_object(init: &types::JsCaptionOptionsInit) -> Result<CaptionOptions, JsValue> {
+        let init = types::CaptionOptionsInit::from_js(init)?;
+        let defaults = CaptionOptions::default();
+        let position = match types::within(init.position, "position", 0, 2)? {
+            Some(value) if value == CaptionPosition::Top as u32 => CaptionPosition::Top,
+            Some(value) if value == CaptionPosition::Center as u32 => CaptionPosition::Center,
+            Some(_) => CaptionPosition::Bottom,
+            None => defaults.position,
+        };
+
+        Ok(CaptionOptions {
+            size: init.size.map_or(defaults.size, u32::from),
+            color: types::within(init.color, "color", 0, 0xffffff)?.unwrap_or(defaults.color),
+            outline_color: types::within(init.outline_color, "outlineColor", 0, 0xffffff)?
+                .unwrap_or(defaults.outline_color),
+            outline_width: init.outline_width.map_or(defaults.outline_width, u32::from),
+            position,
+            margin: init.margin.map_or(defaults.margin, u32::from),
+            font: match init.font {
+                Some(font) => Some(Rc::new(parse_font(font.into())?)),
+                None => None,
+            },
+        })
+    }
+
+    /// Draws the caption with a TrueType font instead of the built-in one, e.g.
+    /// for scripts other than Latin, Greek and Cyrillic or the typeface of a brand.
+    ///
+    /// `font` holds the bytes of a `.ttf` file or an `.otf` file with TrueType
+    /// outlines. Characters are drawn one after another without kerning or
+    /// shaping, so scripts that join or reorder letters like Arabic or Devanagari
+    /// don't come out right. Characters that the font lacks are drawn as its
+    /// placeholder glyph. Pass `undefined` to go back to the built-in font.
+    pub fn set_font(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "Uint8Array | undefined")] font: &JsValue,
+    ) -> Result<(), JsValue> {
+        self.font = if font.is_undefined() || font.is_null() {
+            None
+        } else {
+            Some(Rc::new(parse_font(font.clone())?))
+        };
+        Ok(())
+    }
+}
+
+/// Reads a font from a `Uint8Array`.
+fn parse_font(font: JsValue) -> Result<font::Font, JsValue> {
+    let bytes = font
+        .dyn_into::<js_sys::Uint8Array>()
+        .map_err(|_| js_error("the font has to be a Uint8Array"))?;
+    font::Font::parse(bytes.to_vec()).map_err(|e| js_error(&format!("invalid font: {}", e)))
+}
+
+impl Default for CaptionOptions {
+    fn default() -> CaptionOptions {
+        CaptionOptions {
+            size: 24,
+            color: 0xffffff,
+            outline_color: 0x000000,
+            outline_width: 2,
+            position: CaptionPosition::Bottom,
+            margin: 8,
+            font: None,
+        }
+    }
+}
+
+/// A small function that decodes a gif and returns its dimensions.
+/// Input is a u8 slice which corresponds to a Uint8Array in JavaScript. Fails
+/// if the data isn't a gif.
+#[wasm_bindgen]
+pub fn get_dimension(data: &[u8]) -> Result<Dimension, JsValue> {
+    let mut decoder = DecodeOptions::new();
+
+    // Configure the decoder such that it will expand the image to RGBA.
+    decoder.set_color_output(ColorOutput::RGBA);
+
+    // Read the file header
+    let decoder = decoder.read_info(data).map_err(|e| js_error(&e.to_string()))?;
+
+    Ok(Dimension {
+        width: decoder.width(),
+        height: decoder.height(),
+    })
+}
+
+/// Reads the header of a gif
+///
+/// Only the first 13 bytes are looked at, so this is cheap even for large gifs.
+#[wasm_bindgen]
+pub fn get_info(data: &[u8]) -> Result<GifInfo, JsValue> {
+    let header = scan::header(data).map_err(|e| js_error(&e))?;
+
+    Ok(GifInfo {
+        width: header.width,
+        height: header.height,
+        version: header.version,
+        has_global_palette: header.global_palette_size > 0,
+        global_palette_size: header.global_palette_size as u16,
+        background_color_index: header.background_color_index,
+    })
+}
+
+/// Reads the metadata of a gif without decoding its frames
+///
+/// Returns an object `{ width, height, frameCount, delays, duration, loopCount,
+/// globalPaletteSize }`. `delays` contains the delay of every frame and
+/// `duration` the sum of all delays, both in milliseconds. `loopCount` uses the
+/// same convention as `EncodeOptions.loop_count`: `0` if the gif plays once, `-1`
+/// if it loops forever and otherwise how often it is repeated.
+#[wasm_bindgen]
+pub fn get_metadata(data: &[u8]) -> Result<types::JsGifMetadata, JsValue> {
+    let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+
+    let delays: Vec<u32> = summary.delays.iter().map(|&delay| delay as u32 * 10).collect();
+    Ok(types::GifMetadata {
+        width: summary.width,
+        height: summary.height,
+        frame_count: delays.len() as u32,
+        duration: delays.iter().sum(),
+        delays,
+        loop_count: summary.loop_count(),
+        global_palette_size: summary.global_palette_size as u32,
+    }
+    .into_object())
+}
+
+/// Returns the dominant colors of a gif
+///
+/// The colors are found with median cut over the visible pixels of all composed
+/// frames, large gifs are sampled. Returns up to `num_colors` colors as `[r, g, b]`
+/// arrays, the ones that cover the most pixels first. `num_colors` ranges from 1 to
+/// 256, gifs without any opaque pixel have no colors.
+#[wasm_bindgen(unchecked_return_type = "[number, number, number][]")]
+pub fn extract_palette(data: &[u8], num_colors: u16, limits: &Limits) -> Result<js_sys::Array, JsValue> {
+    console_error_panic_hook::set_once();
+
+    if !(1..=256).contains(&num_colors) {
+        return Err(js_error("num_colors must be between 1 and 256"));
+    }
+
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let (frames, _) = collect_frames(&mut reader, width, height, limits)?;
+
+    // about a million pixels are plenty to find the colors that stand out
+    let pixels: usize = frames.iter().map(|frame| frame.rgba.len() / 4).sum();
+    let step = pixels / 1_000_000 + 1;
+    let colors = quantize::dominant_colors(frames.iter().map(|frame| &frame.rgba[..]), step, num_colors as usize);
+
+    Ok(colors
+        .iter()
+        .map(|color| -> JsValue {
+            color
+                .iter()
+                .map(|&c| JsValue::from(c))
+                .collect::<js_sys::Array>()
+                .into()
+        })
+        .collect())
+}
+
+/// Estimates how many bytes the gif `data` takes up once it's encoded with `options`
+///
+/// Gifs with more than 16 frames aren't encoded completely. Four runs of four
+/// frames spread over the gif are encoded instead and the size of the rest is
+/// extrapolated from them, which is usually within a few percent for gifs whose
+/// content doesn't change much over time. Chunk callbacks and `keep_output` of the
+/// options are ignored.
+#[wasm_bindgen]
+pub fn estimate_size(data: &[u8], options: &EncodeOptions) -> Result<f64, JsValue> {
+    console_error_panic_hook::set_once();
+
+    const RUNS: usize = 4;
+    const RUN_LENGTH: usize = 4;
+
+    let mut reader = decode_data(data)?;
+    let (width, height, global_palette) = metadata(&reader);
+    let (frames, warnings) = collect_frames_with(&mut reader, width, height, &options.limits(), |_| {
+        options.check_cancelled()
+    })?;
+    options.add_warnings(warnings);
+    let options = options.with_source(data).without_output();
+    let encoded_size = |frames: &[FrameData]| -> Result<f64, JsValue> {
+        gif_from_frames(frames, width, height, global_palette.clone(), &options, &|_| {}).map(|gif| gif.len() as f64)
+    };
+
+    if frames.len() <= RUNS * RUN_LENGTH {
+        return encoded_size(&frames);
+    }
+
+    // the header, the palette and the trailer are written only once. runs of
+    // consecutive frames keep the savings of `optimize` between frames.
+    let overhead = encoded_size(&[])?;
+    let mut sampled = 0.0;
+    for run in 0..RUNS {
+        let start = run * (frames.len() - RUN_LENGTH) / (RUNS - 1);
+        sampled += encoded_size(&frames[start..start + RUN_LENGTH])? - overhead;
+    }
+
+    Ok((overhead + sampled / (RUNS * RUN_LENGTH) as f64 * frames.len() as f64).round())
+}
+
+/// Counts how often every value occurs in each composed frame of a gif
+///
+/// Returns an array with an object per frame. With `HistogramKind.Rgb` the objects
+/// are `{ red, green, blue }` and with `HistogramKind.Luma` they are `{ luma }`,
+/// each a `Uint32Array` of 256 counts. Only visible pixels are counted, pixels of
+/// the canvas that no frame has covered yet are left out. The histograms are taken
+/// while the frames are composed, so the frames are never all in memory at once.
+#[wasm_bindgen]
+pub fn frame_histograms(
+    data: &[u8],
+    kind: HistogramKind,
+    limits: &Limits,
+) -> Result<Vec<types::JsFrameHistogram>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let mut histograms = Vec::new();
+    compose_frames(&mut reader, width, height, limits, js_error, |frame| {
+        let visible = frame.rgba.chunks_exact(4).filter(|pixel| pixel[3] > 0);
+        match kind {
+            HistogramKind::Rgb => {
+                let mut counts = [[0u32; 256]; 3];
+                for pixel in visible {
+                    for (channel, &value) in counts.iter_mut().zip(pixel) {
+                        channel[value as usize] += 1;
+                    }
+                }
+                let [red, green, blue] = counts.map(|channel| Some(js_sys::Uint32Array::from(&channel[..])));
+                histograms.push(
+                    types::FrameHistogram {
+                        red,
+                        green,
+                        blue,
+                        luma: None,
+                    }
+                    .into_object(),
+                );
+            }
+            HistogramKind::Luma => {
+                let mut counts = [0u32; 256];
+                for pixel in visible {
+                    counts[filters::luma(pixel) as usize] += 1;
+                }
+                let luma = Some(js_sys::Uint32Array::from(&counts[..]));
+                histograms.push(
+                    types::FrameHistogram {
+                        red: None,
+                        green: None,
+                        blue: None,
+                        luma,
+                    }
+                    .into_object(),
+                );
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(histograms)
+}
+
+/// Returns how every frame of a gif is stored, without decoding it
+///
+/// Returns an array with an object `{ left, top, width, height, delay, disposal,
+/// interlaced, localPaletteSize, transparentIndex }` per frame. `delay` is in
+/// hundredths of a second and `disposal` one of `"any"`, `"keep"`, `"background"`
+/// and `"previous"`, reserved values count as `"any"` like decoders treat them.
+/// `localPaletteSize` is `0` for frames that use the global palette and
+/// `transparentIndex` is left out for frames without transparency. Frames after a
+/// defect are left out, `validate_gif` tells what's wrong there.
+#[wasm_bindgen]
+pub fn frame_info(data: &[u8]) -> Result<Vec<types::JsFrameInfo>, JsValue> {
+    let frames = scan::frames_until_defect(data).map_err(|e| js_error(&e))?;
+
+    Ok(frames
+        .iter()
+        .map(|frame| {
+            let disposal = match frame.disposal() {
+                1 => "keep",
+                2 => "background",
+                3 => "previous",
+                _ => "any",
+            };
+            types::FrameInfo {
+                left: frame.left(),
+                top: frame.top(),
+                width: frame.width(),
+                height: frame.height(),
+                delay: frame.delay(),
+                disposal,
+                interlaced: frame.interlaced(),
+                local_palette_size: frame.local_palette_size() as u32,
+                transparent_index: frame.transparent_index(),
+            }
+            .into_object()
+        })
+        .collect())
+}
+
+/// Returns the text of all comment extensions of a gif as an array of strings
+#[wasm_bindgen(unchecked_return_type = "string[]")]
+pub fn get_comments(data: &[u8]) -> Result<js_sys::Array, JsValue> {
+    let comments = scan::comments(data).map_err(|e| js_error(&e))?;
+
+    Ok(comments.iter().map(|comment| JsValue::from(comment.as_str())).collect())
+}
+
+/// Creates a JavaScript `Error` that is thrown when returned as the `Err` variant
+/// from an exported function.
+pub(crate) fn js_error(message: &str) -> JsValue {
+    logging::error(message);
+    errors::report(message, false);
+    js_sys::Error::new(message).into()
+}
+
+/// Converts a frame into a plain JavaScript object `{ width, height, delay, rgba }`.
+pub(crate) fn frame_to_js(frame: &FrameData) -> types::JsDecodedFrame {
+    types::DecodedFrame {
+        width: frame.width,
+        height: frame.height,
+        delay: frame.delay,
+        rgba: js_sys::Uint8Array::from(&frame.rgba[..]),
+    }
+    .into_object()
+}
+
+/// Reads a frame from a JavaScript object `{ width, height, delay, rgba }`, the
+/// counterpart of `frame_to_js`. `rgba` can be a `Uint8Array` or the
+/// `Uint8ClampedArray` of an `ImageData`.
+fn frame_from_js(value: &JsValue) -> Result<FrameData, JsValue> {
+    let frame = types::FrameInput::from_js(value)?;
+    let (width, height) = (frame.width, frame.height);
+    if width == 0 || height == 0 {
+        return Err(js_error(&format!(
+            "a frame has to be at least 1x1 but is {}x{}",
+            width, height
+        )));
+    }
+    let delay = match frame.delay {
+        Some(delay) if delay >= 0.0 && delay <= u16::MAX as f64 => delay.round() as u16,
+        Some(_) => return Err(js_error(&format!("delay has to be between 0 and {}", u16::MAX))),
+        None => 0,
+    };
+
+    let rgba = frame.rgba.to_vec();
+    if rgba.len() != width as usize * height as usize * 4 {
+        return Err(js_error(&format!(
+            "rgba has {} bytes but a {}x{} frame needs {}",
+            rgba.len(),
+            width,
+            height,
+            width as usize * height as usize * 4
+        )));
+    }
+
+    Ok(FrameData {
+        width,
+        height,
+        rgba,
+        delay,
+    })
+}
+
+/// The RGBA color to fill new areas of a canvas with, opaque `0xRRGGBB` or
+/// transparent without a color.
+fn fill_color(color: Option<u32>) -> Result<[u8; 4], JsValue> {
+    match color {
+        Some(color) if color > 0xffffff => Err(js_error("color has to be given as 0xRRGGBB")),
+        Some(color) => {
+            let [r, g, b] = rgb(color);
+            Ok([r, g, b, 255])
+        }
+        None => Ok([0, 0, 0, 0]),
+    }
+}
+
+/// Creates the error that is thrown when an operation was cancelled. Its `name` is
+/// `CancelledError` so it can be told apart from actual failures.
+pub(crate) fn cancelled_error() -> JsValue {
+    errors::report("the operation was cancelled", true);
+    let error = js_sys::Error::new("the operation was cancelled");
+    error.set_name("CancelledError");
+    error.into()
+}
+
+// Instantiate a gif reader from the byte slice
+pub(crate) fn decode_data(data: &[u8]) -> Result<Decoder<&[u8]>, JsValue> {
+    let mut decoder = DecodeOptions::new();
+    decoder.set_color_output(ColorOutput::Indexed);
+
+    decoder.read_info(data).map_err(|e| js_error(&e.to_string()))
+}
+
+/// Like `decode_data` but the reader owns the gif, so it can be kept around
+/// between calls from JavaScript.
+pub(crate) fn decode_owned_data(data: Vec<u8>) -> Result<Decoder<Cursor<Vec<u8>>>, JsValue> {
+    let mut decoder = DecodeOptions::new();
+    decoder.set_color_output(ColorOutput::Indexed);
+
+    decoder
+        .read_info(Cursor::new(data))
+        .map_err(|e| js_error(&e.to_string()))
+}
+
+/// Extract all the frames from the gif
+///
+/// The `reader` has to be mutable because `read_next_frame()` probably has some state that
+/// is mutated like "what's the current frame". The returned vector contains all frames fully
+/// decoded. Gifs can sometime contain only partial images of just the areas that change from
+/// one frame to the next. This may cause reversed gifs to look funny because only parts of the
+/// image are rendered. That's why every frame is drawn onto a canvas that keeps what the
+/// previous frames left behind according to their disposal method. The warnings of
+/// lenient decoding are returned together with the frames.
+pub(crate) fn collect_frames(
+    reader: &mut Decoder<&[u8]>,
+    width: u16,
+    height: u16,
+    limits: &Limits,
+) -> Result<(Vec<FrameData>, Vec<String>), JsValue> {
+    collect_frames_with(reader, width, height, limits, |_| Ok(()))
+}
+
+/// Like `collect_frames` but calls `after_frame` with the number of frames decoded so
+/// far after every frame. Decoding stops as soon as `after_frame` fails or the gif
+/// exceeds the limits. A defect in the gif is an error unless decoding is lenient.
+pub(crate) fn collect_frames_with<F>(
+    reader: &mut Decoder<&[u8]>,
+    width: u16,
+    height: u16,
+    limits: &Limits,
+    after_frame: F,
+) -> Result<(Vec<FrameData>, Vec<String>), JsValue>
+where
+    F: FnMut(usize) -> Result<(), JsValue>,
+{
+    read_frames(reader, width, height, limits, js_error, after_frame)
+}
+
+/// Like `read_frames` but composes the frames into an arena with room for the
+/// `expected` number of frames, so no frame needs an allocation of its own. The
+/// warnings of lenient decoding are returned together with the arena.
+fn read_arena<E, M, F>(
+    reader: &mut Decoder<&[u8]>,
+    width: u16,
+    height: u16,
+    expected: usize,
+    limits: &Limits,
+    error: M,
+    mut after_frame: F,
+) -> Result<(FrameArena, Vec<String>), E>
+where
+    M: Fn(&str) -> E,
+    F: FnMut(usize) -> Result<(), E>,
+{
+    limits.check_size(width, height).map_err(|e| error(&e))?;
+    limits.check_memory(expected, width, height).map_err(|e| error(&e))?;
+
+    let mut arena = FrameArena::with_capacity(width, height, expected);
+    let frame_size = width as usize * height as usize * 4;
+    let warnings = decode_indexed(
+        reader,
+        width,
+        height,
+        limits,
+        None,
+        &error,
+        |compositor, frame, buffer| {
+            limits
+                .check_memory(arena.len() + 1, width, height)
+                .map_err(|e| error(&e))?;
+            stats::time(Phase::Compose, || compositor.compose_into(frame, buffer, &mut arena));
+            stats::frame_bytes(arena.len() * frame_size);
+            after_frame(arena.len())
+        },
+    )?;
+
+    Ok((arena, warnings))
+}
+
+/// Counts the frames of `data` that are going to be decoded without decoding them.
+/// Gifs with too many frames are rejected right away.
+fn frames_to_decode(data: &[u8], limits: &Limits) -> Result<usize, JsValue> {
+    limits.check_frames(scan::count_frames(data)).map_err(|e| js_error(&e))
+}
+
+/// Tells the `register` callback of the job `id` how many frames are going to be
+/// processed in `phase`.
+fn register_phase(register: &js_sys::Function, id: &str, name: &str, frames: usize, phase: &str) {
+    register
+        .call4(
+            &JsValue::NULL,
+            &JsValue::from(id),
+            &JsValue::from(name),
+            &JsValue::from(frames),
+            &JsValue::from(phase),
+        )
+        .unwrap();
+}
+
+/// Returns a closure that forwards the number of frames processed in `phase` to
+/// the `report` callback of the job `id`.
+fn progress_reporter<'a>(id: &'a str, report: &'a js_sys::Function, phase: &'a str) -> impl Fn(usize) + 'a {
+    move |processed| {
+        report
+            .call3(
+                &JsValue::NULL,
+                &JsValue::from(id),
+                &JsValue::from(processed),
+                &JsValue::from(phase),
+            )
+            .unwrap();
+    }
+}
+
+/// Decodes a gif, applies `transform` to the fully composed frames and encodes
+/// the result again.
+///
+/// The frames are composed into a single arena. `transform` can change them in
+/// place or add new ones and returns the entries of the frames to encode, which
+/// are handed to the encoder straight out of the arena.
+///
+/// `register` is called at the start of both phases with the job id, its name,
+/// the number of frames that will be processed and the phase, i.e. `"decoding"`
+/// or `"encoding"`. `report` is called with the job id, the number of frames
+/// processed so far and the phase after every frame that was decoded or written.
+/// If `transform` fails the error is passed on and nothing is encoded.
+fn transform_gif<F>(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &js_sys::Function,
+    report: &js_sys::Function,
+    options: &EncodeOptions,
+    transform: F,
+) -> Result<Vec<u8>, JsValue>
+where
+    F: FnOnce(&mut FrameArena) -> Result<Vec<timeline::Entry>, JsValue>,
+{
+    console_error_panic_hook::set_once();
+    stats::start();
+    let _job = errors::begin(id, name);
+
+    logging::debug(&format!("{}: reading the metadata of {} bytes", name, data.len()));
+    let mut reader = decode_data(data)?;
+    let (width, height, global_palette) = metadata(&reader);
+
+    logging::debug(&format!("{}: decoding frames of {}x{} pixels", name, width, height));
+    let limits = options.limits();
+    let count = frames_to_decode(data, &limits)?;
+    register_phase(register, id, name, count, DECODING);
+    let decoded = progress_reporter(id, report, DECODING);
+    let (mut arena, warnings) = read_arena(&mut reader, width, height, count, &limits, js_error, |count| {
+        options.check_cancelled()?;
+        decoded(count);
+        Ok(())
+    })?;
+    options.add_warnings(warnings);
+    logging::info(&format!("{}: decoded {} frames", name, arena.len()));
+
+    options.check_cancelled()?;
+    logging::debug(&format!("{}: transforming the frames", name));
+    errors::at("transform", None);
+    let entries = stats::time(Phase::Transform, || transform(&mut arena))?;
+    stats::frame_bytes(arena.len() * width as usize * height as usize * 4);
+    let options = &options.with_source(data);
+
+    register_phase(register, id, name, entries.len(), ENCODING);
+
+    logging::debug(&format!(
+        "{}: encoding {} frames of {}x{} pixels",
+        name,
+        entries.len(),
+        width,
+        height
+    ));
+    let frames = entries
+        .iter()
+        .map(|entry| arena.frame(entry.index).with_delay(entry.delay));
+    let gif = gif_from_iter(
+        frames,
+        width,
+        height,
+        global_palette,
+        options,
+        &progress_reporter(id, report, ENCODING),
+    )?;
+    logging::info(&format!("{}: encoded {} frames", name, entries.len()));
+    Ok(gif)
+}
+
+/// Like `transform_gif` for transforms that change every frame on its own.
+///
+/// `map` is called with every composed frame and its index. Frames are encoded as
+/// soon as they are decoded and changed, so only one of them is in memory at a
+/// time. With a chunk callback in the options the encoded gif isn't kept either.
+fn map_gif<F>(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &js_sys::Function,
+    report: &js_sys::Function,
+    options: &EncodeOptions,
+    mut map: F,
+) -> Result<Vec<u8>, JsValue>
+where
+    F: FnMut(&mut FrameData, usize) -> Result<(), JsValue>,
+{
+    console_error_panic_hook::set_once();
+    stats::start();
+    let _job = errors::begin(id, name);
+
+    logging::debug(&format!("{}: reading the metadata of {} bytes", name, data.len()));
+    let mut reader = decode_data(data)?;
+    let (width, height, global_palette) = metadata(&reader);
+
+    logging::debug(&format!(
+        "{}: decoding, transforming and encoding frames of {}x{} pixels",
+        name, width, height
+    ));
+    let limits = options.limits();
+    let count = frames_to_decode(data, &limits)?;
+    register_phase(register, id, name, count, DECODING);
+    register_phase(register, id, name, count, ENCODING);
+    let decoded = progress_reporter(id, report, DECODING);
+    let options = &options.with_source(data);
+
+    let mut frames = 0;
+    let gif = gif_from_stream(
+        width,
+        height,
+        global_palette,
+        options,
+        &progress_reporter(id, report, ENCODING),
+        |sink| {
+            let warnings = compose_frames(&mut reader, width, height, &limits, js_error, |mut frame| {
+                options.check_cancelled()?;
+                decoded(frames + 1);
+                errors::at("transform", Some(frames));
+                stats::time(Phase::Transform, || map(&mut frame, frames))?;
+                frames += 1;
+                sink(frame.as_ref())
+            })?;
+            options.add_warnings(warnings);
+            Ok(())
+        },
+    )?;
+    logging::info(&format!("{}: encoded {} frames", name, frames));
+    Ok(gif)
+}
+
+/// Like `transform_gif` for transforms that only change the order, number or
+/// timing of frames.
+///
+/// `transform` rearranges entries that stand in for the frames. Unless the options
+/// change how frames are encoded, the compressed frames are copied as they are if
+/// they keep their order or don't build on each other, which neither decodes them
+/// nor expands them to RGBA. Otherwise, unless the options ask for smaller
+/// palettes, the frames keep their original palettes and pixels, which is faster
+/// and doesn't lose any quality. Gifs that mix several palettes within a frame
+/// can't be handled that way and take the same route as all other transforms.
+fn retime_gif<F>(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &js_sys::Function,
+    report: &js_sys::Function,
+    options: &EncodeOptions,
+    mut transform: F,
+) -> Result<Vec<u8>, JsValue>
+where
+    F: FnMut(&mut Vec<timeline::Entry>) -> Result<(), JsValue>,
+{
+    console_error_panic_hook::set_once();
+    stats::start();
+    let _job = errors::begin(id, name);
+
+    if let Some(encoded) = retime_raw(id, name, data, register, report, options, &mut transform)? {
+        return Ok(encoded);
+    }
+    if let Some(encoded) = retime_indexed(id, name, data, register, report, options, &mut transform)? {
+        return Ok(encoded);
+    }
+
+    // frames that are shown more than once are encoded from the same pixels
+    transform_gif(id, name, data, register, report, options, |frames| {
+        let mut entries = frames.entries();
+        transform(&mut entries)?;
+        Ok(entries)
+    })
+}
+
+/// The pass-through route of `retime_gif`, returns `None` if the options change
+/// how frames are encoded, the gif has a defect or the transform rearranges frames
+/// that build on the ones before them.
+fn retime_raw<F>(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &js_sys::Function,
+    report: &js_sys::Function,
+    options: &EncodeOptions,
+    transform: &mut F,
+) -> Result<Option<Vec<u8>>, JsValue>
+where
+    F: FnMut(&mut Vec<timeline::Entry>) -> Result<(), JsValue>,
+{
+    if !options.keeps_frames() {
+        return Ok(None);
+    }
+    let mut frames = match scan::raw_frames(data) {
+        Ok(frames) => frames,
+        Err(_) => return Ok(None),
+    };
+    let header = scan::header(data).map_err(|e| js_error(&e))?;
+    let kept = options
+        .limits()
+        .check_size(header.width, header.height)
+        .and_then(|_| options.limits().check_frames(frames.len()))
+        .map_err(|e| js_error(&e))?;
+    frames.truncate(kept);
+
+    register_phase(register, id, name, frames.len(), DECODING);
+    progress_reporter(id, report, DECODING)(frames.len());
+
+    let mut entries: Vec<timeline::Entry> = frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| timeline::Entry {
+            index,
+            delay: frame.delay(),
+        })
+        .collect();
+    transform(&mut entries)?;
+
+    // frames that build on the ones before them only look right in their original
+    // order, dropping frames at the end is fine though
+    let in_order = entries
+        .iter()
+        .enumerate()
+        .all(|(position, entry)| entry.index == position);
+    if !in_order && reverse::opaque_frames(data).is_none() {
+        return Ok(None);
+    }
+
+    // the graphic control extensions are copied with the new delays
+    let controls: Vec<[u8; 4]> = entries
+        .iter()
+        .map(|entry| {
+            let mut control = [0; 4];
+            if let Some(original) = frames[entry.index].control {
+                control.copy_from_slice(&original[..4]);
+            }
+            control[1..3].copy_from_slice(&entry.delay.to_le_bytes());
+            control
+        })
+        .collect();
+    let arranged: Vec<scan::RawFrame> = entries
+        .iter()
+        .zip(controls.iter())
+        .map(|(entry, control)| {
+            let frame = &frames[entry.index];
+            scan::RawFrame {
+                control: if frame.control.is_some() || entry.delay > 0 {
+                    Some(&control[..])
+                } else {
+                    None
+                },
+                image: frame.image,
+            }
+        })
+        .collect();
+
+    let global_palette = &data[13..13 + header.global_palette_size * 3];
+    let options = &options
+        .with_source(data)
+        .with_background_color_index(header.background_color_index);
+    register_phase(register, id, name, arranged.len(), ENCODING);
+
+    gif_from_raw_frames(
+        &arranged,
+        header.width,
+        header.height,
+        global_palette,
+        options,
+        &progress_reporter(id, report, ENCODING),
+    )
+    .map(Some)
+}
+
+/// The lossless route of `retime_gif`, returns `None` if the options ask for
+/// smaller palettes or the gif mixes several palettes within a frame.
+fn retime_indexed<F>(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &js_sys::Function,
+    report: &js_sys::Function,
+    options: &EncodeOptions,
+    transform: &mut F,
+) -> Result<Option<Vec<u8>>, JsValue>
+where
+    F: FnMut(&mut Vec<timeline::Entry>) -> Result<(), JsValue>,
+{
+    if !options.keeps_palettes() {
+        return Ok(None);
+    }
+
+    let limits = options.limits();
+    register_phase(register, id, name, frames_to_decode(data, &limits)?, DECODING);
+    let decoded = progress_reporter(id, report, DECODING);
+    let indexed = match indexed::collect_frames(data, &limits, |count| {
+        options.check_cancelled()?;
+        decoded(count);
+        Ok(())
+    })? {
+        Some(indexed) => indexed,
+        None => return Ok(None),
+    };
+    options.add_warnings(indexed.warnings);
+
+    options.check_cancelled()?;
+    let mut entries = timeline::entries(&indexed.frames);
+    transform(&mut entries)?;
+    let frames = timeline::arrange(indexed.frames, &entries);
+
+    let options = &options.with_source(data);
+    register_phase(register, id, name, frames.len(), ENCODING);
+
+    gif_from_frames(
+        &frames,
+        indexed.width,
+        indexed.height,
+        indexed.global_palette,
+        options,
+        &progress_reporter(id, report, ENCODING),
+    )
+    .map(Some)
+}
+
+/// Reverses a gif
+///
+/// With `start_frame` and `end_frame` only the frames from `start_frame` up to but
+/// not including `end_frame` play backwards, e.g. for a rewind effect in the middle
+/// of a clip. Leaving out `end_frame` reverses everything from `start_frame` on.
+///
+/// Frames that have to be composed are written as the area that changed from the
+/// frame before, with unchanged pixels transparent, as if `optimize` was set.
+#[wasm_bindgen]
+pub fn reverse_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+    start_frame: Option<u32>,
+    end_frame: Option<u32>,
+) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let start = start_frame.unwrap_or(0) as usize;
+    let end = end_frame.map(|end| end as usize);
+    let reverse =
+        |frames: &mut [timeline::Entry]| timeline::reverse_range(frames, start, end).map_err(|e| js_error(&e));
+
+    // frames that don't build on each other are copied without decoding them
+    if options.keeps_frames() {
+        if let Some(mut frames) = reverse::opaque_frames(data) {
+            let header = scan::header(data).map_err(|e| js_error(&e))?;
+            let kept = options
+                .limits()
+                .check_size(header.width, header.height)
+                .and_then(|_| options.limits().check_frames(frames.len()))
+                .map_err(|e| js_error(&e))?;
+            frames.truncate(kept);
+            timeline::reverse_range(&mut frames, start, end).map_err(|e| js_error(&e))?;
+            let global_palette = &data[13..13 + header.global_palette_size * 3];
+
+            register_phase(register, id, name, frames.len(), DECODING);
+            progress_reporter(id, report, DECODING)(frames.len());
+            let options = &options
+                .with_source(data)
+                .with_background_color_index(header.background_color_index);
+            register_phase(register, id, name, frames.len(), ENCODING);
+
+            return gif_from_raw_frames(
+                &frames,
+                header.width,
+                header.height,
+                global_palette,
+                options,
+                &progress_reporter(id, report, ENCODING),
+            );
+        }
+    }
+
+    // reversed frames are composed onto the full canvas, so only writing what
+    // changes keeps the result from growing far beyond the original
+    let options = &options.with_optimize();
+
+    // the frames around a section that is reversed are composed as usual
+    if start_frame.is_some() || end_frame.is_some() {
+        return retime_gif(id, name, data, register, report, options, |frames| reverse(frames));
+    }
+
+    let mut transform = |frames: &mut Vec<timeline::Entry>| reverse(frames);
+    if let Some(encoded) = retime_indexed(id, name, data, register, report, options, &mut transform)? {
+        return Ok(encoded);
+    }
+
+    // only one canvas and what every frame changed on it is kept, the composed
+    // frames are created from last to first while they are encoded
+    let limits = options.limits();
+    register_phase(register, id, name, frames_to_decode(data, &limits)?, DECODING);
+    let decoded = progress_reporter(id, report, DECODING);
+    let mut reversed = reverse::decode(data, &limits, |count| {
+        options.check_cancelled()?;
+        decoded(count);
+        Ok(())
+    })?;
+    options.add_warnings(std::mem::take(&mut reversed.warnings));
+
+    options.check_cancelled()?;
+    let options = &options.with_source(data);
+    register_phase(register, id, name, reversed.len(), ENCODING);
+
+    let (width, height, global_palette) = (reversed.width, reversed.height, reversed.global_palette.clone());
+    gif_from_iter(
+        reversed,
+        width,
+        height,
+        global_palette,
+        options,
+        &progress_reporter(id, report, ENCODING),
+    )
+}
+
+/// Mirrors every frame of a gif horizontally and/or vertically
+#[wasm_bindgen]
+pub fn flip_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    horizontal: bool,
+    vertical: bool,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::flip(frame, horizontal, vertical);
+        Ok(())
+    })
+}
+
+/// Converts every frame of a gif to grayscale
+#[wasm_bindgen]
+pub fn grayscale_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::grayscale(frame);
+        Ok(())
+    })
+}
+
+/// Turns every frame of a gif black and white
+///
+/// Pixels with a luminance of at least `cutoff`, from 0 to 255, become white and
+/// all others black. The frames only need two colors then, which makes line art and
+/// screen captures tiny.
+#[wasm_bindgen]
+pub fn threshold_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    cutoff: u8,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::threshold(frame, cutoff);
+        Ok(())
+    })
+}
+
+/// Applies a sepia tone to every frame of a gif
+#[wasm_bindgen]
+pub fn sepia_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    intensity: f32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::sepia(frame, intensity);
+        Ok(())
+    })
+}
+
+/// Inverts the colors of every frame of a gif
+#[wasm_bindgen]
+pub fn invert_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::invert(frame);
+        Ok(())
+    })
+}
+
+/// Adjusts brightness and contrast of every frame of a gif
+///
+/// Both `brightness` and `contrast` range from -100 to 100, 0 leaves the gif unchanged.
+#[wasm_bindgen]
+pub fn adjust_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    brightness: i32,
+    contrast: i32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let lut = filters::brightness_contrast_lut(brightness, contrast);
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::apply_lut(frame, &lut);
+        Ok(())
+    })
+}
+
+/// Reduces every color channel of every frame of a gif to `levels` values
+///
+/// `levels` ranges from 2 to 256, where 256 leaves the gif unchanged. Fewer levels
+/// give flat areas of color and much smaller palettes and files.
+#[wasm_bindgen]
+pub fn posterize_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    levels: u16,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if !(2..=256).contains(&levels) {
+        return Err(js_error("levels must be between 2 and 256"));
+    }
+    let lut = filters::posterize_lut(levels);
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::apply_lut(frame, &lut);
+        Ok(())
+    })
+}
+
+/// Pixelates every frame of a gif
+///
+/// Every `block_size` × `block_size` square of pixels gets its average color. With a
+/// `region` only that part of the frames is pixelated, e.g. to hide a face.
+#[wasm_bindgen]
+pub fn pixelate_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    block_size: u16,
+    region: Option<types::JsRegion>,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if block_size == 0 {
+        return Err(js_error("block_size has to be at least 1"));
+    }
+    let region = match region {
+        Some(region) => {
+            let region = types::Region::from_js(&region)?;
+            Some((region.x, region.y, region.width, region.height))
+        }
+        None => None,
+    };
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::pixelate(frame, block_size, region);
+        Ok(())
+    })
+}
+
+/// Blurs every frame of a gif with a Gaussian blur
+///
+/// `sigma` is the standard deviation of the blur in pixels, from above 0 up to 100.
+/// The blur reaches about three times as far.
+#[wasm_bindgen]
+pub fn blur_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    sigma: f32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if !(sigma > 0.0 && sigma <= 100.0) {
+        return Err(js_error("sigma must be above 0 and at most 100"));
+    }
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::blur(frame, sigma);
+        Ok(())
+    })
+}
+
+/// Sharpens every frame of a gif with an unsharp mask
+///
+/// `amount` ranges from 0 for no change to 5, 1 is a good start for gifs that
+/// became soft from resizing. Details of about a pixel are sharpened.
+#[wasm_bindgen]
+pub fn sharpen_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    amount: f32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if !(0.0..=5.0).contains(&amount) {
+        return Err(js_error("amount must be between 0 and 5"));
+    }
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::sharpen(frame, amount, 1.0);
+        Ok(())
+    })
+}
+
+/// Applies a JavaScript function to every frame of a gif
+///
+/// `filter` is called for every composed frame with its RGBA pixels, its width and
+/// height and its index. It can change the pixels in place or return new pixels of
+/// the same length, e.g. an `ImageData`'s `data` after drawing on a canvas.
+///
+/// The pixels are a view into wasm memory that is only valid while `filter` runs.
+/// Calling into the module from `filter` may invalidate it, a filter that needs to
+/// do so has to return its result instead.
+#[wasm_bindgen]
+pub fn filter_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    filter: &FrameFilter,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, index| {
+        // safe because the view isn't used after the call, see above
+        let rgba = unsafe { js_sys::Uint8Array::view_mut_raw(frame.rgba.as_mut_ptr(), frame.rgba.len()) };
+        let result = filter.call4(
+            &JsValue::NULL,
+            &rgba,
+            &JsValue::from(frame.width),
+            &JsValue::from(frame.height),
+            &JsValue::from(index as u32),
+        )?;
+
+        if result.is_undefined() || result.is_null() {
+            return Ok(());
+        }
+        let pixels = if let Some(pixels) = result.dyn_ref::<js_sys::Uint8ClampedArray>() {
+            pixels.to_vec()
+        } else if let Some(pixels) = result.dyn_ref::<js_sys::Uint8Array>() {
+            pixels.to_vec()
+        } else {
+            return Err(js_error(
+                "the filter has to return a Uint8Array, a Uint8ClampedArray or nothing",
+            ));
+        };
+        if pixels.len() != frame.rgba.len() {
+            return Err(js_error(&format!(
+                "the filter returned {} bytes for frame {} but it has {}",
+                pixels.len(),
+                index,
+                frame.rgba.len()
+            )));
+        }
+        frame.rgba = pixels;
+        Ok(())
+    })
+}
+
+/// Makes a color of a gif transparent, e.g. the solid background of a sticker
+///
+/// Every pixel whose red, green and blue each differ by at most `tolerance` from
+/// `key_color`, given as `0xRRGGBB`, becomes transparent. The frames of the result
+/// get a transparent color wherever they have transparent pixels.
+#[wasm_bindgen]
+pub fn chroma_key_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    key_color: u32,
+    tolerance: u8,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if key_color > 0xffffff {
+        return Err(js_error("key_color has to be given as 0xRRGGBB"));
+    }
+    let key = rgb(key_color);
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::chroma_key(frame, key, tolerance);
+        Ok(())
+    })
+}
+
+/// Adds a border around a gif
+///
+/// The canvas grows by `top`, `right`, `bottom` and `left` pixels on the respective
+/// side, the margin is filled with `color` given as `0xRRGGBB` or stays
+/// transparent without a color. The canvas can be at most 65535 pixels wide and
+/// high.
+#[wasm_bindgen]
+pub fn pad_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    top: u16,
+    right: u16,
+    bottom: u16,
+    left: u16,
+    color: Option<u32>,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let fill = fill_color(color)?;
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        let width = frame.width as u32 + left as u32 + right as u32;
+        let height = frame.height as u32 + top as u32 + bottom as u32;
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(js_error(&format!(
+                "a padded canvas of {}x{} pixels is too large for a gif",
+                width, height
+            )));
+        }
+        options
+            .limits()
+            .check_size(width as u16, height as u16)
+            .map_err(|e| js_error(&e))?;
+        filters::place(frame, width as u16, height as u16, left as i32, top as i32, fill);
+        Ok(())
+    })
+}
+
+/// Rounds the corners of a gif
+///
+/// The corners outside of quarter circles with a radius of `radius` pixels become
+/// transparent, the radius is at most half the shorter side of the gif.
+#[wasm_bindgen]
+pub fn round_corners_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    radius: u16,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::round_corners(frame, radius);
+        Ok(())
+    })
+}
+
+/// Scales a gif to exactly `target_width` × `target_height` keeping its aspect ratio
+///
+/// With `FitMode.Contain` the whole gif stays visible and the bars on two sides are
+/// filled with `color` given as `0xRRGGBB`, or stay transparent without a color.
+/// With `FitMode.Cover` the gif fills the whole size and is cropped evenly on two
+/// sides.
+#[wasm_bindgen]
+pub fn fit_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    target_width: u16,
+    target_height: u16,
+    mode: FitMode,
+    color: Option<u32>,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if target_width == 0 || target_height == 0 {
+        return Err(js_error(&format!(
+            "can't fit a gif into {}x{}",
+            target_width, target_height
+        )));
+    }
+    options
+        .limits()
+        .check_size(target_width, target_height)
+        .map_err(|e| js_error(&e))?;
+    let fill = fill_color(color)?;
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        match mode {
+            FitMode::Contain => filters::letterbox(frame, target_width, target_height, fill),
+            FitMode::Cover => filters::cover(frame, target_width, target_height),
+        }
+        Ok(())
+    })
+}
+
+/// Changes the size of the canvas of a gif without scaling it
+///
+/// The gif is put onto a canvas of `new_width` × `new_height` at the `anchor`, e.g.
+/// in the center. Parts that don't fit onto a smaller canvas are cut off, the free
+/// area of a larger one is filled with `color` given as `0xRRGGBB` or stays
+/// transparent without a color.
+#[wasm_bindgen]
+pub fn resize_canvas(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    new_width: u16,
+    new_height: u16,
+    anchor: Anchor,
+    color: Option<u32>,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if new_width == 0 || new_height == 0 {
+        return Err(js_error(&format!(
+            "can't resize the canvas to {}x{}",
+            new_width, new_height
+        )));
+    }
+    options
+        .limits()
+        .check_size(new_width, new_height)
+        .map_err(|e| js_error(&e))?;
+    let fill = fill_color(color)?;
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        let (left, top) = anchor.place(frame.width, frame.height, new_width, new_height);
+        filters::place(frame, new_width, new_height, left, top, fill);
+        Ok(())
+    })
+}
+
+/// Turns every frame of a gif into its edges
+///
+/// Edges are drawn in white, the stronger the brighter, either over black or with
+/// `on_original` over the frame itself. Edges with a strength below `threshold`,
+/// from 0 to 255, are left out to hide noise.
+#[wasm_bindgen]
+pub fn edges_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    threshold: u8,
+    on_original: bool,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::edges(frame, threshold, on_original);
+        Ok(())
+    })
+}
+
+/// Darkens the corners of every frame of a gif
+///
+/// `strength` ranges from 0 for no change to 1 for black corners. The darkening
+/// starts at `radius` times the distance from the center to the corners, from 0
+/// to 1, and grows smoothly towards the corners.
+#[wasm_bindgen]
+pub fn vignette_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    strength: f32,
+    radius: f32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if !(0.0..=1.0).contains(&strength) || !(0.0..=1.0).contains(&radius) {
+        return Err(js_error("strength and radius must be between 0 and 1"));
+    }
+
+    // all frames have the size of the canvas, so they share the mask
+    let mut mask = None;
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        let mask = mask.get_or_insert_with(|| filters::vignette_mask(frame.width, frame.height, strength, radius));
+        filters::apply_mask(frame, mask);
+        Ok(())
+    })
+}
+
+/// Shifts the hue and adjusts saturation and lightness of every frame of a gif
+///
+/// `hue_shift_deg` is given in degrees, `saturation` and `lightness` range from -100
+/// to 100 with 0 leaving the gif unchanged.
+#[wasm_bindgen]
+pub fn hsl_adjust_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    hue_shift_deg: f32,
+    saturation: f32,
+    lightness: f32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::hsl_adjust(frame, hue_shift_deg, saturation, lightness);
+        Ok(())
+    })
+}
+
+/// Decodes the frames of a gif up to the frame `index` and returns it composed.
+/// Only a single canvas is kept in memory while the frames before it are drawn.
+fn frame_at(data: &[u8], index: usize, limits: &Limits) -> Result<FrameData, JsValue> {
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+
+    let mut decoded = 0;
+    let mut composed = None;
+    decode_indexed(
+        &mut reader,
+        width,
+        height,
+        limits,
+        Some(index),
+        js_error,
+        |compositor, frame, buffer| {
+            if decoded == index {
+                composed = Some(compositor.compose(frame, buffer));
+            } else {
+                compositor.skip(frame, buffer);
+            }
+            decoded += 1;
+            Ok(())
+        },
+    )?;
+
+    composed.ok_or_else(|| {
+        js_error(&format!(
+            "frame {} does not exist, the gif has {} frames",
+            index, decoded
+        ))
+    })
+}
+
+/// Decodes a single fully composed frame of a gif
+///
+/// Only the frames up to `index` are decoded and none of them is kept, which makes
+/// this a lot cheaper than `decode_frames` for frames near the start. Returns an
+/// object `{ width, height, delay, rgba }`.
+#[wasm_bindgen]
+pub fn get_frame(data: &[u8], index: u32, limits: &Limits) -> Result<types::JsDecodedFrame, JsValue> {
+    console_error_panic_hook::set_once();
+
+    Ok(frame_to_js(&frame_at(data, index as usize, limits)?))
+}
+
+/// Decodes the fully composed frame of a gif that is on screen `ms` milliseconds
+/// into playback
+///
+/// Times before the start show the first frame and times past the end the last
+/// one. Frames without a delay count as shown for 1/10s like browsers play them.
+/// Like `get_frame` only the frames up to that one are decoded, `GifSession` has
+/// the same lookup for gifs that are decoded already. Returns an object `{ width,
+/// height, delay, rgba }`.
+#[wasm_bindgen]
+pub fn get_frame_at_time(data: &[u8], ms: f64, limits: &Limits) -> Result<types::JsDecodedFrame, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+    let index = timeline::index_at_time(summary.delays, ms).ok_or_else(|| js_error("the gif has no frames"))?;
+    Ok(frame_to_js(&frame_at(data, index, limits)?))
+}
+
+/// Extracts a single fully composed frame of a gif and returns it encoded as PNG
+#[wasm_bindgen]
+pub fn extract_frame_png(data: &[u8], index: u32, limits: &Limits) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let frame = frame_at(data, index as usize, limits)?;
+
+    Ok(png::encode(frame.width as u32, frame.height as u32, &frame.rgba))
+}
+
+/// Creates a preview image of a gif
+///
+/// Only the first frame is decoded. It is scaled down so that its longer side is
+/// at most `max_size` pixels, smaller gifs keep their size. Returns the preview as
+/// PNG bytes.
+#[wasm_bindgen]
+pub fn thumbnail(data: &[u8], max_size: u16, limits: &Limits) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    if max_size == 0 {
+        return Err(js_error("max_size has to be at least 1"));
+    }
+
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    limits.check_size(width, height).map_err(|e| js_error(&e))?;
+    let frame = reader
+        .next_frame_info()
+        .map_err(|e| js_error(&e.to_string()))?
+        .ok_or_else(|| js_error("the gif has no frames"))?
+        .clone();
+    limits.check_size(frame.width, frame.height).map_err(|e| js_error(&e))?;
+    let mut buffer = vec![0; reader.buffer_size()];
+    reader
+        .read_into_buffer(&mut buffer)
+        .map_err(|e| js_error(&e.to_string()))?;
+    let mut frame = Compositor::for_decoder(&reader).compose(&frame, &buffer);
+
+    let longer_side = width.max(height);
+    if longer_side > max_size {
+        let scale = max_size as f32 / longer_side as f32;
+        let thumbnail_width = ((width as f32 * scale).round() as u16).max(1);
+        let thumbnail_height = ((height as f32 * scale).round() as u16).max(1);
+        filters::shrink(&mut frame, thumbnail_width, thumbnail_height);
+    }
+
+    Ok(png::encode(frame.width as u32, frame.height as u32, &frame.rgba))
+}
+
+/// Tiles all composed frames of a gif into one PNG, e.g. for games or CSS
+/// animations
+///
+/// The frames are laid out left to right in rows of `columns` frames and scaled
+/// by `scale`, which ranges from 0 to 1. Scaling up is left to whoever shows the
+/// sheet, e.g. with `image-rendering: pixelated`, which keeps pixel art sharp.
+/// Returns an object `{ png, width, height, columns, rows, frameWidth,
+/// frameHeight, frames, warnings }` where `frames` holds `{ x, y, width, height,
+/// delay }` for every frame with its position on the sheet in pixels and its delay
+/// in milliseconds. Everything but `png` can be stored as JSON as it is.
+#[wasm_bindgen]
+pub fn to_spritesheet(data: &[u8], columns: u32, scale: f32, limits: &Limits) -> Result<types::JsSpriteSheet, JsValue> {
+    console_error_panic_hook::set_once();
+
+    if columns == 0 {
+        return Err(js_error("columns has to be at least 1"));
+    }
+    if scale.is_nan() || scale <= 0.0 || scale > 1.0 {
+        return Err(js_error(&format!(
+            "scale has to be more than 0 and at most 1 but is {}",
+            scale
+        )));
+    }
+
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let frame_width = ((width as f32 * scale).round() as u16).max(1);
+    let frame_height = ((height as f32 * scale).round() as u16).max(1);
+
+    // the frames are scaled right away, so only the small versions are kept
+    let mut frames = Vec::new();
+    let warnings = compose_frames(&mut reader, width, height, limits, js_error, |mut frame| {
+        if frame.width != frame_width || frame.height != frame_height {
+            filters::shrink(&mut frame, frame_width, frame_height);
+        }
+        frames.push(frame);
+        Ok(())
+    })?;
+    if frames.is_empty() {
+        return Err(js_error("the gif has no frames"));
+    }
+
+    let columns = (columns as usize).min(frames.len());
+    let rows = frames.len().div_ceil(columns);
+    let sheet_width = columns as u32 * frame_width as u32;
+    let sheet_height = rows as u32 * frame_height as u32;
+    limits
+        .check_pixels(sheet_width, sheet_height)
+        .map_err(|e| js_error(&e))?;
+
+    let mut sheet = vec![0; sheet_width as usize * sheet_height as usize * 4];
+    let mut descriptors = Vec::with_capacity(frames.len());
+    let row_length = frame_width as usize * 4;
+    for (index, frame) in frames.iter().enumerate() {
+        let x = (index % columns) * frame_width as usize;
+        let y = (index / columns) * frame_height as usize;
+        for (row, pixels) in frame.rgba.chunks_exact(row_length).enumerate() {
+            let start = ((y + row) * sheet_width as usize + x) * 4;
+            sheet[start..start + row_length].copy_from_slice(pixels);
+        }
+
+        descriptors.push(types::SpriteFrame {
+            x: x as u32,
+            y: y as u32,
+            width: frame_width,
+            height: frame_height,
+            delay: frame.delay as u32 * 10,
+        });
+    }
+
+    let png = png::encode(sheet_width, sheet_height, &sheet);
+    Ok(types::SpriteSheet {
+        png: js_sys::Uint8Array::from(&png[..]),
+        width: sheet_width,
+        height: sheet_height,
+        columns: columns as u32,
+        rows: rows as u32,
+        frame_width,
+        frame_height,
+        frames: descriptors,
+        warnings,
+    }
+    .into_object())
+}
+
+/// Converts a gif into an animated PNG
+///
+/// The composed frames are stored with their full colors and transparency, the
+/// delays and the loop count of the gif are kept.
+#[wasm_bindgen]
+pub fn to_apng(data: &[u8], limits: &Limits) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let (frames, _) = collect_frames(&mut reader, width, height, limits)?;
+    if frames.is_empty() {
+        return Err(js_error("the gif has no frames"));
+    }
+
+    // APNG counts how often the animation is played, 0 meaning forever
+    let plays = match summary.loop_count() {
+        count if count < 0 => 0,
+        count => count as u32 + 1,
+    };
+
+    Ok(apng::encode(width as u32, height as u32, &frames, plays))
+}
+
+/// Converts a gif into an animated WebP
+///
+/// The delays and the loop count of the gif are kept. `quality` ranges from 0 to
+/// 100: at 100 the frames are stored with WebP's lossless format and the colors are
+/// kept exactly, lower values store them as lossy VP8 images of that quality, which
+/// gives much smaller files.
+#[wasm_bindgen]
+pub fn to_webp(data: &[u8], quality: u8, limits: &Limits) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    if quality > 100 {
+        return Err(js_error("quality must be between 0 and 100"));
+    }
+
+    let summary = scan::summary(data).map_err(|e| js_error(&e))?;
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let (frames, _) = collect_frames(&mut reader, width, height, limits)?;
+    if frames.is_empty() {
+        return Err(js_error("the gif has no frames"));
+    }
+
+    // WebP counts how often the animation is played, 0 meaning forever
+    let loops = match summary.loop_count() {
+        count if count < 0 => 0,
+        count => (count as u32 + 1).min(u16::MAX as u32) as u16,
+    };
+
+    webp::encode_animation(width as u32, height as u32, &frames, loops, quality).map_err(|e| js_error(&e))
+}
+
+/// Converts an animated WebP into a gif
+///
+/// The frames are composed and quantized like any other frames. The timing and the
+/// loop count of the WebP are kept unless the options set a loop count. Frames can
+/// be lossless or lossy, with or without transparency.
+#[wasm_bindgen]
+pub fn webp_to_gif(data: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let animation = webp::decode_animation(data).map_err(|e| js_error(&e))?;
+    gif_from_animation(&animation, options)
+}
+
+/// Converts an animated PNG into a gif
+///
+/// The frames are composed with their blend and dispose operations and quantized
+/// like any other frames. The timing and the loop count of the APNG are kept
+/// unless the options set a loop count. PNGs without animation become a gif with a
+/// single frame.
+#[wasm_bindgen]
+pub fn apng_to_gif(data: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let animation = apng::decode(data).map_err(|e| js_error(&e))?;
+    gif_from_animation(&animation, options)
+}
+
+/// Encodes an animation that was decoded from another format.
+fn gif_from_animation(animation: &Animation, options: &EncodeOptions) -> Result<Vec<u8>, JsValue> {
+    // other formats count how often the animation is played, 0 meaning forever
+    let loop_count = match animation.plays {
+        0 => -1,
+        plays => (plays - 1).min(u16::MAX as u32) as i32,
+    };
+    let options = &options.with_default_loop_count(Some(loop_count));
+
+    gif_from_frames(
+        &animation.frames,
+        animation.width,
+        animation.height,
+        Vec::new(),
+        options,
+        &|_| {},
+    )
+}
+
+/// Decodes all frames of a gif
+///
+/// Returns an array of objects `{ width, height, delay, rgba }` where `delay` is
+/// given in hundredths of a second and `rgba` is a `Uint8Array` containing the
+/// fully composed frame. The frames can be put onto a canvas directly with
+/// `new ImageData(new Uint8ClampedArray(rgba), width, height)`.
+#[wasm_bindgen]
+pub fn decode_frames(data: &[u8], limits: &Limits) -> Result<Vec<types::JsDecodedFrame>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let (frames, _) = collect_frames(&mut reader, width, height, limits)?;
+
+    Ok(frames.iter().map(frame_to_js).collect())
+}
+
+/// Encodes frames given as RGBA pixels as a gif
+///
+/// `frames` is an array of objects `{ width, height, delay, rgba }` like the ones
+/// returned by `decode_frames`, where `delay` is given in hundredths of a second
+/// and `rgba` is a `Uint8Array` or `Uint8ClampedArray`, so the data of an
+/// `ImageData` from a canvas can be passed directly. All frames need to have the
+/// same size. The gif loops forever unless the options set a loop count.
+#[wasm_bindgen]
+pub fn encode_gif(frames: Vec<types::JsFrameInput>, options: &EncodeOptions) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let frames = frames
+        .iter()
+        .map(|frame| frame_from_js(frame))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = frames
+        .first()
+        .ok_or_else(|| js_error("there are no frames to encode"))?;
+    let (width, height) = (first.width, first.height);
+    if let Some((i, frame)) = frames
+        .iter()
+        .enumerate()
+        .find(|(_, frame)| frame.width != width || frame.height != height)
+    {
+        return Err(js_error(&format!(
+            "frame {} is {}x{} but the first frame is {}x{}",
+            i, frame.width, frame.height, width, height
+        )));
+    }
+
+    gif_from_frames(&frames, width, height, Vec::new(), options, &|_| {})
+}
+
+/// Creates a gif from a sequence of still images
+///
+/// `images` is an array of `Uint8Array`s with PNG, JPEG or WebP images.
+/// The gif has the size of the first image, the other images are scaled to fit
+/// and centered on a transparent background. Every image is shown for `delay`
+/// hundredths of a second and the gif loops forever unless the options set a
+/// loop count.
+#[wasm_bindgen]
+pub fn images_to_gif(
+    #[wasm_bindgen(unchecked_param_type = "Uint8Array[]")] images: &js_sys::Array,
+    delay: u16,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let limits = options.limits();
+    let mut frames = Vec::with_capacity(images.length() as usize);
+    for (i, image) in images.iter().enumerate() {
+        let bytes = image
+            .dyn_into::<js_sys::Uint8Array>()
+            .map_err(|_| js_error("all images have to be Uint8Arrays"))?
+            .to_vec();
+        let mut frame = decode_image(&bytes, &limits).map_err(|e| js_error(&format!("image {}: {}", i, e)))?;
+        limits
+            .check_memory(frames.len() + 1, frame.width, frame.height)
+            .map_err(|e| js_error(&e))?;
+        frame.delay = delay;
+        frames.push(frame);
+    }
+
+    let first = frames.first().ok_or_else(|| js_error("there are no images"))?;
+    let (width, height) = (first.width, first.height);
+    for frame in frames.iter_mut() {
+        if frame.width != width || frame.height != height {
+            filters::letterbox(frame, width, height, [0, 0, 0, 0]);
+        }
+    }
+
+    gif_from_frames(&frames, width, height, Vec::new(), options, &|_| {})
+}
+
+/// Decodes a still PNG, JPEG or WebP image, animated images give their first
+/// frame.
+fn decode_image(data: &[u8], limits: &Limits) -> Result<FrameData, String> {
+    let (width, height, rgba) = decode_pixels(data, limits)?;
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!("{}x{} is too large for a gif", width, height));
+    }
+    Ok(FrameData {
+        width: width as u16,
+        height: height as u16,
+        rgba,
+        delay: 0,
+    })
+}
+
+/// Like `decode_image` for images that may be larger than a gif, returns their
+/// width, height and RGBA pixels. Images with more pixels than `limits` allow
+/// are rejected before they are decoded.
+fn decode_pixels(data: &[u8], limits: &Limits) -> Result<(u32, u32, Vec<u8>), String> {
+    let (width, height) = image_size(data)?;
+    limits.check_pixels(width, height)?;
+
+    Ok(if data.starts_with(&png::SIGNATURE) {
+        let frame = apng::decode(data)?.frames.swap_remove(0);
+        (frame.width as u32, frame.height as u32, frame.rgba)
+    } else if data.starts_with(b"RIFF") {
+        let frame = webp::decode_animation(data)?.frames.swap_remove(0);
+        (frame.width as u32, frame.height as u32, frame.rgba)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        jpeg::decode(data)?
+    } else {
+        return Err("unknown image format, only PNG, JPEG and WebP are supported".to_string());
+    })
+}
+
+/// Reads the width and height of a PNG, JPEG or WebP image from its header.
+fn image_size(data: &[u8]) -> Result<(u32, u32), String> {
+    if data.starts_with(&png::SIGNATURE) {
+        png::size(data)
+    } else if data.starts_with(b"RIFF") {
+        webp::size(data)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        jpeg::size(data)
+    } else {
+        Err("unknown image format, only PNG, JPEG and WebP are supported".to_string())
+    }
+}
+
+/// Creates a gif from the frames of a sprite sheet
+///
+/// `image` is a PNG, JPEG or WebP image that is sliced into frames of
+/// `frame_width` x `frame_height` pixels, left to right and top to bottom like
+/// `to_spritesheet` lays them out. What is left over at the right and bottom edge
+/// is ignored, as are completely transparent frames at the end of a sheet that
+/// isn't full. Every frame is shown for `delay` hundredths of a second and the gif
+/// loops forever unless the options set a loop count.
+#[wasm_bindgen]
+pub fn spritesheet_to_gif(
+    image: &[u8],
+    frame_width: u16,
+    frame_height: u16,
+    delay: u16,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    if frame_width == 0 || frame_height == 0 {
+        return Err(js_error("frame_width and frame_height have to be at least 1"));
+    }
+    let limits = options.limits();
+    let (width, height, rgba) = decode_pixels(image, &limits).map_err(|e| js_error(&e))?;
+    let columns = (width / frame_width as u32) as usize;
+    let rows = (height / frame_height as u32) as usize;
+    if columns == 0 || rows == 0 {
+        return Err(js_error(&format!(
+            "the sheet of {}x{} pixels is smaller than a frame of {}x{} pixels",
+            width, height, frame_width, frame_height
+        )));
+    }
+    let count = limits
+        .check_size(frame_width, frame_height)
+        .and_then(|_| limits.check_frames(columns * rows))
+        .map_err(|e| js_error(&e))?;
+    limits
+        .check_memory(count, frame_width, frame_height)
+        .map_err(|e| js_error(&e))?;
+
+    let row_length = frame_width as usize * 4;
+    let mut frames: Vec<FrameData> = (0..count)
+        .map(|index| {
+            let x = (index % columns) * frame_width as usize;
+            let y = (index / columns) * frame_height as usize;
+            let mut frame = Vec::with_capacity(row_length * frame_height as usize);
+            for row in y..y + frame_height as usize {
+                let start = (row * width as usize + x) * 4;
+                frame.extend_from_slice(&rgba[start..start + row_length]);
+            }
+            FrameData {
+                width: frame_width,
+                height: frame_height,
+                rgba: frame,
+                delay,
+            }
+        })
+        .collect();
+    while frames.len() > 1
+        && frames
+            .last()
+            .is_some_and(|frame| frame.rgba.chunks_exact(4).all(|pixel| pixel[3] == 0))
+    {
+        frames.pop();
+    }
+
+    gif_from_frames(&frames, frame_width, frame_height, Vec::new(), options, &|_| {})
+}
+
+/// Decodes all frames of a gif into one buffer for video encoding
+///
+/// Returns an object `{ width, height, format, frameCount, frameSize, data,
+/// timestamps, durations, duration, warnings }`. `data` is a `Uint8Array` with the composed
+/// frames packed one after another without any padding, frame `i` starts at byte
+/// `i * frameSize`. `format` is always `"RGBA"`. `timestamps` and `durations` are
+/// `Float64Array`s in microseconds, the unit WebCodecs uses, and `duration` is the
+/// total length. Short delays are converted the way browsers play them. A frame
+/// can be created with `new VideoFrame(data.subarray(i * frameSize, (i + 1) *
+/// frameSize), { format, codedWidth: width, codedHeight: height, timestamp:
+/// timestamps[i], duration: durations[i] })`.
+#[wasm_bindgen]
+pub fn export_raw_frames(data: &[u8], limits: &Limits) -> Result<types::JsRawFrames, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let mut reader = decode_data(data)?;
+    let (width, height, _) = metadata(&reader);
+    let (frames, warnings) = collect_frames(&mut reader, width, height, limits)?;
+    if frames.is_empty() {
+        return Err(js_error("the gif has no frames"));
+    }
+
+    let frame_size = width as usize * height as usize * 4;
+    let mut pixels = Vec::with_capacity(frame_size * frames.len());
+    let mut timestamps = Vec::with_capacity(frames.len());
+    let mut durations = Vec::with_capacity(frames.len());
+    let mut timestamp = 0.0;
+    for frame in &frames {
+        pixels.extend_from_slice(&frame.rgba);
+
+        let duration = timeline::playback_delay(frame.delay) as f64 * 10_000.0;
+        timestamps.push(timestamp);
+        durations.push(duration);
+        timestamp += duration;
+    }
+
+    Ok(types::RawFrames {
+        width,
+        height,
+        format: "RGBA",
+        frame_count: frames.len() as u32,
+        frame_size: frame_size as u32,
+        data: js_sys::Uint8Array::from(&pixels[..]),
+        timestamps: js_sys::Float64Array::from(&timestamps[..]),
+        durations: js_sys::Float64Array::from(&durations[..]),
+        duration: timestamp,
+        warnings,
+    }
+    .into_object())
+}
+
+/// Cuts a gif down to the frames from `start_frame` up to but not including `end_frame`
+#[wasm_bindgen]
+pub fn trim_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    start_frame: u32,
+    end_frame: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::trim(frames, start_frame as usize, end_frame as usize).map_err(|e| js_error(&e))
+    })
+}
+
+/// Cuts a gif down to what is shown from `start_ms` up to `end_ms` milliseconds
+///
+/// Frames that are only partly within that time are shortened to the part that
+/// is. Both times have to be multiples of 10 since gifs store delays in hundredths
+/// of a second, `end_ms` may lie beyond the end of the gif. Frames without a delay
+/// count as shown for 1/10s like browsers play them.
+#[wasm_bindgen]
+pub fn trim_time(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    start_ms: u32,
+    end_ms: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    for ms in [start_ms, end_ms] {
+        if !ms.is_multiple_of(10) {
+            return Err(js_error(&format!(
+                "{}ms is not a multiple of 10ms, gifs can't store that",
+                ms
+            )));
+        }
+    }
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::trim_time(frames, start_ms as u64 / 10, end_ms as u64 / 10).map_err(|e| js_error(&e))
+    })
+}
+
+/// Extracts the frames from `start_frame` up to but not including `end_frame` as an
+/// endless loop
+///
+/// The section is played `repeat_count` times in a row within the gif. Unlike
+/// `trim_gif`, the result loops forever regardless of the loop count of the
+/// original, unless the options set a loop count.
+#[wasm_bindgen]
+pub fn loop_section(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    start_frame: u32,
+    end_frame: u32,
+    repeat_count: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if repeat_count == 0 {
+        return Err(js_error("repeat_count has to be at least 1"));
+    }
+
+    let options = &options.with_default_loop_count(Some(-1));
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::trim(frames, start_frame as usize, end_frame as usize).map_err(|e| js_error(&e))?;
+        timeline::repeat(frames, repeat_count as usize);
+        Ok(())
+    })
+}
+
+/// Holds the first and the last frame of a gif for longer
+///
+/// The first frame is shown `start_ms` and the last frame `end_ms` milliseconds
+/// longer than before, e.g. to pause on the punchline of a looping gif. Both have
+/// to be multiples of 10. Holds longer than a gif can store in a single delay,
+/// about 11 minutes, are spread over copies of the frame.
+#[wasm_bindgen]
+pub fn hold_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    start_ms: u32,
+    end_ms: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    for &ms in [start_ms, end_ms].iter() {
+        if !ms.is_multiple_of(10) {
+            return Err(js_error(&format!(
+                "hold of {}ms is not a multiple of 10ms, gifs can't store that",
+                ms
+            )));
+        }
+    }
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::hold(frames, start_ms / 10, end_ms / 10);
+        Ok(())
+    })
+}
+
+/// Fades a gif in from and out to a solid color
+///
+/// The frames in the first `fade_in_ms` milliseconds are blended from `color`, given
+/// as `0xRRGGBB`, to the original image and the frames in the last `fade_out_ms`
+/// milliseconds back to it. Without a color the gif fades from and to transparency.
+#[wasm_bindgen]
+pub fn fade_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    fade_in_ms: u32,
+    fade_out_ms: u32,
+    color: Option<u32>,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if color.is_some_and(|color| color > 0xffffff) {
+        return Err(js_error("color has to be given as 0xRRGGBB"));
+    }
+
+    transform_gif(id, name, data, register, report, options, |frames| {
+        let entries = frames.entries();
+        let amounts = timeline::fade_amounts(&entries, fade_in_ms, fade_out_ms);
+        let width = frames.width;
+        for (index, amount) in amounts.into_iter().enumerate() {
+            if amount > 0.0 {
+                filters::fade(frames.rgba_mut(index), width, color.map(rgb), amount);
+            }
+        }
+        Ok(entries)
+    })
+}
+
+/// Makes a gif loop seamlessly by cross-fading its end into its start
+///
+/// The last `overlap_frames` frames are blended with the first `overlap_frames`
+/// frames, which are removed from the start. The gif needs at least twice as many
+/// frames as the overlap.
+#[wasm_bindgen]
+pub fn loopify_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    overlap_frames: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    transform_gif(id, name, data, register, report, options, |frames| {
+        timeline::crossfade_loop(frames, overlap_frames as usize).map_err(|e| js_error(&e))
+    })
+}
+
+/// Keeps only every `keep_every_n`th frame of a gif
+///
+/// The delays of dropped frames are added to the frame that is kept before them
+/// so the animation plays as long as the original.
+#[wasm_bindgen]
+pub fn decimate_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    keep_every_n: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if keep_every_n == 0 {
+        return Err(js_error("keep_every_n has to be at least 1"));
+    }
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::decimate(frames, keep_every_n as usize);
+        Ok(())
+    })
+}
+
+/// Resamples a gif to play at `fps` frames per second
+///
+/// Frames are dropped or repeated so that a new frame starts every 1/`fps`
+/// seconds, which makes the gif easier to sync with audio or video. Gifs store
+/// delays in hundredths of a second, so the delays alternate to keep rates like 30
+/// frames per second on average. `fps` ranges up to 50 since browsers slow down
+/// shorter delays. Frames without a delay count as shown for 1/10s like browsers
+/// play them.
+#[wasm_bindgen]
+pub fn retarget_fps(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    fps: f32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if fps.is_nan() || fps <= 0.0 || fps > 50.0 {
+        return Err(js_error(&format!(
+            "fps has to be more than 0 and at most 50 but is {}",
+            fps
+        )));
+    }
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::retarget(frames, fps);
+        Ok(())
+    })
+}
+
+/// Rebuilds a gif with its frames in the given order
+///
+/// `order` contains indices into the original frames. If `allow_duplicates` is
+/// false every frame may only be used once.
+#[wasm_bindgen]
+pub fn reorder_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    order: &[u32],
+    allow_duplicates: bool,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::reorder(frames, order, allow_duplicates).map_err(|e| js_error(&e))
+    })
+}
+
+/// Joins several gifs into a single animation
+///
+/// `inputs` is an array of `Uint8Array`s. The output is as large as the widest and
+/// the tallest input. Smaller gifs are centered on a transparent background, or
+/// scaled up until they fit if `scale_to_fit` is set. Every frame gets its own
+/// palette so gifs with different color palettes can be combined without
+/// losing colors.
+#[wasm_bindgen]
+pub fn concat_gifs(
+    id: &str,
+    name: &str,
+    #[wasm_bindgen(unchecked_param_type = "Uint8Array[]")] inputs: &js_sys::Array,
+    scale_to_fit: bool,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let mut buffers = Vec::new();
+    for input in inputs.iter() {
+        buffers.push(
+            input
+                .dyn_into::<js_sys::Uint8Array>()
+                .map_err(|_| js_error("all inputs have to be Uint8Arrays"))?
+                .to_vec(),
+        );
+    }
+
+    let limits = options.limits();
+    let total = buffers
+        .iter()
+        .map(|bytes| frames_to_decode(bytes, &limits))
+        .sum::<Result<usize, JsValue>>()?;
+    register_phase(register, id, name, total, DECODING);
+    let decoded = progress_reporter(id, report, DECODING);
+
+    let mut animations: Vec<Vec<FrameData>> = Vec::new();
+    for bytes in buffers.iter() {
+        let before: usize = animations.iter().map(|frames| frames.len()).sum();
+        let mut reader = decode_data(bytes)?;
+        let (width, height, _) = metadata(&reader);
+        let (frames, warnings) = collect_frames_with(&mut reader, width, height, &limits, |count| {
+            options.check_cancelled()?;
+            decoded(before + count);
+            Ok(())
+        })?;
+        options.add_warnings(warnings);
+        animations.push(frames);
+    }
+
+    let width = animations.iter().flatten().map(|frame| frame.width).max().unwrap_or(0);
+    let height = animations.iter().flatten().map(|frame| frame.height).max().unwrap_or(0);
+    if width == 0 || height == 0 {
+        return Err(js_error("no frames to concatenate"));
+    }
+
+    let mut frames: Vec<FrameData> = animations.into_iter().flatten().collect();
+    for frame in frames.iter_mut() {
+        if frame.width == width && frame.height == height {
+            continue;
+        }
+
+        if scale_to_fit {
+            filters::letterbox(frame, width, height, [0, 0, 0, 0]);
+        } else {
+            let left = (width - frame.width) as i32 / 2;
+            let top = (height - frame.height) as i32 / 2;
+            filters::place(frame, width, height, left, top, [0, 0, 0, 0]);
+        }
+    }
+
+    // the result loops like the first gif unless told otherwise
+    let options = &match buffers.first() {
+        Some(first) => options.with_source(first),
+        None => options.clone(),
+    };
+
+    register_phase(register, id, name, frames.len(), ENCODING);
+
+    gif_from_frames(
+        &frames,
+        width,
+        height,
+        Vec::new(),
+        options,
+        &progress_reporter(id, report, ENCODING),
+    )
+}
+
+/// Splits a gif into several gifs
+///
+/// Every entry in `boundaries` is the index of the frame that starts a new part,
+/// so `[10, 20]` splits a gif with 30 frames into the frames 0-9, 10-19 and
+/// 20-29. Returns an array with one `Uint8Array` per part, or one `GifBuffer` per
+/// part if the options keep the output in wasm memory.
+#[wasm_bindgen(unchecked_return_type = "Uint8Array[] | GifBuffer[]")]
+pub fn split_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    boundaries: &[u32],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<js_sys::Array, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let mut reader = decode_data(data)?;
+    let (width, height, global_palette) = metadata(&reader);
+    let limits = options.limits();
+    register_phase(register, id, name, frames_to_decode(data, &limits)?, DECODING);
+    let decoded = progress_reporter(id, report, DECODING);
+    let (frames, warnings) = collect_frames_with(&mut reader, width, height, &limits, |count| {
+        options.check_cancelled()?;
+        decoded(count);
+        Ok(())
+    })?;
+    options.add_warnings(warnings);
+
+    let options = &options.with_source(data);
+
+    let mut starts = vec![0];
+    for &boundary in boundaries {
+        let boundary = boundary as usize;
+        if boundary <= *starts.last().unwrap() || boundary >= frames.len() {
+            return Err(js_error(&format!(
+                "invalid boundary {}, boundaries have to be increasing and within the {} frames of the gif",
+                boundary,
+                frames.len()
+            )));
+        }
+        starts.push(boundary);
+    }
+    starts.push(frames.len());
+
+    register_phase(register, id, name, frames.len(), ENCODING);
+
+    let report = progress_reporter(id, report, ENCODING);
+    let parts = js_sys::Array::new();
+    for part in starts.windows(2) {
+        let (start, end) = (part[0], part[1]);
+        let buffer = gif_from_frames(
+            &frames[start..end],
+            width,
+            height,
+            global_palette.clone(),
+            options,
+            &|written| report(start + written),
+        )?;
+        match options.take_output() {
+            Some(kept) => parts.push(&JsValue::from(kept)),
+            None => parts.push(&js_sys::Uint8Array::from(&buffer[..])),
+        };
+    }
+
+    Ok(parts)
+}
+
+/// Renders a caption onto every frame of a gif
+///
+/// The text may contain several lines separated by `\n`. The built-in font is
+/// DejaVu Sans Bold with Latin, Greek and Cyrillic characters, other characters
+/// are shown as a box unless a font is set with `CaptionOptions.set_font`.
+#[wasm_bindgen]
+pub fn caption_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    text: &str,
+    caption: &CaptionOptions,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let outline_width = caption.outline_width as usize;
+    let glyphs = match caption.font {
+        Some(ref font) => text::render(text, caption.size, font),
+        None => text::render(text, caption.size, &font::Font::builtin()),
+    };
+    let outline = text::dilate(&glyphs, outline_width);
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        let left = (frame.width as i32 - outline.width as i32) / 2;
+        let top = match caption.position {
+            CaptionPosition::Top => caption.margin as i32,
+            CaptionPosition::Center => (frame.height as i32 - outline.height as i32) / 2,
+            CaptionPosition::Bottom => frame.height as i32 - outline.height as i32 - caption.margin as i32,
+        };
+
+        if outline_width > 0 {
+            text::draw(frame, &outline, left, top, rgb(caption.outline_color));
+        }
+        text::draw(
+            frame,
+            &glyphs,
+            left + outline_width as i32,
+            top + outline_width as i32,
+            rgb(caption.color),
+        );
+        Ok(())
+    })
+}
+
+/// Blends an RGBA image onto every frame of a gif
+///
+/// `overlay_rgba` contains `overlay_width * overlay_height` pixels with four
+/// bytes each, e.g. the `data` of an `ImageData`. Its top left corner is placed at
+/// `x`/`y`, which may be negative or reach beyond the gif.
+#[wasm_bindgen]
+pub fn overlay_image(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    overlay_rgba: &[u8],
+    overlay_width: u16,
+    overlay_height: u16,
+    x: i32,
+    y: i32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    if overlay_rgba.len() != overlay_width as usize * overlay_height as usize * 4 {
+        return Err(js_error(&format!(
+            "expected {} bytes for a {}x{} overlay but got {}",
+            overlay_width as usize * overlay_height as usize * 4,
+            overlay_width,
+            overlay_height,
+            overlay_rgba.len()
+        )));
+    }
+
+    map_gif(id, name, data, register, report, options, |frame, _| {
+        filters::overlay(frame, overlay_rgba, overlay_width, overlay_height, x, y);
+        Ok(())
+    })
+}
+
+/// Replaces the delay of every frame of a gif
+///
+/// `delays_ms` needs exactly one entry per frame. Gifs store delays in hundredths
+/// of a second, so all delays have to be multiples of 10.
+#[wasm_bindgen]
+pub fn set_delays(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    delays_ms: &[u32],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let delays = delays_ms
+        .iter()
+        .map(|&ms| timeline::delay_from_ms(ms))
+        .collect::<Result<Vec<u16>, String>>()
+        .map_err(|e| js_error(&e))?;
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        if delays.len() != frames.len() {
+            return Err(js_error(&format!(
+                "got {} delays but the gif has {} frames",
+                delays.len(),
+                frames.len()
+            )));
+        }
+
+        for (frame, &delay) in frames.iter_mut().zip(delays.iter()) {
+            frame.delay = delay;
+        }
+        Ok(())
+    })
+}
+
+/// Changes the speed of a gif gradually, e.g. for a slow motion intro
+///
+/// `keyframes` is an array of objects `{ frame, speed }` that set the speed factor
+/// at a frame position. The factors of the frames in between are interpolated, the
+/// frames before the first and after the last keyframe keep its factor.
+#[wasm_bindgen]
+pub fn speed_ramp_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    keyframes: Vec<types::JsSpeedKeyframe>,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let keyframes = keyframes
+        .iter()
+        .map(|keyframe| {
+            let keyframe = types::SpeedKeyframe::from_js(keyframe)?;
+            Ok((keyframe.frame as usize, keyframe.speed as f32))
+        })
+        .collect::<Result<Vec<(usize, f32)>, JsValue>>()?;
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        timeline::speed_ramp(frames, &keyframes).map_err(|e| js_error(&e))
+    })
+}
+
+/// Sets the delay of all frames of a gif to `delay_ms`, which has to be a multiple of 10
+#[wasm_bindgen]
+pub fn set_delay(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    delay_ms: u32,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let delay = timeline::delay_from_ms(delay_ms).map_err(|e| js_error(&e))?;
+
+    retime_gif(id, name, data, register, report, options, |frames| {
+        for frame in frames.iter_mut() {
+            frame.delay = delay;
+        }
+        Ok(())
+    })
+}
+
+/// Writes a gif again with the loop count and comment of the options
+///
+/// Comments, application extensions and other metadata of the original are left
+/// out, only the loop count is kept unless the options set one. Unless the options
+/// change how frames are encoded, the compressed frames are copied without
+/// decoding them, which is fast and barely needs any memory. That makes this the
+/// way to change the loop count or strip the metadata of a gif.
+#[wasm_bindgen]
+pub fn remux_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    retime_gif(id, name, data, register, report, options, |_| Ok(()))
+}
+
+/// Removes consecutive identical frames from a gif
+///
+/// The delay of every removed frame is added to the frame before it, so the gif
+/// plays exactly like before but with fewer frames.
+#[wasm_bindgen]
+pub fn dedupe_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    transform_gif(id, name, data, register, report, options, |frames| {
+        let mut entries = frames.entries();
+        timeline::merge_duplicates(&mut entries, |a, b| frames.rgba(a) == frames.rgba(b));
+        Ok(entries)
+    })
+}
+
+/// Makes a gif play smoother by blending `factor - 1` intermediate frames between
+/// every two frames
+///
+/// The delays are split up between the original and the inserted frames, so the
+/// gif plays as long as before. Frames get fewer inserted frames if their delay is
+/// too short to split into parts of at least 20ms. If the result would exceed
+/// the memory limit of the options the factor is lowered until it fits.
+#[wasm_bindgen]
+pub fn smooth_gif(
+    id: &str,
+    name: &str,
+    data: &[u8],
+    factor: u16,
+    register: &RegisterPhase,
+    report: &ReportProgress,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, JsValue> {
+    transform_gif(id, name, data, register, report, options, |frames| {
+        timeline::interpolate(frames, factor, &options.limits()).map_err(|e| js_error(&e))
+    })
+}
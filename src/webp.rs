@@ -0,0 +1,1475 @@
+//! Animated WebP.
+//!
+//! Frames are stored with WebP's lossless format (VP8L) at full quality. The
+//! encoder keeps things simple: the subtract green transform, backward references
+//! found with hash chains and one set of Huffman codes per frame. That's enough to
+//! beat gifs in size for most animations while keeping the full colors.
+//!
+//! Below full quality, frames are lossy VP8 images (see `vp8`) and their
+//! transparency goes into a separate lossless ALPH chunk. Both kinds of images
+//! can be decoded.
+
+use filters;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use vp8;
+use zlib::{BitReader, BitWriter};
+use {Animation, FrameData};
+
+const VP8L_SIGNATURE: u8 = 0x2f;
+const SUBTRACT_GREEN: u32 = 2;
+
+const NUM_LENGTH_CODES: usize = 24;
+const NUM_DISTANCE_CODES: usize = 40;
+/// Literal green values followed by the length prefix codes
+const GREEN_ALPHABET: usize = 256 + NUM_LENGTH_CODES;
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+/// The order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 4096;
+const HASH_BITS: usize = 16;
+const MAX_CHAIN: usize = 32;
+/// Distances are stored with an offset of 120, the codes below are reserved for
+/// nearby pixels
+const DISTANCE_OFFSET: usize = 120;
+const MAX_DISTANCE: usize = (1 << 20) - DISTANCE_OFFSET;
+
+/// Something that's written to the entropy coded image.
+enum Token {
+    Literal(u32),
+    Copy { length: usize, distance_code: usize },
+}
+
+/// Splits a length or distance into its prefix code, the number of extra bits and
+/// their value.
+fn prefix_encode(value: usize) -> (usize, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+
+    let highest_bit = (usize::BITS - 1 - value.leading_zeros()) as usize;
+    let second_bit = (value >> (highest_bit - 1)) & 1;
+    let extra_bits = highest_bit as u32 - 1;
+    (
+        2 * highest_bit + second_bit,
+        extra_bits,
+        (value & ((1 << extra_bits) - 1)) as u32,
+    )
+}
+
+/// Builds Huffman code lengths for the symbol frequencies that don't exceed
+/// `max_length`. If the lengths get too long, the frequencies are flattened and the
+/// code is built again.
+fn code_lengths(frequencies: &[u32], max_length: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    loop {
+        let lengths = huffman_lengths(&frequencies);
+        if lengths.iter().all(|&length| length <= max_length) {
+            return lengths;
+        }
+        for frequency in frequencies.iter_mut().filter(|frequency| **frequency > 0) {
+            *frequency = (*frequency >> 1).max(1);
+        }
+    }
+}
+
+fn huffman_lengths(frequencies: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; frequencies.len()];
+    let used: Vec<usize> = (0..frequencies.len()).filter(|&i| frequencies[i] > 0).collect();
+    if used.len() == 1 {
+        lengths[used[0]] = 1;
+    }
+    if used.len() < 2 {
+        return lengths;
+    }
+
+    // nodes below `frequencies.len()` are symbols, all others are inner nodes
+    let mut parents = vec![0usize; frequencies.len()];
+    let mut heap = BinaryHeap::new();
+    for &symbol in &used {
+        heap.push(Reverse((frequencies[symbol] as u64, symbol)));
+    }
+
+    let mut next = frequencies.len();
+    while heap.len() > 1 {
+        let Reverse((a_weight, a)) = heap.pop().unwrap();
+        let Reverse((b_weight, b)) = heap.pop().unwrap();
+        parents.push(0);
+        parents[a] = next;
+        parents[b] = next;
+        heap.push(Reverse((a_weight + b_weight, next)));
+        next += 1;
+    }
+
+    let root = next - 1;
+    for &symbol in &used {
+        let mut depth = 0;
+        let mut node = symbol;
+        while node != root {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[symbol] = depth.min(u8::MAX as usize) as u8;
+    }
+    lengths
+}
+
+/// A Huffman code for writing symbols.
+struct PrefixCode {
+    codes: Vec<u32>,
+    lengths: Vec<u8>,
+    /// Decoders don't read any bits for codes with a single symbol
+    single: bool,
+}
+
+impl PrefixCode {
+    fn new(lengths: Vec<u8>) -> PrefixCode {
+        // canonical codes, the same way deflate assigns them
+        let mut count = [0u32; 16];
+        for &length in &lengths {
+            count[length as usize] += 1;
+        }
+        count[0] = 0;
+
+        let mut next_code = [0u32; 16];
+        let mut code = 0;
+        for bits in 1..16 {
+            code = (code + count[bits - 1]) << 1;
+            next_code[bits] = code;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&length| {
+                if length == 0 {
+                    return 0;
+                }
+                let code = next_code[length as usize];
+                next_code[length as usize] += 1;
+                code
+            })
+            .collect();
+
+        PrefixCode {
+            codes,
+            single: lengths.iter().filter(|&&length| length > 0).count() <= 1,
+            lengths,
+        }
+    }
+
+    fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        if !self.single {
+            writer.write_code(self.codes[symbol], self.lengths[symbol] as u32);
+        }
+    }
+}
+
+/// Writes the code lengths of a prefix code, either as a simple code for up to two
+/// small symbols or as a normal code that is compressed with a code length code.
+fn write_code_lengths(writer: &mut BitWriter, lengths: &[u8]) {
+    let used: Vec<usize> = (0..lengths.len()).filter(|&i| lengths[i] > 0).collect();
+
+    if used.len() <= 2 && used.iter().all(|&symbol| symbol < 256) {
+        writer.write_bits(1, 1);
+        writer.write_bits(used.len().max(1) as u32 - 1, 1);
+
+        let first = used.first().cloned().unwrap_or(0);
+        if first < 2 {
+            writer.write_bits(0, 1);
+            writer.write_bits(first as u32, 1);
+        } else {
+            writer.write_bits(1, 1);
+            writer.write_bits(first as u32, 8);
+        }
+        if let Some(&second) = used.get(1) {
+            writer.write_bits(second as u32, 8);
+        }
+        return;
+    }
+
+    // runs of zeros are shortened with the codes 17 and 18, everything else is
+    // written as is. each token is (symbol, extra bits, value of the extra bits)
+    let mut tokens: Vec<(usize, u32, u32)> = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let run = lengths[i..].iter().take_while(|&&length| length == 0).count().min(138);
+        if run >= 11 {
+            tokens.push((18, 7, run as u32 - 11));
+            i += run;
+        } else if run >= 3 {
+            tokens.push((17, 3, run as u32 - 3));
+            i += run;
+        } else {
+            tokens.push((lengths[i] as usize, 0, 0));
+            i += 1;
+        }
+    }
+
+    let mut frequencies = [0u32; 19];
+    for &(symbol, _, _) in &tokens {
+        frequencies[symbol] += 1;
+    }
+    let code_length_lengths = code_lengths(&frequencies, MAX_CODE_LENGTH_CODE_LENGTH);
+    let code_length_code = PrefixCode::new(code_length_lengths.clone());
+
+    let stored = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| code_length_lengths[symbol] > 0)
+        .map_or(4, |last| (last + 1).max(4));
+
+    writer.write_bits(0, 1);
+    writer.write_bits(stored as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..stored] {
+        writer.write_bits(code_length_lengths[symbol] as u32, 3);
+    }
+
+    // all symbols are coded, there's no shorter maximum
+    writer.write_bits(0, 1);
+
+    for &(symbol, extra_bits, extra) in &tokens {
+        code_length_code.write(writer, symbol);
+        writer.write_bits(extra, extra_bits);
+    }
+}
+
+fn hash(pixels: &[u32], position: usize) -> usize {
+    let value = (pixels[position] as u64) << 32 | pixels[position + 1] as u64;
+    (value.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - HASH_BITS)) as usize
+}
+
+/// Finds backward references with hash chains. The pixel to the left and the one
+/// above are always tried first since that's where matches usually are.
+fn find_matches(pixels: &[u32], width: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; pixels.len()];
+
+    let insert = |head: &mut Vec<usize>, previous: &mut Vec<usize>, position: usize| {
+        if position + 1 < pixels.len() {
+            let h = hash(pixels, position);
+            previous[position] = head[h];
+            head[h] = position;
+        }
+    };
+
+    let match_length = |candidate: usize, position: usize| {
+        let max_length = MAX_MATCH.min(pixels.len() - position);
+        (0..max_length)
+            .take_while(|&i| pixels[candidate + i] == pixels[position + i])
+            .count()
+    };
+
+    let mut position = 0;
+    while position < pixels.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        for &distance in &[1, width] {
+            if distance > 0 && distance <= position {
+                let length = match_length(position - distance, position);
+                if length > best_length {
+                    best_length = length;
+                    best_distance = distance;
+                }
+            }
+        }
+
+        if position + 1 < pixels.len() {
+            let mut candidate = head[hash(pixels, position)];
+            let mut chain = 0;
+            while candidate != usize::MAX && position - candidate <= MAX_DISTANCE && chain < MAX_CHAIN {
+                let length = match_length(candidate, position);
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            // the row above and the pixel to the left have short codes of their own
+            let distance_code = if best_distance == width {
+                1
+            } else if best_distance == 1 {
+                2
+            } else {
+                best_distance + DISTANCE_OFFSET
+            };
+            tokens.push(Token::Copy {
+                length: best_length,
+                distance_code,
+            });
+            for p in position..position + best_length {
+                insert(&mut head, &mut previous, p);
+            }
+            position += best_length;
+        } else {
+            tokens.push(Token::Literal(pixels[position]));
+            insert(&mut head, &mut previous, position);
+            position += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Encodes an RGBA image as a VP8L bitstream.
+pub fn encode_lossless(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let alpha_used = rgba.chunks_exact(4).any(|pixel| pixel[3] != 255);
+
+    // ARGB pixels with the green value subtracted from red and blue
+    let pixels: Vec<u32> = rgba
+        .chunks_exact(4)
+        .map(|pixel| {
+            let red = pixel[0].wrapping_sub(pixel[1]);
+            let blue = pixel[2].wrapping_sub(pixel[1]);
+            (pixel[3] as u32) << 24 | (red as u32) << 16 | (pixel[1] as u32) << 8 | blue as u32
+        })
+        .collect();
+
+    let mut writer = BitWriter::new(vec![VP8L_SIGNATURE]);
+    writer.write_bits(width - 1, 14);
+    writer.write_bits(height - 1, 14);
+    writer.write_bits(alpha_used as u32, 1);
+    // version
+    writer.write_bits(0, 3);
+
+    // a single transform
+    writer.write_bits(1, 1);
+    writer.write_bits(SUBTRACT_GREEN, 2);
+    writer.write_bits(0, 1);
+
+    // no color cache, no meta prefix codes
+    writer.write_bits(0, 1);
+    writer.write_bits(0, 1);
+
+    let tokens = find_matches(&pixels, width as usize);
+
+    let mut green = vec![0u32; GREEN_ALPHABET];
+    let mut red = vec![0u32; 256];
+    let mut blue = vec![0u32; 256];
+    let mut alpha = vec![0u32; 256];
+    let mut distance = vec![0u32; NUM_DISTANCE_CODES];
+    for token in &tokens {
+        match *token {
+            Token::Literal(argb) => {
+                green[(argb >> 8 & 0xff) as usize] += 1;
+                red[(argb >> 16 & 0xff) as usize] += 1;
+                blue[(argb & 0xff) as usize] += 1;
+                alpha[(argb >> 24) as usize] += 1;
+            }
+            Token::Copy { length, distance_code } => {
+                green[256 + prefix_encode(length).0] += 1;
+                distance[prefix_encode(distance_code).0] += 1;
+            }
+        }
+    }
+
+    let codes: Vec<PrefixCode> = [green, red, blue, alpha, distance]
+        .iter()
+        .map(|frequencies| {
+            let lengths = code_lengths(frequencies, MAX_CODE_LENGTH);
+            write_code_lengths(&mut writer, &lengths);
+            PrefixCode::new(lengths)
+        })
+        .collect();
+
+    for token in &tokens {
+        match *token {
+            Token::Literal(argb) => {
+                codes[0].write(&mut writer, (argb >> 8 & 0xff) as usize);
+                codes[1].write(&mut writer, (argb >> 16 & 0xff) as usize);
+                codes[2].write(&mut writer, (argb & 0xff) as usize);
+                codes[3].write(&mut writer, (argb >> 24) as usize);
+            }
+            Token::Copy { length, distance_code } => {
+                let (code, extra_bits, extra) = prefix_encode(length);
+                codes[0].write(&mut writer, 256 + code);
+                writer.write_bits(extra, extra_bits);
+
+                let (code, extra_bits, extra) = prefix_encode(distance_code);
+                codes[4].write(&mut writer, code);
+                writer.write_bits(extra, extra_bits);
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// Appends a RIFF chunk, padded to an even size.
+fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(kind);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+fn push_u24(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&value.to_le_bytes()[..3]);
+}
+
+/// The chunks of a lossy frame: the transparency as a lossless image in the green
+/// channel, if there is any, followed by the VP8 image.
+fn encode_lossy(width: u32, height: u32, rgba: &[u8], quality: u8) -> Result<Vec<u8>, String> {
+    let mut image = Vec::new();
+    if rgba.chunks_exact(4).any(|pixel| pixel[3] != 255) {
+        let alpha: Vec<u8> = rgba.chunks_exact(4).flat_map(|pixel| [0, pixel[3], 0, 255]).collect();
+        // compressed losslessly without filtering, the VP8L header is left out
+        let mut data = vec![1];
+        data.extend_from_slice(&encode_lossless(width, height, &alpha)[5..]);
+        write_chunk(&mut image, b"ALPH", &data);
+    }
+    write_chunk(&mut image, b"VP8 ", &vp8::encode(width, height, rgba, quality)?);
+    Ok(image)
+}
+
+/// Encodes full canvas sized frames as an animated WebP.
+///
+/// `loops` is how often the animation is played, 0 plays it forever. `quality`
+/// ranges from 0 to 100, 100 stores the frames losslessly and lower values encode
+/// them as lossy VP8 images of that quality.
+pub fn encode_animation(
+    width: u32,
+    height: u32,
+    frames: &[FrameData],
+    loops: u16,
+    quality: u8,
+) -> Result<Vec<u8>, String> {
+    let alpha_used = frames
+        .iter()
+        .any(|frame| frame.rgba.chunks_exact(4).any(|pixel| pixel[3] != 255));
+
+    let mut body = b"WEBP".to_vec();
+
+    let mut header = Vec::with_capacity(10);
+    // the animation flag and the alpha flag if any frame is transparent
+    header.push(0x02 | if alpha_used { 0x10 } else { 0 });
+    header.extend_from_slice(&[0, 0, 0]);
+    push_u24(&mut header, width - 1);
+    push_u24(&mut header, height - 1);
+    write_chunk(&mut body, b"VP8X", &header);
+
+    // transparent background color in BGRA and the loop count
+    let mut animation = vec![0, 0, 0, 0];
+    animation.extend_from_slice(&loops.to_le_bytes());
+    write_chunk(&mut body, b"ANIM", &animation);
+
+    for frame in frames {
+        let (frame_width, frame_height) = (frame.width as u32, frame.height as u32);
+        let image = if quality >= 100 {
+            let mut image = Vec::new();
+            write_chunk(
+                &mut image,
+                b"VP8L",
+                &encode_lossless(frame_width, frame_height, &frame.rgba),
+            );
+            image
+        } else {
+            encode_lossy(frame_width, frame_height, &frame.rgba, quality)?
+        };
+
+        let mut frame_header = Vec::with_capacity(16 + image.len());
+        // offsets are stored divided by two
+        push_u24(&mut frame_header, 0);
+        push_u24(&mut frame_header, 0);
+        push_u24(&mut frame_header, frame.width as u32 - 1);
+        push_u24(&mut frame_header, frame.height as u32 - 1);
+        push_u24(&mut frame_header, frame.delay as u32 * 10);
+        // don't blend with the previous frame, don't dispose
+        frame_header.push(0x02);
+        frame_header.extend_from_slice(&image);
+        write_chunk(&mut body, b"ANMF", &frame_header);
+    }
+
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(&body);
+    Ok(output)
+}
+
+/// A Huffman code for reading symbols, decoded one bit at a time with the counts
+/// of the canonical code.
+struct HuffmanDecoder {
+    /// Number of codes of every length
+    counts: [u16; 16],
+    /// Symbols ordered by their code
+    symbols: Vec<u16>,
+}
+
+impl HuffmanDecoder {
+    fn new(lengths: &[u8]) -> Result<HuffmanDecoder, String> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[15] as usize + counts[15] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        if symbols.is_empty() {
+            return Err("invalid prefix code without symbols".to_string());
+        }
+        Ok(HuffmanDecoder { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, String> {
+        // a code with a single symbol doesn't take any bits
+        if self.symbols.len() == 1 {
+            return Ok(self.symbols[0] as usize);
+        }
+
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= reader.read_bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid prefix code".to_string())
+    }
+}
+
+fn read_code_lengths(reader: &mut BitReader, alphabet_size: usize) -> Result<Vec<u8>, String> {
+    let mut lengths = vec![0u8; alphabet_size];
+
+    if reader.read_bit()? {
+        let num_symbols = reader.read_bits(1)? + 1;
+        let first_bits = if reader.read_bit()? { 8 } else { 1 };
+        let first = reader.read_bits(first_bits)? as usize;
+        *lengths.get_mut(first).ok_or("invalid simple prefix code")? = 1;
+        if num_symbols == 2 {
+            let second = reader.read_bits(8)? as usize;
+            *lengths.get_mut(second).ok_or("invalid simple prefix code")? = 1;
+        }
+        return Ok(lengths);
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    let stored = reader.read_bits(4)? as usize + 4;
+    for &symbol in &CODE_LENGTH_ORDER[..stored] {
+        code_length_lengths[symbol] = reader.read_bits(3)? as u8;
+    }
+    let code_length_code = HuffmanDecoder::new(&code_length_lengths)?;
+
+    let mut max_symbol = if reader.read_bit()? {
+        let length_bits = 2 + 2 * reader.read_bits(3)?;
+        2 + reader.read_bits(length_bits)? as usize
+    } else {
+        alphabet_size
+    };
+
+    let mut previous = 8;
+    let mut symbol = 0;
+    while symbol < alphabet_size && max_symbol > 0 {
+        max_symbol -= 1;
+        let length = code_length_code.decode(reader)?;
+        if length < 16 {
+            lengths[symbol] = length as u8;
+            symbol += 1;
+            if length != 0 {
+                previous = length as u8;
+            }
+            continue;
+        }
+
+        let (repeat, value) = match length {
+            16 => (3 + reader.read_bits(2)? as usize, previous),
+            17 => (3 + reader.read_bits(3)? as usize, 0),
+            _ => (11 + reader.read_bits(7)? as usize, 0),
+        };
+        if symbol + repeat > alphabet_size {
+            return Err("invalid code lengths".to_string());
+        }
+        for length in &mut lengths[symbol..symbol + repeat] {
+            *length = value;
+        }
+        symbol += repeat;
+    }
+
+    Ok(lengths)
+}
+
+/// The five prefix codes that are used together for a region of the image.
+struct PrefixCodeGroup {
+    green: HuffmanDecoder,
+    red: HuffmanDecoder,
+    blue: HuffmanDecoder,
+    alpha: HuffmanDecoder,
+    distance: HuffmanDecoder,
+}
+
+impl PrefixCodeGroup {
+    fn read(reader: &mut BitReader, color_cache_size: usize) -> Result<PrefixCodeGroup, String> {
+        let mut read_code = |alphabet_size| HuffmanDecoder::new(&read_code_lengths(reader, alphabet_size)?);
+        Ok(PrefixCodeGroup {
+            green: read_code(GREEN_ALPHABET + color_cache_size)?,
+            red: read_code(256)?,
+            blue: read_code(256)?,
+            alpha: read_code(256)?,
+            distance: read_code(NUM_DISTANCE_CODES)?,
+        })
+    }
+}
+
+fn prefix_decode(reader: &mut BitReader, code: usize) -> Result<usize, String> {
+    if code < 4 {
+        return Ok(code + 1);
+    }
+    let extra_bits = (code as u32 - 2) >> 1;
+    let offset = (2 + (code & 1)) << extra_bits;
+    Ok(offset + reader.read_bits(extra_bits)? as usize + 1)
+}
+
+/// Offsets `(x, y)` of the nearby pixels the first 120 distance codes refer to.
+const NEARBY_PIXELS: [(i8, i8); 120] = [
+    (0, 1),
+    (1, 0),
+    (1, 1),
+    (-1, 1),
+    (0, 2),
+    (2, 0),
+    (1, 2),
+    (-1, 2),
+    (2, 1),
+    (-2, 1),
+    (2, 2),
+    (-2, 2),
+    (0, 3),
+    (3, 0),
+    (1, 3),
+    (-1, 3),
+    (3, 1),
+    (-3, 1),
+    (2, 3),
+    (-2, 3),
+    (3, 2),
+    (-3, 2),
+    (0, 4),
+    (4, 0),
+    (1, 4),
+    (-1, 4),
+    (4, 1),
+    (-4, 1),
+    (3, 3),
+    (-3, 3),
+    (2, 4),
+    (-2, 4),
+    (4, 2),
+    (-4, 2),
+    (0, 5),
+    (3, 4),
+    (-3, 4),
+    (4, 3),
+    (-4, 3),
+    (5, 0),
+    (1, 5),
+    (-1, 5),
+    (5, 1),
+    (-5, 1),
+    (2, 5),
+    (-2, 5),
+    (5, 2),
+    (-5, 2),
+    (4, 4),
+    (-4, 4),
+    (3, 5),
+    (-3, 5),
+    (5, 3),
+    (-5, 3),
+    (0, 6),
+    (6, 0),
+    (1, 6),
+    (-1, 6),
+    (6, 1),
+    (-6, 1),
+    (2, 6),
+    (-2, 6),
+    (6, 2),
+    (-6, 2),
+    (4, 5),
+    (-4, 5),
+    (5, 4),
+    (-5, 4),
+    (3, 6),
+    (-3, 6),
+    (6, 3),
+    (-6, 3),
+    (0, 7),
+    (7, 0),
+    (1, 7),
+    (-1, 7),
+    (5, 5),
+    (-5, 5),
+    (7, 1),
+    (-7, 1),
+    (4, 6),
+    (-4, 6),
+    (6, 4),
+    (-6, 4),
+    (2, 7),
+    (-2, 7),
+    (7, 2),
+    (-7, 2),
+    (3, 7),
+    (-3, 7),
+    (7, 3),
+    (-7, 3),
+    (5, 6),
+    (-5, 6),
+    (6, 5),
+    (-6, 5),
+    (8, 0),
+    (4, 7),
+    (-4, 7),
+    (7, 4),
+    (-7, 4),
+    (8, 1),
+    (8, 2),
+    (6, 6),
+    (-6, 6),
+    (8, 3),
+    (5, 7),
+    (-5, 7),
+    (7, 5),
+    (-7, 5),
+    (8, 4),
+    (6, 7),
+    (-6, 7),
+    (7, 6),
+    (-7, 6),
+    (8, 5),
+    (7, 7),
+    (-7, 7),
+    (8, 6),
+    (8, 7),
+];
+
+fn distance_from_code(code: usize, width: usize) -> usize {
+    if code > DISTANCE_OFFSET {
+        return code - DISTANCE_OFFSET;
+    }
+    let (x, y) = NEARBY_PIXELS[code - 1];
+    (x as isize + y as isize * width as isize).max(1) as usize
+}
+
+fn div_round_up(value: usize, bits: u32) -> usize {
+    (value + (1 << bits) - 1) >> bits
+}
+
+/// Decodes an entropy coded image. Only the main image can be split into regions
+/// with their own prefix codes.
+fn decode_image(reader: &mut BitReader, width: usize, height: usize, is_main: bool) -> Result<Vec<u32>, String> {
+    let cache_bits = if reader.read_bit()? {
+        let bits = reader.read_bits(4)?;
+        if !(1..=11).contains(&bits) {
+            return Err(format!("invalid color cache size of {} bits", bits));
+        }
+        bits
+    } else {
+        0
+    };
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+
+    let mut regions = None;
+    let mut group_count = 1;
+    if is_main && reader.read_bit()? {
+        let bits = reader.read_bits(3)? + 2;
+        let regions_width = div_round_up(width, bits);
+        let image = decode_image(reader, regions_width, div_round_up(height, bits), false)?;
+        group_count = image
+            .iter()
+            .map(|&pixel| (pixel >> 8 & 0xffff) as usize + 1)
+            .max()
+            .unwrap_or(1);
+        regions = Some((bits, regions_width, image));
+    }
+
+    let groups = (0..group_count)
+        .map(|_| PrefixCodeGroup::read(reader, cache_size))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut cache = vec![0u32; cache_size];
+    let cache_index = |argb: u32| (argb.wrapping_mul(0x1e35_a7bd) >> (32 - cache_bits)) as usize;
+
+    let size = width * height;
+    let mut pixels: Vec<u32> = Vec::with_capacity(size);
+    let mut cached = 0;
+    while pixels.len() < size {
+        let position = pixels.len();
+        let group = match regions {
+            Some((bits, regions_width, ref image)) => {
+                let (x, y) = (position % width, position / width);
+                &groups[(image[(y >> bits) * regions_width + (x >> bits)] >> 8 & 0xffff) as usize]
+            }
+            None => &groups[0],
+        };
+
+        let symbol = group.green.decode(reader)?;
+        if symbol < 256 {
+            let red = group.red.decode(reader)? as u32;
+            let blue = group.blue.decode(reader)? as u32;
+            let alpha = group.alpha.decode(reader)? as u32;
+            pixels.push(alpha << 24 | red << 16 | (symbol as u32) << 8 | blue);
+        } else if symbol < GREEN_ALPHABET {
+            let length = prefix_decode(reader, symbol - 256)?;
+            let distance_symbol = group.distance.decode(reader)?;
+            let distance_code = prefix_decode(reader, distance_symbol)?;
+            let distance = distance_from_code(distance_code, width);
+            if distance > position || position + length > size {
+                return Err("invalid backward reference".to_string());
+            }
+            for i in position..position + length {
+                pixels.push(pixels[i - distance]);
+            }
+        } else {
+            pixels.push(cache[symbol - GREEN_ALPHABET]);
+        }
+
+        // every pixel goes into the cache, including copies and cached ones
+        if cache_bits > 0 {
+            for &argb in &pixels[cached..] {
+                cache[cache_index(argb)] = argb;
+            }
+            cached = pixels.len();
+        }
+    }
+
+    Ok(pixels)
+}
+
+enum Transform {
+    Predictor { bits: u32, data: Vec<u32> },
+    Color { bits: u32, data: Vec<u32> },
+    SubtractGreen,
+    ColorIndexing { bits: u32, palette: Vec<u32> },
+}
+
+/// Adds two pixels channel by channel.
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xff00_ff00).wrapping_add(b & 0xff00_ff00) & 0xff00_ff00;
+    let red_blue = (a & 0x00ff_00ff).wrapping_add(b & 0x00ff_00ff) & 0x00ff_00ff;
+    alpha_green | red_blue
+}
+
+fn channels(pixel: u32) -> [i32; 4] {
+    [
+        (pixel >> 24) as i32,
+        (pixel >> 16 & 0xff) as i32,
+        (pixel >> 8 & 0xff) as i32,
+        (pixel & 0xff) as i32,
+    ]
+}
+
+fn from_channels(channels: [i32; 4]) -> u32 {
+    channels
+        .iter()
+        .fold(0, |pixel, &channel| pixel << 8 | channel.clamp(0, 255) as u32)
+}
+
+fn average(a: u32, b: u32) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    from_channels([
+        (a[0] + b[0]) / 2,
+        (a[1] + b[1]) / 2,
+        (a[2] + b[2]) / 2,
+        (a[3] + b[3]) / 2,
+    ])
+}
+
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+    let distance_left: i32 = (0..4).map(|i| (t[i] - tl[i]).abs()).sum();
+    let distance_top: i32 = (0..4).map(|i| (l[i] - tl[i]).abs()).sum();
+    if distance_left < distance_top {
+        left
+    } else {
+        top
+    }
+}
+
+fn clamp_add_subtract_full(a: u32, b: u32, c: u32) -> u32 {
+    let (a, b, c) = (channels(a), channels(b), channels(c));
+    from_channels([
+        a[0] + b[0] - c[0],
+        a[1] + b[1] - c[1],
+        a[2] + b[2] - c[2],
+        a[3] + b[3] - c[3],
+    ])
+}
+
+fn clamp_add_subtract_half(a: u32, b: u32) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    from_channels([
+        a[0] + (a[0] - b[0]) / 2,
+        a[1] + (a[1] - b[1]) / 2,
+        a[2] + (a[2] - b[2]) / 2,
+        a[3] + (a[3] - b[3]) / 2,
+    ])
+}
+
+fn predict(mode: u32, pixels: &[u32], position: usize, width: usize) -> u32 {
+    let left = pixels[position - 1];
+    let top = pixels[position - width];
+    let top_left = pixels[position - width - 1];
+    // the rightmost pixel takes the leftmost pixel of its own row as top right
+    let top_right = pixels[position - width + 1];
+
+    match mode {
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average(average(left, top_right), top),
+        6 => average(left, top_left),
+        7 => average(left, top),
+        8 => average(top_left, top),
+        9 => average(top, top_right),
+        10 => average(average(left, top_left), average(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => clamp_add_subtract_full(left, top, top_left),
+        13 => clamp_add_subtract_half(average(left, top), top_left),
+        _ => 0xff00_0000,
+    }
+}
+
+fn color_transform_delta(transform: u8, color: u8) -> u8 {
+    ((transform as i8 as i32 * color as i8 as i32) >> 5) as u8
+}
+
+impl Transform {
+    fn read(reader: &mut BitReader, width: usize, height: usize) -> Result<Transform, String> {
+        match reader.read_bits(2)? {
+            kind @ 0..=1 => {
+                let bits = reader.read_bits(3)? + 2;
+                let data = decode_image(reader, div_round_up(width, bits), div_round_up(height, bits), false)?;
+                Ok(if kind == 0 {
+                    Transform::Predictor { bits, data }
+                } else {
+                    Transform::Color { bits, data }
+                })
+            }
+            2 => Ok(Transform::SubtractGreen),
+            _ => {
+                let size = reader.read_bits(8)? as usize + 1;
+                let mut palette = decode_image(reader, size, 1, false)?;
+                for i in 1..size {
+                    palette[i] = add_pixels(palette[i], palette[i - 1]);
+                }
+                let bits = match size {
+                    0..=2 => 3,
+                    3..=4 => 2,
+                    5..=16 => 1,
+                    _ => 0,
+                };
+                Ok(Transform::ColorIndexing { bits, palette })
+            }
+        }
+    }
+
+    /// Reverts the transform, `width` is the width of the image before it was
+    /// transformed.
+    fn invert(&self, pixels: Vec<u32>, width: usize, height: usize) -> Vec<u32> {
+        match *self {
+            Transform::Predictor { bits, ref data } => {
+                let mut pixels = pixels;
+                let blocks_width = div_round_up(width, bits);
+                for y in 0..height {
+                    for x in 0..width {
+                        let position = y * width + x;
+                        let prediction = match (x, y) {
+                            (0, 0) => 0xff00_0000,
+                            (_, 0) => pixels[position - 1],
+                            (0, _) => pixels[position - width],
+                            _ => {
+                                let mode = data[(y >> bits) * blocks_width + (x >> bits)] >> 8 & 0x0f;
+                                predict(mode, &pixels, position, width)
+                            }
+                        };
+                        pixels[position] = add_pixels(pixels[position], prediction);
+                    }
+                }
+                pixels
+            }
+            Transform::Color { bits, ref data } => {
+                let blocks_width = div_round_up(width, bits);
+                pixels
+                    .iter()
+                    .enumerate()
+                    .map(|(position, &pixel)| {
+                        let (x, y) = (position % width, position / width);
+                        let element = data[(y >> bits) * blocks_width + (x >> bits)];
+                        let [alpha, red, green, blue] = channels(pixel).map(|channel| channel as u8);
+
+                        let red = red.wrapping_add(color_transform_delta(element as u8, green));
+                        let blue = blue
+                            .wrapping_add(color_transform_delta((element >> 8) as u8, green))
+                            .wrapping_add(color_transform_delta((element >> 16) as u8, red));
+                        (alpha as u32) << 24 | (red as u32) << 16 | (green as u32) << 8 | blue as u32
+                    })
+                    .collect()
+            }
+            Transform::SubtractGreen => pixels
+                .iter()
+                .map(|&pixel| {
+                    let green = pixel >> 8 & 0xff;
+                    add_pixels(pixel, green << 16 | green)
+                })
+                .collect(),
+            Transform::ColorIndexing { bits, ref palette } => {
+                let packed_width = div_round_up(width, bits);
+                let bits_per_pixel = 8 >> bits;
+                let mask = (1 << bits_per_pixel) - 1;
+                let mut output = Vec::with_capacity(width * height);
+                for y in 0..height {
+                    for x in 0..width {
+                        let packed = pixels[y * packed_width + (x >> bits)] >> 8 & 0xff;
+                        let shift = bits_per_pixel * (x as u32 & ((1 << bits) - 1));
+                        let index = (packed >> shift & mask) as usize;
+                        // indices outside of the palette are transparent black
+                        output.push(palette.get(index).cloned().unwrap_or(0));
+                    }
+                }
+                output
+            }
+        }
+    }
+}
+
+/// Decodes a VP8L bitstream into RGBA pixels.
+pub fn decode_lossless(data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    if data.len() < 5 || data[0] != VP8L_SIGNATURE {
+        return Err("not a lossless WebP image".to_string());
+    }
+
+    let mut reader = BitReader::new(&data[1..]);
+    let width = reader.read_bits(14)? as usize + 1;
+    let height = reader.read_bits(14)? as usize + 1;
+    // whether alpha is used is only a hint
+    reader.read_bits(1)?;
+    if reader.read_bits(3)? != 0 {
+        return Err("unknown version of the lossless WebP format".to_string());
+    }
+
+    let pixels = decode_transformed_image(&mut reader, width, height)?;
+    let rgba = pixels
+        .iter()
+        .flat_map(|&argb| [(argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8])
+        .collect();
+    Ok((width as u32, height as u32, rgba))
+}
+
+/// Decodes the transforms and the main image and reverts the transforms.
+fn decode_transformed_image(reader: &mut BitReader, width: usize, height: usize) -> Result<Vec<u32>, String> {
+    // transforms are read in the order they were applied and reverted backwards,
+    // together with the width of the image they apply to
+    let mut transforms = Vec::new();
+    let mut transformed_width = width;
+    while reader.read_bit()? {
+        let transform = Transform::read(reader, transformed_width, height)?;
+        let image_width = transformed_width;
+        if let Transform::ColorIndexing { bits, .. } = transform {
+            transformed_width = div_round_up(transformed_width, bits);
+        }
+        transforms.push((transform, image_width));
+    }
+
+    let mut pixels = decode_image(reader, transformed_width, height, true)?;
+    for (transform, image_width) in transforms.iter().rev() {
+        pixels = transform.invert(pixels, *image_width, height);
+    }
+    Ok(pixels)
+}
+
+fn u24_at(data: &[u8], offset: usize) -> u32 {
+    data[offset] as u32 | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16
+}
+
+/// The kind of a RIFF chunk and its content.
+type Chunk<'a> = (&'a [u8], &'a [u8]);
+
+/// Splits RIFF data into its chunks.
+fn chunks(mut data: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        if data.len() < 8 + size {
+            return Err("unexpected end of data".to_string());
+        }
+        chunks.push((&data[..4], &data[8..8 + size]));
+        data = &data[(8 + size + size % 2).min(data.len())..];
+    }
+    Ok(chunks)
+}
+
+/// Decodes an ALPH chunk into the alpha channel of `rgba`.
+fn decode_alpha(data: &[u8], width: usize, height: usize, rgba: &mut [u8]) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("invalid ALPH chunk".to_string());
+    }
+
+    let mut alpha: Vec<u8> = match data[0] & 0x03 {
+        0 if data.len() > width * height => data[1..1 + width * height].to_vec(),
+        0 => return Err("unexpected end of data".to_string()),
+        // a lossless image without its header, with the values in green
+        1 => decode_transformed_image(&mut BitReader::new(&data[1..]), width, height)?
+            .iter()
+            .map(|&argb| (argb >> 8) as u8)
+            .collect(),
+        _ => return Err("unknown compression of an ALPH chunk".to_string()),
+    };
+
+    // the filters store the difference to a prediction from the neighbors. The first
+    // row is always predicted from the left and the first column from above.
+    let filter = (data[0] >> 2) & 0x03;
+    if filter != 0 {
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let prediction = match (filter, x, y) {
+                    (_, 0, 0) => 0,
+                    (_, _, 0) | (1, _, _) => alpha[i - 1],
+                    (_, 0, _) | (2, _, _) => alpha[i - width],
+                    _ => (alpha[i - 1] as i32 + alpha[i - width] as i32 - alpha[i - width - 1] as i32).clamp(0, 255)
+                        as u8,
+                };
+                alpha[i] = alpha[i].wrapping_add(prediction);
+            }
+        }
+    }
+
+    for (pixel, &value) in rgba.chunks_exact_mut(4).zip(alpha.iter()) {
+        pixel[3] = value;
+    }
+    Ok(())
+}
+
+/// Decodes the image of a frame, which is either a lone VP8L chunk or a lossy VP8
+/// chunk with an optional ALPH chunk.
+fn decode_frame_image(chunks: &[Chunk]) -> Result<(u32, u32, Vec<u8>), String> {
+    for &(kind, data) in chunks {
+        match kind {
+            b"VP8L" => return decode_lossless(data),
+            b"VP8 " => {
+                let (width, height, mut rgba) = vp8::decode(data)?;
+                if let Some(&(_, alpha)) = chunks.iter().find(|&&(kind, _)| kind == b"ALPH") {
+                    decode_alpha(alpha, width as usize, height as usize, &mut rgba)?;
+                }
+                return Ok((width, height, rgba));
+            }
+            _ => {}
+        }
+    }
+    Err("a frame has no image data".to_string())
+}
+
+/// Reads the size of a WebP from its VP8X chunk or the header of its image
+/// without decoding it.
+pub fn size(data: &[u8]) -> Result<(u32, u32), String> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err("not a WebP image".to_string());
+    }
+
+    for (kind, chunk) in chunks(&data[12..])? {
+        match kind {
+            b"VP8X" if chunk.len() >= 10 => return Ok((u24_at(chunk, 4) + 1, u24_at(chunk, 7) + 1)),
+            b"VP8L" if chunk.len() >= 5 && chunk[0] == VP8L_SIGNATURE => {
+                let mut reader = BitReader::new(&chunk[1..]);
+                let width = reader.read_bits(14)? + 1;
+                let height = reader.read_bits(14)? + 1;
+                return Ok((width, height));
+            }
+            b"VP8 " if chunk.len() >= 10 => {
+                let width = u16::from_le_bytes([chunk[6], chunk[7]]) & 0x3fff;
+                let height = u16::from_le_bytes([chunk[8], chunk[9]]) & 0x3fff;
+                return Ok((width as u32, height as u32));
+            }
+            _ => {}
+        }
+    }
+    Err("the WebP has no image".to_string())
+}
+
+/// Decodes a WebP and composes its frames. Still images are returned as a single
+/// frame.
+pub fn decode_animation(data: &[u8]) -> Result<Animation, String> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err("not a WebP image".to_string());
+    }
+
+    let chunks = chunks(&data[12..])?;
+    let header = chunks.iter().find(|&&(kind, _)| kind == b"VP8X");
+    let header = match header {
+        Some(&(_, header)) if header.len() >= 10 => header,
+        Some(_) => return Err("invalid VP8X chunk".to_string()),
+        None => {
+            let (width, height, rgba) = decode_frame_image(&chunks)?;
+            return Ok(Animation {
+                width: width as u16,
+                height: height as u16,
+                plays: 0,
+                frames: vec![FrameData {
+                    width: width as u16,
+                    height: height as u16,
+                    rgba,
+                    delay: 0,
+                }],
+            });
+        }
+    };
+
+    let width = u24_at(header, 4) as usize + 1;
+    let height = u24_at(header, 7) as usize + 1;
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(format!("{}x{} is too large for a gif", width, height));
+    }
+
+    let plays = chunks
+        .iter()
+        .find(|&&(kind, animation)| kind == b"ANIM" && animation.len() >= 6)
+        .map_or(0, |&(_, animation)| {
+            u16::from_le_bytes([animation[4], animation[5]]) as u32
+        });
+
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut frames = Vec::new();
+    for &(kind, frame) in &chunks {
+        if kind != b"ANMF" {
+            continue;
+        }
+        if frame.len() < 16 {
+            return Err("invalid ANMF chunk".to_string());
+        }
+
+        let left = u24_at(frame, 0) as usize * 2;
+        let top = u24_at(frame, 3) as usize * 2;
+        let duration = u24_at(frame, 12);
+        let blend = frame[15] & 0x02 == 0;
+        let dispose = frame[15] & 0x01 != 0;
+
+        let (frame_width, frame_height, rgba) = decode_frame_image(&self::chunks(&frame[16..])?)?;
+        let right = (left + frame_width as usize).min(width);
+        let bottom = (top + frame_height as usize).min(height);
+
+        for y in top..bottom {
+            for x in left..right {
+                let source = &rgba[((y - top) * frame_width as usize + x - left) * 4..][..4];
+                let target = &mut canvas[(y * width + x) * 4..][..4];
+                if blend {
+                    filters::blend_over(target, source);
+                } else {
+                    target.copy_from_slice(source);
+                }
+            }
+        }
+
+        frames.push(FrameData {
+            width: width as u16,
+            height: height as u16,
+            rgba: canvas.clone(),
+            delay: ((duration + 5) / 10).min(u16::MAX as u32) as u16,
+        });
+
+        if dispose {
+            for y in top..bottom {
+                for pixel in &mut canvas[(y * width + left) * 4..(y * width + right) * 4] {
+                    *pixel = 0;
+                }
+            }
+        }
+    }
+
+    if frames.is_empty() {
+        let (_, _, rgba) = decode_frame_image(&chunks)?;
+        frames.push(FrameData {
+            width: width as u16,
+            height: height as u16,
+            rgba,
+            delay: 0,
+        });
+    }
+
+    Ok(Animation {
+        width: width as u16,
+        height: height as u16,
+        plays,
+        frames,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A lossless WebP of 4x4 pixels in three colors, one of them half transparent,
+    /// written by libwebp. It uses the color indexing transform.
+    const LIBWEBP_PALETTE: [u8; 60] = [
+        0x52, 0x49, 0x46, 0x46, 0x34, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50, 0x38, 0x4c, 0x27, 0x00,
+        0x00, 0x00, 0x2f, 0x03, 0xc0, 0x00, 0x10, 0x17, 0x30, 0xff, 0x02, 0x82, 0x22, 0xff, 0x47, 0x13, 0x10, 0x14,
+        0xf9, 0x3f, 0x9a, 0x80, 0xa0, 0xe8, 0xba, 0xe5, 0x82, 0x77, 0x26, 0x88, 0x05, 0xd3, 0x90, 0x46, 0x7a, 0x93,
+        0x29, 0xa2, 0xff, 0xa1, 0x01, 0x00,
+    ];
+
+    /// A lossless WebP of a 4x4 gradient written by libwebp.
+    const LIBWEBP_GRADIENT: [u8; 74] = [
+        0x52, 0x49, 0x46, 0x46, 0x42, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50, 0x38, 0x4c, 0x35, 0x00,
+        0x00, 0x00, 0x2f, 0x03, 0xc0, 0x00, 0x00, 0x7f, 0x20, 0x10, 0x20, 0x84, 0xc9, 0x97, 0x26, 0x62, 0x78, 0x04,
+        0x02, 0xc9, 0x24, 0xf9, 0x73, 0x4d, 0x32, 0xff, 0x6d, 0x40, 0x85, 0x10, 0x24, 0xcb, 0x32, 0x72, 0x90, 0x81,
+        0x20, 0x00, 0x22, 0xce, 0x81, 0x03, 0x07, 0x3e, 0x1d, 0x38, 0x70, 0x20, 0x25, 0xa2, 0xff, 0xe1, 0x6b, 0x2c,
+        0x02, 0x00,
+    ];
+
+    /// A lossy WebP of 8x8 pixels of the color (200, 100, 50) written by libwebp.
+    const LIBWEBP_LOSSY: [u8; 70] = [
+        0x52, 0x49, 0x46, 0x46, 0x3e, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50, 0x38, 0x20, 0x32, 0x00,
+        0x00, 0x00, 0xd0, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x08, 0x00, 0x08, 0x00, 0x00, 0xc0, 0x12, 0x25, 0xa0, 0x02,
+        0x74, 0xba, 0x01, 0xf8, 0x00, 0x03, 0xb0, 0x00, 0xfe, 0xda, 0x26, 0xff, 0xee, 0xf3, 0x7e, 0xd3, 0xd7, 0xb4,
+        0xf5, 0xfd, 0x4c, 0xff, 0xf8, 0xca, 0x9f, 0x20, 0x3f, 0xe3, 0x2a, 0x7f, 0xc5, 0xcc, 0x00, 0x00,
+    ];
+
+    fn frame(width: u16, height: u16, delay: u16, pixel: impl Fn(usize, usize) -> [u8; 4]) -> FrameData {
+        let rgba = (0..height as usize)
+            .flat_map(|y| (0..width as usize).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(x, y))
+            .collect();
+        FrameData {
+            width,
+            height,
+            rgba,
+            delay,
+        }
+    }
+
+    #[test]
+    fn lossless_images_decode_to_the_same_pixels() {
+        let noise = frame(23, 17, 0, |x, y| {
+            [
+                (x * 31 + y * 7) as u8,
+                (x * y) as u8,
+                ((x ^ y) * 9) as u8,
+                (y * 15) as u8,
+            ]
+        });
+        // long runs and repeated rows make backward references
+        let stripes = frame(64, 40, 0, |x, y| {
+            if (x / 8 + y / 4) % 2 == 0 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            }
+        });
+
+        for image in &[noise, stripes] {
+            let (width, height) = (image.width as u32, image.height as u32);
+            let data = encode_lossless(width, height, &image.rgba);
+            assert_eq!(decode_lossless(&data), Ok((width, height, image.rgba.clone())));
+        }
+    }
+
+    #[test]
+    fn images_from_libwebp_are_decoded() {
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 255], [0, 255, 0, 128]];
+        let expected = frame(4, 4, 0, |x, y| colors[(x + y) % 3]);
+        let animation = decode_animation(&LIBWEBP_PALETTE).unwrap();
+        assert_eq!((animation.width, animation.height, animation.frames.len()), (4, 4, 1));
+        assert_eq!(animation.frames[0].rgba, expected.rgba);
+
+        let expected = frame(4, 4, 0, |x, y| {
+            [(x * 60) as u8, (y * 60) as u8, ((x + y) * 30) as u8, 255]
+        });
+        let animation = decode_animation(&LIBWEBP_GRADIENT).unwrap();
+        assert_eq!(animation.frames[0].rgba, expected.rgba);
+
+        let animation = decode_animation(&LIBWEBP_LOSSY).unwrap();
+        assert_eq!((animation.width, animation.height), (8, 8));
+        assert!(animation.frames[0]
+            .rgba
+            .chunks_exact(4)
+            .all(|pixel| pixel[1..] == [100, 50, 255]));
+    }
+
+    #[test]
+    fn lossless_animations_keep_their_frames() {
+        let frames = [
+            frame(6, 5, 10, |x, _| [x as u8 * 40, 0, 0, 255]),
+            frame(6, 5, 25, |_, y| [0, y as u8 * 50, 0, 128]),
+            frame(6, 5, 7, |_, _| [0, 0, 0, 0]),
+        ];
+        let data = encode_animation(6, 5, &frames, 3, 100).unwrap();
+        assert_eq!(size(&data), Ok((6, 5)));
+
+        let animation = decode_animation(&data).unwrap();
+        assert_eq!((animation.width, animation.height, animation.plays), (6, 5, 3));
+        assert_eq!(animation.frames.len(), frames.len());
+        for (decoded, frame) in animation.frames.iter().zip(&frames) {
+            assert_eq!(decoded.rgba, frame.rgba);
+            assert_eq!(decoded.delay, frame.delay);
+        }
+    }
+
+    #[test]
+    fn lossy_animations_keep_the_transparency() {
+        let frames = [
+            frame(20, 18, 4, |x, y| {
+                [(x * 12) as u8, (y * 14) as u8, 100, if x < 10 { 255 } else { 60 }]
+            }),
+            frame(20, 18, 4, |x, y| [100, (x * 12) as u8, (y * 14) as u8, 255]),
+        ];
+        let data = encode_animation(20, 18, &frames, 0, 90).unwrap();
+        assert_eq!(size(&data), Ok((20, 18)));
+
+        let animation = decode_animation(&data).unwrap();
+        assert_eq!(animation.frames.len(), 2);
+        for (decoded, frame) in animation.frames.iter().zip(&frames) {
+            for (a, b) in decoded.rgba.chunks_exact(4).zip(frame.rgba.chunks_exact(4)) {
+                assert_eq!(a[3], b[3]);
+                assert!(
+                    (0..3).all(|i| (a[i] as i32 - b[i] as i32).abs() <= 24),
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn size_reads_every_kind_of_header() {
+        assert_eq!(size(&LIBWEBP_PALETTE), Ok((4, 4)));
+        assert_eq!(size(&LIBWEBP_LOSSY), Ok((8, 8)));
+        assert!(size(b"RIFF\x04\x00\x00\x00WEBP").is_err());
+    }
+
+    #[test]
+    fn invalid_data_is_an_error() {
+        assert!(decode_animation(b"GIF89a").is_err());
+        assert!(decode_animation(b"RIFF\x04\x00\x00\x00WEBP").is_err());
+        assert!(decode_animation(&LIBWEBP_GRADIENT[..40]).is_err());
+        assert!(decode_lossless(&[0x2f, 0, 0, 0, 0xe0]).is_err());
+        assert!(decode_lossless(&LIBWEBP_GRADIENT[20..30]).is_err());
+    }
+}
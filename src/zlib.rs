@@ -0,0 +1,507 @@
+//! A small zlib/deflate implementation.
+//!
+//! The gif crate only ships an LZW implementation and PNG needs deflate. This is a
+//! straightforward LZ77 compressor with hash chains that emits a single block with
+//! the fixed Huffman codes from RFC 1951. It doesn't compress as well as zlib with
+//! dynamic Huffman tables but it's small and good enough for frame exports. The
+//! decompressor handles all block types so PNGs from any encoder can be read.
+
+/// Size of the sliding window, the maximum distance deflate can refer back to.
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+/// How many previous positions with the same hash are tried to find a match.
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// Writes bits LSB first as required by deflate. WebP lossless packs its bits the
+/// same way.
+pub struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new(output: Vec<u8>) -> BitWriter {
+        BitWriter {
+            output,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are stored MSB first, so they have to be reversed.
+    pub fn write_code(&mut self, code: u32, length: u32) {
+        let mut reversed = 0;
+        for i in 0..length {
+            reversed |= ((code >> i) & 1) << (length - 1 - i);
+        }
+        self.write_bits(reversed, length);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+/// Reads bits LSB first, the counterpart of `BitWriter`.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            offset: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, String> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.offset)
+                .ok_or("unexpected end of the compressed data")?;
+            self.buffer |= (byte as u64) << self.count;
+            self.offset += 1;
+            self.count += 8;
+        }
+        let value = (self.buffer & ((1 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, String> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Skips the remaining bits of the current byte.
+    fn align_to_byte(&mut self) {
+        let skipped = self.count % 8;
+        self.buffer >>= skipped;
+        self.count -= skipped;
+    }
+}
+
+/// Writes a literal/length symbol with the fixed Huffman code.
+fn write_literal_length(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let length_code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_literal_length(writer, 257 + length_code as u32);
+    writer.write_bits(
+        (length - LENGTH_BASE[length_code] as usize) as u32,
+        LENGTH_EXTRA[length_code] as u32,
+    );
+
+    let distance_code = DISTANCE_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    writer.write_code(distance_code as u32, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[distance_code] as usize) as u32,
+        DISTANCE_EXTRA[distance_code] as u32,
+    );
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let value = (data[position] as usize) << 16 | (data[position + 1] as usize) << 8 | data[position + 2] as usize;
+    (value.wrapping_mul(2_654_435_761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+/// Compresses `data` into a raw deflate stream.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new(Vec::with_capacity(data.len() / 2));
+
+    // a single final block with fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    // head contains the most recent position for every hash, prev links every position
+    // to the previous one with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut position = 0;
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, position: usize| {
+        if position + MIN_MATCH <= data.len() {
+            let h = hash(data, position);
+            prev[position % WINDOW_SIZE] = head[h];
+            head[h] = position;
+        }
+    };
+
+    while position < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if position + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - position);
+            let mut candidate = head[hash(data, position)];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && position - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[position..position + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for p in position..position + best_length {
+                insert(&mut head, &mut prev, p);
+            }
+            position += best_length;
+        } else {
+            write_literal_length(&mut writer, data[position] as u32);
+            insert(&mut head, &mut prev, position);
+            position += 1;
+        }
+    }
+
+    write_literal_length(&mut writer, 256);
+    writer.finish()
+}
+
+/// A Huffman code for decoding, read one bit at a time with the counts of the
+/// canonical code.
+struct Huffman {
+    /// Number of codes of every length
+    counts: [u16; 16],
+    /// Symbols ordered by their code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[15] as usize + counts[15] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= reader.read_bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+/// The order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    for length in &mut lengths[144..256] {
+        *length = 9;
+    }
+    for length in &mut lengths[256..280] {
+        *length = 7;
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Reads the literal/length and distance codes of a block with dynamic codes.
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.read_bits(5)? as usize + 257;
+    let distance_count = reader.read_bits(5)? as usize + 1;
+    let stored = reader.read_bits(4)? as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..stored] {
+        code_length_lengths[symbol] = reader.read_bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            length @ 0..=15 => (length as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("invalid code lengths")?;
+                (previous, 3 + reader.read_bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.read_bits(3)? as usize),
+            _ => (0, 11 + reader.read_bits(7)? as usize),
+        };
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err("invalid code lengths".to_string());
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+/// Decodes the symbols of a compressed block until its end.
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let length_code = symbol - 257;
+        if length_code >= LENGTH_BASE.len() {
+            return Err("invalid length code".to_string());
+        }
+        let length = LENGTH_BASE[length_code] as usize + reader.read_bits(LENGTH_EXTRA[length_code] as u32)? as usize;
+
+        let distance_code = distances.decode(reader)?;
+        if distance_code >= DISTANCE_BASE.len() {
+            return Err("invalid distance code".to_string());
+        }
+        let distance =
+            DISTANCE_BASE[distance_code] as usize + reader.read_bits(DISTANCE_EXTRA[distance_code] as u32)? as usize;
+        if distance > output.len() {
+            return Err("distance too far back".to_string());
+        }
+
+        // the copy may overlap with itself, so it has to be done byte by byte
+        let start = output.len() - distance;
+        for i in start..start + length {
+            output.push(output[i]);
+        }
+    }
+}
+
+/// Decompresses a raw deflate stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::with_capacity(data.len() * 4);
+
+    loop {
+        let last = reader.read_bit()?;
+        match reader.read_bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let length = reader.read_bits(16)?;
+                if reader.read_bits(16)? != !length & 0xffff {
+                    return Err("invalid length of a stored block".to_string());
+                }
+                for _ in 0..length {
+                    output.push(reader.read_bits(8)? as u8);
+                }
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// Computes the Adler-32 checksum used by the zlib container.
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    // 5552 is the largest number of bytes that can be summed up before b overflows
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+/// Compresses `data` and wraps it in a zlib container.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window and no preset dictionary, the second byte makes the
+    // header a multiple of 31
+    let mut output = vec![0x78, 0x01];
+    output.extend(deflate(data));
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+
+    output
+}
+
+/// Decompresses data in a zlib container. The checksum isn't verified.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31) {
+        return Err("invalid zlib header".to_string());
+    }
+    if data[1] & 0x20 != 0 {
+        return Err("zlib data with a preset dictionary is not supported".to_string());
+    }
+
+    inflate(&data[2..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_data_decompresses_to_the_input() {
+        let inputs: [Vec<u8>; 4] = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabc hello hello hello".repeat(20),
+            (0..100_000u64).map(|i| (i * i % 253) as u8).collect(),
+        ];
+        for input in &inputs {
+            assert_eq!(decompress(&compress(input)).as_ref(), Ok(input));
+        }
+    }
+
+    #[test]
+    fn repetitions_are_compressed() {
+        let input = vec![7; 10_000];
+        assert!(compress(&input).len() < 100);
+    }
+
+    #[test]
+    fn streams_from_zlib_are_decompressed() {
+        // stored, fixed and dynamic Huffman blocks, as written by zlib
+        let stored = [120, 1, 1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100, 9, 60, 2, 146];
+        assert_eq!(decompress(&stored).unwrap(), b"stored");
+
+        let fixed = [
+            120, 218, 75, 76, 74, 78, 68, 69, 10, 25, 169, 57, 57, 249, 200, 36, 0, 249, 243, 13, 129,
+        ];
+        assert_eq!(decompress(&fixed).unwrap(), b"abcabcabcabcabcabc hello hello hello");
+
+        let dynamic = [
+            0x78, 0xda, 0x1d, 0x88, 0xc1, 0x11, 0x00, 0x30, 0x0c, 0x40, 0x66, 0x25, 0xf6, 0x9f, 0xa1, 0x69, 0x1e, 0xee,
+            0x20, 0x03, 0xf2, 0x59, 0x09, 0x26, 0xdb, 0xd6, 0xfb, 0xa9, 0xe1, 0xf4, 0x00, 0x39, 0x8c, 0x0f, 0x51,
+        ];
+        assert_eq!(
+            decompress(&dynamic).unwrap(),
+            b"bacaabaaabacaadaacdbdbaabbcaabadbbbdabcd"
+        );
+    }
+
+    #[test]
+    fn adler32_matches_the_reference() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        // not deflate, a header that isn't a multiple of 31 and a preset dictionary
+        assert!(decompress(&[0x79, 0x01, 0x03, 0x00]).is_err());
+        assert!(decompress(&[0x78, 0x02, 0x03, 0x00]).is_err());
+        assert!(decompress(&[0x78, 0xbb, 0x03, 0x00]).is_err());
+        assert!(decompress(&[]).is_err());
+
+        // a reserved block type, a stored block with a broken length and a truncated stream
+        assert!(inflate(&[0x07]).is_err());
+        assert!(inflate(&[0x01, 0x06, 0x00, 0x00, 0x00]).is_err());
+        let compressed = compress(b"abcdefghijklmnopqrstuvwxyz");
+        assert!(decompress(&compressed[..compressed.len() - 10]).is_err());
+    }
+}